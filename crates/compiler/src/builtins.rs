@@ -173,23 +173,23 @@ fn mk_builtin_table() -> Vec<Builtin> {
         Builtin {
             name: "value_hash".to_string(),
             min_args: Q(1),
-            max_args: Q(1),
-            types: vec![Any],
-            implemented: false,
+            max_args: Q(2),
+            types: vec![Any, Typed(TYPE_STR)],
+            implemented: true,
         },
         Builtin {
             name: "string_hash".to_string(),
             min_args: Q(1),
-            max_args: Q(1),
-            types: vec![Typed(TYPE_STR)],
-            implemented: false,
+            max_args: Q(2),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR)],
+            implemented: true,
         },
         Builtin {
             name: "binary_hash".to_string(),
             min_args: Q(1),
-            max_args: Q(1),
-            types: vec![Typed(TYPE_STR)],
-            implemented: false,
+            max_args: Q(2),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR)],
+            implemented: true,
         },
         Builtin {
             name: "decode_binary".to_string(),
@@ -390,8 +390,8 @@ fn mk_builtin_table() -> Vec<Builtin> {
         Builtin {
             name: "random".to_string(),
             min_args: Q(0),
-            max_args: Q(1),
-            types: vec![Typed(TYPE_INT)],
+            max_args: Q(2),
+            types: vec![Any, Typed(TYPE_INT)],
             implemented: true,
         },
         Builtin {
@@ -947,6 +947,349 @@ fn mk_builtin_table() -> Vec<Builtin> {
             types: vec![Typed(TYPE_STR)],
             implemented: true,
         },
+        Builtin {
+            name: "commit".to_string(),
+            min_args: Q(0),
+            max_args: Q(0),
+            types: vec![],
+            implemented: true,
+        },
+        Builtin {
+            name: "rollback_to_start".to_string(),
+            min_args: Q(0),
+            max_args: Q(0),
+            types: vec![],
+            implemented: true,
+        },
+        Builtin {
+            name: "set_task_retry_safe".to_string(),
+            min_args: Q(1),
+            max_args: Q(1),
+            types: vec![Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "generate_json".to_string(),
+            min_args: Q(1),
+            max_args: Q(2),
+            types: vec![Any, Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "parse_json".to_string(),
+            min_args: Q(1),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "pcre_match".to_string(),
+            min_args: Q(2),
+            max_args: Q(4),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR), Any, Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "pcre_replace".to_string(),
+            min_args: Q(2),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "ancestors".to_string(),
+            min_args: Q(1),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_OBJ), Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "descendants".to_string(),
+            min_args: Q(1),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_OBJ), Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "isa".to_string(),
+            min_args: Q(2),
+            max_args: Q(3),
+            types: vec![Typed(TYPE_OBJ), Any, Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "occupants".to_string(),
+            min_args: Q(1),
+            max_args: Q(3),
+            types: vec![Typed(TYPE_OBJ), Any, Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "locations".to_string(),
+            min_args: Q(1),
+            max_args: Q(3),
+            types: vec![Typed(TYPE_OBJ), Typed(TYPE_OBJ), Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "sort".to_string(),
+            min_args: Q(1),
+            max_args: Q(4),
+            types: vec![Typed(TYPE_LIST), Typed(TYPE_LIST), Any, Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "reverse".to_string(),
+            min_args: Q(1),
+            max_args: Q(1),
+            types: vec![Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "slice".to_string(),
+            min_args: Q(1),
+            max_args: Q(3),
+            types: vec![Typed(TYPE_LIST), Any, Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "flatten".to_string(),
+            min_args: Q(1),
+            max_args: Q(1),
+            types: vec![Typed(TYPE_LIST)],
+            implemented: true,
+        },
+        Builtin {
+            name: "complex_match".to_string(),
+            min_args: Q(2),
+            max_args: Q(3),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_LIST), Typed(TYPE_LIST)],
+            implemented: true,
+        },
+        Builtin {
+            name: "explode".to_string(),
+            min_args: Q(1),
+            max_args: Q(3),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR), Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "strtr".to_string(),
+            min_args: Q(3),
+            max_args: Q(4),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR), Typed(TYPE_STR), Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "upcase".to_string(),
+            min_args: Q(1),
+            max_args: Q(1),
+            types: vec![Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "downcase".to_string(),
+            min_args: Q(1),
+            max_args: Q(1),
+            types: vec![Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "capitalize".to_string(),
+            min_args: Q(1),
+            max_args: Q(1),
+            types: vec![Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "trim".to_string(),
+            min_args: Q(1),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "ltrim".to_string(),
+            min_args: Q(1),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "rtrim".to_string(),
+            min_args: Q(1),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "starts_with".to_string(),
+            min_args: Q(2),
+            max_args: Q(3),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR), Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "ends_with".to_string(),
+            min_args: Q(2),
+            max_args: Q(3),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR), Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "format".to_string(),
+            min_args: Q(1),
+            max_args: U,
+            types: vec![Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "spellnum".to_string(),
+            min_args: Q(1),
+            max_args: Q(1),
+            types: vec![Typed(TYPE_INT)],
+            implemented: true,
+        },
+        Builtin {
+            name: "argon2".to_string(),
+            min_args: Q(2),
+            max_args: Q(3),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR), Typed(TYPE_LIST)],
+            implemented: true,
+        },
+        Builtin {
+            name: "argon2_verify".to_string(),
+            min_args: Q(2),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "salt".to_string(),
+            min_args: Q(0),
+            max_args: Q(0),
+            types: vec![],
+            implemented: true,
+        },
+        Builtin {
+            name: "random_bytes".to_string(),
+            min_args: Q(1),
+            max_args: Q(1),
+            types: vec![Typed(TYPE_INT)],
+            implemented: true,
+        },
+        Builtin {
+            name: "hmac".to_string(),
+            min_args: Q(3),
+            max_args: Q(3),
+            types: vec![Typed(TYPE_STR), Typed(TYPE_STR), Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "encode_base64".to_string(),
+            min_args: Q(1),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_STR), Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "decode_base64".to_string(),
+            min_args: Q(1),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_STR), Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "ftime".to_string(),
+            min_args: Q(0),
+            max_args: Q(1),
+            types: vec![Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "format_time".to_string(),
+            min_args: Q(2),
+            max_args: Q(3),
+            types: vec![Any, Typed(TYPE_STR), Typed(TYPE_STR)],
+            implemented: true,
+        },
+        Builtin {
+            name: "bitand".to_string(),
+            min_args: Q(2),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_INT), Typed(TYPE_INT)],
+            implemented: true,
+        },
+        Builtin {
+            name: "bitor".to_string(),
+            min_args: Q(2),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_INT), Typed(TYPE_INT)],
+            implemented: true,
+        },
+        Builtin {
+            name: "bitxor".to_string(),
+            min_args: Q(2),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_INT), Typed(TYPE_INT)],
+            implemented: true,
+        },
+        Builtin {
+            name: "bitnot".to_string(),
+            min_args: Q(1),
+            max_args: Q(1),
+            types: vec![Typed(TYPE_INT)],
+            implemented: true,
+        },
+        Builtin {
+            name: "bitshl".to_string(),
+            min_args: Q(2),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_INT), Typed(TYPE_INT)],
+            implemented: true,
+        },
+        Builtin {
+            name: "bitshr".to_string(),
+            min_args: Q(2),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_INT), Typed(TYPE_INT)],
+            implemented: true,
+        },
+        Builtin {
+            name: "distance".to_string(),
+            min_args: Q(2),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_LIST), Typed(TYPE_LIST)],
+            implemented: true,
+        },
+        Builtin {
+            name: "relative_heading".to_string(),
+            min_args: Q(2),
+            max_args: Q(2),
+            types: vec![Typed(TYPE_LIST), Typed(TYPE_LIST)],
+            implemented: true,
+        },
+        Builtin {
+            name: "task_local".to_string(),
+            min_args: Q(0),
+            max_args: Q(0),
+            types: vec![],
+            implemented: true,
+        },
+        Builtin {
+            name: "set_task_local".to_string(),
+            min_args: Q(1),
+            max_args: Q(2),
+            types: vec![Any, Any],
+            implemented: true,
+        },
+        Builtin {
+            name: "backup_database".to_string(),
+            min_args: Q(0),
+            max_args: Q(0),
+            types: vec![],
+            implemented: true,
+        },
     ]
 }
 
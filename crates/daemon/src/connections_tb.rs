@@ -25,7 +25,7 @@ use tracing::{error, warn};
 use uuid::Uuid;
 
 use moor_kernel::tasks::sessions::SessionError;
-use moor_rdb::{relation_info_for, RelBox, RelationId, RelationInfo, Transaction};
+use moor_rdb::{relation_info_for, RelBox, RelationId, RelationInfo, Transaction, WalConfig};
 use moor_values::util::SliceRef;
 use moor_values::var::Objid;
 use moor_values::AsByteBuffer;
@@ -83,6 +83,11 @@ enum ConnectionRelation {
 }
 
 const CONNECTIONS_DB_MEM_SIZE: usize = 1 << 26;
+
+/// The version of the encoding of what's in the connections db. Relations may be added to the end
+/// of `ConnectionRelation`, but changing anything else needs a new version.
+const CONNECTIONS_DB_SCHEMA_VERSION: u64 = 1;
+
 pub struct ConnectionsTb {
     tb: Arc<RelBox>,
 }
@@ -93,7 +98,33 @@ impl ConnectionsTb {
             ConnectionRelation::iter().map(relation_info_for).collect();
         relations[ConnectionRelation::ClientConnection as usize].secondary_indexed = true;
 
-        let tb = RelBox::new(CONNECTIONS_DB_MEM_SIZE, path, &relations, 1);
+        // Connections dbs from before the schema was recorded are the same as version 1, so just
+        // need it recording.
+        if let Some(path) = &path {
+            let stored = RelBox::stored_schema(path).expect("Unable to read connections db schema");
+            if stored.is_some_and(|schema| schema.version == 0) {
+                let tb = RelBox::open_for_upgrade(
+                    CONNECTIONS_DB_MEM_SIZE,
+                    path.clone(),
+                    WalConfig::default(),
+                    &relations,
+                    1,
+                    0,
+                )
+                .expect("Unable to open connections db for upgrade");
+                tb.set_schema_version(CONNECTIONS_DB_SCHEMA_VERSION)
+                    .expect("Unable to record connections db schema");
+                return Self { tb };
+            }
+        }
+
+        let tb = RelBox::new(
+            CONNECTIONS_DB_MEM_SIZE,
+            path,
+            &relations,
+            1,
+            CONNECTIONS_DB_SCHEMA_VERSION,
+        );
         Self { tb }
     }
 }
@@ -14,6 +14,7 @@
 
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use clap::builder::ValueHint;
 use clap::Parser;
@@ -26,10 +27,11 @@ use rand::rngs::OsRng;
 use rusty_paseto::core::Key;
 use tracing::info;
 
-use moor_db::DatabaseBuilder;
-use moor_kernel::config::Config;
+use moor_db::{DatabaseBuilder, DEFAULT_RESIDENT_MEMORY};
+use moor_kernel::config::{Config, MemoryLimits, TickCosts};
 use moor_kernel::tasks::scheduler::Scheduler;
 use moor_kernel::textdump::textdump_load;
+use moor_rdb::{recover_to, RecoveryTarget, SyncPolicy};
 
 use crate::rpc_server::zmq_loop;
 
@@ -66,6 +68,61 @@ struct Args {
     )]
     textdump_out: Option<PathBuf>,
 
+    #[arg(
+        long,
+        value_name = "backup-dir",
+        help = "Directory under which `backup_database()` writes online backups of the database, if any. Backups are only taken when a wizard calls `backup_database()`; there's no command line option to take one",
+        value_hint = ValueHint::DirPath
+    )]
+    backup_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "wal-archive",
+        help = "Directory to archive every database commit to, for point-in-time recovery. Nothing \
+                is ever removed from it, so it grows without bound until pruned by hand",
+        value_hint = ValueHint::DirPath
+    )]
+    wal_archive: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "wal-sync",
+        help = "When to fsync the write-ahead log: `per-commit`, `batched` (once per group of \
+                concurrent commits), or an interval like `100ms` (commits may be lost on a crash of \
+                the machine within the interval)",
+        default_value = "batched"
+    )]
+    wal_sync: SyncPolicy,
+
+    #[arg(
+        long,
+        value_name = "max-resident-memory",
+        help = "How many bytes of database pages to keep in memory; colder pages beyond this are \
+                evicted to disk and read back in as needed",
+        default_value_t = DEFAULT_RESIDENT_MEMORY
+    )]
+    max_resident_memory: usize,
+
+    #[arg(
+        long,
+        value_name = "recover-to-ts",
+        help = "Instead of starting, rebuild the (new) database from --wal-archive, up to and including \
+                the commit with this transaction timestamp, then exit",
+        requires = "wal_archive",
+        conflicts_with = "recover_to_time"
+    )]
+    recover_to_ts: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "recover-to-time",
+        help = "Instead of starting, rebuild the (new) database from --wal-archive as it was at this \
+                time (in seconds since the UNIX epoch, as from `time()`), then exit",
+        requires = "wal_archive"
+    )]
+    recover_to_time: Option<u64>,
+
     #[arg(
         short,
         long,
@@ -123,6 +180,95 @@ struct Args {
         default_value = "8"
     )]
     num_io_threads: i32,
+
+    #[arg(
+        long,
+        value_name = "rehash-legacy-passwords",
+        help = "Replace legacy DES crypt hashes in player.password with argon2 hashes on successful login. \
+                Only enable this once the core's login code checks passwords with argon2_verify()",
+        default_value = "false"
+    )]
+    rehash_legacy_passwords: bool,
+
+    #[arg(
+        long,
+        value_name = "max-task-retries",
+        help = "Number of times a task is restarted after transaction conflicts before it is aborted",
+        default_value = "10"
+    )]
+    max_task_retries: usize,
+
+    #[arg(
+        long,
+        value_name = "retry-backoff-ms",
+        help = "Initial delay in milliseconds before restarting a conflicted task; doubles with each retry",
+        default_value = "1"
+    )]
+    retry_backoff_ms: u64,
+
+    #[arg(
+        long,
+        value_name = "max-retry-backoff-ms",
+        help = "Upper bound in milliseconds on the delay before restarting a conflicted task",
+        default_value = "500"
+    )]
+    max_retry_backoff_ms: u64,
+
+    #[arg(
+        long,
+        value_name = "verb-call-ticks",
+        help = "Number of ticks charged for calling a verb",
+        default_value = "1"
+    )]
+    verb_call_ticks: usize,
+
+    #[arg(
+        long,
+        value_name = "builtin-call-ticks",
+        help = "Number of ticks charged for calling a builtin function, on top of the function's own cost",
+        default_value = "1"
+    )]
+    builtin_call_ticks: usize,
+
+    #[arg(
+        long,
+        value_name = "property-ticks",
+        help = "Number of ticks charged for reading or writing a property",
+        default_value = "1"
+    )]
+    property_ticks: usize,
+
+    #[arg(
+        long,
+        value_name = "fork-ticks",
+        help = "Number of ticks charged for forking a task",
+        default_value = "1"
+    )]
+    fork_ticks: usize,
+
+    #[arg(
+        long,
+        value_name = "max-list-concat",
+        help = "Maximum number of elements in a list built by a task, beyond which E_QUOTA is raised",
+        default_value = "4194302"
+    )]
+    max_list_concat: usize,
+
+    #[arg(
+        long,
+        value_name = "max-string-concat",
+        help = "Maximum length in bytes of a string built by a task, beyond which E_QUOTA is raised",
+        default_value = "67108864"
+    )]
+    max_string_concat: usize,
+
+    #[arg(
+        long,
+        value_name = "max-task-memory",
+        help = "Approximate number of bytes of values a task may hold at once, beyond which E_QUOTA is raised",
+        default_value = "1073741824"
+    )]
+    max_task_memory: usize,
 }
 
 fn main() -> Result<(), Report> {
@@ -182,8 +328,30 @@ fn main() -> Result<(), Report> {
         }
     };
 
+    // In recovery mode we just build the database from the archive, and leave starting it up for
+    // later, once it's been checked.
+    let recovery_target = match (args.recover_to_ts, args.recover_to_time) {
+        (Some(ts), _) => Some(RecoveryTarget::Timestamp(ts)),
+        (_, Some(time)) => Some(RecoveryTarget::Time(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(time),
+        )),
+        _ => None,
+    };
+    if let (Some(target), Some(wal_archive)) = (recovery_target, &args.wal_archive) {
+        info!(?target, ?wal_archive, db = ?args.db, "Recovering database from WAL archive...");
+        let replayed = recover_to(wal_archive, &args.db, target)?;
+        info!("Recovered database by replaying {} commits", replayed);
+        return Ok(());
+    }
+
     info!("Daemon starting...");
-    let db_source_builder = DatabaseBuilder::new().with_path(args.db.clone());
+    let mut db_source_builder = DatabaseBuilder::new()
+        .with_path(args.db.clone())
+        .with_sync_policy(args.wal_sync)
+        .with_resident_memory(args.max_resident_memory);
+    if let Some(wal_archive) = args.wal_archive.clone() {
+        db_source_builder = db_source_builder.with_wal_archive(wal_archive);
+    }
     let (db_source, freshly_made) = db_source_builder.open_db().unwrap();
     info!(path = ?args.db, "Opened database");
 
@@ -209,6 +377,21 @@ fn main() -> Result<(), Report> {
 
     let config = Config {
         textdump_output: args.textdump_out,
+        backup_dir: args.backup_dir,
+        max_task_retries: args.max_task_retries,
+        retry_backoff_base: Duration::from_millis(args.retry_backoff_ms),
+        retry_backoff_max: Duration::from_millis(args.max_retry_backoff_ms),
+        tick_costs: TickCosts {
+            verb_call: args.verb_call_ticks,
+            builtin_call: args.builtin_call_ticks,
+            property: args.property_ticks,
+            fork: args.fork_ticks,
+        },
+        memory_limits: MemoryLimits {
+            max_list_concat: args.max_list_concat,
+            max_string_concat: args.max_string_concat,
+            max_task_memory: args.max_task_memory,
+        },
     };
 
     let state_source = db_source
@@ -234,6 +417,7 @@ fn main() -> Result<(), Report> {
         args.rpc_listen.as_str(),
         args.narrative_listen.as_str(),
         Some(args.num_io_threads),
+        args.rehash_legacy_passwords,
     )
     .expect("RPC server loop failed");
 
@@ -28,12 +28,13 @@ use tracing::{debug, error, info, trace, warn};
 use uuid::Uuid;
 use zmq::{Socket, SocketType};
 
+use moor_kernel::builtins::bf_passwords::rehash_legacy_crypt;
 use moor_kernel::tasks::scheduler::{Scheduler, SchedulerError, TaskWaiterResult};
 use moor_kernel::tasks::sessions::SessionError::DeliveryError;
 use moor_kernel::tasks::sessions::{Session, SessionError};
 use moor_kernel::tasks::TaskId;
-use moor_values::model::NarrativeEvent;
 use moor_values::model::WorldStateSource;
+use moor_values::model::{CommitResult, NarrativeEvent};
 use moor_values::util::parse_into_words;
 use moor_values::var::Objid;
 use moor_values::var::Var;
@@ -57,6 +58,9 @@ pub struct RpcServer {
     world_state_source: Arc<dyn WorldStateSource>,
     scheduler: Arc<Scheduler>,
     connections: Arc<dyn ConnectionsDB + Send + Sync>,
+    /// Whether to replace legacy DES `crypt` hashes in `player.password` with argon2 hashes when
+    /// a player successfully logs in with them.
+    rehash_legacy_passwords: bool,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -83,6 +87,7 @@ impl RpcServer {
         narrative_endpoint: &str,
         wss: Arc<dyn WorldStateSource>,
         scheduler: Arc<Scheduler>,
+        rehash_legacy_passwords: bool,
     ) -> Self {
         info!(
             "Creating new RPC server; with {} ZMQ IO threads...",
@@ -105,6 +110,7 @@ impl RpcServer {
             scheduler,
             connections,
             publish: Arc::new(Mutex::new(publish)),
+            rehash_legacy_passwords,
         }
     }
 
@@ -295,7 +301,7 @@ impl RpcServer {
                 )
             }
 
-            RpcRequest::Eval(token, auth_token, evalstr) => {
+            RpcRequest::Eval(token, auth_token, evalstr, read_only) => {
                 let Some(connection) = self.connections.connection_object_for_client(client_id)
                 else {
                     return make_response(Err(RpcRequestError::NoConnection));
@@ -318,7 +324,7 @@ impl RpcServer {
                     );
                     return make_response(Err(RpcRequestError::PermissionDenied));
                 };
-                make_response(self.clone().eval(client_id, connection, evalstr))
+                make_response(self.clone().eval(client_id, connection, evalstr, read_only))
             }
             RpcRequest::Detach(token) => {
                 let Ok(_) = self.validate_client_token(token, client_id) else {
@@ -409,7 +415,7 @@ impl RpcServer {
         object: String,
         property: String,
     ) -> Result<RpcResponse, RpcRequestError> {
-        let Ok(world_state) = self.world_state_source.new_world_state() else {
+        let Ok(world_state) = self.world_state_source.new_read_only_world_state() else {
             return Err(RpcRequestError::CreateSessionFailed);
         };
 
@@ -509,6 +515,13 @@ impl RpcServer {
             }
         };
 
+        if self.rehash_legacy_passwords && connect_type == ConnectType::Connected {
+            // `connect <name> <password>`
+            if let Some(password) = args.get(2) {
+                self.rehash_legacy_password(player, password);
+            }
+        }
+
         // Update the connection records.
         trace!(
             ?connection,
@@ -542,6 +555,52 @@ impl RpcServer {
         Ok(LoginResult(Some((auth_token, connect_type, player))))
     }
 
+    /// If the player's `password` property holds a legacy DES crypt hash of `password`, replace it
+    /// with an argon2 hash. Failures are logged, but never fail the login itself.
+    fn rehash_legacy_password(&self, player: Objid, password: &str) {
+        let mut world_state = match self.world_state_source.new_world_state() {
+            Ok(ws) => ws,
+            Err(e) => {
+                error!(error = ?e, "Unable to open transaction to rehash password");
+                return;
+            }
+        };
+        // The password property is normally only readable by wizards, so act as the owner of the
+        // system object.
+        let result = world_state.owner_of(SYSTEM_OBJECT).and_then(|wizard| {
+            let stored = world_state.retrieve_property(wizard, player, "password")?;
+            let Variant::Str(stored) = stored.variant() else {
+                return Ok(false);
+            };
+            let Some(rehashed) = rehash_legacy_crypt(password, stored.as_str()) else {
+                return Ok(false);
+            };
+            world_state.update_property(wizard, player, "password", &v_string(rehashed))?;
+            Ok(true)
+        });
+        match result {
+            Ok(true) => match world_state.commit() {
+                Ok(CommitResult::Success) => {
+                    info!(?player, "Rehashed legacy crypt password with argon2")
+                }
+                Ok(CommitResult::ConflictRetry(_)) => {
+                    warn!(
+                        ?player,
+                        "Conflict rehashing legacy password; will retry next login"
+                    )
+                }
+                Err(e) => error!(error = ?e, ?player, "Unable to commit rehashed password"),
+            },
+            Ok(false) => {
+                let _ = world_state.rollback();
+            }
+            Err(e) => {
+                debug!(error = ?e, ?player, "Unable to rehash legacy password");
+                let _ = world_state.rollback();
+            }
+        }
+    }
+
     fn submit_connected_task(
         self: Arc<Self>,
         client_id: Uuid,
@@ -721,6 +780,7 @@ impl RpcServer {
         client_id: Uuid,
         connection: Objid,
         expression: String,
+        read_only: bool,
     ) -> Result<RpcResponse, RpcRequestError> {
         let Ok(session) = self.clone().new_session(client_id, connection) else {
             return Err(RpcRequestError::CreateSessionFailed);
@@ -729,7 +789,7 @@ impl RpcServer {
         let task_id = match self
             .clone()
             .scheduler
-            .submit_eval_task(connection, connection, expression, session)
+            .submit_eval_task(connection, connection, expression, read_only, session)
         {
             Ok(t) => t,
             Err(e) => {
@@ -1004,6 +1064,7 @@ impl RpcServer {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn zmq_loop(
     keypair: Key<64>,
     connections_db_path: PathBuf,
@@ -1012,6 +1073,7 @@ pub(crate) fn zmq_loop(
     rpc_endpoint: &str,
     narrative_endpoint: &str,
     num_threads: Option<i32>,
+    rehash_legacy_passwords: bool,
 ) -> eyre::Result<()> {
     let zmq_ctx = zmq::Context::new();
     if let Some(num_threads) = num_threads {
@@ -1025,6 +1087,7 @@ pub(crate) fn zmq_loop(
         narrative_endpoint,
         wss,
         scheduler,
+        rehash_legacy_passwords,
     ));
 
     // Start up the ping-ponger timer in a background thread...
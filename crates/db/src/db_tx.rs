@@ -37,6 +37,9 @@ pub trait DbTransaction {
     /// Returns all the ancestors (+ self) of the given object, in order from self to root.
     fn ancestors(&self, obj: Objid) -> Result<ObjSet, WorldStateError>;
 
+    /// Returns all the descendants (not including self) of the given object, breadth first.
+    fn descendants(&self, obj: Objid) -> Result<ObjSet, WorldStateError>;
+
     /// Get the list of all objects
     fn get_objects(&self) -> Result<ObjSet, WorldStateError>;
 
@@ -697,6 +697,25 @@ impl WorldState for DbTxWorldState {
         self.tx.get_object_children(obj)
     }
 
+    #[tracing::instrument(skip(self))]
+    fn ancestors_of(&self, _perms: Objid, obj: Objid) -> Result<ObjSet, WorldStateError> {
+        // Like `parent_of`, no permissions are needed to see up the hierarchy.
+        if !self.valid(obj)? {
+            return Err(WorldStateError::ObjectNotFound(obj));
+        }
+        let ancestors = self.tx.ancestors(obj)?;
+        Ok(ObjSet::from_oid_iter(ancestors.iter().skip(1)))
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn descendants_of(&self, perms: Objid, obj: Objid) -> Result<ObjSet, WorldStateError> {
+        let (objflags, owner) = (self.flags_of(obj)?, self.owner_of(obj)?);
+        self.perms(perms)?
+            .check_object_allows(owner, objflags, ObjFlag::Read)?;
+
+        self.tx.descendants(obj)
+    }
+
     #[tracing::instrument(skip(self))]
     fn valid(&self, obj: Objid) -> Result<bool, WorldStateError> {
         self.tx.object_valid(obj)
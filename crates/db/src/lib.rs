@@ -12,9 +12,11 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use std::path::Path;
 use std::rc::Rc;
 use std::sync::Arc;
 
+use moor_rdb::{SyncPolicy, WalConfig};
 use moor_values::model::WorldStateError;
 use moor_values::model::WorldStateSource;
 
@@ -28,21 +30,29 @@ pub mod loader;
 
 pub mod odb;
 
+/// How much memory to keep database pages resident in, unless told otherwise.
+pub const DEFAULT_RESIDENT_MEMORY: usize = 1 << 32;
+
 pub struct DatabaseBuilder {
     path: Option<std::path::PathBuf>,
-    memory_size: Option<usize>,
+    wal_config: WalConfig,
+    resident_memory: Option<usize>,
 }
 
 pub trait Database {
     fn loader_client(self: Arc<Self>) -> Result<Rc<dyn LoaderInterface>, WorldStateError>;
     fn world_state_source(self: Arc<Self>) -> Result<Arc<dyn WorldStateSource>, WorldStateError>;
+    /// Write a consistent, openable copy of the database to the (new or empty) directory `path`,
+    /// without stopping other transactions from committing while it happens.
+    fn backup(&self, path: &Path) -> Result<(), WorldStateError>;
 }
 
 impl DatabaseBuilder {
     pub fn new() -> Self {
         Self {
             path: None,
-            memory_size: None,
+            wal_config: WalConfig::default(),
+            resident_memory: None,
         }
     }
 
@@ -51,16 +61,36 @@ impl DatabaseBuilder {
         self
     }
 
-    pub fn with_memory_size(&mut self, memory_size_bytes: usize) -> &mut Self {
-        self.memory_size = Some(memory_size_bytes);
+    /// Archive every commit to the given directory, so the database can later be rebuilt as of
+    /// any point in time since (see `moor_rdb::recover_to`).
+    pub fn with_wal_archive(mut self, wal_archive: std::path::PathBuf) -> Self {
+        self.wal_config.archive = Some(wal_archive);
+        self
+    }
+
+    /// When to fsync the write-ahead log, and so when commits are acknowledged as durable.
+    pub fn with_sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.wal_config.sync_policy = sync_policy;
+        self
+    }
+
+    /// How many bytes of database pages to keep in memory. Beyond this, the least recently used
+    /// pages are evicted to disk, and read back in when next needed. A database with no path has
+    /// nowhere to evict to, and can't grow any larger than this.
+    pub fn with_resident_memory(mut self, resident_memory_bytes: usize) -> Self {
+        self.resident_memory = Some(resident_memory_bytes);
         self
     }
 
     /// Returns a new database instance. The second value in the result tuple is true if the
     /// database was newly created, and false if it was already present.
     pub fn open_db(&self) -> Result<(Arc<dyn Database + Send + Sync>, bool), String> {
-        let (db, fresh) =
-            RelBoxWorldState::open(self.path.clone(), self.memory_size.unwrap_or(1 << 40));
+        let (db, fresh) = RelBoxWorldState::open_with_wal_config(
+            self.path.clone(),
+            self.wal_config.clone(),
+            self.resident_memory.unwrap_or(DEFAULT_RESIDENT_MEMORY),
+        )
+        .map_err(|e| e.to_string())?;
         Ok((Arc::new(db), fresh))
     }
 }
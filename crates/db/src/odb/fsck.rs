@@ -0,0 +1,303 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Offline consistency checking (and repair) of a world state database, for `moor-fsck`.
+//!
+//! The rdb checks its own pages and tuples as it loads them; here we check the things the world
+//! state relies on which the relations themselves don't enforce.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use strum::{EnumCount, IntoEnumIterator};
+use thiserror::Error;
+use uuid::Uuid;
+
+use moor_rdb::{relation_info_for, Damage, OpenError, RelBox, RelationInfo, WalConfig};
+use moor_rdb::{RelationError, Transaction};
+use moor_values::model::{BinaryType, HasUuid, ObjFlag, PropDefs, VerbDefs, WorldStateError};
+use moor_values::util::{BitEnum, SliceRef};
+use moor_values::var::Objid;
+use moor_values::{AsByteBuffer, NOTHING};
+
+use crate::odb::migrations::WORLD_STATE_SCHEMA_VERSION;
+use crate::odb::object_relations::{
+    composite_key_for, decode_composite_key, decode_oid, delete_composite_if_exists, encode_oid,
+    get_inherited_object_values, upsert_object_object, upsert_object_value, WorldStateRelation,
+    WorldStateSequences,
+};
+
+/// Something wrong with a world state database, and (when repairing) what's done about it.
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum Inconsistency {
+    /// A damaged page or tuple, which was left out of the database when it was opened, and is
+    /// removed from it for good when repairing.
+    #[error("{0}")]
+    Damaged(Damage),
+    /// An object with a parent but missing one of its other attributes. Repaired by giving it
+    /// `#-1` as its location or owner, or no flags.
+    #[error("{0} has a parent but no {1}")]
+    MissingAttribute(Objid, WorldStateRelation),
+    /// A verb with no program. Repaired by removing the verb.
+    #[error("Verb {1} on {0} has no program")]
+    MissingProgram(Objid, Uuid),
+    /// A verb whose program can't be decoded. Repaired by removing the verb.
+    #[error("Program of verb {1} on {0} can't be decoded: {2}")]
+    UndecodableProgram(Objid, Uuid, String),
+    /// A property value for a property not defined on the object or any of its ancestors.
+    /// Repaired by removing the value.
+    #[error("{0} has a value for property {1}, which it doesn't define or inherit")]
+    UndefinedProperty(Objid, Uuid),
+    /// Objects which contain each other, starting from the lowest numbered. Repaired by moving
+    /// that one to `#-1`.
+    #[error("Location cycle: {}", .0.iter().map(|o| o.to_string()).collect::<Vec<_>>().join(" -> "))]
+    LocationCycle(Vec<Objid>),
+}
+
+fn relations() -> Vec<RelationInfo> {
+    WorldStateRelation::iter().map(relation_info_for).collect()
+}
+
+/// Open the world state database at `path` to be checked, without writing anything to it. It
+/// has to be at the current schema version.
+pub fn open_read_only(path: PathBuf, memory_size: usize) -> Result<Arc<RelBox>, OpenError> {
+    RelBox::open_read_only(
+        memory_size,
+        path,
+        &relations(),
+        WorldStateSequences::COUNT,
+        WORLD_STATE_SCHEMA_VERSION,
+    )
+}
+
+/// Open the world state database at `path` to be repaired, removing any damaged pages or tuples
+/// from it as it's opened. It has to be at the current schema version.
+pub fn open_for_repair(path: PathBuf, memory_size: usize) -> Result<Arc<RelBox>, OpenError> {
+    RelBox::salvage(
+        memory_size,
+        path,
+        WalConfig::default(),
+        &relations(),
+        WorldStateSequences::COUNT,
+        WORLD_STATE_SCHEMA_VERSION,
+    )
+}
+
+/// Check the world state in `db`, returning everything found wrong with it, including any damage
+/// found when it was opened. If `repair`, each inconsistency is also fixed, and the fixes
+/// committed. `check_program` is given the type and bytes of each verb program, and says why it
+/// can't be decoded, if it can't.
+pub fn check<F>(
+    db: Arc<RelBox>,
+    check_program: F,
+    repair: bool,
+) -> Result<Vec<Inconsistency>, WorldStateError>
+where
+    F: Fn(BinaryType, &[u8]) -> Result<(), String>,
+{
+    let mut found: Vec<_> = db
+        .damage()
+        .iter()
+        .cloned()
+        .map(Inconsistency::Damaged)
+        .collect();
+
+    let tx = db.start_tx();
+    check_attributes(&tx, repair, &mut found)?;
+    check_verbs(&tx, &check_program, repair, &mut found)?;
+    check_property_values(&tx, repair, &mut found)?;
+    check_locations(&tx, repair, &mut found)?;
+    let result = if repair { tx.commit() } else { tx.rollback() };
+    result.map_err(|e| WorldStateError::DatabaseError(e.to_string()))?;
+    Ok(found)
+}
+
+fn scan(
+    tx: &Transaction,
+    rel: WorldStateRelation,
+) -> Result<Vec<(SliceRef, SliceRef)>, WorldStateError> {
+    let mut tuples: Vec<_> = tx
+        .relation(rel.into())
+        .predicate_scan(&|_| true)
+        .map_err(relation_error)?
+        .into_iter()
+        .map(|t| (t.domain(), t.codomain()))
+        .collect();
+    tuples.sort();
+    Ok(tuples)
+}
+
+fn relation_error(e: RelationError) -> WorldStateError {
+    WorldStateError::DatabaseError(e.to_string())
+}
+
+/// Every object with a parent should also have a location, owner, and flags.
+fn check_attributes(
+    tx: &Transaction,
+    repair: bool,
+    found: &mut Vec<Inconsistency>,
+) -> Result<(), WorldStateError> {
+    for (domain, _) in scan(tx, WorldStateRelation::ObjectParent)? {
+        let obj = decode_oid(&domain);
+        for rel in [
+            WorldStateRelation::ObjectLocation,
+            WorldStateRelation::ObjectOwner,
+            WorldStateRelation::ObjectFlags,
+        ] {
+            let present = !tx
+                .relation(rel.into())
+                .seek_by_domain(encode_oid(obj))
+                .map_err(relation_error)?
+                .is_empty();
+            if present {
+                continue;
+            }
+            found.push(Inconsistency::MissingAttribute(obj, rel));
+            if !repair {
+                continue;
+            }
+            match rel {
+                WorldStateRelation::ObjectFlags => {
+                    upsert_object_value(tx, rel, obj, BitEnum::<ObjFlag>::new())?
+                }
+                _ => upsert_object_object(tx, rel, obj, NOTHING)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every verb should have a program, which decodes.
+fn check_verbs<F>(
+    tx: &Transaction,
+    check_program: &F,
+    repair: bool,
+    found: &mut Vec<Inconsistency>,
+) -> Result<(), WorldStateError>
+where
+    F: Fn(BinaryType, &[u8]) -> Result<(), String>,
+{
+    for (domain, codomain) in scan(tx, WorldStateRelation::ObjectVerbs)? {
+        let obj = decode_oid(&domain);
+        let verbdefs = VerbDefs::from_sliceref(codomain);
+        let mut bad = vec![];
+        for verbdef in verbdefs.iter() {
+            let uuid = verbdef.uuid();
+            let program = tx
+                .relation(WorldStateRelation::VerbProgram.into())
+                .seek_by_domain(composite_key_for(obj, &uuid))
+                .map_err(relation_error)?;
+            let problem = match program.into_iter().next() {
+                None => Inconsistency::MissingProgram(obj, uuid),
+                Some(t) => {
+                    let checked = Vec::<u8>::from_sliceref(t.codomain())
+                        .map_err(|e| e.to_string())
+                        .and_then(|binary| check_program(verbdef.binary_type(), &binary));
+                    match checked {
+                        Ok(()) => continue,
+                        Err(reason) => Inconsistency::UndecodableProgram(obj, uuid, reason),
+                    }
+                }
+            };
+            found.push(problem);
+            bad.push(uuid);
+        }
+        if repair && !bad.is_empty() {
+            upsert_object_value(
+                tx,
+                WorldStateRelation::ObjectVerbs,
+                obj,
+                verbdefs.with_all_removed(&bad),
+            )?;
+            for uuid in bad {
+                delete_composite_if_exists(tx, WorldStateRelation::VerbProgram, obj, uuid)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every property value should be for a property defined on the object or one of its ancestors.
+fn check_property_values(
+    tx: &Transaction,
+    repair: bool,
+    found: &mut Vec<Inconsistency>,
+) -> Result<(), WorldStateError> {
+    let mut defined: HashMap<Objid, HashSet<Uuid>> = HashMap::new();
+    for (domain, _) in scan(tx, WorldStateRelation::ObjectPropertyValue)? {
+        let (obj, uuid) = decode_composite_key(&domain);
+        let props = defined.entry(obj).or_insert_with(|| {
+            get_inherited_object_values::<PropDefs>(tx, WorldStateRelation::ObjectPropDefs, obj)
+                .flat_map(|(_, propdefs)| propdefs.iter().map(|p| p.uuid()).collect::<Vec<_>>())
+                .collect()
+        });
+        if props.contains(&uuid) {
+            continue;
+        }
+        found.push(Inconsistency::UndefinedProperty(obj, uuid));
+        if repair {
+            delete_composite_if_exists(tx, WorldStateRelation::ObjectPropertyValue, obj, uuid)?;
+        }
+    }
+    Ok(())
+}
+
+/// Nothing should (however indirectly) be inside itself.
+fn check_locations(
+    tx: &Transaction,
+    repair: bool,
+    found: &mut Vec<Inconsistency>,
+) -> Result<(), WorldStateError> {
+    let locations: HashMap<Objid, Objid> = scan(tx, WorldStateRelation::ObjectLocation)?
+        .iter()
+        .map(|(domain, codomain)| (decode_oid(domain), decode_oid(codomain)))
+        .collect();
+    let mut objects: Vec<_> = locations.keys().copied().collect();
+    objects.sort();
+
+    // Follow each object outwards until reaching somewhere already followed, or #-1, or
+    // somewhere on the path so far, which makes a cycle.
+    let mut followed = HashSet::new();
+    for start in objects {
+        let mut path = vec![];
+        let mut on_path = HashMap::new();
+        let mut current = start;
+        while current != NOTHING && !followed.contains(&current) {
+            if let Some(&cycle_start) = on_path.get(&current) {
+                let mut cycle: Vec<Objid> = path[cycle_start..].to_vec();
+                let lowest = (0..cycle.len()).min_by_key(|i| cycle[*i]).unwrap();
+                cycle.rotate_left(lowest);
+                if repair {
+                    upsert_object_object(
+                        tx,
+                        WorldStateRelation::ObjectLocation,
+                        cycle[0],
+                        NOTHING,
+                    )?;
+                }
+                found.push(Inconsistency::LocationCycle(cycle));
+                break;
+            }
+            on_path.insert(current, path.len());
+            path.push(current);
+            match locations.get(&current) {
+                Some(location) => current = *location,
+                None => break,
+            }
+        }
+        followed.extend(path);
+    }
+    Ok(())
+}
@@ -0,0 +1,366 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Upgrading world state databases written by older versions of moor.
+//!
+//! A world state database records the schema version it was written with, along with the name and
+//! types of each of its relations, and won't open unless they're what we expect. Whenever the
+//! encoding of anything in it changes, `WORLD_STATE_SCHEMA_VERSION` goes up, and a `Migration` to
+//! the new version is added to `MIGRATIONS`. Relations can only be added (to the end of
+//! `WorldStateRelation`), never removed or reordered; an older database is opened with the ones it
+//! lacks empty, for migrations to fill in.
+//!
+//! Databases from before the schema version was recorded (which read as version 0) could have been
+//! written with any of the encodings used up to then, so there's no telling how to upgrade them.
+//! They're refused, and have to be carried over as a textdump instead.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use thiserror::Error;
+use tracing::info;
+
+use moor_rdb::{OpenError, RelBox, RelationInfo, Transaction, WalConfig};
+use moor_values::model::WorldStateError;
+
+/// The schema version of the world state this version of moor reads and writes.
+pub const WORLD_STATE_SCHEMA_VERSION: u64 = 1;
+
+/// Upgrades a world state database from the schema version before `version` to `version`.
+pub struct Migration {
+    pub version: u64,
+    pub description: &'static str,
+    /// Rewrite whatever's changed, within the one transaction. On success it's committed, with
+    /// the database recorded as being at `version` in the same commit.
+    pub upgrade: fn(&Transaction) -> Result<(), WorldStateError>,
+}
+
+/// Every migration, in version order, one for each version after the first.
+pub const MIGRATIONS: &[Migration] = &[];
+
+/// Errors which can occur while opening (and upgrading) a world state database.
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum MigrationError {
+    #[error(transparent)]
+    Open(#[from] OpenError),
+    #[error("Database at {} has schema version {1}, newer than the {2} this version of moor supports", .0.display())]
+    NewerVersion(PathBuf, u64, u64),
+    #[error("Database at {} was written before schema versions were recorded, and can't be upgraded; dump it to a textdump with the version of moor that wrote it, and load that instead", .0.display())]
+    Unversioned(PathBuf),
+    #[error("No migration to bring database at {} from schema version {1} to {2}", .0.display())]
+    NoMigration(PathBuf, u64, u64),
+    #[error("Upgrade to schema version {0} ({1}) failed: {2}")]
+    UpgradeFailed(u64, &'static str, String),
+}
+
+/// Open the world state database at `path` (or a transient one, if there's no path), first
+/// upgrading it in place to `WORLD_STATE_SCHEMA_VERSION` if it was written with an older one.
+pub fn open(
+    memory_size: usize,
+    path: Option<PathBuf>,
+    wal_config: WalConfig,
+    relations: &[RelationInfo],
+    num_sequences: usize,
+) -> Result<Arc<RelBox>, MigrationError> {
+    let Some(path) = path else {
+        return Ok(RelBox::new(
+            memory_size,
+            None,
+            relations,
+            num_sequences,
+            WORLD_STATE_SCHEMA_VERSION,
+        ));
+    };
+    open_upgraded(
+        memory_size,
+        path,
+        wal_config,
+        relations,
+        num_sequences,
+        WORLD_STATE_SCHEMA_VERSION,
+        MIGRATIONS,
+    )
+}
+
+/// As `open`, with `version` as the current schema version, reached through `migrations`.
+fn open_upgraded(
+    memory_size: usize,
+    path: PathBuf,
+    wal_config: WalConfig,
+    relations: &[RelationInfo],
+    num_sequences: usize,
+    version: u64,
+    migrations: &[Migration],
+) -> Result<Arc<RelBox>, MigrationError> {
+    let stored_version = match RelBox::stored_schema(&path)? {
+        Some(stored) if stored.version > version => {
+            return Err(MigrationError::NewerVersion(path, stored.version, version));
+        }
+        Some(stored) if stored.version == 0 => {
+            return Err(MigrationError::Unversioned(path));
+        }
+        Some(stored) if stored.version < version => stored.version,
+        _ => {
+            return Ok(RelBox::new_with_wal_config(
+                memory_size,
+                path,
+                wal_config,
+                relations,
+                num_sequences,
+                version,
+            )?);
+        }
+    };
+
+    // Make sure we can get all the way there, one version at a time, before changing anything.
+    let steps: Vec<_> = migrations
+        .iter()
+        .filter(|m| m.version > stored_version && m.version <= version)
+        .collect();
+    if !steps
+        .iter()
+        .map(|m| m.version)
+        .eq(stored_version + 1..=version)
+    {
+        return Err(MigrationError::NoMigration(path, stored_version, version));
+    }
+
+    info!(
+        "Upgrading database at {} from schema version {} to {}",
+        path.display(),
+        stored_version,
+        version
+    );
+    let db = RelBox::open_for_upgrade(
+        memory_size,
+        path,
+        wal_config,
+        relations,
+        num_sequences,
+        stored_version,
+    )?;
+    for migration in steps {
+        info!(
+            "Upgrading to schema version {}: {}",
+            migration.version, migration.description
+        );
+        let failed = |reason: String| {
+            MigrationError::UpgradeFailed(migration.version, migration.description, reason)
+        };
+        // The version goes in the same commit as the changes, so a crash can't leave them
+        // recorded without it, and have them made again.
+        let tx = db.clone().start_tx();
+        let result = (migration.upgrade)(&tx)
+            .map_err(|e| failed(e.to_string()))
+            .and_then(|_| {
+                tx.commit_with_schema_version(migration.version)
+                    .map_err(|e| failed(e.to_string()))
+            });
+        if let Err(e) = result {
+            db.shutdown();
+            return Err(e);
+        }
+    }
+    Ok(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use strum::{EnumCount, IntoEnumIterator};
+
+    use moor_rdb::{relation_info_for, RelBox, RelationId, RelationInfo, WalConfig};
+    use moor_values::util::SliceRef;
+    use moor_values::var::Objid;
+
+    use crate::odb::migrations::{open, open_upgraded, Migration, MigrationError};
+    use crate::odb::object_relations::encode_oid;
+    use crate::odb::{WorldStateRelation, WorldStateSequences};
+
+    fn relations() -> Vec<RelationInfo> {
+        WorldStateRelation::iter().map(relation_info_for).collect()
+    }
+
+    fn open_at(
+        path: &Path,
+        relations: &[RelationInfo],
+        version: u64,
+        migrations: &[Migration],
+    ) -> Result<std::sync::Arc<RelBox>, MigrationError> {
+        open_upgraded(
+            1 << 24,
+            path.to_path_buf(),
+            WalConfig::default(),
+            relations,
+            WorldStateSequences::COUNT,
+            version,
+            migrations,
+        )
+    }
+
+    fn value(v: u8) -> SliceRef {
+        SliceRef::from_vec(vec![v])
+    }
+
+    fn values_in(db: &std::sync::Arc<RelBox>, relation: usize) -> Vec<Vec<u8>> {
+        let tx = db.clone().start_read_only_tx();
+        let mut values: Vec<_> = tx
+            .relation(RelationId(relation))
+            .predicate_scan(&|_| true)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.codomain().as_slice().to_vec())
+            .collect();
+        values.sort();
+        tx.rollback().unwrap();
+        values
+    }
+
+    /// A database from before the schema was recorded is refused, and left as it was.
+    #[test]
+    fn refuse_unrecorded_schema() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let db = open(
+            1 << 24,
+            Some(tmpdir.path().into()),
+            WalConfig::default(),
+            &relations(),
+            WorldStateSequences::COUNT,
+        )
+        .unwrap();
+        db.clone().update_sequence_max(0, 7);
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(WorldStateRelation::ObjectName as usize))
+            .insert_tuple(encode_oid(Objid(1)), value(2))
+            .unwrap();
+        tx.commit().unwrap();
+        db.shutdown();
+
+        // Put back the sequence page as it used to be written: the number of sequences, then
+        // the id and value of each.
+        let mut legacy_page = (WorldStateSequences::COUNT as u64).to_le_bytes().to_vec();
+        for id in 0..WorldStateSequences::COUNT as u64 {
+            legacy_page.extend(id.to_le_bytes());
+            legacy_page.extend(if id == 0 { 7u64 } else { 0 }.to_le_bytes());
+        }
+        let sequence_page = tmpdir.path().join("pages/sequences.page");
+        std::fs::write(&sequence_page, &legacy_page).unwrap();
+        assert_eq!(
+            RelBox::stored_schema(tmpdir.path())
+                .unwrap()
+                .unwrap()
+                .version,
+            0
+        );
+
+        let err = open(
+            1 << 24,
+            Some(tmpdir.path().into()),
+            WalConfig::default(),
+            &relations(),
+            WorldStateSequences::COUNT,
+        )
+        .unwrap_err();
+        assert_eq!(err, MigrationError::Unversioned(tmpdir.path().into()));
+        assert!(err.to_string().contains("textdump"));
+        assert_eq!(std::fs::read(&sequence_page).unwrap(), legacy_page);
+    }
+
+    /// Each step from the stored version to the current one is run, in order, including filling
+    /// in a relation added since the database was written.
+    #[test]
+    fn multi_step_upgrade() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let all_relations = relations();
+        let fewer_relations = &all_relations[..all_relations.len() - 1];
+        let added = all_relations.len() - 1;
+        let migrations = [
+            Migration {
+                version: 1,
+                description: "first",
+                upgrade: |_| Ok(()),
+            },
+            Migration {
+                version: 2,
+                description: "rewrite names",
+                upgrade: |tx| {
+                    let relation = tx.relation(RelationId(WorldStateRelation::ObjectName as usize));
+                    let tuple = relation
+                        .seek_unique_by_domain(encode_oid(Objid(1)))
+                        .unwrap();
+                    relation.update_by_domain(tuple.domain(), value(3)).unwrap();
+                    Ok(())
+                },
+            },
+            Migration {
+                version: 3,
+                description: "fill in the new relation",
+                upgrade: |tx| {
+                    let added = WorldStateRelation::COUNT - 1;
+                    tx.relation(RelationId(added))
+                        .insert_tuple(value(1), value(4))
+                        .unwrap();
+                    Ok(())
+                },
+            },
+        ];
+
+        let db = open_at(tmpdir.path(), fewer_relations, 1, &migrations[..1]).unwrap();
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(WorldStateRelation::ObjectName as usize))
+            .insert_tuple(encode_oid(Objid(1)), value(2))
+            .unwrap();
+        tx.commit().unwrap();
+        db.shutdown();
+
+        // Not without a way to get there, nor skipping a version on the way.
+        assert_eq!(
+            open_at(tmpdir.path(), &all_relations, 3, &migrations[..2]).unwrap_err(),
+            MigrationError::NoMigration(tmpdir.path().into(), 1, 3)
+        );
+        assert_eq!(
+            open_at(tmpdir.path(), &all_relations, 3, &migrations[2..]).unwrap_err(),
+            MigrationError::NoMigration(tmpdir.path().into(), 1, 3)
+        );
+        assert_eq!(
+            RelBox::stored_schema(tmpdir.path())
+                .unwrap()
+                .unwrap()
+                .version,
+            1
+        );
+
+        let db = open_at(tmpdir.path(), &all_relations, 3, &migrations).unwrap();
+        assert_eq!(db.schema().version, 3);
+        assert_eq!(
+            values_in(&db, WorldStateRelation::ObjectName as usize),
+            vec![vec![3]]
+        );
+        assert_eq!(values_in(&db, added), vec![vec![4]]);
+        db.shutdown();
+
+        // Nor back again.
+        assert_eq!(
+            open_at(tmpdir.path(), &all_relations, 2, &migrations).unwrap_err(),
+            MigrationError::NewerVersion(tmpdir.path().into(), 3, 2)
+        );
+        let db = open_at(tmpdir.path(), &all_relations, 3, &migrations).unwrap();
+        assert_eq!(
+            values_in(&db, WorldStateRelation::ObjectName as usize),
+            vec![vec![3]]
+        );
+        db.shutdown();
+    }
+}
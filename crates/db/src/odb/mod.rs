@@ -15,5 +15,7 @@
 pub use object_relations::{WorldStateRelation, WorldStateSequences};
 pub use rb_worldstate::{RelBoxTransaction, RelBoxWorldState};
 
+pub mod fsck;
+pub mod migrations;
 mod object_relations;
 mod rb_worldstate;
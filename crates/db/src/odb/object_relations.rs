@@ -19,12 +19,15 @@ use moor_values::model::ObjSet;
 use moor_values::model::WorldStateError;
 use moor_values::util::SliceRef;
 use moor_values::var::Objid;
-use moor_values::AsByteBuffer;
+use moor_values::{AsByteBuffer, NOTHING};
 
-use moor_rdb::RelationError;
+use moor_rdb::{Direction, RelationError};
 use moor_rdb::{RelationId, Transaction};
 
 /// The set of binary relations that are used to represent the world state in the moor system.
+///
+/// Their names, ids, and domain & codomain types are recorded in the database (see
+/// `migrations`), so new relations can only go on the end, with a new schema version.
 #[repr(usize)]
 #[derive(
     Copy, Clone, Debug, Eq, PartialEq, EnumIter, EnumCount, Display, EnumProperty, AsRefStr,
@@ -35,8 +38,8 @@ pub enum WorldStateRelation {
         DomainType = "Integer",
         CodomainType = "Integer",
         SecondaryIndexed = "true",
-        IndexType = "Hash",
-        SecondaryIndexType = "Hash",
+        IndexType = "AdaptiveRadixTree",
+        CodomainIndexType = "AdaptiveRadixTree",
     ))]
     ObjectParent = 0,
     /// Object<->Location
@@ -44,27 +47,47 @@ pub enum WorldStateRelation {
         DomainType = "Integer",
         CodomainType = "Integer",
         SecondaryIndexed = "true",
-        IndexType = "Hash",
-        SecondaryIndexType = "Hash",
+        IndexType = "AdaptiveRadixTree",
+        CodomainIndexType = "AdaptiveRadixTree",
     ))]
     ObjectLocation = 1,
     /// Object->Flags (BitEnum<ObjFlag>)
-    #[strum(props(DomainType = "Integer", CodomainType = "Bytes", IndexType = "Hash"))]
+    #[strum(props(
+        DomainType = "Integer",
+        CodomainType = "Bytes",
+        IndexType = "AdaptiveRadixTree"
+    ))]
     ObjectFlags = 2,
     /// Object->Name
-    #[strum(props(DomainType = "Integer", CodomainType = "String", IndexType = "Hash"))]
+    #[strum(props(
+        DomainType = "Integer",
+        CodomainType = "String",
+        IndexType = "AdaptiveRadixTree"
+    ))]
     ObjectName = 3,
     /// Object->Owner
-    #[strum(props(DomainType = "Integer", CodomainType = "Integer", IndexType = "Hash"))]
+    #[strum(props(
+        DomainType = "Integer",
+        CodomainType = "Integer",
+        IndexType = "AdaptiveRadixTree"
+    ))]
     ObjectOwner = 4,
     /// Object->Verbs (Verbdefs)
-    #[strum(props(DomainType = "Integer", CodomainType = "Bytes", IndexType = "Hash"))]
+    #[strum(props(
+        DomainType = "Integer",
+        CodomainType = "Bytes",
+        IndexType = "AdaptiveRadixTree"
+    ))]
     ObjectVerbs = 5,
     /// Verb UUID->VerbProgram (Binary)
     #[strum(props(DomainType = "Bytes", CodomainType = "Bytes", IndexType = "Hash"))]
     VerbProgram = 6,
     /// Object->Properties (Propdefs)
-    #[strum(props(DomainType = "Integer", CodomainType = "Bytes", IndexType = "Hash"))]
+    #[strum(props(
+        DomainType = "Integer",
+        CodomainType = "Bytes",
+        IndexType = "AdaptiveRadixTree"
+    ))]
     ObjectPropDefs = 7,
     /// Property UUID->PropertyValue (Var)
     #[strum(props(DomainType = "Bytes", CodomainType = "Bytes", IndexType = "Hash"))]
@@ -95,12 +118,22 @@ pub fn encode_oid(oid: Objid) -> SliceRef {
     SliceRef::from_vec(bytes.to_vec())
 }
 
-fn decode_oid(sr: &SliceRef) -> Objid {
+pub fn decode_oid(sr: &SliceRef) -> Objid {
     let bytes = sr.as_slice();
     let oid_i = i64::from_le_bytes(bytes.try_into().expect("Could not decode OID"));
     Objid(oid_i)
 }
 
+/// The object and uuid in a key made by `composite_key_for`.
+pub fn decode_composite_key(sr: &SliceRef) -> (Objid, Uuid) {
+    let (oid, uuid) = sr.as_slice().split_at(8);
+    let oid = Objid(i64::from_le_bytes(
+        oid.try_into().expect("Could not decode OID"),
+    ));
+    let uuid = Uuid::from_slice(uuid).expect("Could not decode UUID");
+    (oid, uuid)
+}
+
 pub fn upsert_object_value<Codomain: Clone + Eq + PartialEq + AsByteBuffer>(
     tx: &Transaction,
     rel: WorldStateRelation,
@@ -229,6 +262,70 @@ pub fn tuple_size_for_object_domain(
     }
 }
 
+/// The keys of `oid` and then each of its ancestors in turn, up the `ObjectParent` chain.
+fn ancestor_keys(
+    tx: &Transaction,
+    oid: Objid,
+) -> impl Iterator<Item = Result<SliceRef, RelationError>> + '_ {
+    tx.relation(WorldStateRelation::ObjectParent.into())
+        .transitive_closure(encode_oid(oid), Direction::Forward)
+        .take_while(|key| match key {
+            Ok(key) => decode_oid(key) != NOTHING,
+            Err(_) => true,
+        })
+}
+
+/// `oid` and then each of its ancestors in turn, nearest first.
+pub fn get_ancestors(tx: &Transaction, oid: Objid) -> impl Iterator<Item = Objid> + '_ {
+    ancestor_keys(tx, oid).map(|key| decode_oid(&key.expect("Unable to walk ancestors")))
+}
+
+/// Everything descended from `oid` (not including it), nearest first.
+pub fn get_descendants(tx: &Transaction, oid: Objid) -> impl Iterator<Item = Objid> + '_ {
+    tx.relation(WorldStateRelation::ObjectParent.into())
+        .transitive_closure(encode_oid(oid), Direction::Backward)
+        .skip(1)
+        .map(|key| decode_oid(&key.expect("Unable to walk descendants")))
+}
+
+/// The values in `rel` for `oid` and each of its ancestors which has one, nearest first.
+pub fn get_inherited_object_values<Codomain: Clone + Eq + PartialEq + AsByteBuffer>(
+    tx: &Transaction,
+    rel: WorldStateRelation,
+    oid: Objid,
+) -> impl Iterator<Item = (Objid, Codomain)> + '_ {
+    tx.relation(RelationId(rel as usize))
+        .join(ancestor_keys(tx, oid), |key| key.clone())
+        .map(|joined| {
+            let (key, t) = joined.expect("Unable to join ancestors");
+            (
+                decode_oid(&key),
+                Codomain::from_sliceref(t.codomain()).expect("Could not decode codomain value"),
+            )
+        })
+}
+
+/// The values in a relation keyed by (object, uuid) for `uuid` on `oid` and each of its
+/// ancestors which has one, nearest first.
+pub fn get_inherited_composite_values<Codomain: Clone + Eq + PartialEq + AsByteBuffer>(
+    tx: &Transaction,
+    rel: WorldStateRelation,
+    oid: Objid,
+    uuid: Uuid,
+) -> impl Iterator<Item = (Objid, Codomain)> + '_ {
+    tx.relation(RelationId(rel as usize))
+        .join(ancestor_keys(tx, oid), move |key| {
+            composite_key_for(decode_oid(key), &uuid)
+        })
+        .map(|joined| {
+            let (key, t) = joined.expect("Unable to join ancestors");
+            (
+                decode_oid(&key),
+                Codomain::from_sliceref(t.codomain()).expect("Could not decode codomain value"),
+            )
+        })
+}
+
 #[allow(dead_code)]
 pub fn get_object_by_object_codomain(
     tx: &Transaction,
@@ -381,6 +478,7 @@ mod tests {
     use moor_values::model::ObjSet;
     use moor_values::var::Objid;
 
+    use crate::odb::migrations::WORLD_STATE_SCHEMA_VERSION;
     use crate::odb::object_relations::WorldStateRelation::ObjectParent;
     use crate::odb::object_relations::{
         get_object_by_object_codomain, get_object_object, get_objects_by_object_codomain,
@@ -392,7 +490,13 @@ mod tests {
         let relations: Vec<RelationInfo> =
             WorldStateRelation::iter().map(relation_info_for).collect();
 
-        RelBox::new(1 << 24, None, &relations, WorldStateSequences::COUNT)
+        RelBox::new(
+            1 << 24,
+            None,
+            &relations,
+            WorldStateSequences::COUNT,
+            WORLD_STATE_SCHEMA_VERSION,
+        )
     }
 
     /// Test simple relations mapping oid->oid (with secondary index), independent of all other
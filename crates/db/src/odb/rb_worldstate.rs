@@ -12,8 +12,8 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
 
@@ -25,7 +25,7 @@ use moor_values::model::ObjSet;
 use moor_values::model::PropFlag;
 use moor_values::model::VerbArgsSpec;
 use moor_values::model::{BinaryType, VerbAttrs, VerbFlag};
-use moor_values::model::{CommitResult, WorldStateError};
+use moor_values::model::{CommitResult, ConflictInfo, WorldStateError};
 use moor_values::model::{HasUuid, Named};
 use moor_values::model::{ObjAttrs, ObjFlag};
 use moor_values::model::{PropDef, PropDefs};
@@ -39,6 +39,7 @@ use moor_values::{AsByteBuffer, NOTHING, SYSTEM_OBJECT};
 use crate::db_tx::DbTransaction;
 use crate::db_worldstate::DbTxWorldState;
 use crate::loader::LoaderInterface;
+use crate::odb::migrations::{self, MigrationError};
 use crate::odb::object_relations;
 use crate::odb::object_relations::{
     encode_oid, get_all_object_keys_matching, WorldStateRelation, WorldStateSequences,
@@ -46,7 +47,7 @@ use crate::odb::object_relations::{
 use crate::Database;
 use moor_rdb::{relation_info_for, RelationError};
 use moor_rdb::{CommitError, Transaction};
-use moor_rdb::{RelBox, RelationInfo};
+use moor_rdb::{RelBox, RelationInfo, WalConfig};
 
 /// An implementation of `WorldState` / `WorldStateSource` that uses the rdb as its backing
 pub struct RelBoxWorldState {
@@ -55,10 +56,28 @@ pub struct RelBoxWorldState {
 
 impl RelBoxWorldState {
     pub fn open(path: Option<PathBuf>, memory_size: usize) -> (Self, bool) {
+        Self::open_with_wal_config(path, WalConfig::default(), memory_size)
+            .unwrap_or_else(|e| panic!("Unable to open database: {}", e))
+    }
+
+    /// As `open`, but with the write-ahead log (if the database isn't transient) set up per
+    /// `wal_config`, and returning an error, rather than panicking, if the database can't be
+    /// opened or upgraded to the current schema.
+    pub fn open_with_wal_config(
+        path: Option<PathBuf>,
+        wal_config: WalConfig,
+        memory_size: usize,
+    ) -> Result<(Self, bool), MigrationError> {
         let relations: Vec<RelationInfo> =
             WorldStateRelation::iter().map(relation_info_for).collect();
 
-        let db = RelBox::new(memory_size, path, &relations, WorldStateSequences::COUNT);
+        let db = migrations::open(
+            memory_size,
+            path,
+            wal_config,
+            &relations,
+            WorldStateSequences::COUNT,
+        )?;
 
         // Check the db for sys (#0) object to see if this is a fresh DB or not.
         let fresh_db = {
@@ -68,7 +87,7 @@ impl RelBoxWorldState {
                 .expect("Could not seek for freshness check on DB")
                 .is_empty()
         };
-        (Self { db }, fresh_db)
+        Ok((Self { db }, fresh_db))
     }
 }
 
@@ -77,6 +96,11 @@ impl WorldStateSource for RelBoxWorldState {
         let tx = RelBoxTransaction::new(self.db.clone());
         Ok(Box::new(DbTxWorldState { tx: Box::new(tx) }))
     }
+
+    fn new_read_only_world_state(&self) -> Result<Box<dyn WorldState>, WorldStateError> {
+        let tx = RelBoxTransaction::new_read_only(self.db.clone());
+        Ok(Box::new(DbTxWorldState { tx: Box::new(tx) }))
+    }
 }
 
 pub struct RelBoxTransaction {
@@ -511,40 +535,26 @@ impl DbTransaction for RelBoxTransaction {
         name: String,
         argspec: Option<VerbArgsSpec>,
     ) -> Result<VerbDef, WorldStateError> {
-        let mut search_o = obj;
-        loop {
-            if let Some(verbdefs) = object_relations::get_object_value::<VerbDefs>(
-                &self.tx,
-                WorldStateRelation::ObjectVerbs,
-                search_o,
-            ) {
-                // If we found the verb, return it.
-                let name_matches = verbdefs.find_named(name.as_str());
-                for verb in name_matches {
-                    match argspec {
-                        Some(argspec) => {
-                            if verb.args().matches(&argspec) {
-                                return Ok(verb.clone());
-                            }
-                        }
-                        None => {
+        // Look up the inheritance chain, nearest first, for verbs with the name (and argspec).
+        let inherited = object_relations::get_inherited_object_values::<VerbDefs>(
+            &self.tx,
+            WorldStateRelation::ObjectVerbs,
+            obj,
+        );
+        for (_, verbdefs) in inherited {
+            let name_matches = verbdefs.find_named(name.as_str());
+            for verb in name_matches {
+                match argspec {
+                    Some(argspec) => {
+                        if verb.args().matches(&argspec) {
                             return Ok(verb.clone());
                         }
                     }
+                    None => {
+                        return Ok(verb.clone());
+                    }
                 }
             }
-            // Otherwise, find our parent.  If it's, then set o to it and continue unless we've
-            // hit the end of the chain.
-            search_o = match object_relations::get_object_object(
-                &self.tx,
-                WorldStateRelation::ObjectParent,
-                search_o,
-            ) {
-                Some(NOTHING) | None => {
-                    break;
-                }
-                Some(parent) => parent,
-            };
         }
         Err(WorldStateError::VerbNotFound(obj, name))
     }
@@ -837,58 +847,32 @@ impl DbTransaction for RelBoxTransaction {
             .ok_or_else(|| WorldStateError::PropertyNotFound(obj, name.clone()))?;
 
         // Then we're going to resolve the value up the tree, skipping 'clear' (un-found) until we
-        // get a value.
-        let mut search_obj = obj;
-        loop {
-            // Look for the value. If we're not 'clear', we can return straight away. that's our thing.
-            if let Some(found) = object_relations::get_composite_value::<Var>(
-                &self.tx,
-                WorldStateRelation::ObjectPropertyValue,
-                search_obj,
-                propdef.uuid(),
-            ) {
-                return Ok((propdef, found));
-            }
-
-            // But if it was clear, we have to continue up the inheritance hierarchy. (But we return
-            // the of handle we got, because this is what we want to return for information
-            // about permissions, etc.)
-            let Some(parent) = object_relations::get_object_object(
-                &self.tx,
-                WorldStateRelation::ObjectParent,
-                search_obj,
-            ) else {
-                // If we hit the end of the chain, we're done.
-                break;
-            };
-
-            if parent == NOTHING {
-                // This is an odd one, clear all the way up. so our value will end up being
-                // NONE, I guess.
-                break;
-            }
-            search_obj = parent;
+        // get a value. If it's clear all the way up, our value ends up being NONE, I guess.
+        // (But we return the propdef we got either way, because this is what we want to return
+        // for information about permissions, etc.)
+        if let Some((_, found)) = object_relations::get_inherited_composite_values::<Var>(
+            &self.tx,
+            WorldStateRelation::ObjectPropertyValue,
+            obj,
+            propdef.uuid(),
+        )
+        .next()
+        {
+            return Ok((propdef, found));
         }
         Ok((propdef, v_none()))
     }
 
     fn ancestors(&self, obj: Objid) -> Result<ObjSet, WorldStateError> {
-        let mut ancestors = vec![];
-        let mut search = obj;
-        loop {
-            if search == NOTHING {
-                break;
-            }
-            ancestors.push(search);
-            let parent = object_relations::get_object_object(
-                &self.tx,
-                WorldStateRelation::ObjectParent,
-                search,
-            )
-            .unwrap_or(NOTHING);
-            search = parent;
-        }
-        Ok(ObjSet::from(&ancestors))
+        Ok(ObjSet::from_oid_iter(object_relations::get_ancestors(
+            &self.tx, obj,
+        )))
+    }
+
+    fn descendants(&self, obj: Objid) -> Result<ObjSet, WorldStateError> {
+        Ok(ObjSet::from_oid_iter(object_relations::get_descendants(
+            &self.tx, obj,
+        )))
     }
 
     fn object_valid(&self, obj: Objid) -> Result<bool, WorldStateError> {
@@ -991,14 +975,29 @@ impl DbTransaction for RelBoxTransaction {
     }
 
     fn commit(&self) -> Result<CommitResult, WorldStateError> {
+        let conflict = || {
+            self.tx.conflict().map(|c| ConflictInfo {
+                relation: c.relation_name,
+                key: c.domain,
+                committed_ts: c.committed_ts,
+            })
+        };
         match self.tx.commit() {
             Ok(_) => Ok(CommitResult::Success),
-            Err(CommitError::TupleVersionConflict) => Ok(CommitResult::ConflictRetry),
-            Err(CommitError::UniqueConstraintViolation) => Ok(CommitResult::ConflictRetry),
+            Err(CommitError::TupleVersionConflict) => Ok(CommitResult::ConflictRetry(conflict())),
+            Err(CommitError::UniqueConstraintViolation) => {
+                Ok(CommitResult::ConflictRetry(conflict()))
+            }
             Err(CommitError::RelationContentionConflict) => {
                 warn!("Contention conflict; too many concurrent writes on the same relation(s) after retries.");
-                Ok(CommitResult::ConflictRetry)
+                Ok(CommitResult::ConflictRetry(None))
             }
+            Err(CommitError::DurabilityFailure) => Err(WorldStateError::DatabaseError(
+                "Commit could not be made durable".to_string(),
+            )),
+            Err(CommitError::ReadOnly) => Err(WorldStateError::DatabaseError(
+                "Database is open read-only".to_string(),
+            )),
         }
     }
 
@@ -1020,27 +1019,11 @@ impl RelBoxTransaction {
         Self { tx }
     }
 
-    pub(crate) fn descendants(&self, obj: Objid) -> Result<ObjSet, WorldStateError> {
-        let children = object_relations::get_objects_by_object_codomain(
-            &self.tx,
-            WorldStateRelation::ObjectParent,
-            obj,
-        );
-
-        let mut descendants = vec![];
-        let mut queue: VecDeque<_> = children.iter().collect();
-        while let Some(o) = queue.pop_front() {
-            descendants.push(o);
-            let children = object_relations::get_objects_by_object_codomain(
-                &self.tx,
-                WorldStateRelation::ObjectParent,
-                o,
-            );
-            queue.extend(children.iter());
-        }
-
-        Ok(ObjSet::from(&descendants))
+    pub fn new_read_only(db: Arc<RelBox>) -> Self {
+        let tx = db.start_read_only_tx();
+        Self { tx }
     }
+
     fn closest_common_ancestor_with_ancestors(
         &self,
         a: Objid,
@@ -1107,6 +1090,13 @@ impl Database for RelBoxWorldState {
     fn world_state_source(self: Arc<Self>) -> Result<Arc<dyn WorldStateSource>, WorldStateError> {
         Ok(self)
     }
+
+    fn backup(&self, path: &Path) -> Result<(), WorldStateError> {
+        self.db
+            .backup(path)
+            .map(|_| ())
+            .map_err(|e| WorldStateError::DatabaseError(e.to_string()))
+    }
 }
 
 #[cfg(test)]
@@ -1127,6 +1117,7 @@ mod tests {
     use moor_values::NOTHING;
 
     use crate::db_tx::DbTransaction;
+    use crate::odb::migrations::WORLD_STATE_SCHEMA_VERSION;
     use crate::odb::object_relations::{WorldStateRelation, WorldStateSequences};
     use crate::odb::rb_worldstate::RelBoxTransaction;
     use moor_rdb::{relation_info_for, RelBox, RelationInfo};
@@ -1135,7 +1126,13 @@ mod tests {
         let relations: Vec<RelationInfo> =
             WorldStateRelation::iter().map(relation_info_for).collect();
 
-        RelBox::new(1 << 24, None, &relations, WorldStateSequences::COUNT)
+        RelBox::new(
+            1 << 24,
+            None,
+            &relations,
+            WorldStateSequences::COUNT,
+            WORLD_STATE_SCHEMA_VERSION,
+        )
     }
 
     #[test]
@@ -1947,4 +1944,52 @@ mod tests {
         );
         assert_eq!(tx.commit(), Ok(CommitResult::Success));
     }
+
+    /// Two transactions which write the same object attribute; the loser's commit result should
+    /// say where the conflict was.
+    #[test]
+    fn test_conflict_info() {
+        let db = test_db();
+        let tx = RelBoxTransaction::new(db.clone());
+        let oid = tx.create_object(None, ObjAttrs::default()).unwrap();
+        assert_eq!(tx.commit(), Ok(CommitResult::Success));
+
+        let tx1 = RelBoxTransaction::new(db.clone());
+        let tx2 = RelBoxTransaction::new(db);
+        tx1.set_object_name(oid, "one".into()).unwrap();
+        tx2.set_object_name(oid, "two".into()).unwrap();
+        assert_eq!(tx1.commit(), Ok(CommitResult::Success));
+
+        let Ok(CommitResult::ConflictRetry(Some(conflict))) = tx2.commit() else {
+            panic!("Expected conflict with details");
+        };
+        assert_eq!(
+            conflict.relation,
+            WorldStateRelation::ObjectName.to_string()
+        );
+        assert_eq!(conflict.key, oid.0.to_le_bytes().to_vec());
+        assert!(conflict.committed_ts.is_some());
+    }
+
+    /// Read-only transactions see committed state, and still work if they end up writing.
+    #[test]
+    fn test_read_only_transaction() {
+        let db = test_db();
+        let tx = RelBoxTransaction::new(db.clone());
+        let oid = tx.create_object(None, ObjAttrs::default()).unwrap();
+        tx.set_object_name(oid, "test".into()).unwrap();
+        assert_eq!(tx.commit(), Ok(CommitResult::Success));
+
+        let ro = RelBoxTransaction::new_read_only(db.clone());
+        assert_eq!(ro.get_object_name(oid).unwrap(), "test");
+        assert_eq!(ro.commit(), Ok(CommitResult::Success));
+
+        let ro = RelBoxTransaction::new_read_only(db.clone());
+        ro.set_object_name(oid, "test2".into()).unwrap();
+        assert_eq!(ro.get_object_name(oid).unwrap(), "test2");
+        assert_eq!(ro.commit(), Ok(CommitResult::Success));
+
+        let tx = RelBoxTransaction::new(db);
+        assert_eq!(tx.get_object_name(oid).unwrap(), "test2");
+    }
 }
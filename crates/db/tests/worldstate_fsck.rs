@@ -0,0 +1,174 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use strum::{EnumCount, IntoEnumIterator};
+    use uuid::Uuid;
+
+    use moor_db::db_tx::DbTransaction;
+    use moor_db::odb::fsck::{self, Inconsistency};
+    use moor_db::odb::migrations::WORLD_STATE_SCHEMA_VERSION;
+    use moor_db::odb::{RelBoxTransaction, WorldStateRelation, WorldStateSequences};
+    use moor_rdb::{relation_info_for, RelBox, RelationInfo};
+    use moor_values::model::{BinaryType, HasUuid, ObjAttrs, VerbArgsSpec, WorldStateError};
+    use moor_values::util::{BitEnum, SliceRef};
+    use moor_values::var::{v_int, Objid};
+    use moor_values::NOTHING;
+
+    fn test_db(dir: &Path) -> Arc<RelBox> {
+        let relations: Vec<RelationInfo> =
+            WorldStateRelation::iter().map(relation_info_for).collect();
+
+        RelBox::new(
+            1 << 24,
+            Some(dir.into()),
+            &relations,
+            WorldStateSequences::COUNT,
+            WORLD_STATE_SCHEMA_VERSION,
+        )
+    }
+
+    fn oid_key(oid: Objid) -> SliceRef {
+        SliceRef::from_bytes(&oid.0.to_le_bytes())
+    }
+
+    /// Programs here are a single byte, and 0xff is the one which doesn't decode.
+    fn check_program(_: BinaryType, binary: &[u8]) -> Result<(), String> {
+        match binary {
+            [0xff] => Err("bad opcode".into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn check(db: Arc<RelBox>, repair: bool) -> Result<Vec<Inconsistency>, WorldStateError> {
+        let found = fsck::check(db.clone(), check_program, repair);
+        db.shutdown();
+        found
+    }
+
+    fn create(tx: &RelBoxTransaction, parent: Objid) -> Objid {
+        tx.create_object(
+            None,
+            ObjAttrs {
+                owner: Some(NOTHING),
+                name: Some("test".into()),
+                parent: Some(parent),
+                location: Some(NOTHING),
+                flags: Some(BitEnum::new()),
+            },
+        )
+        .unwrap()
+    }
+
+    fn add_verb(tx: &RelBoxTransaction, obj: Objid, name: &str, binary: Vec<u8>) -> Uuid {
+        tx.add_object_verb(
+            obj,
+            obj,
+            vec![name.into()],
+            binary,
+            BinaryType::LambdaMoo18X,
+            BitEnum::new(),
+            VerbArgsSpec::this_none_this(),
+        )
+        .unwrap();
+        tx.resolve_verb(obj, name.into(), None).unwrap().uuid()
+    }
+
+    #[test]
+    fn check_and_repair() {
+        let tmpdir = tempfile::tempdir().unwrap();
+
+        let (a, b, c, no_program, undecodable, undefined) = {
+            let db = test_db(tmpdir.path());
+
+            let tx = RelBoxTransaction::new(db.clone());
+            let a = create(&tx, NOTHING);
+            let b = create(&tx, a);
+            let c = create(&tx, a);
+
+            add_verb(&tx, a, "good", vec![1]);
+            let no_program = add_verb(&tx, a, "no_program", vec![2]);
+            let undecodable = add_verb(&tx, a, "undecodable", vec![0xff]);
+
+            let p = tx
+                .define_property(a, a, "p".into(), a, BitEnum::new(), Some(v_int(1)))
+                .unwrap();
+            tx.set_property(b, p, v_int(2)).unwrap();
+            let undefined = Uuid::new_v4();
+            tx.set_property(b, undefined, v_int(3)).unwrap();
+            tx.commit().unwrap();
+
+            // Break what the world state itself won't let us.
+            let tx = db.clone().start_tx();
+            tx.relation(WorldStateRelation::ObjectOwner.into())
+                .remove_by_domain(oid_key(c))
+                .unwrap();
+            let mut program_key = a.0.to_le_bytes().to_vec();
+            program_key.extend_from_slice(no_program.as_bytes());
+            tx.relation(WorldStateRelation::VerbProgram.into())
+                .remove_by_domain(SliceRef::from_vec(program_key))
+                .unwrap();
+            let locations = tx.relation(WorldStateRelation::ObjectLocation.into());
+            locations.upsert_by_domain(oid_key(b), oid_key(c)).unwrap();
+            locations.upsert_by_domain(oid_key(c), oid_key(b)).unwrap();
+            tx.commit().unwrap();
+
+            db.shutdown();
+            (a, b, c, no_program, undecodable, undefined)
+        };
+
+        let mut expected = vec![
+            Inconsistency::MissingAttribute(c, WorldStateRelation::ObjectOwner),
+            Inconsistency::MissingProgram(a, no_program),
+            Inconsistency::UndecodableProgram(a, undecodable, "bad opcode".into()),
+            Inconsistency::UndefinedProperty(b, undefined),
+            Inconsistency::LocationCycle(vec![b, c]),
+        ];
+        expected.sort_by_key(|i| i.to_string());
+        let sorted = |mut found: Vec<Inconsistency>| {
+            found.sort_by_key(|i| i.to_string());
+            found
+        };
+
+        // Checking read-only finds everything, but leaves it as it was.
+        let db = fsck::open_read_only(tmpdir.path().into(), 1 << 24).unwrap();
+        assert_eq!(sorted(check(db, false).unwrap()), expected);
+        let db = fsck::open_read_only(tmpdir.path().into(), 1 << 24).unwrap();
+        assert!(check(db, true).is_err());
+        let db = fsck::open_read_only(tmpdir.path().into(), 1 << 24).unwrap();
+        assert_eq!(sorted(check(db, false).unwrap()), expected);
+
+        // Repairing finds the same, and fixes it.
+        let db = fsck::open_for_repair(tmpdir.path().into(), 1 << 24).unwrap();
+        assert_eq!(sorted(check(db, true).unwrap()), expected);
+        let db = fsck::open_read_only(tmpdir.path().into(), 1 << 24).unwrap();
+        assert_eq!(check(db, false).unwrap(), vec![]);
+
+        let db = test_db(tmpdir.path());
+        let tx = RelBoxTransaction::new(db.clone());
+        assert_eq!(tx.get_object_owner(c).unwrap(), NOTHING);
+        assert_eq!(tx.get_object_location(b).unwrap(), NOTHING);
+        assert_eq!(tx.get_object_location(c).unwrap(), b);
+        let verbs: Vec<_> = tx.get_verbs(a).unwrap().iter().map(|v| v.uuid()).collect();
+        assert_eq!(verbs.len(), 1);
+        assert!(!verbs.contains(&no_program) && !verbs.contains(&undecodable));
+        assert!(tx.retrieve_property(b, undefined).is_err());
+        tx.rollback().unwrap();
+        db.shutdown();
+    }
+}
@@ -20,6 +20,7 @@ mod test {
     use strum::{EnumCount, IntoEnumIterator};
 
     use moor_db::db_tx::DbTransaction;
+    use moor_db::odb::migrations::WORLD_STATE_SCHEMA_VERSION;
     use moor_db::odb::{RelBoxTransaction, WorldStateRelation, WorldStateSequences};
     use moor_rdb::{relation_info_for, RelBox, RelationInfo};
     use moor_values::model::BinaryType;
@@ -34,7 +35,13 @@ mod test {
         let relations: Vec<RelationInfo> =
             WorldStateRelation::iter().map(relation_info_for).collect();
 
-        RelBox::new(1 << 24, Some(dir), &relations, WorldStateSequences::COUNT)
+        RelBox::new(
+            1 << 24,
+            Some(dir),
+            &relations,
+            WorldStateSequences::COUNT,
+            WORLD_STATE_SCHEMA_VERSION,
+        )
     }
 
     #[test]
@@ -79,8 +86,8 @@ mod test {
             std::thread::sleep(Duration::from_millis(100));
             a
         };
-        // Verify the WAL directory is not empty.
-        assert!(std::fs::read_dir(format!("{}/wal", tmpdir_str))
+        // Verify the write-ahead log directory is not empty.
+        assert!(std::fs::read_dir(format!("{}/journal", tmpdir_str))
             .unwrap()
             .next()
             .is_some());
@@ -0,0 +1,130 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::builder::ValueHint;
+use clap::Parser;
+use clap_derive::Parser;
+use eyre::Error;
+use tracing::{error, info, warn};
+
+use moor_compiler::Program;
+use moor_db::odb::fsck;
+use moor_db::DEFAULT_RESIDENT_MEMORY;
+use moor_rdb::OpenError;
+use moor_values::model::BinaryType;
+use moor_values::util::SliceRef;
+use moor_values::AsByteBuffer;
+
+/// Checks a moor database for damage and inconsistencies, and optionally repairs them.
+/// The daemon must not be running against the database at the same time.
+#[derive(Parser, Debug)]
+struct Args {
+    #[arg(value_name = "db", help = "Path to the database to check", value_hint = ValueHint::DirPath)]
+    db: PathBuf,
+
+    #[arg(
+        long,
+        help = "Repair what's found, by removing damaged data and whatever can't be made consistent"
+    )]
+    repair: bool,
+
+    #[arg(
+        long,
+        value_name = "max-resident-memory",
+        help = "How many bytes of database pages to keep in memory while checking",
+        default_value_t = DEFAULT_RESIDENT_MEMORY
+    )]
+    max_resident_memory: usize,
+}
+
+fn check_program(binary_type: BinaryType, binary: &[u8]) -> Result<(), String> {
+    match binary_type {
+        BinaryType::None => Ok(()),
+        BinaryType::LambdaMoo18X => Program::from_sliceref(SliceRef::from_vec(binary.to_vec()))
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+    }
+}
+
+fn main() -> Result<ExitCode, Error> {
+    color_eyre::install()?;
+
+    let args: Args = Args::parse();
+
+    let main_subscriber = tracing_subscriber::fmt()
+        .compact()
+        .with_ansi(true)
+        .with_file(false)
+        .with_line_number(false)
+        .with_thread_names(false)
+        .without_time()
+        .with_target(false)
+        .with_max_level(tracing::Level::INFO)
+        .finish();
+    tracing::subscriber::set_global_default(main_subscriber)
+        .expect("Unable to set configure logging");
+
+    let opened = if args.repair {
+        info!(db = ?args.db, "Opening database for repair...");
+        fsck::open_for_repair(args.db.clone(), args.max_resident_memory)
+    } else {
+        info!(db = ?args.db, "Opening database read-only...");
+        fsck::open_read_only(args.db.clone(), args.max_resident_memory)
+    };
+    let db = match opened {
+        Ok(db) => db,
+        Err(e @ OpenError::UnappliedJournal(_)) => {
+            error!(
+                "{}; run again with --repair (which applies it), or start and stop the daemon once",
+                e
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+        Err(e @ OpenError::SchemaMismatch { .. }) => {
+            error!(
+                "{}; if it's from an older version of moor, start and stop the daemon once to upgrade it",
+                e
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    let found = fsck::check(db.clone(), check_program, args.repair);
+    db.shutdown();
+    let found = found?;
+
+    for inconsistency in &found {
+        warn!("{}", inconsistency);
+    }
+    if found.is_empty() {
+        info!("No problems found");
+        Ok(ExitCode::SUCCESS)
+    } else if args.repair {
+        info!("Repaired {} problems", found.len());
+        Ok(ExitCode::SUCCESS)
+    } else {
+        error!(
+            "Found {} problems; run again with --repair to repair them",
+            found.len()
+        );
+        Ok(ExitCode::FAILURE)
+    }
+}
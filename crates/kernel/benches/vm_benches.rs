@@ -23,6 +23,7 @@ use criterion::{criterion_group, criterion_main, Criterion};
 
 use moor_compiler::compile;
 use moor_db::odb::RelBoxWorldState;
+use moor_kernel::config::{MemoryLimits, TickCosts};
 use moor_kernel::tasks::scheduler::AbortLimitReason;
 use moor_kernel::tasks::sessions::{NoopClientSession, Session};
 use moor_kernel::tasks::vm_host::{VMHostResponse, VmHost};
@@ -58,6 +59,8 @@ pub fn prepare_call_verb(
         20,
         max_ticks,
         Duration::from_secs(15),
+        TickCosts::default(),
+        MemoryLimits::default(),
         session.clone(),
         scs_tx,
     );
@@ -138,6 +141,12 @@ fn execute(world_state: &mut dyn WorldState, vm_host: &mut VmHost) -> bool {
             VMHostResponse::SuspendNeedInput => {
                 panic!("Unexpected suspend need input");
             }
+            VMHostResponse::Commit => {
+                panic!("Unexpected commit");
+            }
+            VMHostResponse::Rollback => {
+                panic!("Unexpected rollback");
+            }
             VMHostResponse::CompleteAbort => {
                 panic!("Unexpected abort");
             }
@@ -0,0 +1,216 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::sync::Arc;
+
+use serde_json::{Map, Number, Value};
+
+use moor_compiler::offset_for_builtin;
+use moor_values::var::Error;
+use moor_values::var::Error::{E_FLOAT, E_INVARG, E_TYPE};
+use moor_values::var::Variant;
+use moor_values::var::{v_err, v_float, v_int, v_listv, v_none, v_obj, v_str, v_string, Var};
+
+use crate::bf_declare;
+use crate::builtins::BfRet::Ret;
+use crate::builtins::{args_cost, result_cost, BfCallState, BfRet, BuiltinFunction};
+use crate::vm::VM;
+
+/// How MOO values which have no JSON equivalent are represented, following ToastStunt.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum JsonMode {
+    /// Objects and errors become plain strings ("#12", "E_PERM"), and strings stay strings when
+    /// parsed back.
+    CommonSubset,
+    /// Objects and errors carry a type suffix ("#12|obj", "E_PERM|err"), and suffixed strings
+    /// are turned back into the typed value when parsed.
+    EmbeddedTypes,
+}
+
+impl JsonMode {
+    fn from_args(args: &[Var]) -> Result<Self, Error> {
+        let Some(mode) = args.get(1) else {
+            return Ok(JsonMode::CommonSubset);
+        };
+        let Variant::Str(mode) = mode.variant() else {
+            return Err(E_TYPE);
+        };
+        match mode.as_str() {
+            "common-subset" => Ok(JsonMode::CommonSubset),
+            "embedded-types" => Ok(JsonMode::EmbeddedTypes),
+            _ => Err(E_INVARG),
+        }
+    }
+}
+
+fn error_from_name(name: &str) -> Option<Error> {
+    (0..=u8::MAX)
+        .map_while(Error::from_repr)
+        .find(|e| e.name() == name)
+}
+
+fn var_to_json(v: &Var, mode: JsonMode) -> Result<Value, Error> {
+    let typed = |s: String, suffix: &str| match mode {
+        JsonMode::CommonSubset => Value::String(s),
+        JsonMode::EmbeddedTypes => Value::String(format!("{s}|{suffix}")),
+    };
+    let json = match v.variant() {
+        Variant::None => Value::Null,
+        Variant::Int(i) => Value::Number((*i).into()),
+        Variant::Float(f) => Value::Number(Number::from_f64(*f).ok_or(E_FLOAT)?),
+        Variant::Str(s) => Value::String(s.as_str().to_string()),
+        Variant::Obj(o) => typed(o.to_string(), "obj"),
+        Variant::Err(e) => typed(e.name().to_string(), "err"),
+        Variant::List(l) => Value::Array(
+            l.iter()
+                .map(|v| var_to_json(v, mode))
+                .collect::<Result<_, _>>()?,
+        ),
+    };
+    Ok(json)
+}
+
+/// Undo the type suffix put on a string by "embedded-types" mode, if it has one we understand.
+fn typed_string(s: &str) -> Option<Var> {
+    let (value, suffix) = s.rsplit_once('|')?;
+    match suffix {
+        "obj" => {
+            let id = value.strip_prefix('#')?.parse::<i64>().ok()?;
+            Some(v_obj(id))
+        }
+        "err" => error_from_name(value).map(v_err),
+        "int" => value.parse::<i64>().ok().map(v_int),
+        "float" => value.parse::<f64>().ok().map(v_float),
+        "str" => Some(v_str(value)),
+        _ => None,
+    }
+}
+
+fn json_to_var(json: &Value, mode: JsonMode) -> Var {
+    match json {
+        Value::Null => v_none(),
+        Value::Bool(b) => v_int(i64::from(*b)),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => v_int(i),
+            None => v_float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => match mode {
+            JsonMode::EmbeddedTypes => typed_string(s).unwrap_or_else(|| v_str(s)),
+            JsonMode::CommonSubset => v_str(s),
+        },
+        Value::Array(a) => v_listv(a.iter().map(|v| json_to_var(v, mode)).collect()),
+        // We have no map type, so objects become a list of {key, value} pairs.
+        Value::Object(o) => object_to_alist(o, mode),
+    }
+}
+
+fn object_to_alist(o: &Map<String, Value>, mode: JsonMode) -> Var {
+    v_listv(
+        o.iter()
+            .map(|(k, v)| v_listv(vec![v_str(k), json_to_var(v, mode)]))
+            .collect(),
+    )
+}
+
+fn bf_generate_json(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: generate_json(value [, mode]) => str
+    //
+    // Returns the JSON representation of `value`. `mode` is either "common-subset" (the default)
+    // or "embedded-types", which tags objects and errors with their type so that `parse_json`
+    // can restore them.
+    if bf_args.args.is_empty() || bf_args.args.len() > 2 {
+        return Err(E_INVARG);
+    }
+    let mode = JsonMode::from_args(&bf_args.args)?;
+    let json = var_to_json(&bf_args.args[0], mode)?;
+    let json = serde_json::to_string(&json).map_err(|_| E_INVARG)?;
+    bf_args.exec_state.charge_string(json.len())?;
+    Ok(Ret(v_string(json)))
+}
+bf_declare!(generate_json, bf_generate_json, result_cost);
+
+fn bf_parse_json(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: parse_json(str [, mode]) => value
+    //
+    // Returns the MOO value for the JSON in `str`. Arrays become lists, and (since MOO has no
+    // map type) objects become lists of {key, value} pairs. `mode` is as for `generate_json`.
+    if bf_args.args.is_empty() || bf_args.args.len() > 2 {
+        return Err(E_INVARG);
+    }
+    let Variant::Str(text) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    let mode = JsonMode::from_args(&bf_args.args)?;
+    let json: Value = serde_json::from_str(text.as_str()).map_err(|_| E_INVARG)?;
+    Ok(Ret(json_to_var(&json, mode)))
+}
+bf_declare!(parse_json, bf_parse_json, args_cost);
+
+impl VM {
+    pub(crate) fn register_bf_json(&mut self) {
+        self.builtins[offset_for_builtin("generate_json")] = Arc::new(BfGenerateJson {});
+        self.builtins[offset_for_builtin("parse_json")] = Arc::new(BfParseJson {});
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use moor_values::var::Error::E_PERM;
+    use moor_values::var::{v_err, v_float, v_int, v_list, v_none, v_obj, v_str};
+
+    use crate::builtins::bf_json::{json_to_var, var_to_json, JsonMode};
+
+    fn round_trip(mode: JsonMode, v: moor_values::var::Var) -> moor_values::var::Var {
+        let json = serde_json::to_string(&var_to_json(&v, mode).unwrap()).unwrap();
+        json_to_var(&serde_json::from_str(&json).unwrap(), mode)
+    }
+
+    #[test]
+    fn test_generate_common_subset() {
+        let v = v_list(&[v_int(1), v_float(1.5), v_str("a"), v_obj(12), v_err(E_PERM)]);
+        let json = var_to_json(&v, JsonMode::CommonSubset).unwrap();
+        assert_eq!(json.to_string(), r##"[1,1.5,"a","#12","E_PERM"]"##);
+    }
+
+    #[test]
+    fn test_embedded_types_round_trip() {
+        let v = v_list(&[
+            v_int(1),
+            v_float(1.5),
+            v_str("a"),
+            v_obj(12),
+            v_err(E_PERM),
+            v_list(&[]),
+            v_none(),
+        ]);
+        assert_eq!(round_trip(JsonMode::EmbeddedTypes, v.clone()), v);
+        // Without type tags, objects and errors come back as strings.
+        assert_eq!(
+            round_trip(JsonMode::CommonSubset, v_list(&[v_obj(12), v_err(E_PERM)])),
+            v_list(&[v_str("#12"), v_str("E_PERM")])
+        );
+    }
+
+    #[test]
+    fn test_parse_object_to_alist() {
+        let json = serde_json::from_str(r#"{"a": 1, "b": [true, "x|obj"]}"#).unwrap();
+        assert_eq!(
+            json_to_var(&json, JsonMode::CommonSubset),
+            v_list(&[
+                v_list(&[v_str("a"), v_int(1)]),
+                v_list(&[v_str("b"), v_list(&[v_int(1), v_str("x|obj")])]),
+            ])
+        );
+    }
+}
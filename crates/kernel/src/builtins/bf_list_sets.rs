@@ -12,20 +12,23 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use std::cmp::Ordering;
 use std::ops::BitOr;
 use std::sync::Arc;
 
 use onig::{Region, SearchOptions, SyntaxOperator};
 
 use moor_compiler::offset_for_builtin;
-use moor_values::var::Error::{E_INVARG, E_TYPE};
+use moor_values::var::Error::{E_INVARG, E_RANGE, E_TYPE};
 use moor_values::var::Variant;
-use moor_values::var::{v_empty_list, v_int, v_list, v_string};
-use moor_values::var::{v_listv, Error};
+use moor_values::var::{v_empty_list, v_int, v_list, v_objid, v_string};
+use moor_values::var::{v_listv, Error, List, Var};
+use moor_values::{AMBIGUOUS, FAILED_MATCH};
 
 use crate::bf_declare;
 use crate::builtins::BfRet::Ret;
-use crate::builtins::{BfCallState, BfRet, BuiltinFunction};
+use crate::builtins::{args_cost, BfCallState, BfRet, BuiltinFunction, BF_UNITS_PER_TICK};
+use crate::vm::exec_state::var_size;
 use crate::vm::vm_execute::one_to_zero_index;
 use crate::vm::VM;
 
@@ -43,12 +46,23 @@ fn bf_is_member(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
         Ok(Ret(v_int(0)))
     }
 }
-bf_declare!(is_member, bf_is_member);
+bf_declare!(is_member, bf_is_member, args_cost);
+
+/// Check that adding the second argument to the list in the first won't take the task over its
+/// list size or memory quota, and charge for it.
+fn charge_list_add(bf_args: &mut BfCallState<'_>) -> Result<(), Error> {
+    let Variant::List(list) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    let (len, bytes) = (list.len() + 1, var_size(&bf_args.args[1]));
+    bf_args.exec_state.charge_list(len, bytes)
+}
 
 fn bf_listinsert(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() < 2 || bf_args.args.len() > 3 {
         return Err(E_INVARG);
     }
+    charge_list_add(bf_args)?;
     let len = bf_args.args.len();
     let value = bf_args.args[1].clone();
     if len == 2 {
@@ -70,12 +84,13 @@ fn bf_listinsert(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
         Ok(Ret(list.insert(index as isize, value)))
     }
 }
-bf_declare!(listinsert, bf_listinsert);
+bf_declare!(listinsert, bf_listinsert, args_cost);
 
 fn bf_listappend(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() < 2 || bf_args.args.len() > 3 {
         return Err(E_INVARG);
     }
+    charge_list_add(bf_args)?;
     let value = bf_args.args[1].clone();
     let list = &mut bf_args.args[0];
     let Variant::List(mut list) = list.variant_mut().clone() else {
@@ -92,7 +107,7 @@ fn bf_listappend(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     };
     Ok(Ret(new_list))
 }
-bf_declare!(listappend, bf_listappend);
+bf_declare!(listappend, bf_listappend, args_cost);
 
 fn bf_listdelete(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() != 2 {
@@ -109,7 +124,7 @@ fn bf_listdelete(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     };
     Ok(Ret(list.remove_at(index)))
 }
-bf_declare!(listdelete, bf_listdelete);
+bf_declare!(listdelete, bf_listdelete, args_cost);
 
 fn bf_listset(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() != 3 {
@@ -127,7 +142,7 @@ fn bf_listset(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     };
     Ok(Ret(list.set(index as usize, value.clone())))
 }
-bf_declare!(listset, bf_listset);
+bf_declare!(listset, bf_listset, args_cost);
 
 fn bf_setadd(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() != 2 {
@@ -139,11 +154,14 @@ fn bf_setadd(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
         return Err(E_TYPE);
     };
     if !list.contains(&value) {
+        bf_args
+            .exec_state
+            .charge_list(list.len() + 1, var_size(&value))?;
         return Ok(Ret(list.push(value.clone())));
     }
     Ok(Ret(bf_args.args[0].clone()))
 }
-bf_declare!(setadd, bf_setadd);
+bf_declare!(setadd, bf_setadd, args_cost);
 
 fn bf_setremove(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() != 2 {
@@ -156,7 +174,7 @@ fn bf_setremove(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     };
     Ok(Ret(list.setremove(&value)))
 }
-bf_declare!(setremove, bf_setremove);
+bf_declare!(setremove, bf_setremove, args_cost);
 
 /// Translate a MOO pattern into a more standard syntax.  Effectively, this
 /// just involves remove `%' escapes into `\' escapes.
@@ -321,15 +339,28 @@ fn do_re_match(bf_args: &mut BfCallState<'_>, reverse: bool) -> Result<BfRet, Er
         bf_args.args[0].clone(),
     ])))
 }
+/// Regular expression matching is charged by the size of the subject times that of the pattern,
+/// since that's roughly how bad backtracking can get.
+pub(crate) fn regex_match_cost(args: &[Var], _result: Option<&Var>) -> usize {
+    let (Some(subject), Some(pattern)) = (args.first(), args.get(1)) else {
+        return 0;
+    };
+    let (Variant::Str(subject), Variant::Str(pattern)) = (subject.variant(), pattern.variant())
+    else {
+        return 0;
+    };
+    subject.len().saturating_mul(pattern.len().max(1)) / BF_UNITS_PER_TICK
+}
+
 fn bf_match(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     do_re_match(bf_args, false)
 }
-bf_declare!(match, bf_match);
+bf_declare!(match, bf_match, regex_match_cost);
 
 fn bf_rmatch(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     do_re_match(bf_args, true)
 }
-bf_declare!(rmatch, bf_rmatch);
+bf_declare!(rmatch, bf_rmatch, regex_match_cost);
 
 fn substitute(template: &str, subs: &[(isize, isize)], source: &str) -> Result<String, Error> {
     // textual patterns of form %<int> (e.g. %1, %9, %11) are replaced by the text matched by the
@@ -429,12 +460,314 @@ fn bf_substitute(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
         mysubs.push((*start as isize, *end as isize));
     }
 
-    match substitute(template.as_str(), &mysubs, source.as_str()) {
-        Ok(r) => Ok(Ret(v_string(r))),
-        Err(e) => Err(e),
+    let result = substitute(template.as_str(), &mysubs, source.as_str())?;
+    bf_args.exec_state.charge_string(result.len())?;
+    Ok(Ret(v_string(result)))
+}
+bf_declare!(substitute, bf_substitute, args_cost);
+
+/// Compare strings the way a person would: case-insensitively, and with runs of digits compared
+/// by their numeric value, so that "item2" comes before "item10".
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let digits = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut run = String::new();
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+                        run.push(c);
+                    }
+                    run
+                };
+                let (x, y) = (digits(&mut a), digits(&mut b));
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_ascii_lowercase().cmp(&y.to_ascii_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Sort order for `sort`. Only values of the same (non-list) type can be compared.
+fn sort_cmp(a: &Var, b: &Var, natural: bool) -> Result<Ordering, Error> {
+    match (a.variant(), b.variant()) {
+        (Variant::Str(a), Variant::Str(b)) if natural => Ok(natural_cmp(a.as_str(), b.as_str())),
+        (Variant::Str(a), Variant::Str(b)) => {
+            Ok(a.as_str().to_lowercase().cmp(&b.as_str().to_lowercase()))
+        }
+        (Variant::Int(_), Variant::Int(_))
+        | (Variant::Float(_), Variant::Float(_))
+        | (Variant::Obj(_), Variant::Obj(_))
+        | (Variant::Err(_), Variant::Err(_)) => Ok(a.cmp(b)),
+        _ => Err(E_TYPE),
+    }
+}
+
+/// Sorting is charged for n log n comparisons.
+fn sort_cost(args: &[Var], _result: Option<&Var>) -> usize {
+    let Some(Variant::List(list)) = args.first().map(|v| v.variant()) else {
+        return 0;
+    };
+    let n = list.len();
+    n.saturating_mul(n.max(2).ilog2() as usize) / BF_UNITS_PER_TICK
+}
+
+fn bf_sort(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: sort(list values [, list keys [, int natural [, int reverse]]]) => list
+    //
+    // Returns `values` sorted, or if `keys` is given (and not empty), sorted by the
+    // corresponding elements of `keys`. All the values being compared must be of the same type.
+    // `natural` orders strings with embedded numbers numerically, and `reverse` reverses the
+    // order.
+    if bf_args.args.is_empty() || bf_args.args.len() > 4 {
+        return Err(E_INVARG);
+    }
+    let Variant::List(values) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    let keys = match bf_args.args.get(1).map(|v| v.variant()) {
+        None => values,
+        Some(Variant::List(keys)) if keys.is_empty() => values,
+        Some(Variant::List(keys)) if keys.len() == values.len() => keys,
+        Some(Variant::List(_)) => return Err(E_INVARG),
+        Some(_) => return Err(E_TYPE),
+    };
+    let natural = bf_args.args.get(2).map(|v| v.is_true()).unwrap_or(false);
+    let reverse = bf_args.args.get(3).map(|v| v.is_true()).unwrap_or(false);
+
+    // Check the keys are all comparable up front, so the sort itself can't fail.
+    if let Some(first) = keys.get(0) {
+        for key in keys.iter() {
+            sort_cmp(first, key, natural)?;
+        }
+    }
+
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|a, b| sort_cmp(&keys[*a], &keys[*b], natural).unwrap_or(Ordering::Equal));
+    if reverse {
+        order.reverse();
+    }
+    Ok(Ret(v_listv(
+        order.into_iter().map(|i| values[i].clone()).collect(),
+    )))
+}
+bf_declare!(sort, bf_sort, sort_cost);
+
+fn bf_reverse(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: reverse(list|str value) => list|str
+    //
+    // Returns the elements of a list, or the characters of a string, in reverse order.
+    if bf_args.args.len() != 1 {
+        return Err(E_INVARG);
+    }
+    match bf_args.args[0].variant() {
+        Variant::List(l) => Ok(Ret(v_listv(l[..].iter().rev().cloned().collect()))),
+        Variant::Str(s) => Ok(Ret(v_string(s.as_str().chars().rev().collect()))),
+        _ => Err(E_TYPE),
+    }
+}
+bf_declare!(reverse, bf_reverse, args_cost);
+
+fn bf_slice(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: slice(list alist [, int|list index [, default]]) => list
+    //
+    // Returns the `index`th element (by default, the first) of each of the lists in `alist`. If
+    // `index` is a list of indices, each result is the list of those elements. Elements which
+    // are too short yield `default` if one is given, and raise E_RANGE otherwise.
+    if bf_args.args.is_empty() || bf_args.args.len() > 3 {
+        return Err(E_INVARG);
+    }
+    let Variant::List(alist) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    let index = bf_args.args.get(1).cloned().unwrap_or(v_int(1));
+    let (indices, multiple) = match index.variant() {
+        Variant::Int(_) => (vec![one_to_zero_index(&index)?], false),
+        Variant::List(l) if !l.is_empty() => (
+            l.iter()
+                .map(one_to_zero_index)
+                .collect::<Result<Vec<_>, _>>()?,
+            true,
+        ),
+        Variant::List(_) => return Err(E_RANGE),
+        _ => return Err(E_TYPE),
+    };
+    let default = bf_args.args.get(2);
+
+    let mut result = Vec::with_capacity(alist.len());
+    for element in alist.iter() {
+        let Variant::List(element) = element.variant() else {
+            return Err(E_TYPE);
+        };
+        let mut picked = Vec::with_capacity(indices.len());
+        for i in &indices {
+            match (element.get(*i), default) {
+                (Some(v), _) => picked.push(v.clone()),
+                (None, Some(default)) => picked.push(default.clone()),
+                (None, None) => return Err(E_RANGE),
+            }
+        }
+        if multiple {
+            result.push(v_listv(picked));
+        } else {
+            result.extend(picked);
+        }
+    }
+    Ok(Ret(v_listv(result)))
+}
+bf_declare!(slice, bf_slice, args_cost);
+
+fn flatten_into(list: &List, into: &mut Vec<Var>) {
+    for v in list.iter() {
+        match v.variant() {
+            Variant::List(l) => flatten_into(l, into),
+            _ => into.push(v.clone()),
+        }
+    }
+}
+
+fn bf_flatten(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: flatten(list value) => list
+    //
+    // Returns the non-list elements of `value`, and of any lists inside it, all in one list.
+    if bf_args.args.len() != 1 {
+        return Err(E_INVARG);
+    }
+    let Variant::List(list) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    let mut flattened = vec![];
+    flatten_into(list, &mut flattened);
+    let len = flattened.len();
+    let flattened = v_listv(flattened);
+    bf_args.exec_state.charge_list(len, var_size(&flattened))?;
+    Ok(Ret(flattened))
+}
+bf_declare!(flatten, bf_flatten, args_cost);
+
+/// How an ordinal prefix like "second" or "2nd" reads, if `word` is one.
+fn parse_ordinal(word: &str) -> Option<usize> {
+    const ORDINALS: [&str; 10] = [
+        "first", "second", "third", "fourth", "fifth", "sixth", "seventh", "eighth", "ninth",
+        "tenth",
+    ];
+    let word = word.to_lowercase();
+    if let Some(i) = ORDINALS.iter().position(|o| *o == word) {
+        return Some(i + 1);
+    }
+    let digits = word.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let suffix = &word[digits.len()..];
+    if !["st", "nd", "rd", "th"].contains(&suffix) {
+        return None;
+    }
+    digits.parse().ok().filter(|n| *n > 0)
+}
+
+/// How well `token` matches `target`, best first: exact, prefix of the whole thing, prefix of one
+/// of its words, anywhere in it.
+fn match_tier(token: &str, target: &str) -> Option<usize> {
+    let target = target.to_lowercase();
+    if target == token {
+        Some(0)
+    } else if target.starts_with(token) {
+        Some(1)
+    } else if target.split_whitespace().any(|w| w.starts_with(token)) {
+        Some(2)
+    } else if target.contains(token) {
+        Some(3)
+    } else {
+        None
     }
 }
-bf_declare!(substitute, bf_substitute);
+
+fn bf_complex_match(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: complex_match(str token, list targets [, list keys]) => value
+    //
+    // Matches `token` against `targets` (each a string, or a list of alternative names) and
+    // returns the best matching target, or the corresponding element of `keys` if given. Exact
+    // matches beat prefix matches, which beat word prefix matches, which beat substring matches.
+    // A leading ordinal ("2nd foo", "second foo") picks among equally good matches. Returns
+    // $failed_match (#-3) if nothing matches, and $ambiguous_match (#-2) if more than one thing
+    // matches equally well and there is no ordinal.
+    if bf_args.args.len() < 2 || bf_args.args.len() > 3 {
+        return Err(E_INVARG);
+    }
+    let (Variant::Str(token), Variant::List(targets)) =
+        (bf_args.args[0].variant(), bf_args.args[1].variant())
+    else {
+        return Err(E_TYPE);
+    };
+    let keys = match bf_args.args.get(2).map(|v| v.variant()) {
+        None => targets,
+        Some(Variant::List(keys)) if keys.len() == targets.len() => keys,
+        Some(Variant::List(_)) => return Err(E_INVARG),
+        Some(_) => return Err(E_TYPE),
+    };
+
+    let mut token = token.as_str().trim().to_lowercase();
+    let mut ordinal = None;
+    if let Some((first, rest)) = token.split_once(char::is_whitespace) {
+        if let Some(n) = parse_ordinal(first) {
+            ordinal = Some(n);
+            token = rest.trim().to_string();
+        }
+    }
+    if token.is_empty() {
+        return Ok(Ret(v_objid(FAILED_MATCH)));
+    }
+
+    // The best tier any target reaches, and which targets reach it.
+    let mut best: Option<(usize, Vec<usize>)> = None;
+    for (i, target) in targets.iter().enumerate() {
+        let tier = match target.variant() {
+            Variant::Str(s) => match_tier(&token, s.as_str()),
+            Variant::List(names) => names
+                .iter()
+                .filter_map(|n| match n.variant() {
+                    Variant::Str(s) => match_tier(&token, s.as_str()),
+                    _ => None,
+                })
+                .min(),
+            _ => return Err(E_TYPE),
+        };
+        let Some(tier) = tier else {
+            continue;
+        };
+        match &mut best {
+            Some((best_tier, matches)) if *best_tier == tier => matches.push(i),
+            Some((best_tier, _)) if *best_tier < tier => {}
+            _ => best = Some((tier, vec![i])),
+        }
+    }
+
+    let Some((_, matches)) = best else {
+        return Ok(Ret(v_objid(FAILED_MATCH)));
+    };
+    let chosen = match ordinal {
+        Some(n) => matches.get(n - 1).copied(),
+        None if matches.len() == 1 => Some(matches[0]),
+        None => return Ok(Ret(v_objid(AMBIGUOUS))),
+    };
+    match chosen {
+        Some(i) => Ok(Ret(keys[i].clone())),
+        None => Ok(Ret(v_objid(FAILED_MATCH))),
+    }
+}
+bf_declare!(complex_match, bf_complex_match, args_cost);
 
 impl VM {
     pub(crate) fn register_bf_list_sets(&mut self) {
@@ -448,12 +781,21 @@ impl VM {
         self.builtins[offset_for_builtin("match")] = Arc::new(BfMatch {});
         self.builtins[offset_for_builtin("rmatch")] = Arc::new(BfRmatch {});
         self.builtins[offset_for_builtin("substitute")] = Arc::new(BfSubstitute {});
+        self.builtins[offset_for_builtin("sort")] = Arc::new(BfSort {});
+        self.builtins[offset_for_builtin("reverse")] = Arc::new(BfReverse {});
+        self.builtins[offset_for_builtin("slice")] = Arc::new(BfSlice {});
+        self.builtins[offset_for_builtin("flatten")] = Arc::new(BfFlatten {});
+        self.builtins[offset_for_builtin("complex_match")] = Arc::new(BfComplexMatch {});
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::builtins::bf_list_sets::{perform_regex_match, substitute};
+    use std::cmp::Ordering;
+
+    use crate::builtins::bf_list_sets::{
+        natural_cmp, parse_ordinal, perform_regex_match, substitute,
+    };
 
     #[test]
     fn test_match_substitute() {
@@ -555,4 +897,21 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_natural_cmp() {
+        assert_eq!(natural_cmp("item2", "item10"), Ordering::Less);
+        assert_eq!(natural_cmp("Item2", "item02"), Ordering::Equal);
+        assert_eq!(natural_cmp("b", "A"), Ordering::Greater);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_parse_ordinal() {
+        assert_eq!(parse_ordinal("second"), Some(2));
+        assert_eq!(parse_ordinal("3rd"), Some(3));
+        assert_eq!(parse_ordinal("21st"), Some(21));
+        assert_eq!(parse_ordinal("0th"), None);
+        assert_eq!(parse_ordinal("sword"), None);
+    }
 }
@@ -20,7 +20,7 @@ use rand::Rng;
 use moor_values::var::Error;
 use moor_values::var::Error::{E_INVARG, E_TYPE};
 use moor_values::var::Variant;
-use moor_values::var::{v_float, v_int, v_str};
+use moor_values::var::{v_float, v_int, v_list, v_str, Var};
 
 use crate::bf_declare;
 use crate::builtins::BfRet::Ret;
@@ -74,19 +74,50 @@ fn bf_max(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
 bf_declare!(max, bf_max);
 
 fn bf_random(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
-    if bf_args.args.len() > 1 {
+    // Syntax: random([int mod]) => int
+    //         random(int min, int max) => int
+    //
+    // With no arguments, returns a random integer between 1 and the largest integer. With `mod`,
+    // between 1 and `mod`, and with `min` and `max`, between the two, inclusive.
+    if bf_args.args.len() > 2 {
         return Err(E_INVARG);
     }
 
     let mut rng = rand::thread_rng();
-    match bf_args.args[0].variant() {
-        Variant::Int(i) => Ok(Ret(v_int(rng.gen_range(0..*i)))),
-        Variant::Float(f) => Ok(Ret(v_float(rng.gen_range(0.0..*f)))),
+    let args: Vec<_> = bf_args.args.iter().map(|a| a.variant()).collect();
+    match args[..] {
+        [] => Ok(Ret(v_int(rng.gen_range(1..=i64::MAX)))),
+        [Variant::Int(max)] if *max > 0 => Ok(Ret(v_int(rng.gen_range(1..=*max)))),
+        [Variant::Float(max)] if *max > 0.0 => Ok(Ret(v_float(rng.gen_range(0.0..*max)))),
+        [Variant::Int(min), Variant::Int(max)] if min <= max => {
+            Ok(Ret(v_int(rng.gen_range(*min..=*max))))
+        }
+        [Variant::Int(_)] | [Variant::Float(_)] | [Variant::Int(_), Variant::Int(_)] => {
+            Err(E_INVARG)
+        }
         _ => Err(E_TYPE),
     }
 }
 bf_declare!(random, bf_random);
 
+/// The largest precision `floatstr` will honour; anything past this is noise in an f64.
+const MAX_FLOATSTR_PRECISION: usize = 19;
+
+/// Format `x` with `precision` digits after the decimal point, like C's `%.*f`, or `%.*e` if
+/// `scientific` (so always with a sign and at least two digits in the exponent).
+fn floatstr(x: f64, precision: usize, scientific: bool) -> String {
+    if !scientific || !x.is_finite() {
+        return format!("{:.*}", precision, x);
+    }
+    let formatted = format!("{:.*e}", precision, x);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap();
+    let (sign, digits) = match exponent.strip_prefix('-') {
+        Some(digits) => ('-', digits),
+        None => ('+', exponent),
+    };
+    format!("{mantissa}e{sign}{digits:0>2}")
+}
+
 fn bf_floatstr(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() < 2 || bf_args.args.len() > 3 {
         return Err(E_INVARG);
@@ -98,21 +129,14 @@ fn bf_floatstr(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     };
 
     let precision = match bf_args.args[1].variant() {
-        Variant::Int(i) if *i > 0 => *i as usize,
+        Variant::Int(i) if *i < 0 => return Err(E_INVARG),
+        Variant::Int(i) => (*i as usize).min(MAX_FLOATSTR_PRECISION),
         _ => return Err(E_TYPE),
     };
 
-    let scientific = match bf_args.args[2].variant() {
-        Variant::Int(b) => *b == 1,
-        _ => return Err(E_TYPE),
-    };
+    let scientific = bf_args.args.len() == 3 && bf_args.args[2].is_true();
 
-    let mut s = format!("{:.*}", precision, x);
-    if scientific {
-        s = format!("{:e}", x);
-    }
-
-    Ok(Ret(v_str(s.as_str())))
+    Ok(Ret(v_str(floatstr(*x, precision, scientific).as_str())))
 }
 bf_declare!(floatstr, bf_floatstr);
 
@@ -365,6 +389,126 @@ fn bf_trunc(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
 }
 bf_declare!(trunc, bf_trunc);
 
+/// Both arguments as integers, for the two-argument bitwise operations.
+fn int_pair(bf_args: &BfCallState<'_>) -> Result<(i64, i64), Error> {
+    if bf_args.args.len() != 2 {
+        return Err(E_INVARG);
+    }
+    match (bf_args.args[0].variant(), bf_args.args[1].variant()) {
+        (Variant::Int(a), Variant::Int(b)) => Ok((*a, *b)),
+        _ => Err(E_TYPE),
+    }
+}
+
+fn bf_bitand(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    let (a, b) = int_pair(bf_args)?;
+    Ok(Ret(v_int(a & b)))
+}
+bf_declare!(bitand, bf_bitand);
+
+fn bf_bitor(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    let (a, b) = int_pair(bf_args)?;
+    Ok(Ret(v_int(a | b)))
+}
+bf_declare!(bitor, bf_bitor);
+
+fn bf_bitxor(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    let (a, b) = int_pair(bf_args)?;
+    Ok(Ret(v_int(a ^ b)))
+}
+bf_declare!(bitxor, bf_bitxor);
+
+fn bf_bitnot(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    if bf_args.args.len() != 1 {
+        return Err(E_INVARG);
+    }
+    let Variant::Int(a) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    Ok(Ret(v_int(!a)))
+}
+bf_declare!(bitnot, bf_bitnot);
+
+fn bf_bitshl(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    let (a, n) = int_pair(bf_args)?;
+    let n = u32::try_from(n).map_err(|_| E_INVARG)?;
+    Ok(Ret(v_int(a.checked_shl(n).ok_or(E_INVARG)?)))
+}
+bf_declare!(bitshl, bf_bitshl);
+
+fn bf_bitshr(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Logical shift: the vacated high bits are always zero, whatever the sign of `a`.
+    let (a, n) = int_pair(bf_args)?;
+    let n = u32::try_from(n).map_err(|_| E_INVARG)?;
+    Ok(Ret(
+        v_int((a as u64).checked_shr(n).ok_or(E_INVARG)? as i64),
+    ))
+}
+bf_declare!(bitshr, bf_bitshr);
+
+/// A list of numbers as coordinates, E_TYPE if it's not a list or holds anything else.
+fn coordinates(v: &Var) -> Result<Vec<f64>, Error> {
+    let Variant::List(l) = v.variant() else {
+        return Err(E_TYPE);
+    };
+    l.iter()
+        .map(|c| match c.variant() {
+            Variant::Int(i) => Ok(*i as f64),
+            Variant::Float(f) => Ok(*f),
+            _ => Err(E_TYPE),
+        })
+        .collect()
+}
+
+fn bf_distance(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: distance(list point1, list point2) => float
+    //
+    // Returns the Euclidean distance between two points, given as lists of coordinates with the
+    // same number of dimensions.
+    if bf_args.args.len() != 2 {
+        return Err(E_INVARG);
+    }
+    let (a, b) = (
+        coordinates(&bf_args.args[0])?,
+        coordinates(&bf_args.args[1])?,
+    );
+    if a.len() != b.len() {
+        return Err(E_INVARG);
+    }
+    let squared: f64 = a.iter().zip(&b).map(|(a, b)| (b - a).powi(2)).sum();
+    Ok(Ret(v_float(squared.sqrt())))
+}
+bf_declare!(distance, bf_distance);
+
+/// The heading from `from` to `to` as {xy angle, z angle}, in whole degrees. The xy angle runs
+/// counter-clockwise from the positive x axis over [0, 360), and the z angle is the elevation
+/// above (or below) the xy plane.
+fn relative_heading(from: &[f64], to: &[f64]) -> (i64, i64) {
+    let (dx, dy, dz) = (to[0] - from[0], to[1] - from[1], to[2] - from[2]);
+    let xy = dy.atan2(dx).to_degrees().rem_euclid(360.0);
+    let z = dz.atan2(dx.hypot(dy)).to_degrees();
+    (xy.round() as i64 % 360, z.round() as i64)
+}
+
+fn bf_relative_heading(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: relative_heading(list point1, list point2) => list
+    //
+    // Returns {xy angle, z angle}, in degrees, of the heading from one 3D point to another.
+    if bf_args.args.len() != 2 {
+        return Err(E_INVARG);
+    }
+    let (from, to) = (
+        coordinates(&bf_args.args[0])?,
+        coordinates(&bf_args.args[1])?,
+    );
+    if from.len() != 3 || to.len() != 3 {
+        return Err(E_INVARG);
+    }
+    let (xy, z) = relative_heading(&from, &to);
+    Ok(Ret(v_list(&[v_int(xy), v_int(z)])))
+}
+bf_declare!(relative_heading, bf_relative_heading);
+
 impl VM {
     pub(crate) fn register_bf_num(&mut self) {
         self.builtins[offset_for_builtin("abs")] = Arc::new(BfAbs {});
@@ -388,5 +532,43 @@ impl VM {
         self.builtins[offset_for_builtin("ceil")] = Arc::new(BfCeil {});
         self.builtins[offset_for_builtin("floor")] = Arc::new(BfFloor {});
         self.builtins[offset_for_builtin("trunc")] = Arc::new(BfTrunc {});
+        self.builtins[offset_for_builtin("bitand")] = Arc::new(BfBitand {});
+        self.builtins[offset_for_builtin("bitor")] = Arc::new(BfBitor {});
+        self.builtins[offset_for_builtin("bitxor")] = Arc::new(BfBitxor {});
+        self.builtins[offset_for_builtin("bitnot")] = Arc::new(BfBitnot {});
+        self.builtins[offset_for_builtin("bitshl")] = Arc::new(BfBitshl {});
+        self.builtins[offset_for_builtin("bitshr")] = Arc::new(BfBitshr {});
+        self.builtins[offset_for_builtin("distance")] = Arc::new(BfDistance {});
+        self.builtins[offset_for_builtin("relative_heading")] = Arc::new(BfRelativeHeading {});
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::bf_num::{floatstr, relative_heading};
+
+    #[test]
+    fn test_floatstr() {
+        assert_eq!(floatstr(2.675, 2, false), "2.67");
+        assert_eq!(floatstr(1.5, 0, false), "2");
+        assert_eq!(floatstr(1234.5, 3, true), "1.234e+03");
+        assert_eq!(floatstr(0.00012, 1, true), "1.2e-04");
+    }
+
+    #[test]
+    fn test_relative_heading() {
+        assert_eq!(relative_heading(&[0.0, 0.0, 0.0], &[1.0, 0.0, 0.0]), (0, 0));
+        assert_eq!(
+            relative_heading(&[0.0, 0.0, 0.0], &[0.0, -1.0, 0.0]),
+            (270, 0)
+        );
+        assert_eq!(
+            relative_heading(&[1.0, 1.0, 0.0], &[0.0, 0.0, 0.0]),
+            (225, 0)
+        );
+        assert_eq!(
+            relative_heading(&[0.0, 0.0, 0.0], &[1.0, 0.0, 1.0]),
+            (0, 45)
+        );
     }
 }
@@ -22,12 +22,12 @@ use moor_values::util::BitEnum;
 use moor_values::var::Error::{E_INVARG, E_NACC, E_TYPE};
 use moor_values::var::Variant;
 use moor_values::var::{v_bool, v_int, v_none, v_objid, v_str};
-use moor_values::var::{v_listv, Error};
+use moor_values::var::{v_listv, Error, Objid, Var};
 use moor_values::NOTHING;
 
 use crate::bf_declare;
 use crate::builtins::BfRet::{Ret, VmInstr};
-use crate::builtins::{BfCallState, BfRet, BuiltinFunction};
+use crate::builtins::{result_cost, BfCallState, BfRet, BuiltinFunction};
 use crate::tasks::VerbCall;
 use crate::vm::ExecutionResult::ContinueVerb;
 use crate::vm::VM;
@@ -101,7 +101,191 @@ fn bf_children(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     let children = children.iter().map(v_objid).collect::<Vec<_>>();
     Ok(Ret(v_listv(children)))
 }
-bf_declare!(children, bf_children);
+bf_declare!(children, bf_children, result_cost);
+
+/// An object, or a list of objects, as taken by the hierarchy builtins.
+fn objects_arg(arg: &Var) -> Result<Vec<Objid>, Error> {
+    match arg.variant() {
+        Variant::Obj(o) => Ok(vec![*o]),
+        Variant::List(l) => l
+            .iter()
+            .map(|v| match v.variant() {
+                Variant::Obj(o) => Ok(*o),
+                _ => Err(E_TYPE),
+            })
+            .collect(),
+        _ => Err(E_TYPE),
+    }
+}
+
+/// Which of `parents`, if any, `obj` is or inherits from. The nearest ancestor wins.
+fn isa(
+    bf_args: &mut BfCallState<'_>,
+    obj: Objid,
+    parents: &[Objid],
+) -> Result<Option<Objid>, Error> {
+    if !bf_args.world_state.valid(obj).map_err(world_state_err)? {
+        return Ok(None);
+    }
+    let ancestors = bf_args
+        .world_state
+        .ancestors_of(bf_args.task_perms_who(), obj)
+        .map_err(world_state_err)?;
+    Ok(std::iter::once(obj)
+        .chain(ancestors.iter())
+        .find(|a| parents.contains(a)))
+}
+
+fn bf_ancestors(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: ancestors(obj object [, int full]) => list
+    //
+    // Returns the parent of `object`, its parent, and so on up to the root. If `full` is true,
+    // `object` itself comes first.
+    if bf_args.args.is_empty() || bf_args.args.len() > 2 {
+        return Err(E_INVARG);
+    }
+    let Variant::Obj(obj) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    let full = bf_args.args.len() == 2 && bf_args.args[1].is_true();
+    let ancestors = bf_args
+        .world_state
+        .ancestors_of(bf_args.task_perms_who(), *obj)
+        .map_err(world_state_err)?;
+
+    let start = full.then_some(*obj);
+    let ancestors = start.into_iter().chain(ancestors.iter()).map(v_objid);
+    Ok(Ret(v_listv(ancestors.collect())))
+}
+bf_declare!(ancestors, bf_ancestors, result_cost);
+
+fn bf_descendants(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: descendants(obj object [, int full]) => list
+    //
+    // Returns the children of `object`, their children, and so on. If `full` is true, `object`
+    // itself comes first.
+    if bf_args.args.is_empty() || bf_args.args.len() > 2 {
+        return Err(E_INVARG);
+    }
+    let Variant::Obj(obj) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    let full = bf_args.args.len() == 2 && bf_args.args[1].is_true();
+    let descendants = bf_args
+        .world_state
+        .descendants_of(bf_args.task_perms_who(), *obj)
+        .map_err(world_state_err)?;
+
+    let start = full.then_some(*obj);
+    let descendants = start.into_iter().chain(descendants.iter()).map(v_objid);
+    Ok(Ret(v_listv(descendants.collect())))
+}
+bf_declare!(descendants, bf_descendants, result_cost);
+
+fn bf_isa(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: isa(obj object, obj|list parent [, int return_parent]) => int|obj
+    //
+    // Returns true if `object` is `parent` or one of its descendants. `parent` may be a list, in
+    // which case any of them will do; with `return_parent`, the matching parent (or #-1) is
+    // returned instead.
+    if bf_args.args.len() < 2 || bf_args.args.len() > 3 {
+        return Err(E_INVARG);
+    }
+    let Variant::Obj(obj) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    let obj = *obj;
+    let parents = objects_arg(&bf_args.args[1])?;
+    let return_parent = bf_args.args.len() == 3 && bf_args.args[2].is_true();
+
+    let found = isa(bf_args, obj, &parents)?;
+    if return_parent {
+        return Ok(Ret(v_objid(found.unwrap_or(NOTHING))));
+    }
+    Ok(Ret(v_bool(found.is_some())))
+}
+bf_declare!(isa, bf_isa);
+
+fn bf_occupants(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: occupants(obj object [, obj|list parent [, int player_flag_set]]) => list
+    //
+    // Returns the contents of `object`, optionally just those which are descendants of (one of)
+    // `parent`, and optionally just those with the player flag set.
+    if bf_args.args.is_empty() || bf_args.args.len() > 3 {
+        return Err(E_INVARG);
+    }
+    let Variant::Obj(obj) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    let parents = match bf_args.args.get(1) {
+        Some(parents) => Some(objects_arg(parents)?),
+        None => None,
+    };
+    let players_only = bf_args.args.len() == 3 && bf_args.args[2].is_true();
+    let contents = bf_args
+        .world_state
+        .contents_of(bf_args.task_perms_who(), *obj)
+        .map_err(world_state_err)?;
+
+    let mut occupants = vec![];
+    for o in contents.iter() {
+        if players_only {
+            let flags = bf_args.world_state.flags_of(o).map_err(world_state_err)?;
+            if !flags.contains(ObjFlag::User) {
+                continue;
+            }
+        }
+        if let Some(parents) = &parents {
+            if isa(bf_args, o, parents)?.is_none() {
+                continue;
+            }
+        }
+        occupants.push(v_objid(o));
+    }
+    Ok(Ret(v_listv(occupants)))
+}
+bf_declare!(occupants, bf_occupants, result_cost);
+
+fn bf_locations(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: locations(obj object [, obj stop [, int is_parent]]) => list
+    //
+    // Returns the location of `object`, the location of that, and so on out to #-1, or until
+    // reaching `stop` (or, with `is_parent`, a descendant of `stop`), which is not included.
+    if bf_args.args.is_empty() || bf_args.args.len() > 3 {
+        return Err(E_INVARG);
+    }
+    let Variant::Obj(obj) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    let stop = match bf_args.args.get(1).map(|v| v.variant()) {
+        Some(Variant::Obj(stop)) => *stop,
+        Some(_) => return Err(E_TYPE),
+        None => NOTHING,
+    };
+    let stop_is_parent = bf_args.args.len() == 3 && bf_args.args[2].is_true();
+
+    let mut locations = vec![];
+    let mut location = *obj;
+    loop {
+        location = bf_args
+            .world_state
+            .location_of(bf_args.task_perms_who(), location)
+            .map_err(world_state_err)?;
+        if location == NOTHING || location == stop {
+            break;
+        }
+        if stop_is_parent && isa(bf_args, location, &[stop])?.is_some() {
+            break;
+        }
+        // Can't happen if `move` is doing its job, but don't spin forever if it hasn't.
+        if locations.contains(&v_objid(location)) {
+            break;
+        }
+        locations.push(v_objid(location));
+    }
+    Ok(Ret(v_listv(locations)))
+}
+bf_declare!(locations, bf_locations, result_cost);
 
 /*
 Syntax:  create (obj <parent> [, obj <owner>])   => obj
@@ -580,7 +764,7 @@ fn bf_verbs(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
         .collect();
     Ok(Ret(v_listv(verbs)))
 }
-bf_declare!(verbs, bf_verbs);
+bf_declare!(verbs, bf_verbs, result_cost);
 
 /*
 Function: list properties (obj object)
@@ -600,7 +784,7 @@ fn bf_properties(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     let props: Vec<_> = props.iter().map(|p| v_str(p.name())).collect();
     Ok(Ret(v_listv(props)))
 }
-bf_declare!(properties, bf_properties);
+bf_declare!(properties, bf_properties, result_cost);
 
 fn bf_set_player_flag(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() != 2 {
@@ -658,7 +842,7 @@ fn bf_players(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
         players.iter().map(v_objid).collect::<Vec<_>>(),
     )))
 }
-bf_declare!(players, bf_players);
+bf_declare!(players, bf_players, result_cost);
 
 impl VM {
     pub(crate) fn register_bf_objects(&mut self) {
@@ -674,5 +858,10 @@ impl VM {
         self.builtins[offset_for_builtin("recycle")] = Arc::new(BfRecycle {});
         self.builtins[offset_for_builtin("max_object")] = Arc::new(BfMaxObject {});
         self.builtins[offset_for_builtin("players")] = Arc::new(BfPlayers {});
+        self.builtins[offset_for_builtin("ancestors")] = Arc::new(BfAncestors {});
+        self.builtins[offset_for_builtin("descendants")] = Arc::new(BfDescendants {});
+        self.builtins[offset_for_builtin("isa")] = Arc::new(BfIsa {});
+        self.builtins[offset_for_builtin("occupants")] = Arc::new(BfOccupants {});
+        self.builtins[offset_for_builtin("locations")] = Arc::new(BfLocations {});
     }
 }
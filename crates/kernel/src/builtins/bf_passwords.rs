@@ -0,0 +1,319 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Password hashing builtins, as a replacement for the DES-based `crypt`.
+
+use std::sync::Arc;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::RngCore;
+
+use moor_compiler::offset_for_builtin;
+use moor_values::var::Error;
+use moor_values::var::Error::{E_INVARG, E_TYPE};
+use moor_values::var::Variant;
+use moor_values::var::{v_bool, v_string, Var};
+
+use crate::bf_declare;
+use crate::builtins::bf_strings::encode_binary;
+use crate::builtins::BfRet::Ret;
+use crate::builtins::{BfCallState, BfRet, BuiltinFunction};
+use crate::vm::VM;
+
+/// Default argon2 cost parameters, matching ToastStunt: 3 iterations over 4MiB with 1 lane.
+const DEFAULT_ITERATIONS: u32 = 3;
+const DEFAULT_MEMORY_KIB: u32 = 4096;
+const DEFAULT_PARALLELISM: u32 = 1;
+
+/// Upper bounds on the argon2 cost parameters which can be asked for (or carried by a hash being
+/// verified), so that a single call can't tie a task's thread up for minutes, or allocate
+/// gigabytes.
+const MAX_ITERATIONS: u32 = 10;
+const MAX_MEMORY_KIB: u32 = 65536;
+const MAX_PARALLELISM: u32 = 4;
+
+/// Upper bound on the number of bytes `random_bytes` will produce in one call.
+const MAX_RANDOM_BYTES: usize = 10_000;
+
+/// Check the given cost parameters against our limits, as well as argon2's own.
+fn checked_params(iterations: u32, memory_kib: u32, parallelism: u32) -> Result<Params, Error> {
+    if iterations > MAX_ITERATIONS || memory_kib > MAX_MEMORY_KIB || parallelism > MAX_PARALLELISM {
+        return Err(E_INVARG);
+    }
+    Params::new(memory_kib, iterations, parallelism, None).map_err(|_| E_INVARG)
+}
+
+/// Hash `password` with argon2id, returning the PHC-format string ("$argon2id$v=19$...") which
+/// carries the salt and parameters along with the hash, so it can be verified on its own.
+pub fn argon2_hash(
+    password: &str,
+    salt: &[u8],
+    iterations: u32,
+    memory_kib: u32,
+    parallelism: u32,
+) -> Result<String, Error> {
+    let params = checked_params(iterations, memory_kib, parallelism)?;
+    let salt = SaltString::encode_b64(salt).map_err(|_| E_INVARG)?;
+    let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|_| E_INVARG)?;
+    Ok(hash.to_string())
+}
+
+/// Check `password` against a PHC-format hash produced by `argon2_hash`. Hashes with cost
+/// parameters beyond what `argon2_hash` will produce are refused.
+pub fn argon2_verify(hash: &str, password: &str) -> Result<bool, Error> {
+    let hash = PasswordHash::new(hash).map_err(|_| E_INVARG)?;
+    let params = Params::try_from(&hash).map_err(|_| E_INVARG)?;
+    checked_params(params.t_cost(), params.m_cost(), params.p_cost())?;
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok())
+}
+
+/// Whether `hash` looks like a traditional 13 character DES `crypt(3)` hash, as produced by
+/// `crypt()` and found in the `password` property of players in older cores.
+pub fn is_legacy_crypt(hash: &str) -> bool {
+    hash.len() == 13
+        && hash
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '/')
+}
+
+/// If `stored` is a legacy DES crypt hash of `password`, produce an argon2 hash of it (with a
+/// fresh salt and the default parameters) to replace it with.
+pub fn rehash_legacy_crypt(password: &str, stored: &str) -> Option<String> {
+    if !is_legacy_crypt(stored) || !pwhash::unix::verify(password, stored) {
+        return None;
+    }
+    argon2_hash(
+        password,
+        &random_salt(),
+        DEFAULT_ITERATIONS,
+        DEFAULT_MEMORY_KIB,
+        DEFAULT_PARALLELISM,
+    )
+    .ok()
+}
+
+fn random_salt() -> [u8; 16] {
+    let mut salt = [0; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+/// Argon2 is deliberately expensive, in proportion to the number of passes it makes over its
+/// memory, so charge by that: a tick per 128KiB pass, or about 100 for the default parameters.
+fn hash_cost(iterations: u32, memory_kib: u32) -> usize {
+    (iterations as usize).saturating_mul(memory_kib as usize) / 128
+}
+
+/// The {iterations, memory, parallelism} asked of `argon2()`, with the defaults filled in.
+fn requested_params(args: &[Var]) -> Result<[u32; 3], Error> {
+    let mut params = [DEFAULT_ITERATIONS, DEFAULT_MEMORY_KIB, DEFAULT_PARALLELISM];
+    if let Some(given) = args.get(2) {
+        let Variant::List(given) = given.variant() else {
+            return Err(E_TYPE);
+        };
+        if given.len() > params.len() {
+            return Err(E_INVARG);
+        }
+        for (param, value) in params.iter_mut().zip(given.iter()) {
+            let Variant::Int(value) = value.variant() else {
+                return Err(E_TYPE);
+            };
+            *param = u32::try_from(*value).map_err(|_| E_INVARG)?;
+        }
+    }
+    Ok(params)
+}
+
+fn argon2_cost(args: &[Var], _result: Option<&Var>) -> usize {
+    match requested_params(args) {
+        Ok([iterations, memory_kib, _]) => hash_cost(iterations, memory_kib),
+        Err(_) => 0,
+    }
+}
+
+fn argon2_verify_cost(args: &[Var], _result: Option<&Var>) -> usize {
+    let Some(Variant::Str(hash)) = args.first().map(|hash| hash.variant()) else {
+        return 0;
+    };
+    let Ok(hash) = PasswordHash::new(hash.as_str()) else {
+        return 0;
+    };
+    match Params::try_from(&hash) {
+        Ok(params) => hash_cost(params.t_cost(), params.m_cost()),
+        Err(_) => 0,
+    }
+}
+
+fn bf_argon2(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: argon2(str password, str salt [, list params]) => str
+    //
+    // Hashes `password` with argon2id. `params` is {iterations, memory (in KiB), parallelism},
+    // any of which may be left off the end to take the default ({3, 4096, 1}). They may be at
+    // most {10, 65536, 4}.
+    if bf_args.args.len() < 2 || bf_args.args.len() > 3 {
+        return Err(E_INVARG);
+    }
+    let (Variant::Str(password), Variant::Str(salt)) =
+        (bf_args.args[0].variant(), bf_args.args[1].variant())
+    else {
+        return Err(E_TYPE);
+    };
+    let [iterations, memory_kib, parallelism] = requested_params(&bf_args.args)?;
+    let hash = argon2_hash(
+        password.as_str(),
+        salt.as_str().as_bytes(),
+        iterations,
+        memory_kib,
+        parallelism,
+    )?;
+    Ok(Ret(v_string(hash)))
+}
+bf_declare!(argon2, bf_argon2, argon2_cost);
+
+fn bf_argon2_verify(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: argon2_verify(str hash, str password) => int
+    //
+    // Returns true if `password` matches `hash`, which was produced by `argon2()`.
+    if bf_args.args.len() != 2 {
+        return Err(E_INVARG);
+    }
+    let (Variant::Str(hash), Variant::Str(password)) =
+        (bf_args.args[0].variant(), bf_args.args[1].variant())
+    else {
+        return Err(E_TYPE);
+    };
+    Ok(Ret(v_bool(argon2_verify(
+        hash.as_str(),
+        password.as_str(),
+    )?)))
+}
+bf_declare!(argon2_verify, bf_argon2_verify, argon2_verify_cost);
+
+fn bf_salt(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: salt() => str
+    //
+    // Returns a fresh random salt suitable for passing to `argon2()`.
+    if !bf_args.args.is_empty() {
+        return Err(E_INVARG);
+    }
+    let salt = SaltString::encode_b64(&random_salt()).map_err(|_| E_INVARG)?;
+    Ok(Ret(v_string(salt.as_str().to_string())))
+}
+bf_declare!(salt, bf_salt);
+
+fn bf_random_bytes(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: random_bytes(int count) => str
+    //
+    // Returns `count` bytes from a cryptographically secure generator, as a binary string.
+    if bf_args.args.len() != 1 {
+        return Err(E_INVARG);
+    }
+    let Variant::Int(count) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    let count = usize::try_from(*count).map_err(|_| E_INVARG)?;
+    if count == 0 || count > MAX_RANDOM_BYTES {
+        return Err(E_INVARG);
+    }
+    let mut bytes = vec![0; count];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let encoded = encode_binary(&bytes);
+    bf_args.exec_state.charge_string(encoded.len())?;
+    Ok(Ret(v_string(encoded)))
+}
+bf_declare!(random_bytes, bf_random_bytes);
+
+impl VM {
+    pub(crate) fn register_bf_passwords(&mut self) {
+        self.builtins[offset_for_builtin("argon2")] = Arc::new(BfArgon2 {});
+        self.builtins[offset_for_builtin("argon2_verify")] = Arc::new(BfArgon2Verify {});
+        self.builtins[offset_for_builtin("salt")] = Arc::new(BfSalt {});
+        self.builtins[offset_for_builtin("random_bytes")] = Arc::new(BfRandomBytes {});
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use moor_values::var::Error::E_INVARG;
+    use moor_values::var::{v_int, v_list, v_str};
+
+    use crate::builtins::bf_passwords::{
+        argon2_cost, argon2_hash, argon2_verify, argon2_verify_cost, is_legacy_crypt,
+        rehash_legacy_crypt,
+    };
+
+    #[test]
+    fn test_argon2_round_trip() {
+        let hash = argon2_hash("secret", b"saltsaltsalt", 1, 64, 1).unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$m=64,t=1,p=1$"));
+        assert!(argon2_verify(&hash, "secret").unwrap());
+        assert!(!argon2_verify(&hash, "Secret").unwrap());
+        assert!(argon2_verify("not a hash", "secret").is_err());
+    }
+
+    #[test]
+    fn test_argon2_limits() {
+        assert_eq!(
+            argon2_hash("secret", b"saltsaltsalt", 11, 64, 1),
+            Err(E_INVARG)
+        );
+        assert_eq!(
+            argon2_hash("secret", b"saltsaltsalt", 1, 65537, 1),
+            Err(E_INVARG)
+        );
+        assert_eq!(
+            argon2_hash("secret", b"saltsaltsalt", 1, 64, 5),
+            Err(E_INVARG)
+        );
+
+        // Nor can a hash get round them by carrying bigger ones.
+        let hash = argon2_hash("secret", b"saltsaltsalt", 1, 64, 1).unwrap();
+        let greedy = hash.replace("m=64,", "m=4194304,");
+        assert_eq!(argon2_verify(&greedy, "secret"), Err(E_INVARG));
+    }
+
+    #[test]
+    fn test_argon2_cost_scales() {
+        let password = [v_str("secret"), v_str("saltsaltsalt")];
+        let defaults = argon2_cost(&password, None);
+        assert_eq!(defaults, 96);
+        let with_params = |params: &[i64]| {
+            let params: Vec<_> = params.iter().map(|p| v_int(*p)).collect();
+            [password[0].clone(), password[1].clone(), v_list(&params)]
+        };
+        assert_eq!(argon2_cost(&with_params(&[3, 4096, 1]), None), defaults);
+        assert_eq!(argon2_cost(&with_params(&[6]), None), 2 * defaults);
+        assert_eq!(argon2_cost(&with_params(&[3, 65536]), None), 16 * defaults);
+
+        let hash = argon2_hash("secret", b"saltsaltsalt", 3, 4096, 1).unwrap();
+        assert_eq!(argon2_verify_cost(&[v_str(&hash)], None), defaults);
+    }
+
+    #[test]
+    fn test_rehash_legacy_crypt() {
+        let legacy = pwhash::unix::crypt("secret", "ab").unwrap();
+        assert!(is_legacy_crypt(&legacy));
+        assert_eq!(rehash_legacy_crypt("wrong", &legacy), None);
+        let rehashed = rehash_legacy_crypt("secret", &legacy).unwrap();
+        assert!(argon2_verify(&rehashed, "secret").unwrap());
+        // Already-migrated hashes are left alone.
+        assert!(!is_legacy_crypt(&rehashed));
+        assert_eq!(rehash_legacy_crypt("secret", &rehashed), None);
+    }
+}
@@ -0,0 +1,355 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Perl-compatible regular expressions, as in ToastStunt, for when LambdaMOO's `match` dialect
+//! isn't enough.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use onig::{Captures, Regex, RegexOptions, Syntax};
+
+use moor_compiler::offset_for_builtin;
+use moor_values::var::Error;
+use moor_values::var::Error::{E_INVARG, E_TYPE};
+use moor_values::var::Variant;
+use moor_values::var::{v_int, v_list, v_listv, v_str, v_string, Var};
+
+use crate::bf_declare;
+use crate::builtins::bf_list_sets::regex_match_cost;
+use crate::builtins::BfRet::Ret;
+use crate::builtins::{BfCallState, BfRet, BuiltinFunction};
+use crate::vm::VM;
+
+/// How many compiled patterns we hold on to before starting over.
+const PATTERN_CACHE_SIZE: usize = 1024;
+
+lazy_static! {
+    /// Compiled patterns, shared by all tasks, keyed by pattern and whether case matters.
+    static ref PATTERN_CACHE: DashMap<(String, bool), Arc<Regex>> = DashMap::new();
+}
+
+fn compile_pattern(pattern: &str, case_matters: bool) -> Result<Arc<Regex>, Error> {
+    let key = (pattern.to_string(), case_matters);
+    if let Some(regex) = PATTERN_CACHE.get(&key) {
+        return Ok(regex.clone());
+    }
+
+    // Plain groups stay capturing even when there are named groups, as in Perl.
+    let mut options = RegexOptions::REGEX_OPTION_CAPTURE_GROUP;
+    if !case_matters {
+        options |= RegexOptions::REGEX_OPTION_IGNORECASE;
+    }
+    let regex = Regex::with_options(pattern, options, Syntax::perl_ng()).map_err(|_| E_INVARG)?;
+    let regex = Arc::new(regex);
+
+    // Crude, but patterns are generally a small fixed set per core, so this should rarely fire.
+    if PATTERN_CACHE.len() >= PATTERN_CACHE_SIZE {
+        PATTERN_CACHE.clear();
+    }
+    PATTERN_CACHE.insert(key, regex.clone());
+    Ok(regex)
+}
+
+/// The names of the named groups in `regex`, by group number.
+fn group_names(regex: &Regex) -> HashMap<usize, String> {
+    let mut names = HashMap::new();
+    regex.foreach_name(|name, groups| {
+        for group in groups {
+            names.insert(*group as usize, name.to_string());
+        }
+        true
+    });
+    names
+}
+
+/// A match as a list of `{group, text, {start, end}}`, one per group that took part in it, where
+/// `group` is the group's name if it has one and its number otherwise.
+fn captures_to_var(captures: &Captures, names: &HashMap<usize, String>) -> Var {
+    let mut groups = vec![];
+    for i in 0..captures.len() {
+        let (Some(text), Some((start, end))) = (captures.at(i), captures.pos(i)) else {
+            continue;
+        };
+        let group = match names.get(&i) {
+            Some(name) => v_str(name),
+            None => v_int(i as i64),
+        };
+        let span = v_list(&[v_int(start as i64 + 1), v_int(end as i64)]);
+        groups.push(v_list(&[group, v_str(text), span]));
+    }
+    v_listv(groups)
+}
+
+fn bf_pcre_match(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: pcre_match(str subject, str pattern [, int case_matters [, int repeat]]) => list
+    //
+    // Matches `pattern`, a Perl-compatible regular expression, against `subject`. Returns a list
+    // with an entry for each match (just the first, unless `repeat` is true, which is the
+    // default) in the form `{{group, text, {start, end}}, ...}`. Group 0 is the whole match.
+    // Case is ignored unless `case_matters` is true.
+    if bf_args.args.len() < 2 || bf_args.args.len() > 4 {
+        return Err(E_INVARG);
+    }
+    let (Variant::Str(subject), Variant::Str(pattern)) =
+        (bf_args.args[0].variant(), bf_args.args[1].variant())
+    else {
+        return Err(E_TYPE);
+    };
+    let case_matters = bf_args.args.get(2).map(|v| v.is_true()).unwrap_or(false);
+    let repeat = bf_args.args.get(3).map(|v| v.is_true()).unwrap_or(true);
+
+    let regex = compile_pattern(pattern.as_str(), case_matters)?;
+    let names = group_names(&regex);
+    let captures = regex.captures_iter(subject.as_str());
+    let matches: Vec<Var> = if repeat {
+        captures.map(|c| captures_to_var(&c, &names)).collect()
+    } else {
+        captures
+            .take(1)
+            .map(|c| captures_to_var(&c, &names))
+            .collect()
+    };
+    Ok(Ret(v_listv(matches)))
+}
+bf_declare!(pcre_match, bf_pcre_match, regex_match_cost);
+
+/// A parsed `s/pattern/replacement/flags` expression.
+struct Substitution {
+    pattern: String,
+    replacement: String,
+    global: bool,
+    case_matters: bool,
+}
+
+/// Parse a Perl-style substitution. Any character may be used as the delimiter, and may appear in
+/// the pattern or replacement if escaped with a backslash.
+fn parse_substitution(expr: &str) -> Option<Substitution> {
+    let mut chars = expr.chars();
+    if chars.next()? != 's' {
+        return None;
+    }
+    let delimiter = chars.next()?;
+    if delimiter.is_alphanumeric() || delimiter == '\\' {
+        return None;
+    }
+
+    let mut parts = vec![String::new()];
+    let mut escaped = false;
+    for c in chars {
+        if parts.len() == 3 {
+            parts[2].push(c);
+            continue;
+        }
+        let part = parts.last_mut().unwrap();
+        if escaped {
+            if c != delimiter {
+                part.push('\\');
+            }
+            part.push(c);
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delimiter {
+            parts.push(String::new());
+        } else {
+            part.push(c);
+        }
+    }
+    if parts.len() != 3 {
+        return None;
+    }
+
+    let flags = parts.pop().unwrap();
+    let mut substitution = Substitution {
+        replacement: parts.pop().unwrap(),
+        pattern: parts.pop().unwrap(),
+        global: false,
+        case_matters: true,
+    };
+    for flag in flags.chars() {
+        match flag {
+            'g' => substitution.global = true,
+            'i' => substitution.case_matters = false,
+            _ => return None,
+        }
+    }
+    Some(substitution)
+}
+
+/// Expand `$n`, `${n}` and `${name}` references to the groups of a match in `replacement`. `$$`
+/// is a literal `$`.
+fn expand_replacement(
+    replacement: &str,
+    captures: &Captures,
+    names: &HashMap<usize, String>,
+) -> Result<String, Error> {
+    let group_text = |group: &str| -> Result<&str, Error> {
+        let index = match group.parse::<usize>() {
+            Ok(i) => i,
+            Err(_) => {
+                *names
+                    .iter()
+                    .find(|(_, name)| name.as_str() == group)
+                    .ok_or(E_INVARG)?
+                    .0
+            }
+        };
+        if index >= captures.len() {
+            return Err(E_INVARG);
+        }
+        Ok(captures.at(index).unwrap_or(""))
+    };
+
+    let mut result = String::new();
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some('$') => {
+                chars.next();
+                result.push('$');
+            }
+            Some('{') => {
+                chars.next();
+                let mut group = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => group.push(c),
+                        None => return Err(E_INVARG),
+                    }
+                }
+                result.push_str(group_text(&group)?);
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut group = String::new();
+                while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    group.push(*c);
+                    chars.next();
+                }
+                result.push_str(group_text(&group)?);
+            }
+            _ => result.push('$'),
+        }
+    }
+    Ok(result)
+}
+
+fn pcre_replace(subject: &str, substitution: &Substitution) -> Result<String, Error> {
+    let regex = compile_pattern(&substitution.pattern, substitution.case_matters)?;
+    let names = group_names(&regex);
+
+    let mut result = String::new();
+    let mut last_end = 0;
+    for captures in regex.captures_iter(subject) {
+        let (start, end) = captures.pos(0).expect("match without a group 0");
+        result.push_str(&subject[last_end..start]);
+        result.push_str(&expand_replacement(
+            &substitution.replacement,
+            &captures,
+            &names,
+        )?);
+        last_end = end;
+        if !substitution.global {
+            break;
+        }
+    }
+    result.push_str(&subject[last_end..]);
+    Ok(result)
+}
+
+fn bf_pcre_replace(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: pcre_replace(str subject, str "s/pattern/replacement/flags") => str
+    //
+    // Replaces the first match of `pattern` in `subject` (or all of them, with the `g` flag) with
+    // `replacement`, in which `$1` or `${name}` stand for the text captured by that group. The
+    // `i` flag makes the match ignore case.
+    if bf_args.args.len() != 2 {
+        return Err(E_INVARG);
+    }
+    let (Variant::Str(subject), Variant::Str(expr)) =
+        (bf_args.args[0].variant(), bf_args.args[1].variant())
+    else {
+        return Err(E_TYPE);
+    };
+    let substitution = parse_substitution(expr.as_str()).ok_or(E_INVARG)?;
+    let result = pcre_replace(subject.as_str(), &substitution)?;
+    bf_args.exec_state.charge_string(result.len())?;
+    Ok(Ret(v_string(result)))
+}
+bf_declare!(pcre_replace, bf_pcre_replace, regex_match_cost);
+
+impl VM {
+    pub(crate) fn register_bf_pcre(&mut self) {
+        self.builtins[offset_for_builtin("pcre_match")] = Arc::new(BfPcreMatch {});
+        self.builtins[offset_for_builtin("pcre_replace")] = Arc::new(BfPcreReplace {});
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use moor_values::var::{v_int, v_list, v_str};
+
+    use crate::builtins::bf_pcre::{
+        captures_to_var, compile_pattern, group_names, parse_substitution, pcre_replace,
+    };
+
+    fn replace(subject: &str, expr: &str) -> String {
+        pcre_replace(subject, &parse_substitution(expr).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_named_and_numbered_captures() {
+        let regex = compile_pattern(r"(?<key>\w+)=(\d+)", true).unwrap();
+        let names = group_names(&regex);
+        let captures = regex.captures("a foo=42").unwrap();
+        assert_eq!(
+            captures_to_var(&captures, &names),
+            v_list(&[
+                v_list(&[v_int(0), v_str("foo=42"), v_list(&[v_int(3), v_int(8)])]),
+                v_list(&[v_str("key"), v_str("foo"), v_list(&[v_int(3), v_int(5)])]),
+                v_list(&[v_int(2), v_str("42"), v_list(&[v_int(7), v_int(8)])]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_case_insensitive_by_default() {
+        let regex = compile_pattern("abc", false).unwrap();
+        assert!(regex.find("xABCx").is_some());
+        let regex = compile_pattern("abc", true).unwrap();
+        assert!(regex.find("xABCx").is_none());
+    }
+
+    #[test]
+    fn test_pcre_replace() {
+        assert_eq!(replace("foo bar foo", "s/foo/baz/"), "baz bar foo");
+        assert_eq!(replace("foo bar foo", "s/foo/baz/g"), "baz bar baz");
+        assert_eq!(replace("FOO", "s/foo/baz/i"), "baz");
+        assert_eq!(
+            replace("key=value", r"s/(\w+)=(?<v>\w+)/${v}=$1/"),
+            "value=key"
+        );
+        assert_eq!(replace("a/b", r"s|/|\||"), "a|b");
+        assert_eq!(replace("cost", "s/cost/$$5/"), "$5");
+        assert!(parse_substitution("s/unterminated").is_none());
+        assert!(parse_substitution("s/a/b/x").is_none());
+    }
+}
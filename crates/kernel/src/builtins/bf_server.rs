@@ -12,13 +12,16 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use std::fmt::Write;
 use std::io::Read;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
-use chrono::{DateTime, Local, TimeZone};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, TimeZone, Utc};
 use chrono_tz::{OffsetName, Tz};
 use iana_time_zone::get_timezone;
+use lazy_static::lazy_static;
 
 use tracing::{debug, error, info, warn};
 
@@ -26,12 +29,12 @@ use moor_values::model::ObjFlag;
 use moor_values::model::{world_state_err, NarrativeEvent, WorldStateError};
 use moor_values::var::Error::{E_INVARG, E_PERM, E_TYPE};
 use moor_values::var::Variant;
-use moor_values::var::{v_bool, v_int, v_list, v_none, v_objid, v_str, v_string, Var};
+use moor_values::var::{v_bool, v_float, v_int, v_list, v_none, v_objid, v_str, v_string, Var};
 use moor_values::var::{v_listv, Error};
 
 use crate::bf_declare;
 use crate::builtins::BfRet::{Ret, VmInstr};
-use crate::builtins::{BfCallState, BfRet, BuiltinFunction};
+use crate::builtins::{result_cost, BfCallState, BfRet, BuiltinFunction};
 use crate::tasks::task_messages::SchedulerControlMsg;
 use crate::tasks::TaskId;
 use crate::vm::{ExecutionResult, VM};
@@ -100,7 +103,7 @@ fn bf_connected_players(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
             .collect::<Vec<Var>>(),
     )))
 }
-bf_declare!(connected_players, bf_connected_players);
+bf_declare!(connected_players, bf_connected_players, result_cost);
 
 fn bf_is_player(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() != 1 {
@@ -178,7 +181,7 @@ fn bf_callers(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
             .collect::<Vec<Var>>(),
     )))
 }
-bf_declare!(callers, bf_callers);
+bf_declare!(callers, bf_callers, result_cost);
 
 fn bf_task_id(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if !bf_args.args.is_empty() {
@@ -189,6 +192,46 @@ fn bf_task_id(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
 }
 bf_declare!(task_id, bf_task_id);
 
+fn bf_task_local(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: task_local() => value
+    //
+    // Returns the value most recently stored with `set_task_local` in this task, or 0 if nothing
+    // has been stored.
+    if !bf_args.args.is_empty() {
+        return Err(E_INVARG);
+    }
+    let perms = bf_args.task_perms().map_err(world_state_err)?;
+    perms.check_wizard().map_err(world_state_err)?;
+
+    let value = bf_args.exec_state.task_local.clone();
+    match value.variant() {
+        Variant::None => Ok(Ret(v_int(0))),
+        _ => Ok(Ret(value)),
+    }
+}
+bf_declare!(task_local, bf_task_local);
+
+fn bf_set_task_local(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: set_task_local(value [, int inherit]) => none
+    //
+    // Stores `value` for the rest of this task, across verb calls and suspensions. If `inherit` is
+    // true, tasks forked from here on start with a copy of it (and inherit it onwards themselves).
+    if bf_args.args.is_empty() || bf_args.args.len() > 2 {
+        return Err(E_INVARG);
+    }
+    let perms = bf_args.task_perms().map_err(world_state_err)?;
+    perms.check_wizard().map_err(world_state_err)?;
+
+    bf_args
+        .exec_state
+        .charge_memory(crate::vm::exec_state::var_size(&bf_args.args[0]))?;
+    bf_args.exec_state.task_local = bf_args.args[0].clone();
+    bf_args.exec_state.inherit_task_local = bf_args.args.len() == 2 && bf_args.args[1].is_true();
+
+    Ok(Ret(v_none()))
+}
+bf_declare!(set_task_local, bf_set_task_local);
+
 fn bf_idle_seconds(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() != 1 {
         return Err(E_INVARG);
@@ -328,6 +371,86 @@ fn bf_ctime(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     Ok(Ret(v_string(datetime_str.to_string())))
 }
 bf_declare!(ctime, bf_ctime);
+
+lazy_static! {
+    /// The reference point for `ftime(1)`; only differences between monotonic times mean anything.
+    static ref MONOTONIC_EPOCH: Instant = Instant::now();
+}
+
+fn bf_ftime(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: ftime([int monotonic]) => float
+    //
+    // Returns the current time in seconds since the Unix epoch, with sub-second precision. If
+    // `monotonic` is true, the result instead comes from a clock which never goes backwards, and is
+    // only useful for measuring the time between two calls.
+    if bf_args.args.len() > 1 {
+        return Err(E_INVARG);
+    }
+    let monotonic = bf_args.args.first().map(|a| a.is_true()).unwrap_or(false);
+    let seconds = if monotonic {
+        MONOTONIC_EPOCH.elapsed().as_secs_f64()
+    } else {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64()
+    };
+    Ok(Ret(v_float(seconds)))
+}
+bf_declare!(ftime, bf_ftime);
+
+/// Format `time` (seconds since the epoch) according to the strftime-style `format`, in the
+/// given zone. E_INVARG if the format has unknown specifiers or the time is out of range.
+fn format_time(time: f64, format: &str, tz: Tz) -> Result<String, Error> {
+    let items = StrftimeItems::new(format).collect::<Vec<_>>();
+    if items.iter().any(|i| matches!(i, Item::Error)) {
+        return Err(E_INVARG);
+    }
+    if !time.is_finite() {
+        return Err(E_INVARG);
+    }
+    let (seconds, fraction) = (time.floor(), time - time.floor());
+    let date_time = Utc
+        .timestamp_opt(seconds as i64, (fraction * 1e9) as u32)
+        .single()
+        .ok_or(E_INVARG)?
+        .with_timezone(&tz);
+    let mut result = String::new();
+    write!(result, "{}", date_time.format_with_items(items.into_iter())).map_err(|_| E_INVARG)?;
+    Ok(result)
+}
+
+fn bf_format_time(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: format_time(num time, str format [, str zone]) => str
+    //
+    // Formats `time` (as returned by `time()` or `ftime()`) using strftime-style `format`
+    // directives, in the IANA time zone `zone` (e.g. "America/Toronto"), or the server's local
+    // zone if none is given.
+    if bf_args.args.len() < 2 || bf_args.args.len() > 3 {
+        return Err(E_INVARG);
+    }
+    let time = match bf_args.args[0].variant() {
+        Variant::Int(i) => *i as f64,
+        Variant::Float(f) => *f,
+        _ => return Err(E_TYPE),
+    };
+    let Variant::Str(format) = bf_args.args[1].variant() else {
+        return Err(E_TYPE);
+    };
+    let tz: Tz = match bf_args.args.get(2).map(|a| a.variant()) {
+        None => get_timezone()
+            .ok()
+            .and_then(|tz| tz.parse().ok())
+            .unwrap_or(Tz::UTC),
+        Some(Variant::Str(zone)) => zone.as_str().parse().map_err(|_| E_INVARG)?,
+        Some(_) => return Err(E_TYPE),
+    };
+    let result = format_time(time, format.as_str(), tz)?;
+    bf_args.exec_state.charge_string(result.len())?;
+    Ok(Ret(v_string(result)))
+}
+bf_declare!(format_time, bf_format_time, result_cost);
+
 fn bf_raise(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     // Syntax:  raise (<code> [, str <message> [, <value>]])   => none
     //
@@ -411,6 +534,51 @@ fn bf_read(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
 }
 bf_declare!(read, bf_read);
 
+fn bf_commit(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax:  commit()   => none
+    //
+    // Commits everything the task has done so far (world state and buffered output) and continues
+    // executing in a new transaction. Like `suspend(0)', but without giving up the thread.
+    if !bf_args.args.is_empty() {
+        return Err(E_INVARG);
+    }
+
+    Ok(VmInstr(ExecutionResult::Commit))
+}
+bf_declare!(commit, bf_commit);
+
+fn bf_rollback_to_start(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax:  rollback_to_start()   => none
+    //
+    // Throws away all changes (and buffered output) made since the start of the current
+    // transaction, that is since the task began or last committed, and continues executing in a
+    // new transaction.
+    if !bf_args.args.is_empty() {
+        return Err(E_INVARG);
+    }
+
+    Ok(VmInstr(ExecutionResult::Rollback))
+}
+bf_declare!(rollback_to_start, bf_rollback_to_start);
+
+fn bf_set_task_retry_safe(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax:  set_task_retry_safe(<value>)   => int
+    //
+    // Declares whether the task is safe to re-run from the beginning if it conflicts with another
+    // task after having already committed some of its work with `commit'.
+    // Without this, such a task is aborted on conflict instead of being retried. Returns the
+    // previous setting.
+    if bf_args.args.len() != 1 {
+        return Err(E_INVARG);
+    }
+
+    let previous = bf_args.exec_state.retry_safe;
+    bf_args.exec_state.retry_safe = bf_args.args[0].is_true();
+
+    Ok(Ret(v_bool(previous)))
+}
+bf_declare!(set_task_retry_safe, bf_set_task_retry_safe);
+
 fn bf_queued_tasks(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if !bf_args.args.is_empty() {
         return Err(E_INVARG);
@@ -457,7 +625,7 @@ fn bf_queued_tasks(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
 
     Ok(Ret(v_listv(tasks)))
 }
-bf_declare!(queued_tasks, bf_queued_tasks);
+bf_declare!(queued_tasks, bf_queued_tasks, result_cost);
 
 fn bf_kill_task(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     // Syntax:  kill_task(<task-id>)   => none
@@ -554,7 +722,10 @@ fn bf_ticks_left(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
         return Err(E_INVARG);
     }
 
-    let ticks_left = bf_args.exec_state.tick_slice - bf_args.exec_state.tick_count;
+    let ticks_left = bf_args
+        .exec_state
+        .tick_slice
+        .saturating_sub(bf_args.exec_state.tick_count);
 
     Ok(Ret(v_int(ticks_left as i64)))
 }
@@ -823,6 +994,39 @@ fn bf_dump_database(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
 }
 bf_declare!(dump_database, bf_dump_database);
 
+fn bf_backup_database(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    // Syntax: backup_database() => none
+    //
+    // Starts an online backup of the database into a new timestamped directory under the server's
+    // configured backup directory, raising E_INVARG if there isn't one. Unlike `dump_database`,
+    // the backup is a copy of the database itself, which the server can be started on directly.
+    // Other tasks carry on meanwhile.
+    if !bf_args.args.is_empty() {
+        return Err(E_INVARG);
+    }
+    bf_args
+        .task_perms()
+        .map_err(world_state_err)?
+        .check_wizard()
+        .map_err(world_state_err)?;
+
+    let (send, receive) = kanal::oneshot();
+    bf_args
+        .scheduler_sender
+        .send((
+            bf_args.exec_state.task_id,
+            SchedulerControlMsg::Backup(send),
+        ))
+        .expect("scheduler is not listening");
+
+    let result = receive.recv().expect("scheduler is not listening");
+    if let Variant::Err(err) = result.variant() {
+        return Err(*err);
+    }
+    Ok(Ret(result))
+}
+bf_declare!(backup_database, bf_backup_database);
+
 fn bf_memory_usage(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if !bf_args.args.is_empty() {
         return Err(E_INVARG);
@@ -901,6 +1105,10 @@ bf_declare!(db_disk_size, db_disk_size);
 impl VM {
     pub(crate) fn register_bf_server(&mut self) {
         self.builtins[offset_for_builtin("notify")] = Arc::new(BfNotify {});
+        self.builtins[offset_for_builtin("ftime")] = Arc::new(BfFtime {});
+        self.builtins[offset_for_builtin("task_local")] = Arc::new(BfTaskLocal {});
+        self.builtins[offset_for_builtin("set_task_local")] = Arc::new(BfSetTaskLocal {});
+        self.builtins[offset_for_builtin("format_time")] = Arc::new(BfFormatTime {});
         self.builtins[offset_for_builtin("connected_players")] = Arc::new(BfConnectedPlayers {});
         self.builtins[offset_for_builtin("is_player")] = Arc::new(BfIsPlayer {});
         self.builtins[offset_for_builtin("caller_perms")] = Arc::new(BfCallerPerms {});
@@ -929,7 +1137,34 @@ impl VM {
         self.builtins[offset_for_builtin("eval")] = Arc::new(BfEval {});
         self.builtins[offset_for_builtin("read")] = Arc::new(BfRead {});
         self.builtins[offset_for_builtin("dump_database")] = Arc::new(BfDumpDatabase {});
+        self.builtins[offset_for_builtin("backup_database")] = Arc::new(BfBackupDatabase {});
         self.builtins[offset_for_builtin("memory_usage")] = Arc::new(BfMemoryUsage {});
         self.builtins[offset_for_builtin("db_disk_size")] = Arc::new(BfDbDiskSize {});
+        self.builtins[offset_for_builtin("commit")] = Arc::new(BfCommit {});
+        self.builtins[offset_for_builtin("rollback_to_start")] = Arc::new(BfRollbackToStart {});
+        self.builtins[offset_for_builtin("set_task_retry_safe")] = Arc::new(BfSetTaskRetrySafe {});
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Tz;
+
+    use moor_values::var::Error::E_INVARG;
+
+    use crate::builtins::bf_server::format_time;
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(
+            format_time(0.0, "%Y-%m-%d %H:%M:%S %Z", Tz::UTC).unwrap(),
+            "1970-01-01 00:00:00 UTC"
+        );
+        assert_eq!(
+            format_time(1_700_000_000.25, "%H:%M:%S%.3f %Z", Tz::America__Toronto).unwrap(),
+            "17:13:20.250 EST"
+        );
+        assert_eq!(format_time(0.0, "%Q", Tz::UTC), Err(E_INVARG));
+        assert_eq!(format_time(f64::NAN, "%Y", Tz::UTC), Err(E_INVARG));
     }
 }
@@ -12,19 +12,25 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use std::fmt::Write;
 use std::sync::Arc;
 
+use base64::engine::GeneralPurpose;
+use base64::Engine;
+use hmac::{Hmac, Mac};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
+use sha2::{Digest, Sha256, Sha512};
 
 use moor_values::var::Error;
 use moor_values::var::Error::{E_INVARG, E_TYPE};
 use moor_values::var::Variant;
-use moor_values::var::{v_int, v_str, v_string};
+use moor_values::var::{v_bool, v_int, v_listv, v_str, v_string, Var};
 
 use crate::bf_declare;
+use crate::builtins::bf_values::to_moo_string;
 use crate::builtins::BfRet::Ret;
-use crate::builtins::{BfCallState, BfRet, BuiltinFunction};
+use crate::builtins::{args_cost, result_cost, BfCallState, BfRet, BuiltinFunction};
 use crate::vm::VM;
 use moor_compiler::offset_for_builtin;
 
@@ -70,13 +76,15 @@ fn bf_strsub(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
         bf_args.args[2].variant(),
     );
     match (subject, what, with) {
-        (Variant::Str(subject), Variant::Str(what), Variant::Str(with)) => Ok(Ret(v_str(
-            strsub(subject.as_str(), what.as_str(), with.as_str(), case_matters).as_str(),
-        ))),
+        (Variant::Str(subject), Variant::Str(what), Variant::Str(with)) => {
+            let result = strsub(subject.as_str(), what.as_str(), with.as_str(), case_matters);
+            bf_args.exec_state.charge_string(result.len())?;
+            Ok(Ret(v_str(result.as_str())))
+        }
         _ => Err(E_TYPE),
     }
 }
-bf_declare!(strsub, bf_strsub);
+bf_declare!(strsub, bf_strsub, args_cost);
 
 fn str_index(subject: &str, what: &str, case_matters: bool) -> i64 {
     if case_matters {
@@ -124,7 +132,7 @@ fn bf_index(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
         _ => Err(E_TYPE),
     }
 }
-bf_declare!(index, bf_index);
+bf_declare!(index, bf_index, args_cost);
 
 fn bf_rindex(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     let case_matters = if bf_args.args.len() == 2 {
@@ -148,7 +156,7 @@ fn bf_rindex(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
         _ => Err(E_TYPE),
     }
 }
-bf_declare!(rindex, bf_rindex);
+bf_declare!(rindex, bf_rindex, args_cost);
 
 fn bf_strcmp(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() != 2 {
@@ -162,7 +170,7 @@ fn bf_strcmp(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
         _ => Err(E_TYPE),
     }
 }
-bf_declare!(strcmp, bf_strcmp);
+bf_declare!(strcmp, bf_strcmp, args_cost);
 
 /*
 str crypt (str text [, str salt])
@@ -201,24 +209,490 @@ fn bf_crypt(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
 }
 bf_declare!(crypt, bf_crypt);
 
+/// Encode arbitrary bytes as a MOO "binary string": printable ASCII is kept as-is, and anything
+/// else (as well as `~` itself) becomes `~XX` with XX the byte in hex.
+pub(crate) fn encode_binary(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(bytes.len());
+    for b in bytes {
+        match b {
+            b' '..=b'}' => result.push(*b as char),
+            _ => result.push_str(&format!("~{:02X}", b)),
+        }
+    }
+    result
+}
+
+/// The bytes represented by a MOO binary string, E_INVARG if it has a malformed `~XX` escape.
+pub(crate) fn decode_binary(s: &str) -> Result<Vec<u8>, Error> {
+    let mut result = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((b, tail)) = rest.split_first() {
+        if *b != b'~' {
+            result.push(*b);
+            rest = tail;
+            continue;
+        }
+        let hex = tail.get(..2).ok_or(E_INVARG)?;
+        let hex = std::str::from_utf8(hex).map_err(|_| E_INVARG)?;
+        result.push(u8::from_str_radix(hex, 16).map_err(|_| E_INVARG)?);
+        rest = &tail[2..];
+    }
+    Ok(result)
+}
+
+/// The digest algorithms accepted by the hashing builtins.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum HashAlgorithm {
+    Md5,
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// Parse an optional algorithm name argument, defaulting to MD5 for compatibility.
+    pub(crate) fn from_arg(arg: Option<&Var>) -> Result<Self, Error> {
+        let Some(arg) = arg else {
+            return Ok(HashAlgorithm::Md5);
+        };
+        let Variant::Str(name) = arg.variant() else {
+            return Err(E_TYPE);
+        };
+        match name.as_str().to_lowercase().as_str() {
+            "md5" => Ok(HashAlgorithm::Md5),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha512" => Ok(HashAlgorithm::Sha512),
+            _ => Err(E_INVARG),
+        }
+    }
+
+    /// The digest of `bytes`, as lowercase hex.
+    pub(crate) fn hex_digest(self, bytes: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Md5 => format!("{:x}", md5::compute(bytes)),
+            HashAlgorithm::Sha256 => to_hex(&Sha256::digest(bytes)),
+            HashAlgorithm::Sha512 => to_hex(&Sha512::digest(bytes)),
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{:02x}", b);
+        hex
+    })
+}
+
+//Function: str string_hash (str text [, str algorithm])
 fn bf_string_hash(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
-    if bf_args.args.len() != 1 {
+    if bf_args.args.is_empty() || bf_args.args.len() > 2 {
         return Err(E_INVARG);
     }
+    let algorithm = HashAlgorithm::from_arg(bf_args.args.get(1))?;
     match bf_args.args[0].variant() {
-        Variant::Str(s) => {
-            let hash_digest = md5::compute(s.as_str().as_bytes());
-            Ok(Ret(v_str(format!("{:x}", hash_digest).as_str())))
-        }
+        Variant::Str(s) => Ok(Ret(v_string(algorithm.hex_digest(s.as_str().as_bytes())))),
         _ => Err(E_INVARG),
     }
 }
-bf_declare!(string_hash, bf_string_hash);
+bf_declare!(string_hash, bf_string_hash, args_cost);
+
+//Function: str binary_hash (str bin-string [, str algorithm])
+fn bf_binary_hash(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    if bf_args.args.is_empty() || bf_args.args.len() > 2 {
+        return Err(E_INVARG);
+    }
+    let algorithm = HashAlgorithm::from_arg(bf_args.args.get(1))?;
+    let bytes = decode_binary(&str_arg(bf_args, 0)?)?;
+    Ok(Ret(v_string(algorithm.hex_digest(&bytes))))
+}
+bf_declare!(binary_hash, bf_binary_hash, args_cost);
+
+//Function: str hmac (str algorithm, str bin-key, str bin-text)
+fn bf_hmac(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    if bf_args.args.len() != 3 {
+        return Err(E_INVARG);
+    }
+    let algorithm = HashAlgorithm::from_arg(bf_args.args.first())?;
+    let key = decode_binary(&str_arg(bf_args, 1)?)?;
+    let text = decode_binary(&str_arg(bf_args, 2)?)?;
+    let digest = match algorithm {
+        HashAlgorithm::Sha256 => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(&key).map_err(|_| E_INVARG)?;
+            mac.update(&text);
+            mac.finalize().into_bytes().to_vec()
+        }
+        HashAlgorithm::Sha512 => {
+            let mut mac = Hmac::<Sha512>::new_from_slice(&key).map_err(|_| E_INVARG)?;
+            mac.update(&text);
+            mac.finalize().into_bytes().to_vec()
+        }
+        // MD5 is only offered for compatibility with the old hash functions.
+        HashAlgorithm::Md5 => return Err(E_INVARG),
+    };
+    Ok(Ret(v_string(to_hex(&digest))))
+}
+bf_declare!(hmac, bf_hmac, args_cost);
+
+fn base64_engine(url_safe: bool) -> &'static GeneralPurpose {
+    if url_safe {
+        &base64::engine::general_purpose::URL_SAFE
+    } else {
+        &base64::engine::general_purpose::STANDARD
+    }
+}
+
+//Function: str encode_base64 (str bin-string [, int url-safe])
+fn bf_encode_base64(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    if bf_args.args.is_empty() || bf_args.args.len() > 2 {
+        return Err(E_INVARG);
+    }
+    let bytes = decode_binary(&str_arg(bf_args, 0)?)?;
+    let url_safe = bf_args.args.len() == 2 && bf_args.args[1].is_true();
+    let encoded = base64_engine(url_safe).encode(bytes);
+    bf_args.exec_state.charge_string(encoded.len())?;
+    Ok(Ret(v_string(encoded)))
+}
+bf_declare!(encode_base64, bf_encode_base64, result_cost);
+
+//Function: str decode_base64 (str encoded [, int url-safe])
+fn bf_decode_base64(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    if bf_args.args.is_empty() || bf_args.args.len() > 2 {
+        return Err(E_INVARG);
+    }
+    let encoded = str_arg(bf_args, 0)?;
+    let url_safe = bf_args.args.len() == 2 && bf_args.args[1].is_true();
+    let bytes = base64_engine(url_safe)
+        .decode(encoded)
+        .map_err(|_| E_INVARG)?;
+    let decoded = encode_binary(&bytes);
+    bf_args.exec_state.charge_string(decoded.len())?;
+    Ok(Ret(v_string(decoded)))
+}
+bf_declare!(decode_base64, bf_decode_base64, result_cost);
+
+/// Pull the string argument at `index`, E_TYPE if it's something else.
+fn str_arg(bf_args: &BfCallState<'_>, index: usize) -> Result<String, Error> {
+    match bf_args.args[index].variant() {
+        Variant::Str(s) => Ok(s.as_str().to_string()),
+        _ => Err(E_TYPE),
+    }
+}
+
+//Function: list explode (str subject [, str delimiter [, int include-empty]])
+fn bf_explode(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    if bf_args.args.is_empty() || bf_args.args.len() > 3 {
+        return Err(E_INVARG);
+    }
+    let subject = str_arg(bf_args, 0)?;
+    let delimiter = match bf_args.args.len() {
+        1 => " ".to_string(),
+        _ => str_arg(bf_args, 1)?,
+    };
+    if delimiter.is_empty() {
+        return Err(E_INVARG);
+    }
+    let include_empty = bf_args.args.len() == 3 && bf_args.args[2].is_true();
+
+    let parts = subject
+        .split(delimiter.as_str())
+        .filter(|p| include_empty || !p.is_empty())
+        .map(v_str)
+        .collect();
+    Ok(Ret(v_listv(parts)))
+}
+bf_declare!(explode, bf_explode, args_cost);
+
+/// Replace each character of `source` found in `from` with the character at the same position in
+/// `to`, or remove it if `to` is too short to have one.
+fn strtr(source: &str, from: &str, to: &str, case_matters: bool) -> String {
+    let fold = |c: char| {
+        if case_matters {
+            c
+        } else {
+            c.to_lowercase().next().unwrap_or(c)
+        }
+    };
+    let from: Vec<char> = from.chars().map(fold).collect();
+    let to: Vec<char> = to.chars().collect();
+    source
+        .chars()
+        .filter_map(|c| match from.iter().position(|f| *f == fold(c)) {
+            Some(i) => to.get(i).copied(),
+            None => Some(c),
+        })
+        .collect()
+}
+
+//Function: str strtr (str source, str from, str to [, int case-matters])
+fn bf_strtr(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    if bf_args.args.len() < 3 || bf_args.args.len() > 4 {
+        return Err(E_INVARG);
+    }
+    let (source, from, to) = (
+        str_arg(bf_args, 0)?,
+        str_arg(bf_args, 1)?,
+        str_arg(bf_args, 2)?,
+    );
+    let case_matters = bf_args.args.len() == 4 && bf_args.args[3].is_true();
+    Ok(Ret(v_string(strtr(&source, &from, &to, case_matters))))
+}
+bf_declare!(strtr, bf_strtr, args_cost);
+
+//Function: str upcase (str subject)
+fn bf_upcase(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    if bf_args.args.len() != 1 {
+        return Err(E_INVARG);
+    }
+    Ok(Ret(v_string(str_arg(bf_args, 0)?.to_uppercase())))
+}
+bf_declare!(upcase, bf_upcase, args_cost);
+
+//Function: str downcase (str subject)
+fn bf_downcase(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    if bf_args.args.len() != 1 {
+        return Err(E_INVARG);
+    }
+    Ok(Ret(v_string(str_arg(bf_args, 0)?.to_lowercase())))
+}
+bf_declare!(downcase, bf_downcase, args_cost);
+
+//Function: str capitalize (str subject)
+fn bf_capitalize(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    if bf_args.args.len() != 1 {
+        return Err(E_INVARG);
+    }
+    let subject = str_arg(bf_args, 0)?;
+    let mut chars = subject.chars();
+    let capitalized = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    };
+    Ok(Ret(v_string(capitalized)))
+}
+bf_declare!(capitalize, bf_capitalize, args_cost);
+
+#[derive(Clone, Copy)]
+enum TrimEnds {
+    Both,
+    Start,
+    End,
+}
+
+/// Trim whitespace, or the characters in the optional second argument, from one or both ends of
+/// the string in the first.
+fn do_trim(bf_args: &mut BfCallState<'_>, ends: TrimEnds) -> Result<BfRet, Error> {
+    if bf_args.args.is_empty() || bf_args.args.len() > 2 {
+        return Err(E_INVARG);
+    }
+    let subject = str_arg(bf_args, 0)?;
+    let chars = match bf_args.args.len() {
+        2 => Some(str_arg(bf_args, 1)?.chars().collect::<Vec<_>>()),
+        _ => None,
+    };
+    let trimmable = |c: char| match &chars {
+        Some(chars) => chars.contains(&c),
+        None => c.is_whitespace(),
+    };
+    let trimmed = match ends {
+        TrimEnds::Both => subject.trim_matches(trimmable),
+        TrimEnds::Start => subject.trim_start_matches(trimmable),
+        TrimEnds::End => subject.trim_end_matches(trimmable),
+    };
+    Ok(Ret(v_str(trimmed)))
+}
+
+//Function: str trim (str subject [, str chars])
+fn bf_trim(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    do_trim(bf_args, TrimEnds::Both)
+}
+bf_declare!(trim, bf_trim, args_cost);
+
+//Function: str ltrim (str subject [, str chars])
+fn bf_ltrim(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    do_trim(bf_args, TrimEnds::Start)
+}
+bf_declare!(ltrim, bf_ltrim, args_cost);
 
-fn bf_binary_hash(_bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
-    unimplemented!("binary_hash")
+//Function: str rtrim (str subject [, str chars])
+fn bf_rtrim(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    do_trim(bf_args, TrimEnds::End)
 }
-bf_declare!(binary_hash, bf_binary_hash);
+bf_declare!(rtrim, bf_rtrim, args_cost);
+
+/// Whether `affix` is at the start (or end) of `subject`, ignoring case unless told otherwise.
+fn do_affix(bf_args: &mut BfCallState<'_>, at_start: bool) -> Result<BfRet, Error> {
+    if bf_args.args.len() < 2 || bf_args.args.len() > 3 {
+        return Err(E_INVARG);
+    }
+    let (mut subject, mut affix) = (str_arg(bf_args, 0)?, str_arg(bf_args, 1)?);
+    if !(bf_args.args.len() == 3 && bf_args.args[2].is_true()) {
+        subject = subject.to_lowercase();
+        affix = affix.to_lowercase();
+    }
+    let found = if at_start {
+        subject.starts_with(&affix)
+    } else {
+        subject.ends_with(&affix)
+    };
+    Ok(Ret(v_bool(found)))
+}
+
+//Function: int starts_with (str subject, str prefix [, int case-matters])
+fn bf_starts_with(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    do_affix(bf_args, true)
+}
+bf_declare!(starts_with, bf_starts_with, args_cost);
+
+//Function: int ends_with (str subject, str suffix [, int case-matters])
+fn bf_ends_with(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    do_affix(bf_args, false)
+}
+bf_declare!(ends_with, bf_ends_with, args_cost);
+
+/// Fill in `{}` (the next argument) and `{n}` (the nth argument) placeholders in `template`.
+/// `{{` and `}}` stand for literal braces.
+fn format_template(template: &str, args: &[Var]) -> Result<String, Error> {
+    let mut result = String::new();
+    let mut next_arg = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                result.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                result.push('}');
+            }
+            '{' => {
+                let mut position = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) if c.is_ascii_digit() => position.push(c),
+                        _ => return Err(E_INVARG),
+                    }
+                }
+                let index = if position.is_empty() {
+                    next_arg += 1;
+                    next_arg - 1
+                } else {
+                    let n: usize = position.parse().map_err(|_| E_INVARG)?;
+                    n.checked_sub(1).ok_or(E_INVARG)?
+                };
+                let arg = args.get(index).ok_or(E_INVARG)?;
+                result.push_str(&to_moo_string(arg));
+            }
+            '}' => return Err(E_INVARG),
+            c => result.push(c),
+        }
+    }
+    Ok(result)
+}
+
+//Function: str format (str template, ...)
+fn bf_format(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    if bf_args.args.is_empty() {
+        return Err(E_INVARG);
+    }
+    let template = str_arg(bf_args, 0)?;
+    let result = format_template(&template, &bf_args.args[1..])?;
+    bf_args.exec_state.charge_string(result.len())?;
+    Ok(Ret(v_string(result)))
+}
+bf_declare!(format, bf_format, result_cost);
+
+/// The English words for a number, e.g. "negative one thousand two hundred thirty-four".
+fn spellnum(n: i64) -> String {
+    const ONES: [&str; 20] = [
+        "zero",
+        "one",
+        "two",
+        "three",
+        "four",
+        "five",
+        "six",
+        "seven",
+        "eight",
+        "nine",
+        "ten",
+        "eleven",
+        "twelve",
+        "thirteen",
+        "fourteen",
+        "fifteen",
+        "sixteen",
+        "seventeen",
+        "eighteen",
+        "nineteen",
+    ];
+    const TENS: [&str; 10] = [
+        "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+    ];
+    const SCALES: [&str; 7] = [
+        "",
+        "thousand",
+        "million",
+        "billion",
+        "trillion",
+        "quadrillion",
+        "quintillion",
+    ];
+
+    fn below_thousand(n: u64, words: &mut Vec<String>) {
+        let (hundreds, rest) = (n / 100, n % 100);
+        if hundreds > 0 {
+            words.push(format!("{} hundred", ONES[hundreds as usize]));
+        }
+        match rest {
+            0 => {}
+            1..=19 => words.push(ONES[rest as usize].to_string()),
+            _ if rest % 10 == 0 => words.push(TENS[(rest / 10) as usize].to_string()),
+            _ => words.push(format!(
+                "{}-{}",
+                TENS[(rest / 10) as usize],
+                ONES[(rest % 10) as usize]
+            )),
+        }
+    }
+
+    if n == 0 {
+        return ONES[0].to_string();
+    }
+    let mut words = vec![];
+    if n < 0 {
+        words.push("negative".to_string());
+    }
+    let magnitude = n.unsigned_abs();
+    let mut groups = vec![];
+    let mut rest = magnitude;
+    while rest > 0 {
+        groups.push(rest % 1000);
+        rest /= 1000;
+    }
+    for (scale, group) in groups.iter().enumerate().rev() {
+        if *group == 0 {
+            continue;
+        }
+        below_thousand(*group, &mut words);
+        if scale > 0 {
+            words.push(SCALES[scale].to_string());
+        }
+    }
+    words.join(" ")
+}
+
+//Function: str spellnum (int number)
+fn bf_spellnum(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
+    if bf_args.args.len() != 1 {
+        return Err(E_INVARG);
+    }
+    let Variant::Int(n) = bf_args.args[0].variant() else {
+        return Err(E_TYPE);
+    };
+    Ok(Ret(v_string(spellnum(*n))))
+}
+bf_declare!(spellnum, bf_spellnum);
 
 impl VM {
     pub(crate) fn register_bf_strings(&mut self) {
@@ -229,12 +703,32 @@ impl VM {
         self.builtins[offset_for_builtin("crypt")] = Arc::new(BfCrypt {});
         self.builtins[offset_for_builtin("string_hash")] = Arc::new(BfStringHash {});
         self.builtins[offset_for_builtin("binary_hash")] = Arc::new(BfBinaryHash {});
+        self.builtins[offset_for_builtin("hmac")] = Arc::new(BfHmac {});
+        self.builtins[offset_for_builtin("encode_base64")] = Arc::new(BfEncodeBase64 {});
+        self.builtins[offset_for_builtin("decode_base64")] = Arc::new(BfDecodeBase64 {});
+        self.builtins[offset_for_builtin("explode")] = Arc::new(BfExplode {});
+        self.builtins[offset_for_builtin("strtr")] = Arc::new(BfStrtr {});
+        self.builtins[offset_for_builtin("upcase")] = Arc::new(BfUpcase {});
+        self.builtins[offset_for_builtin("downcase")] = Arc::new(BfDowncase {});
+        self.builtins[offset_for_builtin("capitalize")] = Arc::new(BfCapitalize {});
+        self.builtins[offset_for_builtin("trim")] = Arc::new(BfTrim {});
+        self.builtins[offset_for_builtin("ltrim")] = Arc::new(BfLtrim {});
+        self.builtins[offset_for_builtin("rtrim")] = Arc::new(BfRtrim {});
+        self.builtins[offset_for_builtin("starts_with")] = Arc::new(BfStartsWith {});
+        self.builtins[offset_for_builtin("ends_with")] = Arc::new(BfEndsWith {});
+        self.builtins[offset_for_builtin("format")] = Arc::new(BfFormat {});
+        self.builtins[offset_for_builtin("spellnum")] = Arc::new(BfSpellnum {});
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::builtins::bf_strings::strsub;
+    use moor_values::var::Error::E_INVARG;
+    use moor_values::var::{v_int, v_str};
+
+    use crate::builtins::bf_strings::{
+        decode_binary, encode_binary, format_template, spellnum, strsub, strtr, HashAlgorithm,
+    };
 
     #[test]
     fn test_strsub_remove_piece() {
@@ -286,4 +780,57 @@ mod tests {
         let expected = "foo bar baz";
         assert_eq!(strsub(subject, "fizz", "buzz", false), expected);
     }
+
+    #[test]
+    fn test_strtr() {
+        assert_eq!(strtr("foobar", "oba", "OBA", true), "fOOBAr");
+        assert_eq!(strtr("FooBar", "ob", "", false), "Far");
+        assert_eq!(strtr("héllo", "é", "e", true), "hello");
+    }
+
+    #[test]
+    fn test_format_template() {
+        let args = [v_str("world"), v_int(2)];
+        assert_eq!(
+            format_template("hello {}, {2} {{x}}", &args).unwrap(),
+            "hello world, 2 {x}"
+        );
+        assert_eq!(format_template("{1}{1}", &args).unwrap(), "worldworld");
+        assert_eq!(format_template("{3}", &args), Err(E_INVARG));
+        assert_eq!(format_template("{0}", &args), Err(E_INVARG));
+        assert_eq!(format_template("oops }", &args), Err(E_INVARG));
+    }
+
+    #[test]
+    fn test_spellnum() {
+        assert_eq!(spellnum(0), "zero");
+        assert_eq!(spellnum(42), "forty-two");
+        assert_eq!(spellnum(-1_000_019), "negative one million nineteen");
+        assert_eq!(spellnum(1234), "one thousand two hundred thirty-four");
+    }
+
+    #[test]
+    fn test_binary_strings() {
+        let bytes = b"ab~\n\xff";
+        assert_eq!(encode_binary(bytes), "ab~7E~0A~FF");
+        assert_eq!(decode_binary("ab~7E~0A~FF").unwrap(), bytes);
+        assert_eq!(decode_binary("~0a").unwrap(), b"\n");
+        assert_eq!(decode_binary("bad~Z1"), Err(E_INVARG));
+        assert_eq!(decode_binary("short~A"), Err(E_INVARG));
+    }
+
+    #[test]
+    fn test_hex_digests() {
+        assert_eq!(
+            HashAlgorithm::Md5.hex_digest(b"abc"),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+        assert_eq!(
+            HashAlgorithm::Sha256.hex_digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(HashAlgorithm::Sha512
+            .hex_digest(b"abc")
+            .starts_with("ddaf35a193617aba"));
+    }
 }
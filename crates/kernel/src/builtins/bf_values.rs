@@ -12,19 +12,18 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
 use moor_values::var::Error;
 use moor_values::var::Error::{E_INVARG, E_TYPE};
-use moor_values::var::Variant;
-use moor_values::var::{v_bool, v_float, v_int, v_obj, v_str};
+use moor_values::var::{v_bool, v_float, v_int, v_obj, v_str, v_string};
+use moor_values::var::{Var, Variant};
 use moor_values::AsByteBuffer;
 
 use crate::bf_declare;
+use crate::builtins::bf_strings::HashAlgorithm;
 use crate::builtins::BfRet::Ret;
-use crate::builtins::{BfCallState, BfRet, BuiltinFunction};
+use crate::builtins::{args_cost, result_cost, BfCallState, BfRet, BuiltinFunction};
 use crate::vm::VM;
 use moor_compiler::offset_for_builtin;
 
@@ -34,31 +33,38 @@ fn bf_typeof(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
 }
 bf_declare!(typeof, bf_typeof);
 
+/// The string form of a value, as `tostr` produces it.
+pub(crate) fn to_moo_string(v: &Var) -> String {
+    match v.variant() {
+        Variant::None => "None".to_string(),
+        Variant::Int(i) => i.to_string(),
+        Variant::Float(f) => format!("{:?}", f),
+        Variant::Str(s) => s.as_str().to_string(),
+        Variant::Obj(o) => o.to_string(),
+        Variant::List(_) => "{list}".to_string(),
+        Variant::Err(e) => e.name().to_string(),
+    }
+}
+
 fn bf_tostr(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     let mut result = String::new();
     for arg in &bf_args.args {
-        match arg.variant() {
-            Variant::None => result.push_str("None"),
-            Variant::Int(i) => result.push_str(&i.to_string()),
-            Variant::Float(f) => result.push_str(format!("{:?}", f).as_str()),
-            Variant::Str(s) => result.push_str(s.as_str()),
-            Variant::Obj(o) => result.push_str(&o.to_string()),
-            Variant::List(_) => result.push_str("{list}"),
-            Variant::Err(e) => result.push_str(e.name()),
-        }
+        result.push_str(&to_moo_string(arg));
     }
+    bf_args.exec_state.charge_string(result.len())?;
     Ok(Ret(v_str(result.as_str())))
 }
-bf_declare!(tostr, bf_tostr);
+bf_declare!(tostr, bf_tostr, result_cost);
 
 fn bf_toliteral(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() != 1 {
         return Err(E_INVARG);
     }
     let literal = bf_args.args[0].to_literal();
+    bf_args.exec_state.charge_string(literal.len())?;
     Ok(Ret(v_str(literal.as_str())))
 }
-bf_declare!(toliteral, bf_toliteral);
+bf_declare!(toliteral, bf_toliteral, result_cost);
 
 fn bf_toint(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() != 1 {
@@ -137,7 +143,7 @@ fn bf_equal(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     };
     Ok(Ret(v_bool(result)))
 }
-bf_declare!(equal, bf_equal);
+bf_declare!(equal, bf_equal, args_cost);
 
 fn bf_value_bytes(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() != 1 {
@@ -148,15 +154,16 @@ fn bf_value_bytes(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
 }
 bf_declare!(value_bytes, bf_value_bytes);
 
+/// The hex digest of the literal form of a value, as `string_hash(toliteral(value))` would give.
 fn bf_value_hash(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
-    if bf_args.args.len() != 1 {
+    if bf_args.args.is_empty() || bf_args.args.len() > 2 {
         return Err(E_INVARG);
     }
-    let mut s = DefaultHasher::new();
-    bf_args.args[0].hash(&mut s);
-    Ok(Ret(v_int(s.finish() as i64)))
+    let algorithm = HashAlgorithm::from_arg(bf_args.args.get(1))?;
+    let literal = bf_args.args[0].to_literal();
+    Ok(Ret(v_string(algorithm.hex_digest(literal.as_bytes()))))
 }
-bf_declare!(value_hash, bf_value_hash);
+bf_declare!(value_hash, bf_value_hash, args_cost);
 
 fn bf_length(bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error> {
     if bf_args.args.len() != 1 {
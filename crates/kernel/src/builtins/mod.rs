@@ -12,9 +12,12 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+mod bf_json;
 mod bf_list_sets;
 mod bf_num;
 mod bf_objects;
+pub mod bf_passwords;
+mod bf_pcre;
 mod bf_properties;
 pub mod bf_server;
 mod bf_strings;
@@ -31,6 +34,7 @@ use moor_values::model::WorldStateError;
 use moor_values::var::Error;
 use moor_values::var::Objid;
 use moor_values::var::Var;
+use moor_values::var::Variant;
 
 use crate::tasks::sessions::Session;
 use crate::tasks::task_messages::SchedulerControlMsg;
@@ -72,6 +76,35 @@ impl BfCallState<'_> {
 pub trait BuiltinFunction: Sync + Send {
     fn name(&self) -> &str;
     fn call(&self, bf_args: &mut BfCallState<'_>) -> Result<BfRet, Error>;
+    /// The number of ticks to charge for a call, on top of the call itself, given its arguments
+    /// and (if it returned one) its result. What it costs without a result is charged before the
+    /// call, so a call the task can't afford never runs; the rest once it returns.
+    fn cost(&self, _args: &[Var], _result: Option<&Var>) -> usize {
+        0
+    }
+}
+
+/// How many bytes (or list elements) a builtin gets to chew through per tick charged.
+pub(crate) const BF_UNITS_PER_TICK: usize = 64;
+
+/// A rough measure of how big a value is to process: the length of a string or list, counting
+/// nested lists, and 1 for anything else.
+fn value_units(v: &Var) -> usize {
+    match v.variant() {
+        Variant::Str(s) => s.len(),
+        Variant::List(l) => l.iter().map(value_units).sum::<usize>().max(1),
+        _ => 1,
+    }
+}
+
+/// Cost for builtins whose work is proportional to the size of their arguments.
+pub(crate) fn args_cost(args: &[Var], _result: Option<&Var>) -> usize {
+    args.iter().map(value_units).sum::<usize>() / BF_UNITS_PER_TICK
+}
+
+/// Cost for builtins whose work is proportional to the size of what they produce.
+pub(crate) fn result_cost(_args: &[Var], result: Option<&Var>) -> usize {
+    result.map(value_units).unwrap_or(0) / BF_UNITS_PER_TICK
 }
 
 /// Return possibilities from a built-in function.
@@ -103,4 +136,27 @@ macro_rules! bf_declare {
             }
         }
     };
+    ( $name:ident, $action:expr, $cost:expr ) => {
+        paste::item! {
+            pub struct [<Bf $name:camel >] {}
+            impl BuiltinFunction for [<Bf $name:camel >] {
+                fn name(&self) -> &str {
+                    return stringify!($name)
+                }
+                fn call(
+                    &self,
+                    bf_args: &mut BfCallState<'_>
+                ) -> Result<BfRet, Error> {
+                    $action(bf_args)
+                }
+                fn cost(
+                    &self,
+                    args: &[moor_values::var::Var],
+                    result: Option<&moor_values::var::Var>
+                ) -> usize {
+                    ($cost)(args, result)
+                }
+            }
+        }
+    };
 }
@@ -16,8 +16,112 @@
 //! available to all components. Used to hold things typically configured by CLI flags, etc.
 
 use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Debug, Default)]
+use moor_compiler::Op;
+
+#[derive(Debug)]
 pub struct Config {
     pub textdump_output: Option<PathBuf>,
+    /// The directory under which `backup_database()` writes its (timestamped) backups, if any.
+    pub backup_dir: Option<PathBuf>,
+    /// How many times a task may be restarted after a transaction conflict before it is given up
+    /// on and aborted.
+    pub max_task_retries: usize,
+    /// The delay before the first restart of a conflicted task. Each further retry doubles this,
+    /// up to `retry_backoff_max`.
+    pub retry_backoff_base: Duration,
+    /// The upper bound on the delay between restarts of a conflicted task.
+    pub retry_backoff_max: Duration,
+    /// How many ticks the VM charges for the more expensive opcodes.
+    pub tick_costs: TickCosts,
+    /// How much memory a task may allocate building values, before it is refused with E_QUOTA.
+    pub memory_limits: MemoryLimits,
+}
+
+/// The number of ticks charged for executing each kind of opcode. Opcodes not covered here cost a
+/// single tick. Builtin functions are additionally charged their own (argument dependent) cost.
+#[derive(Debug, Clone, Copy)]
+pub struct TickCosts {
+    /// Calling a verb (`CallVerb`, `pass`).
+    pub verb_call: usize,
+    /// Calling a builtin function, before the function's own cost.
+    pub builtin_call: usize,
+    /// Reading or writing a property, which has to go to the database.
+    pub property: usize,
+    /// Forking a new task.
+    pub fork: usize,
+}
+
+impl TickCosts {
+    pub fn op_cost(&self, op: &Op) -> usize {
+        let cost = match op {
+            Op::CallVerb | Op::Pass => self.verb_call,
+            Op::FuncCall { .. } => self.builtin_call,
+            Op::GetProp | Op::PushGetProp | Op::PutProp => self.property,
+            Op::Fork { .. } => self.fork,
+            _ => 1,
+        };
+        // Every opcode must cost something, or a loop could run forever inside a single slice.
+        cost.max(1)
+    }
+}
+
+impl Default for TickCosts {
+    fn default() -> Self {
+        Self {
+            verb_call: 1,
+            builtin_call: 1,
+            property: 1,
+            fork: 1,
+        }
+    }
+}
+
+/// Caps on how large the values built by a single task may grow. Exceeding any of these raises
+/// E_QUOTA in the offending task.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryLimits {
+    /// The maximum number of elements in a list built by appending or concatenating.
+    pub max_list_concat: usize,
+    /// The maximum length, in bytes, of a string built by concatenation (or `tostr` etc.)
+    pub max_string_concat: usize,
+    /// The (approximate) number of bytes of values a task may hold at once. Allocations are
+    /// counted as they happen, and only when they add up to this is what's still live measured.
+    pub max_task_memory: usize,
+}
+
+impl Default for MemoryLimits {
+    fn default() -> Self {
+        // The list and string defaults are ToastStunt's.
+        Self {
+            max_list_concat: 4_194_302,
+            max_string_concat: 67_108_864,
+            max_task_memory: 1 << 30,
+        }
+    }
+}
+
+impl Config {
+    /// The delay to wait before the given (1-based) retry attempt of a conflicted task.
+    pub fn retry_backoff(&self, attempt: usize) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31) as u32;
+        self.retry_backoff_base
+            .saturating_mul(1 << exponent)
+            .min(self.retry_backoff_max)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            textdump_output: None,
+            backup_dir: None,
+            max_task_retries: 10,
+            retry_backoff_base: Duration::from_millis(1),
+            retry_backoff_max: Duration::from_millis(500),
+            tick_costs: TickCosts::default(),
+            memory_limits: MemoryLimits::default(),
+        }
+    }
 }
@@ -57,6 +57,8 @@ pub struct TaskDescription {
 }
 
 pub mod vm_test_utils {
+    use crate::config::{MemoryLimits, TickCosts};
+    use crate::tasks::scheduler::AbortLimitReason;
     use crate::tasks::sessions::Session;
     use crate::tasks::vm_host::{VMHostResponse, VmHost};
     use crate::tasks::VerbCall;
@@ -73,12 +75,37 @@ pub mod vm_test_utils {
         verb_name: &str,
         args: Vec<Var>,
     ) -> Var {
+        call_verb_with_limits(
+            world_state,
+            session,
+            verb_name,
+            args,
+            90_000,
+            TickCosts::default(),
+            MemoryLimits::default(),
+        )
+        .unwrap_or_else(|a| panic!("Unexpected abort: {:?}", a))
+    }
+
+    /// As `call_verb`, but with the given tick budget and costs, and memory limits, returning
+    /// why the task was aborted if it ran out of ticks.
+    pub fn call_verb_with_limits(
+        world_state: &mut dyn WorldState,
+        session: Arc<dyn Session>,
+        verb_name: &str,
+        args: Vec<Var>,
+        max_ticks: usize,
+        tick_costs: TickCosts,
+        memory_limits: MemoryLimits,
+    ) -> Result<Var, AbortLimitReason> {
         let (scs_tx, _scs_rx) = kanal::unbounded();
         let mut vm_host = VmHost::new(
             0,
             20,
-            90_000,
+            max_ticks,
             Duration::from_secs(5),
+            tick_costs,
+            memory_limits,
             session.clone(),
             scs_tx,
         );
@@ -87,6 +114,8 @@ pub mod vm_test_utils {
         let _vm_exec_params = VmExecParams {
             scheduler_sender: sched_send.clone(),
             max_stack_depth: 50,
+            max_ticks,
+            tick_costs,
         };
 
         let vi = world_state
@@ -117,13 +146,13 @@ pub mod vm_test_utils {
                     panic!("Unexpected fork: {:?}", f);
                 }
                 VMHostResponse::AbortLimit(a) => {
-                    panic!("Unexpected abort: {:?}", a);
+                    return Err(a);
                 }
                 VMHostResponse::CompleteException(e) => {
                     panic!("Unexpected exception: {:?}", e)
                 }
                 VMHostResponse::CompleteSuccess(v) => {
-                    return v;
+                    return Ok(v);
                 }
                 VMHostResponse::CompleteAbort => {
                     panic!("Unexpected abort");
@@ -134,6 +163,12 @@ pub mod vm_test_utils {
                 VMHostResponse::SuspendNeedInput => {
                     panic!("Unexpected suspend need input");
                 }
+                VMHostResponse::Commit => {
+                    panic!("Unexpected commit");
+                }
+                VMHostResponse::Rollback => {
+                    panic!("Unexpected rollback");
+                }
             }
         }
     }
@@ -33,6 +33,7 @@ use moor_compiler::compile;
 use moor_compiler::CompileError;
 use moor_db::Database;
 use moor_values::model::CommandError;
+use moor_values::model::ConflictInfo;
 use moor_values::model::Perms;
 use moor_values::model::WorldStateSource;
 use moor_values::var::Error::{E_INVARG, E_PERM};
@@ -41,7 +42,8 @@ use moor_values::var::{Objid, Variant};
 use moor_values::SYSTEM_OBJECT;
 use SchedulerError::{
     CommandExecutionError, CouldNotStartTask, EvalCompilationError, InputRequestNotFound,
-    TaskAbortedCancelled, TaskAbortedError, TaskAbortedException, TaskAbortedLimit,
+    TaskAbortedCancelled, TaskAbortedConflict, TaskAbortedError, TaskAbortedException,
+    TaskAbortedLimit,
 };
 
 use crate::config::Config;
@@ -69,6 +71,7 @@ pub struct Scheduler {
     next_task_id: AtomicUsize,
     tasks: DashMap<TaskId, TaskControl>,
     input_requests: DashMap<Uuid, TaskId>,
+    conflict_stats: ConflictStats,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Decode, Encode)]
@@ -105,6 +108,64 @@ pub enum SchedulerError {
     TaskAbortedException(UncaughtException),
     #[error("Task aborted due to cancellation.")]
     TaskAbortedCancelled,
+    #[error("Task aborted after {0} transaction conflict retries.")]
+    TaskAbortedConflict(usize),
+}
+
+/// Counters of the transaction conflicts which caused tasks to be retried, broken down by the
+/// relation the conflict happened in, and the verb that was executing when the task tried to
+/// commit. Used to find contention hot spots in the world (shared counters and the like).
+#[derive(Default)]
+struct ConflictStats {
+    retries: AtomicUsize,
+    abandoned: AtomicUsize,
+    by_relation: DashMap<String, usize>,
+    by_verb: DashMap<String, usize>,
+}
+
+/// A point in time copy of the scheduler's conflict counters.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConflictCounters {
+    /// The total number of times a task was restarted because of a conflict.
+    pub retries: usize,
+    /// The number of tasks which were aborted after exhausting their retries.
+    pub abandoned: usize,
+    /// Conflict counts per relation, most conflicted first.
+    pub by_relation: Vec<(String, usize)>,
+    /// Conflict counts per verb (`#definer:name`), most conflicted first.
+    pub by_verb: Vec<(String, usize)>,
+}
+
+impl ConflictStats {
+    fn record(&self, conflict: &Option<ConflictInfo>, verb: &Option<(Objid, String)>) {
+        self.retries.fetch_add(1, Ordering::SeqCst);
+        if let Some(conflict) = conflict {
+            *self
+                .by_relation
+                .entry(conflict.relation.clone())
+                .or_default() += 1;
+        }
+        if let Some((definer, name)) = verb {
+            *self
+                .by_verb
+                .entry(format!("{}:{}", definer, name))
+                .or_default() += 1;
+        }
+    }
+
+    fn counters(&self) -> ConflictCounters {
+        fn sorted(map: &DashMap<String, usize>) -> Vec<(String, usize)> {
+            let mut counts: Vec<_> = map.iter().map(|e| (e.key().clone(), *e.value())).collect();
+            counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            counts
+        }
+        ConflictCounters {
+            retries: self.retries.load(Ordering::SeqCst),
+            abandoned: self.abandoned.load(Ordering::SeqCst),
+            by_relation: sorted(&self.by_relation),
+            by_verb: sorted(&self.by_verb),
+        }
+    }
 }
 
 struct KillRequest {
@@ -140,6 +201,8 @@ struct TaskControl {
     suspended: bool,
     waiting_input: Option<Uuid>,
     resume_time: Option<SystemTime>,
+    /// How many times this task has been restarted due to transaction conflicts.
+    retries: usize,
     // subscribers for when the task is aborted, succeeded, etc.
     subscribers: Mutex<Vec<OneshotSender<TaskWaiterResult>>>,
     _join_handle: std::thread::JoinHandle<()>,
@@ -168,6 +231,7 @@ impl Scheduler {
             next_task_id: Default::default(),
             tasks: DashMap::new(),
             input_requests: Default::default(),
+            conflict_stats: Default::default(),
             config: config.clone(),
             control_sender,
             control_receiver,
@@ -352,6 +416,7 @@ impl Scheduler {
         player: Objid,
         perms: Objid,
         code: String,
+        read_only: bool,
         sessions: Arc<dyn Session>,
     ) -> Result<TaskId, SchedulerError> {
         // Compile the text into a verb.
@@ -363,6 +428,7 @@ impl Scheduler {
         let task_start = TaskStart::StartEval {
             player,
             program: binary,
+            read_only,
         };
 
         let task_id = self.new_task(
@@ -444,6 +510,11 @@ impl Scheduler {
         Ok(())
     }
 
+    /// Return the current counts of transaction conflicts which caused tasks to be retried.
+    pub fn conflict_counters(&self) -> ConflictCounters {
+        self.conflict_stats.counters()
+    }
+
     pub fn abort_task(&self, id: TaskId) -> Result<(), SchedulerError> {
         let task = self.tasks.get_mut(&id).ok_or(TaskNotFound(id))?;
         let tcs = task.task_control_sender.clone();
@@ -475,6 +546,7 @@ impl Scheduler {
                 let mut number_subscriptions = 0;
                 let mut number_zombies = 0;
                 let number_tasks = this.clone().tasks.len();
+                let conflicts = this.conflict_stats.counters();
 
                 for task in this.clone().tasks.iter() {
                     if task.suspended {
@@ -498,6 +570,10 @@ impl Scheduler {
                     number_subscriptions,
                     number_zombies,
                     number_suspended_tasks,
+                    conflict_retries = conflicts.retries,
+                    conflict_abandoned = conflicts.abandoned,
+                    conflict_hot_relation = ?conflicts.by_relation.first(),
+                    conflict_hot_verb = ?conflicts.by_verb.first(),
                     "..."
                 );
                 std::thread::sleep(METRICS_POLLER_TICK_TIME);
@@ -615,8 +691,62 @@ impl Scheduler {
                     TaskHandleResult::Remove(task_id),
                 ]
             }
-            SchedulerControlMsg::TaskConflictRetry => {
-                trace!(?task_id, "Task retrying due to conflict");
+            SchedulerControlMsg::TaskConflictRetry {
+                conflict,
+                verb,
+                restartable,
+            } => {
+                self.conflict_stats.record(&conflict, &verb);
+
+                let Some(mut task) = self.tasks.get_mut(&task_id) else {
+                    warn!(task_id, "Task not found for conflict retry");
+                    return vec![TaskHandleResult::Remove(task_id)];
+                };
+                task.retries += 1;
+                warn!(
+                    ?task_id,
+                    retries = task.retries,
+                    relation = conflict.as_ref().map(|c| c.relation.as_str()),
+                    key = ?conflict.as_ref().map(|c| &c.key),
+                    committed_ts = ?conflict.as_ref().and_then(|c| c.committed_ts),
+                    verb = ?verb,
+                    "Task conflicted on commit"
+                );
+
+                // If the task keeps losing, give up on it rather than retrying forever. Likewise if
+                // it already committed part of its work and can't safely be re-run from the top.
+                if !restartable || task.retries > self.config.max_task_retries {
+                    self.conflict_stats.abandoned.fetch_add(1, Ordering::SeqCst);
+                    let msg = if restartable {
+                        error!(
+                            ?task_id,
+                            retries = task.retries,
+                            "Task exceeded conflict retries"
+                        );
+                        "Aborted: too many transaction conflicts."
+                    } else {
+                        error!(
+                            ?task_id,
+                            "Task conflicted after a commit, and is not retry-safe"
+                        );
+                        "Aborted: transaction conflict after partial commit."
+                    };
+                    if let Err(send_error) = task.session.send_system_msg(task.player, msg) {
+                        warn!("Could not send abort message to player: {:?}", send_error);
+                    }
+                    let _ = task.session.rollback();
+                    let tcs = task.task_control_sender.clone();
+                    if let Err(e) = tcs.send(TaskControlMsg::Abort) {
+                        warn!(task_id, error = ?e, "Could not send abort for task. Dead?");
+                    }
+                    return vec![
+                        TaskHandleResult::Notify(
+                            task_id,
+                            TaskWaiterResult::Error(TaskAbortedConflict(task.retries - 1)),
+                        ),
+                        TaskHandleResult::Remove(task_id),
+                    ];
+                }
 
                 // Ask the task to restart itself, using its stashed original start info, but with
                 // a brand new transaction.
@@ -931,6 +1061,37 @@ impl Scheduler {
                 }
                 vec![]
             }
+            SchedulerControlMsg::Backup(result_sender) => {
+                let Some(backup_dir) = self.config.backup_dir.clone() else {
+                    error!("Cannot back up database as backup_dir not configured");
+                    result_sender
+                        .send(v_err(E_INVARG))
+                        .expect("Could not send backup result");
+                    return vec![];
+                };
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                let backup_path = backup_dir.join(format!("backup-{}", timestamp));
+
+                let tr = std::thread::Builder::new()
+                    .name("backup-thread".to_string())
+                    .spawn(move || {
+                        info!("Backing up database to {}", backup_path.display());
+                        match self.database.backup(&backup_path) {
+                            Ok(()) => info!("Database backed up to {}", backup_path.display()),
+                            Err(e) => error!(?e, "Could not back up database"),
+                        }
+                    });
+                if let Err(e) = tr {
+                    error!(?e, "Could not start backup thread");
+                }
+                result_sender
+                    .send(v_none())
+                    .expect("Could not send backup result");
+                vec![]
+            }
         }
     }
 
@@ -1289,9 +1450,10 @@ impl Scheduler {
             .expect("Unable to get world source from database");
 
         task.suspended = false;
+        let backoff = self.config.retry_backoff(task.retries);
 
         let tcs = task.task_control_sender.clone();
-        if let Err(e) = tcs.send(TaskControlMsg::Restart(state_source)) {
+        if let Err(e) = tcs.send(TaskControlMsg::Restart(state_source, backoff)) {
             error!(task = task_id, error = ?e,
                     "Could not send resume request to task. Task being removed.");
             return Some(task_id);
@@ -1367,11 +1529,20 @@ impl Scheduler {
         // Spawn the task's thread.
         let task_state_source = state_source.clone();
         let task_session = session.clone();
+        let tick_costs = self.config.tick_costs;
+        let memory_limits = self.config.memory_limits;
+
+        // The task mustn't start until it's in `tasks`, or anything it sends us straight away
+        // (e.g. a suspend) will be for a task we don't know about.
+        let (registered_send, registered_receive) = kanal::oneshot();
 
         let name = format!("moor-task-{}-player-{}", task_id, player);
         let join_handle = std::thread::Builder::new()
             .name(name)
             .spawn(move || {
+                if registered_receive.recv().is_err() {
+                    return;
+                }
                 trace!(?task_id, ?task_start, "Starting up task");
                 Task::run(
                     task_id,
@@ -1380,6 +1551,8 @@ impl Scheduler {
                     delay_start,
                     task_state_source,
                     is_background,
+                    tick_costs,
+                    memory_limits,
                     task_session,
                     task_control_receiver,
                     control_sender,
@@ -1397,10 +1570,12 @@ impl Scheduler {
             suspended: false,
             waiting_input: None,
             resume_time: None,
+            retries: 0,
             subscribers: Mutex::new(vec![]),
             _join_handle: join_handle,
         };
         self.tasks.insert(task_id, task_control);
+        let _ = registered_send.send(());
 
         Ok(task_id)
     }
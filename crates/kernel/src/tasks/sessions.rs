@@ -43,7 +43,8 @@ pub trait Session: Send + Sync {
     /// Commit for current activity, called by the scheduler when a task commits and *after* the world
     /// state has successfully been committed. This is the point at which the session should send
     /// its buffered output.
-    /// The session should not be usable after this point.
+    /// A task may also commit its session mid-execution (e.g. via `commit()`), in which case it
+    /// continues to use the session afterwards.
     /// Note: there is no "two phase" process, so if I/O output fails, the world state will not be
     ///  rolled back. I/O output is not considered "critical" to the transaction's success, and
     ///  the world state's integrity and performance in that path is considered more important.
@@ -21,13 +21,14 @@ use tracing::{debug, error, trace, warn};
 
 use moor_values::model::CommandError::PermissionDenied;
 use moor_values::model::VerbInfo;
-use moor_values::model::{CommandError, CommitResult, WorldStateError};
+use moor_values::model::{CommandError, CommitResult, ConflictInfo, WorldStateError};
 use moor_values::model::{WorldState, WorldStateSource};
 use moor_values::util::parse_into_words;
 use moor_values::var::Objid;
-use moor_values::var::{v_int, v_string};
+use moor_values::var::{v_int, v_none, v_string};
 use moor_values::NOTHING;
 
+use crate::config::{MemoryLimits, TickCosts};
 use crate::matching::match_env::MatchEnvironmentParseMatcher;
 use crate::matching::ws_match_env::WsMatchEnv;
 use crate::tasks::command_parse::{parse_command, ParseCommandError, ParsedCommand};
@@ -63,6 +64,15 @@ pub(crate) struct Task {
     pub(crate) scheduler_control_sender: Sender<(TaskId, SchedulerControlMsg)>,
     /// The transactionaly isolated world state for this task.
     pub(crate) world_state: Box<dyn WorldState>,
+    /// Where we get new transactions from when we commit or roll back mid-execution.
+    pub(crate) state_source: Arc<dyn WorldStateSource>,
+    /// The session we perform I/O through, committed or rolled back along with the world state.
+    pub(crate) session: Arc<dyn Session>,
+    /// Whether some of this task's work has already been committed with an explicit `commit()`.
+    /// If so, restarting the task from the top on conflict would re-apply that work, so it is only
+    /// done if the task has declared itself safe to retry. (Tasks which suspend or read input are
+    /// restarted from the top on conflict, as they always have been.)
+    pub(crate) checkpointed: bool,
     /// The permissions of the task -- the object on behalf of which all permissions are evaluated.
     pub(crate) perms: Objid,
     /// The actual VM host which is managing the execution of this task.
@@ -145,6 +155,8 @@ impl Task {
         delay_start: Option<Duration>,
         state_source: Arc<dyn WorldStateSource>,
         is_background: bool,
+        tick_costs: TickCosts,
+        memory_limits: MemoryLimits,
         session: Arc<dyn Session>,
         task_control_receiver: Receiver<TaskControlMsg>,
         control_sender: Sender<(TaskId, SchedulerControlMsg)>,
@@ -155,10 +167,14 @@ impl Task {
             std::thread::sleep(delay);
         }
 
-        // Start the transaction.
-        let mut world_state = state_source
-            .new_world_state()
-            .expect("Could not start transaction for new task");
+        // Start the transaction. Tasks we've been told won't write get a cheaper one.
+        let mut world_state = match &task_start {
+            TaskStart::StartEval {
+                read_only: true, ..
+            } => state_source.new_read_only_world_state(),
+            _ => state_source.new_world_state(),
+        }
+        .expect("Could not start transaction for new task");
 
         // Find out max ticks, etc. for this task. These are either pulled from server constants in
         // the DB or from default constants.
@@ -171,6 +187,8 @@ impl Task {
             max_stack_depth,
             max_ticks,
             Duration::from_secs(max_seconds),
+            tick_costs,
+            memory_limits,
             session.clone(),
             scheduler_control_sender.clone(),
         );
@@ -181,7 +199,10 @@ impl Task {
             scheduler_control_sender: scheduler_control_sender.clone(),
             vm_host,
             world_state,
+            state_source,
+            session,
             perms,
+            checkpointed: false,
             done: false,
             unsend: Default::default(),
             unsync: Default::default(),
@@ -324,7 +345,9 @@ impl Task {
                 self.vm_host
                     .start_fork(self.task_id, fork_request, suspended);
             }
-            TaskStart::StartEval { player, program } => {
+            TaskStart::StartEval {
+                player, program, ..
+            } => {
                 self.scheduled_start_time = None;
                 self.vm_host.start_eval(self.task_id, player, program);
             }
@@ -374,9 +397,9 @@ impl Task {
                     .world_state
                     .commit()
                     .expect("Could not commit world state before suspend");
-                if let CommitResult::ConflictRetry = commit_result {
-                    warn!("Conflict during commit before suspend");
-                    return Some(SchedulerControlMsg::TaskConflictRetry);
+                if let CommitResult::ConflictRetry(conflict) = commit_result {
+                    warn!(?conflict, "Conflict during commit before suspend");
+                    return Some(self.conflict_retry(conflict));
                 }
 
                 trace!(task_id = self.task_id, "Task suspended");
@@ -401,9 +424,9 @@ impl Task {
                     .world_state
                     .commit()
                     .expect("Could not commit world state before suspend");
-                if let CommitResult::ConflictRetry = commit_result {
-                    warn!("Conflict during commit before suspend");
-                    return Some(SchedulerControlMsg::TaskConflictRetry);
+                if let CommitResult::ConflictRetry(conflict) = commit_result {
+                    warn!(?conflict, "Conflict during commit before suspend");
+                    return Some(self.conflict_retry(conflict));
                 }
 
                 trace!(task_id = self.task_id, "Task suspended for input");
@@ -411,6 +434,48 @@ impl Task {
 
                 Some(SchedulerControlMsg::TaskRequestInput)
             }
+            VMHostResponse::Commit => {
+                trace!(task_id = self.task_id, "Task commit");
+
+                let commit_result = self
+                    .world_state
+                    .commit()
+                    .expect("Could not commit world state at checkpoint");
+                if let CommitResult::ConflictRetry(conflict) = commit_result {
+                    warn!(?conflict, "Conflict during commit at checkpoint");
+                    return Some(self.conflict_retry(conflict));
+                }
+                self.checkpointed = true;
+
+                // Output buffered so far belongs to what we just committed, so send it now.
+                if let Err(e) = self.session.commit() {
+                    warn!(task_id = self.task_id, error = ?e, "Could not commit session");
+                }
+
+                self.world_state = self
+                    .state_source
+                    .new_world_state()
+                    .expect("Unable to start new transaction");
+                self.vm_host.continue_execution(v_none());
+                None
+            }
+            VMHostResponse::Rollback => {
+                trace!(task_id = self.task_id, "Task rollback");
+
+                self.world_state
+                    .rollback()
+                    .expect("Could not rollback world state transaction");
+                if let Err(e) = self.session.rollback() {
+                    warn!(task_id = self.task_id, error = ?e, "Could not rollback session");
+                }
+
+                self.world_state = self
+                    .state_source
+                    .new_world_state()
+                    .expect("Unable to start new transaction");
+                self.vm_host.continue_execution(v_none());
+                None
+            }
             VMHostResponse::ContinueOk => {
                 self.done = false;
                 None
@@ -418,11 +483,14 @@ impl Task {
             VMHostResponse::CompleteSuccess(result) => {
                 trace!(task_id = self.task_id, result = ?result, "Task complete, success");
 
-                let CommitResult::Success =
+                if let CommitResult::ConflictRetry(conflict) =
                     self.world_state.commit().expect("Could not attempt commit")
-                else {
-                    warn!("Conflict during commit before complete, asking scheduler to retry task");
-                    return Some(SchedulerControlMsg::TaskConflictRetry);
+                {
+                    warn!(
+                        ?conflict,
+                        "Conflict during commit before complete, asking scheduler to retry task"
+                    );
+                    return Some(self.conflict_retry(conflict));
                 };
 
                 self.done = true;
@@ -486,20 +554,27 @@ impl Task {
                 self.world_state = state_source
                     .new_world_state()
                     .expect("Unable to start new transaction");
+                self.state_source = state_source;
                 self.scheduled_start_time = None;
                 self.vm_host.resume_execution(value);
                 None
             }
-            TaskControlMsg::Restart(state_source) => {
-                // Try. Again.
+            TaskControlMsg::Restart(state_source, backoff) => {
+                // Try. Again. But give whoever we conflicted with a chance to get out of the way
+                // first, and only then take our new snapshot.
                 debug!(
                     task_id = self.task_id,
+                    ?backoff,
                     "Restarting task, with new transaction"
                 );
+                std::thread::sleep(backoff);
                 self.world_state = state_source
                     .new_world_state()
                     .expect("Unable to start new transaction");
+                self.state_source = state_source;
                 self.scheduled_start_time = None;
+                self.checkpointed = false;
+                self.vm_host.reset();
                 self.setup_task_start(self.task_start.clone());
                 None
             }
@@ -514,6 +589,7 @@ impl Task {
                 self.world_state = state_source
                     .new_world_state()
                     .expect("Unable to start new transaction");
+                self.state_source = state_source;
                 self.scheduled_start_time = None;
                 self.vm_host.resume_execution(v_string(input));
                 None
@@ -554,6 +630,18 @@ impl Task {
         }
     }
 
+    /// Stop execution and build the message asking the scheduler to retry this task after a
+    /// commit conflict.
+    fn conflict_retry(&mut self, conflict: Option<ConflictInfo>) -> SchedulerControlMsg {
+        let verb = self.vm_host.active_verb();
+        self.vm_host.stop();
+        SchedulerControlMsg::TaskConflictRetry {
+            conflict,
+            verb,
+            restartable: !self.checkpointed || self.vm_host.retry_safe(),
+        }
+    }
+
     fn start_command(&mut self, player: Objid, command: &str) -> Option<SchedulerControlMsg> {
         // Command execution is a multi-phase process:
         //   1. Lookup $do_command. If we have the verb, execute it.
@@ -21,11 +21,11 @@ use std::sync::Arc;
 use kanal::OneshotSender;
 use moor_compiler::Program;
 
-use moor_values::model::{CommandError, NarrativeEvent};
+use moor_values::model::{CommandError, ConflictInfo, NarrativeEvent};
 use moor_values::model::{Perms, WorldStateSource};
 use moor_values::var::Objid;
 use moor_values::var::Var;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 #[derive(Debug, Clone)]
 pub enum TaskStart {
@@ -50,13 +50,20 @@ pub enum TaskStart {
         suspended: bool,
     },
     /// The scheduler is telling the task to evaluate a specific (MOO) program.
-    StartEval { player: Objid, program: Program },
+    /// If `read_only` is set, the caller expects the program not to modify the world, and the
+    /// task is run against a shared read-only snapshot.
+    StartEval {
+        player: Objid,
+        program: Program,
+        read_only: bool,
+    },
 }
 
 /// Messages sent to tasks from the scheduler to tell the task to do things.
 pub enum TaskControlMsg {
-    /// The scheduler is telling the task to restart itself in a new transaction.
-    Restart(Arc<dyn WorldStateSource>),
+    /// The scheduler is telling the task to restart itself in a new transaction, after waiting
+    /// for the given (backoff) delay.
+    Restart(Arc<dyn WorldStateSource>, Duration),
     /// The scheduler is telling the task to resume execution. Use the given world state
     /// (transaction) and permissions when doing so.
     Resume(Arc<dyn WorldStateSource>, Var),
@@ -79,8 +86,15 @@ pub enum SchedulerControlMsg {
     /// Everything executed. The task is done.
     TaskSuccess(Var),
     /// The task hit an unresolvable transaction serialization conflict, and needs to be restarted
-    /// in a new transaction.
-    TaskConflictRetry,
+    /// in a new transaction. Carries what the conflict was on (if known), and the verb which was
+    /// executing when the commit was attempted.
+    /// If `restartable` is false, part of the task's work has already been committed and the task
+    /// has not declared itself safe to re-run, so it should be aborted rather than restarted.
+    TaskConflictRetry {
+        conflict: Option<ConflictInfo>,
+        verb: Option<(Objid, String)>,
+        restartable: bool,
+    },
     /// A 'StartCommandVerb' type task failed to parse or match the command.
     TaskCommandError(CommandError),
     /// The verb to be executed was not found.
@@ -119,6 +133,9 @@ pub enum SchedulerControlMsg {
     },
     /// Task is requesting that a textdump checkpoint happen, to the configured file.
     Checkpoint,
+    /// Task is requesting an online backup of the database, into the configured directory. Told
+    /// E_INVARG if there's no directory configured.
+    Backup(OneshotSender<Var>),
     Notify {
         player: Objid,
         event: NarrativeEvent,
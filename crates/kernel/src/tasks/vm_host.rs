@@ -12,6 +12,7 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use crate::config::{MemoryLimits, TickCosts};
 use crate::tasks::command_parse::ParsedCommand;
 use crate::tasks::scheduler::AbortLimitReason;
 use crate::tasks::sessions::Session;
@@ -29,7 +30,7 @@ use moor_values::model::VerbInfo;
 use moor_values::model::WorldState;
 use moor_values::util::SliceRef;
 use moor_values::var::Objid;
-use moor_values::var::Var;
+use moor_values::var::{v_none, Var};
 use moor_values::AsByteBuffer;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
@@ -45,6 +46,10 @@ pub enum VMHostResponse {
     Suspend(Option<Duration>),
     /// Tell the task Johnny 5 needs input from the client (`read` invocation).
     SuspendNeedInput,
+    /// Tell the task to commit what it has so far, and carry on in a new transaction.
+    Commit,
+    /// Tell the task to throw away what it has so far, and carry on in a new transaction.
+    Rollback,
     /// Task timed out or exceeded ticks.
     AbortLimit(AbortLimitReason),
     /// Tell the task that execution has completed, and the task is successful.
@@ -67,6 +72,8 @@ pub struct VmHost {
     max_ticks: usize,
     /// The maximum amount of time allotted to this task
     max_time: Duration,
+    /// What the VM charges, in ticks, for the various things it does.
+    tick_costs: TickCosts,
     sessions: Arc<dyn Session>,
     scheduler_control_sender: Sender<(TaskId, SchedulerControlMsg)>,
     running: bool,
@@ -76,16 +83,20 @@ pub struct VmHost {
 }
 
 impl VmHost {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         task_id: TaskId,
         max_stack_depth: usize,
         max_ticks: usize,
         max_time: Duration,
+        tick_costs: TickCosts,
+        memory_limits: MemoryLimits,
         sessions: Arc<dyn Session>,
         scheduler_control_sender: Sender<(TaskId, SchedulerControlMsg)>,
     ) -> Self {
         let vm = VM::new();
-        let vm_exec_state = VMExecState::new(task_id);
+        let mut vm_exec_state = VMExecState::new(task_id);
+        vm_exec_state.memory_limits = memory_limits;
 
         // Created in an initial suspended state.
         Self {
@@ -94,6 +105,7 @@ impl VmHost {
             max_stack_depth,
             max_ticks,
             max_time,
+            tick_costs,
             sessions,
             scheduler_control_sender,
             running: false,
@@ -154,7 +166,11 @@ impl VmHost {
         self.vm_exec_state.start_time = Some(SystemTime::now());
         self.vm_exec_state.maximum_time = Some(self.max_time);
         self.vm_exec_state.tick_count = 0;
+        self.vm_exec_state.allocated_bytes = 0;
         self.vm_exec_state.task_id = task_id;
+        // Pass the task-local value on, along with the choice to keep passing it on.
+        self.vm_exec_state.inherit_task_local = fork_request.task_local.is_some();
+        self.vm_exec_state.task_local = fork_request.task_local.clone().unwrap_or_else(v_none);
         self.vm
             .exec_fork_vector(&mut self.vm_exec_state, fork_request);
         self.running = !suspended;
@@ -169,6 +185,7 @@ impl VmHost {
         self.vm_exec_state.start_time = Some(SystemTime::now());
         self.vm_exec_state.maximum_time = Some(self.max_time);
         self.vm_exec_state.tick_count = 0;
+        self.vm_exec_state.allocated_bytes = 0;
         self.vm_exec_state.task_id = task_id;
         self.vm
             .exec_call_request(&mut self.vm_exec_state, verb_execution_request);
@@ -180,6 +197,7 @@ impl VmHost {
         self.vm_exec_state.start_time = Some(SystemTime::now());
         self.vm_exec_state.maximum_time = Some(self.max_time);
         self.vm_exec_state.tick_count = 0;
+        self.vm_exec_state.allocated_bytes = 0;
         self.vm_exec_state.task_id = task_id;
         self.vm
             .exec_eval_request(&mut self.vm_exec_state, player, player, program);
@@ -197,6 +215,8 @@ impl VmHost {
         let exec_params = VmExecParams {
             scheduler_sender: self.scheduler_control_sender.clone(),
             max_stack_depth: self.max_stack_depth,
+            max_ticks: self.max_ticks,
+            tick_costs: self.tick_costs,
         };
 
         // Check existing ticks and seconds, and abort the task if we've exceeded the limits.
@@ -210,8 +230,9 @@ impl VmHost {
             }
         };
 
-        // Grant the loop its next tick slice.
-        self.vm_exec_state.tick_slice = self.max_ticks - self.vm_exec_state.tick_count;
+        // Grant the loop its next tick slice. The loop compares it against the task's running
+        // tick count, so it's the point at which to stop, not a number of ticks to run for.
+        self.vm_exec_state.tick_slice = self.max_ticks;
 
         let pre_exec_tick_count = self.vm_exec_state.tick_count;
 
@@ -283,6 +304,8 @@ impl VmHost {
                     let exec_params = VmExecParams {
                         max_stack_depth: self.max_stack_depth,
                         scheduler_sender: self.scheduler_control_sender.clone(),
+                        max_ticks: self.max_ticks,
+                        tick_costs: self.tick_costs,
                     };
                     // Ask the VM to execute the builtin function.
                     // This will push the result onto the stack.
@@ -306,6 +329,12 @@ impl VmHost {
                 ExecutionResult::NeedInput => {
                     return VMHostResponse::SuspendNeedInput;
                 }
+                ExecutionResult::Commit => {
+                    return VMHostResponse::Commit;
+                }
+                ExecutionResult::Rollback => {
+                    return VMHostResponse::Rollback;
+                }
                 ExecutionResult::Complete(a) => {
                     trace!(task_id, "Task completed");
                     return VMHostResponse::CompleteSuccess(a);
@@ -345,6 +374,23 @@ impl VmHost {
         trace!(task_id = self.vm_exec_state.task_id, "Resuming VMHost");
     }
 
+    /// Carry on after a commit or rollback checkpoint. Unlike `resume_execution` the task's
+    /// tick and time budget is not replenished.
+    pub fn continue_execution(&mut self, value: Var) {
+        self.vm_exec_state.top_mut().frame.push(value);
+        trace!(task_id = self.vm_exec_state.task_id, "Continuing VMHost");
+    }
+
+    /// Throw away the current execution stack, and anything the program set for the rest of the
+    /// task, so that the task can be started again from the top.
+    pub fn reset(&mut self) {
+        self.running = false;
+        self.vm_exec_state.stack.clear();
+        self.vm_exec_state.retry_safe = false;
+        self.vm_exec_state.task_local = v_none();
+        self.vm_exec_state.inherit_task_local = false;
+    }
+
     pub fn is_running(&self) -> bool {
         self.running
     }
@@ -377,6 +423,19 @@ impl VmHost {
     pub fn verb_definer(&self) -> Objid {
         self.vm_exec_state.top().verb_definer()
     }
+    /// The definer and name of the innermost (non-builtin) verb activation, if there is one.
+    pub fn active_verb(&self) -> Option<(Objid, String)> {
+        self.vm_exec_state
+            .stack
+            .iter()
+            .rev()
+            .find(|a| a.bf_index.is_none())
+            .map(|a| (a.verb_definer(), a.verb_name.clone()))
+    }
+    /// Whether the running program has declared the task safe to restart from the top.
+    pub fn retry_safe(&self) -> bool {
+        self.vm_exec_state.retry_safe
+    }
     pub fn this(&self) -> Objid {
         self.vm_exec_state.top().this
     }
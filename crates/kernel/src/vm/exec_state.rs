@@ -12,10 +12,12 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use crate::config::MemoryLimits;
 use crate::tasks::{PhantomUnsend, PhantomUnsync, TaskId};
 use crate::vm::activation::{Activation, Caller};
+use moor_values::var::Error::E_QUOTA;
 use moor_values::var::Objid;
-use moor_values::var::Var;
+use moor_values::var::{v_none, Error, Var, Variant};
 use moor_values::NOTHING;
 use std::time::{Duration, SystemTime};
 
@@ -38,6 +40,20 @@ pub struct VMExecState {
     pub(crate) start_time: Option<SystemTime>,
     /// The amount of time the task is allowed to run.
     pub(crate) maximum_time: Option<Duration>,
+    /// Whether the task has declared itself safe to restart from the beginning on conflict, even
+    /// after part of its work has been committed.
+    pub(crate) retry_safe: bool,
+    /// The caps on how big the values this task builds may get.
+    pub(crate) memory_limits: MemoryLimits,
+    /// Approximately how many bytes the task has allocated building values, since it last
+    /// measured what it actually holds, plus however much of that it's still holding beyond half
+    /// its cap.
+    pub(crate) allocated_bytes: usize,
+    /// The value set with `set_task_local`, visible from every verb the task calls. It lives
+    /// as long as the task does, including across suspensions.
+    pub(crate) task_local: Var,
+    /// Whether tasks forked from this one start with a copy of `task_local`.
+    pub(crate) inherit_task_local: bool,
 
     unsend: PhantomUnsend,
     unsync: PhantomUnsync,
@@ -52,6 +68,11 @@ impl VMExecState {
             start_time: None,
             tick_slice: 0,
             maximum_time: None,
+            retry_safe: false,
+            memory_limits: MemoryLimits::default(),
+            allocated_bytes: 0,
+            task_local: v_none(),
+            inherit_task_local: false,
             unsend: Default::default(),
             unsync: Default::default(),
         }
@@ -89,6 +110,65 @@ impl VMExecState {
         callers
     }
 
+    /// Account for `bytes` more allocated by the task, failing with E_QUOTA if that takes it past
+    /// its memory cap. What it holds is only measured now and then, so it can get up to half the
+    /// cap past it before that's noticed.
+    pub(crate) fn charge_memory(&mut self, bytes: usize) -> Result<(), Error> {
+        self.allocated_bytes = self.allocated_bytes.saturating_add(bytes);
+        if self.allocated_bytes > self.memory_limits.max_task_memory {
+            // Much of what was counted has probably been thrown away since. Start again from what
+            // the task is actually holding on to, and only fail if that's still too much.
+            let live_bytes = self.live_bytes().saturating_add(bytes);
+            if live_bytes > self.memory_limits.max_task_memory {
+                return Err(E_QUOTA);
+            }
+            // Measuring walks every frame, so don't do it again until at least another half of
+            // the cap has been allocated, or a task holding close to it would measure on every
+            // allocation.
+            self.allocated_bytes =
+                live_bytes.saturating_sub(self.memory_limits.max_task_memory / 2);
+        }
+        Ok(())
+    }
+
+    /// Roughly how many bytes of values the task holds right now: in its variables, value stacks
+    /// and arguments, and its task local value.
+    fn live_bytes(&self) -> usize {
+        let frames: usize = self
+            .stack
+            .iter()
+            .map(|a| {
+                let f = &a.frame;
+                f.environment
+                    .iter()
+                    .map(|(_, v)| var_size(v))
+                    .sum::<usize>()
+                    + f.valstack.iter().map(var_size).sum::<usize>()
+                    + a.args.iter().map(var_size).sum::<usize>()
+                    + var_size(&f.temp)
+            })
+            .sum();
+        frames + var_size(&self.task_local)
+    }
+
+    /// Check a list about to be built with `len` elements against the list size cap, and charge
+    /// for it.
+    pub(crate) fn charge_list(&mut self, len: usize, bytes: usize) -> Result<(), Error> {
+        if len > self.memory_limits.max_list_concat {
+            return Err(E_QUOTA);
+        }
+        self.charge_memory(bytes)
+    }
+
+    /// Check a string about to be built with `len` bytes against the string size cap, and charge
+    /// for it.
+    pub(crate) fn charge_string(&mut self, len: usize) -> Result<(), Error> {
+        if len > self.memory_limits.max_string_concat {
+            return Err(E_QUOTA);
+        }
+        self.charge_memory(len)
+    }
+
     #[inline]
     pub(crate) fn top_mut(&mut self) -> &mut Activation {
         self.stack.last_mut().expect("activation stack underflow")
@@ -167,3 +247,13 @@ impl VMExecState {
         max_time.checked_sub(elapsed)
     }
 }
+
+/// A rough estimate of the number of bytes taken up by a value, including what it points to.
+pub(crate) fn var_size(v: &Var) -> usize {
+    let own = std::mem::size_of::<Var>();
+    match v.variant() {
+        Variant::Str(s) => own + s.len(),
+        Variant::List(l) => own + l.iter().map(var_size).sum::<usize>(),
+        _ => own,
+    }
+}
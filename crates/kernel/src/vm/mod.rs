@@ -68,6 +68,9 @@ impl VM {
         vm.register_bf_objects();
         vm.register_bf_verbs();
         vm.register_bf_properties();
+        vm.register_bf_json();
+        vm.register_bf_pcre();
+        vm.register_bf_passwords();
 
         vm
     }
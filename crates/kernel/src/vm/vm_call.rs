@@ -235,6 +235,14 @@ impl VM {
         }
         let bf = self.builtins[bf_func_num].clone();
 
+        // Charge up front for what we can tell from the arguments, and don't even start if that
+        // blows the task's budget; it'll be aborted for running out of ticks.
+        let upfront_cost = bf.cost(args, None);
+        vm_state.tick_count += upfront_cost;
+        if vm_state.tick_count >= exec_args.max_ticks {
+            return ExecutionResult::More;
+        }
+
         debug!(
             "Calling builtin: {}({}) caller_perms: {}",
             BUILTIN_DESCRIPTORS[bf_func_num].name,
@@ -263,7 +271,15 @@ impl VM {
             scheduler_sender: exec_args.scheduler_sender.clone(),
         };
 
-        let call_results = match bf.call(&mut bf_args) {
+        let bf_result = bf.call(&mut bf_args);
+
+        // Then for anything more it turned out to cost, given its result.
+        if let Ok(BfRet::Ret(result)) = &bf_result {
+            let cost = bf.cost(&bf_args.args, Some(result));
+            vm_state.tick_count += cost.saturating_sub(upfront_cost);
+        }
+
+        let call_results = match bf_result {
             Ok(BfRet::Ret(result)) => {
                 self.unwind_stack(vm_state, FinallyReason::Return(result.clone()))
             }
@@ -309,7 +325,14 @@ impl VM {
             scheduler_sender: exec_args.scheduler_sender.clone(),
         };
 
-        match bf.call(&mut bf_args) {
+        let bf_result = bf.call(&mut bf_args);
+        if let Ok(BfRet::Ret(result)) = &bf_result {
+            let cost = bf.cost(&bf_args.args, Some(result));
+            let upfront_cost = bf.cost(&bf_args.args, None);
+            vm_state.tick_count += cost.saturating_sub(upfront_cost);
+        }
+
+        match bf_result {
             Ok(BfRet::Ret(result)) => {
                 self.unwind_stack(vm_state, FinallyReason::Return(result.clone()))
             }
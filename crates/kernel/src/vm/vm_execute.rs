@@ -18,6 +18,7 @@ use std::time::Duration;
 
 use moor_compiler::{Name, Offset};
 
+use crate::config::TickCosts;
 use crate::tasks::command_parse::ParsedCommand;
 use crate::tasks::sessions::Session;
 use crate::tasks::task_messages::SchedulerControlMsg;
@@ -33,6 +34,7 @@ use moor_values::var::{v_bool, v_empty_list, v_err, v_int, v_list, v_none, v_obj
 use moor_values::var::{v_listv, Error};
 
 use crate::vm::activation::{Activation, HandlerType};
+use crate::vm::exec_state::var_size;
 use crate::vm::vm_unwind::{FinallyReason, UncaughtException};
 use crate::vm::{VMExecState, VM};
 
@@ -57,12 +59,17 @@ pub struct Fork {
     /// The (optional) variable label where the task ID of the new task should be stored, in both
     /// the parent activation and the new task's activation.
     pub task_id: Option<Name>,
+    /// The forking task's task-local value, if it asked for it to be passed on to forks.
+    pub(crate) task_local: Option<Var>,
 }
 
 /// Represents the set of parameters passed to the VM for execution.
 pub struct VmExecParams {
     pub scheduler_sender: Sender<(TaskId, SchedulerControlMsg)>,
     pub max_stack_depth: usize,
+    /// The total number of ticks the task may use.
+    pub max_ticks: usize,
+    pub tick_costs: TickCosts,
 }
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum ExecutionResult {
@@ -107,6 +114,12 @@ pub enum ExecutionResult {
     Suspend(Option<Duration>),
     /// Request input from the client.
     NeedInput,
+    /// Request that the task commit its transaction (and session output) so far, and continue
+    /// executing in a new transaction.
+    Commit,
+    /// Request that the task throw away its transaction (and session output) so far, and continue
+    /// executing in a new transaction.
+    Rollback,
     /// Request `eval` execution, which is a kind of special activation creation where we've already
     /// been given the program to execute instead of having to look it up.
     PerformEval {
@@ -185,16 +198,15 @@ impl VM {
         let opcodes = state.top_mut().frame.program.main_vector.clone();
 
         while state.tick_count < state.tick_slice {
-            state.tick_count += 1;
+            // Otherwise, start poppin' opcodes.
+            // We panic here if we run out of opcodes, as that means there's a bug in either the
+            // compiler or in opcode execution.
+            let op = &opcodes[state.top().frame.pc];
+            state.tick_count += exec_params.tick_costs.op_cost(op);
 
             // Borrow the top of the activation stack for the lifetime of this execution.
             let a = state.top_mut();
             let f = &mut a.frame;
-
-            // Otherwise, start poppin' opcodes.
-            // We panic here if we run out of opcodes, as that means there's a bug in either the
-            // compiler or in opcode execution.
-            let op = &opcodes[f.pc];
             f.pc += 1;
 
             match op {
@@ -338,31 +350,53 @@ impl VM {
                 }
                 Op::ImmEmptyList => f.push(v_empty_list()),
                 Op::ListAddTail => {
-                    let (tail, list) = (f.pop(), f.peek_top_mut());
-                    let Variant::List(ref mut list) = list.variant_mut() else {
+                    let tail = f.pop();
+                    let Variant::List(list) = f.peek_top().variant() else {
                         f.pop();
                         return self.push_error(state, E_TYPE);
                     };
 
-                    // TODO: quota check SVO_MAX_LIST_CONCAT -> E_QUOTA in list add and append
+                    let len = list.len() + 1;
+                    if let Err(e) = state.charge_list(len, var_size(&tail)) {
+                        state.top_mut().frame.pop();
+                        return self.push_error(state, e);
+                    }
+
+                    let f = &mut state.top_mut().frame;
+                    let Variant::List(ref mut list) = f.peek_top_mut().variant_mut() else {
+                        unreachable!("list on top of stack changed type");
+                    };
                     let result = list.push(tail);
                     f.poke(0, result);
                 }
                 Op::ListAppend => {
-                    let (tail, list) = (f.pop(), f.peek_top_mut());
+                    let (tail, list) = (f.pop(), f.peek_top());
 
-                    let Variant::List(list) = list.variant_mut() else {
+                    let Variant::List(list) = list.variant() else {
                         f.pop();
 
                         return self.push_error(state, E_TYPE);
                     };
 
-                    let Variant::List(tail) = tail.take_variant() else {
+                    let Variant::List(tail_list) = tail.variant() else {
                         f.pop();
 
                         return self.push_error(state, E_TYPE);
                     };
 
+                    let len = list.len() + tail_list.len();
+                    if let Err(e) = state.charge_list(len, var_size(&tail)) {
+                        state.top_mut().frame.pop();
+                        return self.push_error(state, e);
+                    }
+
+                    let f = &mut state.top_mut().frame;
+                    let Variant::List(list) = f.peek_top_mut().variant_mut() else {
+                        unreachable!("list on top of stack changed type");
+                    };
+                    let Variant::List(tail) = tail.take_variant() else {
+                        unreachable!("appended list changed type");
+                    };
                     let new_list = list.append(tail);
                     f.poke(0, new_list);
                 }
@@ -441,6 +475,18 @@ impl VM {
                     binary_var_op!(self, f, state, div);
                 }
                 Op::Add => {
+                    // String concatenation is subject to the task's string size cap.
+                    let (rhs, lhs) = f.peek2();
+                    if let (Variant::Str(l), Variant::Str(r)) = (lhs.variant(), rhs.variant()) {
+                        let len = l.len() + r.len();
+                        if let Err(e) = state.charge_string(len) {
+                            let f = &mut state.top_mut().frame;
+                            f.pop();
+                            f.pop();
+                            return self.push_error(state, e);
+                        }
+                    }
+                    let f = &mut state.top_mut().frame;
                     binary_var_op!(self, f, state, add);
                 }
                 Op::Exp => {
@@ -629,6 +675,7 @@ impl VM {
                         activation: new_activation,
                         fork_vector_offset: *fv_offset,
                         task_id: *id,
+                        task_local: state.inherit_task_local.then(|| state.task_local.clone()),
                     };
                     return ExecutionResult::DispatchFork(fork);
                 }
@@ -24,15 +24,18 @@ mod tests {
     use moor_values::util::BitEnum;
     use moor_values::var::Error::E_DIV;
     use moor_values::var::Objid;
+    use moor_values::var::Variant;
     use moor_values::var::{
-        v_bool, v_empty_list, v_err, v_int, v_list, v_none, v_obj, v_objid, v_str, Var,
+        v_bool, v_empty_list, v_err, v_float, v_int, v_list, v_none, v_obj, v_objid, v_str, Var,
     };
 
     use moor_values::NOTHING;
     use moor_values::{AsByteBuffer, SYSTEM_OBJECT};
 
+    use crate::config::{MemoryLimits, TickCosts};
+    use crate::tasks::scheduler::AbortLimitReason;
     use crate::tasks::sessions::NoopClientSession;
-    use crate::tasks::vm_test_utils::call_verb;
+    use crate::tasks::vm_test_utils::{call_verb, call_verb_with_limits};
     use moor_compiler::compile;
     use moor_compiler::Names;
     use moor_compiler::Op;
@@ -514,6 +517,193 @@ mod tests {
         assert_eq!(result, v_int(5));
     }
 
+    #[test]
+    fn test_string_concat_quota() {
+        // Doubling a string eventually runs into the maximum string size, and E_QUOTA.
+        let program = r#"
+            s = "x";
+            try
+                while (1)
+                    s = s + s;
+                endwhile
+            except (E_QUOTA)
+                return length(s);
+            endtry
+        "#;
+        let mut state = world_with_test_program(program);
+        let session = Arc::new(NoopClientSession::new());
+        let result = call_verb(state.as_mut(), session, "test", vec![]);
+        assert_eq!(result, v_int(1 << 26));
+    }
+
+    #[test]
+    fn test_tick_costs_abort_loops() {
+        // Comfortably within 1000 ticks at a tick per opcode, but not at 20 a property read.
+        let program = "for i in [1..100] x = #0.test; endfor return 1;";
+        let mut state = world_with_test_program(program);
+        let session = Arc::new(NoopClientSession::new());
+        let result = call_verb_with_limits(
+            state.as_mut(),
+            session.clone(),
+            "test",
+            vec![],
+            1000,
+            TickCosts::default(),
+            MemoryLimits::default(),
+        );
+        assert_eq!(result, Ok(v_int(1)));
+
+        let costs = TickCosts {
+            property: 20,
+            ..TickCosts::default()
+        };
+        let result = call_verb_with_limits(
+            state.as_mut(),
+            session,
+            "test",
+            vec![],
+            1000,
+            costs,
+            MemoryLimits::default(),
+        );
+        assert!(matches!(result, Err(AbortLimitReason::Ticks(t)) if t >= 1000));
+    }
+
+    #[test]
+    fn test_builtin_cost_charged_by_arguments() {
+        // A 128KiB string costs index() 2048 ticks to search, on top of the few to build it.
+        let program = r#"
+            s = "x";
+            for i in [1..17]
+                s = s + s;
+            endfor
+            return index(s, "y");
+        "#;
+        let mut state = world_with_test_program(program);
+        let session = Arc::new(NoopClientSession::new());
+        let result = call_verb_with_limits(
+            state.as_mut(),
+            session.clone(),
+            "test",
+            vec![],
+            1000,
+            TickCosts::default(),
+            MemoryLimits::default(),
+        );
+        assert!(matches!(result, Err(AbortLimitReason::Ticks(t)) if t >= 2048));
+
+        let result = call_verb_with_limits(
+            state.as_mut(),
+            session,
+            "test",
+            vec![],
+            3000,
+            TickCosts::default(),
+            MemoryLimits::default(),
+        );
+        assert_eq!(result, Ok(v_int(0)));
+    }
+
+    #[test]
+    fn test_task_memory_counts_live_values() {
+        // 2MB of strings built, but never more than a couple of KB of them held at once...
+        let discarding = r#"
+            x = "xxxxxxxxxx";
+            x = x + x + x + x + x + x + x + x + x + x;
+            x = x + x + x + x + x + x + x + x + x + x;
+            for i in [1..2000]
+                s = x + tostr(i);
+            endfor
+            return length(s);
+        "#;
+        // ...unlike when they're all kept.
+        let keeping = r#"
+            x = "xxxxxxxxxx";
+            x = x + x + x + x + x + x + x + x + x + x;
+            x = x + x + x + x + x + x + x + x + x + x;
+            l = {};
+            try
+                for i in [1..2000]
+                    l = {@l, x + tostr(i)};
+                endfor
+            except (E_QUOTA)
+                return length(l);
+            endtry
+        "#;
+        let limits = MemoryLimits {
+            max_task_memory: 64 * 1024,
+            ..MemoryLimits::default()
+        };
+        let session = Arc::new(NoopClientSession::new());
+        let call = |program: &str| {
+            let mut state = world_with_test_program(program);
+            call_verb_with_limits(
+                state.as_mut(),
+                session.clone(),
+                "test",
+                vec![],
+                90_000,
+                TickCosts::default(),
+                limits,
+            )
+            .unwrap()
+        };
+        assert_eq!(call(discarding), v_int(1004));
+        let Variant::Int(kept) = call(keeping).variant().clone() else {
+            panic!("Keeping every string should have run out of memory");
+        };
+        assert!(kept > 0 && kept < 100);
+    }
+
+    #[test]
+    fn test_task_local_survives_verb_calls() {
+        let set_program = compile(r#"set_task_local({"request", 42}); return #0:get();"#).unwrap();
+        let get_program = compile("return task_local();").unwrap();
+        let state_source = test_db_with_verbs(&[("test", &set_program), ("get", &get_program)]);
+        let mut state = state_source.new_world_state().unwrap();
+        let session = Arc::new(NoopClientSession::new());
+        let result = call_verb(state.as_mut(), session, "test", vec![]);
+        assert_eq!(result, v_list(&[v_str("request"), v_int(42)]));
+    }
+
+    #[test]
+    fn test_hierarchy_builtins() {
+        let program = r#"
+            return {ancestors(#3), ancestors(#3, 1), descendants(#1), isa(#3, #1), isa(#1, #3),
+                    isa(#3, {#0, #2}, 1), locations(#3), locations(#3, #1), occupants(#1, #2)};
+        "#;
+        let mut state = world_with_test_program(program);
+
+        // #1 <- #2 <- #3, with #3 inside #2 inside #1.
+        let mut parent = NOTHING;
+        for _ in 0..3 {
+            let o = state
+                .create_object(SYSTEM_OBJECT, parent, SYSTEM_OBJECT, BitEnum::all())
+                .unwrap();
+            if parent != NOTHING {
+                state.move_object(SYSTEM_OBJECT, o, parent).unwrap();
+            }
+            parent = o;
+        }
+
+        let session = Arc::new(NoopClientSession::new());
+        let result = call_verb(state.as_mut(), session, "test", vec![]);
+        assert_eq!(
+            result,
+            v_list(&[
+                v_list(&[v_objid(Objid(2)), v_objid(Objid(1))]),
+                v_list(&[v_objid(Objid(3)), v_objid(Objid(2)), v_objid(Objid(1))]),
+                v_list(&[v_objid(Objid(2)), v_objid(Objid(3))]),
+                v_int(1),
+                v_int(0),
+                v_objid(Objid(2)),
+                v_list(&[v_objid(Objid(2)), v_objid(Objid(1))]),
+                v_list(&[v_objid(Objid(2))]),
+                v_list(&[v_objid(Objid(2))]),
+            ])
+        );
+    }
+
     #[test_case("return 1;", v_int(1); "simple return")]
     #[test_case(
         r#"rest = "me:words"; rest[1..0] = ""; return rest;"#,
@@ -604,6 +794,57 @@ mod tests {
         v_list(&[v_int(4), v_int(10)]); "for list loop")]
     #[test_case(r#"if (E_INVARG == (vi = `verb_info(#-1, "blerg") ! ANY')) return 666; endif return 333;"#, 
         v_int(666); "verb_info invalid object error")]
+    #[test_case("return sort({3, 1, 2});",
+        v_list(&[v_int(1), v_int(2), v_int(3)]); "sort")]
+    #[test_case(r#"return sort({"b", "a", "c"}, {2, 3, 1});"#,
+        v_list(&[v_str("c"), v_str("b"), v_str("a")]); "sort by keys")]
+    #[test_case(r#"return sort({"item10", "item2", "Item1"}, {}, 1);"#,
+        v_list(&[v_str("Item1"), v_str("item2"), v_str("item10")]); "sort natural")]
+    #[test_case("return sort({1, 2, 3}, {}, 0, 1);",
+        v_list(&[v_int(3), v_int(2), v_int(1)]); "sort reverse")]
+    #[test_case(r#"return {reverse({1, 2, 3}), reverse("abc")};"#,
+        v_list(&[v_list(&[v_int(3), v_int(2), v_int(1)]), v_str("cba")]); "reverse")]
+    #[test_case("return {slice({{1, 2}, {3, 4}}, 2), slice({{1, 2}, {3}}, {2, 1}, 0)};",
+        v_list(&[
+            v_list(&[v_int(2), v_int(4)]),
+            v_list(&[v_list(&[v_int(2), v_int(1)]), v_list(&[v_int(0), v_int(3)])]),
+        ]); "slice")]
+    #[test_case("return flatten({1, {2, {3}}, {}});",
+        v_list(&[v_int(1), v_int(2), v_int(3)]); "flatten")]
+    #[test_case(r#"return {complex_match("foo", {"foobar", "foo", "barfoo"}),
+                          complex_match("fo", {"foobar", "food"}),
+                          complex_match("2nd fo", {"foobar", "food"}),
+                          complex_match("zz", {"a"}),
+                          complex_match("bar", {"foo bar", "xbarx"}, {#1, #2})};"#,
+        v_list(&[v_str("foo"), v_obj(-2), v_str("food"), v_obj(-3), v_obj(1)]); "complex_match")]
+    #[test_case(r#"return {explode(" a  b "), explode("a,,b", ",", 1)};"#,
+        v_list(&[
+            v_list(&[v_str("a"), v_str("b")]),
+            v_list(&[v_str("a"), v_str(""), v_str("b")]),
+        ]); "explode")]
+    #[test_case(r#"return {upcase("straße"), capitalize("émile"), trim("  x  "), rtrim("x--", "-")};"#,
+        v_list(&[v_str("STRASSE"), v_str("Émile"), v_str("x"), v_str("x")]); "case and trim")]
+    #[test_case(r#"return {starts_with("Foobar", "foo"), ends_with("Foobar", "BAR", 1)};"#,
+        v_list(&[v_int(1), v_int(0)]); "affixes")]
+    #[test_case(r#"return format("{} has {2} {1}s", "cat", 3);"#,
+        v_str("cat has 3 cats"); "format")]
+    #[test_case(r#"return {encode_base64("hi~0A"), decode_base64("aGkK"), string_hash("abc", "sha256"),
+                          hmac("sha256", "key", "The quick brown fox jumps over the lazy dog")};"#,
+        v_list(&[
+            v_str("aGkK"),
+            v_str("hi~0A"),
+            v_str("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            v_str("f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"),
+        ]); "encoding and digests")]
+    #[test_case(r#"return hmac("sha256", "~00~FF", "a~0Ab");"#,
+        v_str("4c07827d2826d91a3a4979a0986d4ab0336df38e6f7dcf89762d5ff6d24f2d5f"); "hmac of binary strings")]
+    #[test_case(r#"return value_hash({1, "a"}) == string_hash(toliteral({1, "a"}));"#,
+        v_int(1); "value_hash")]
+    #[test_case("return {bitand(12, 10), bitor(12, 10), bitxor(12, 10), bitnot(0), bitshl(1, 4), bitshr(-1, 60)};",
+        v_list(&[v_int(8), v_int(14), v_int(6), v_int(-1), v_int(16), v_int(15)]); "bitwise")]
+    #[test_case("return {random(3, 3), random(1), distance({0, 0}, {3, 4.0}), relative_heading({0, 0, 0}, {0, 1, 0})};",
+        v_list(&[v_int(3), v_int(1), v_float(5.0), v_list(&[v_int(90), v_int(0)])]); "random and geometry")]
+    #[test_case("return task_local();", v_int(0); "task_local unset")]
     fn test_run(program: &str, expected_result: Var) {
         let mut state = world_with_test_program(program);
         let session = Arc::new(NoopClientSession::new());
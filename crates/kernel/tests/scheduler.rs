@@ -0,0 +1,323 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Transaction control from MOO code (`commit()`, `rollback_to_start()`, `set_task_retry_safe()`)
+//! and what the scheduler does with tasks which conflict, run through the scheduler for real.
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::sync::{Arc, Mutex};
+
+    use uuid::Uuid;
+
+    use moor_db::odb::RelBoxWorldState;
+    use moor_db::Database;
+    use moor_kernel::config::Config;
+    use moor_kernel::tasks::scheduler::{Scheduler, SchedulerError, TaskWaiterResult};
+    use moor_kernel::tasks::sessions::{Session, SessionError};
+    use moor_kernel::textdump::textdump_load;
+    use moor_values::model::{CommitResult, NarrativeEvent, WorldStateSource};
+    use moor_values::util::BitEnum;
+    use moor_values::var::Error::E_INVARG;
+    use moor_values::var::{v_err, v_int, v_list, Objid, Var};
+    use moor_values::SYSTEM_OBJECT;
+
+    const WIZARD: Objid = Objid(3);
+
+    /// A session whose `connected_players()` doesn't return, the first time it's called, until
+    /// the test says so. Tasks call it to wait at a known point in their transaction while the
+    /// test commits something underneath them.
+    struct GatedSession {
+        gate: Mutex<Option<(Sender<()>, Receiver<()>)>>,
+    }
+
+    /// The test's side of the gate: told when a task arrives, and lets it through.
+    struct Gate {
+        arrived: Receiver<()>,
+        release: Sender<()>,
+    }
+
+    impl Gate {
+        fn wait_for_task(&self) {
+            self.arrived.recv().expect("Task never reached the gate");
+        }
+        fn release(&self) {
+            self.release.send(()).expect("Task went away at the gate");
+        }
+    }
+
+    fn gated_session() -> (Arc<GatedSession>, Gate) {
+        let (arrived_send, arrived) = channel();
+        let (release, release_receive) = channel();
+        let session = GatedSession {
+            gate: Mutex::new(Some((arrived_send, release_receive))),
+        };
+        (Arc::new(session), Gate { arrived, release })
+    }
+
+    impl Session for GatedSession {
+        fn commit(&self) -> Result<(), SessionError> {
+            Ok(())
+        }
+        fn rollback(&self) -> Result<(), SessionError> {
+            Ok(())
+        }
+        fn fork(self: Arc<Self>) -> Result<Arc<dyn Session>, SessionError> {
+            Ok(self.clone())
+        }
+        fn request_input(&self, _player: Objid, _request_id: Uuid) -> Result<(), SessionError> {
+            Ok(())
+        }
+        fn send_event(&self, _player: Objid, _event: NarrativeEvent) -> Result<(), SessionError> {
+            Ok(())
+        }
+        fn send_system_msg(&self, _player: Objid, _msg: &str) -> Result<(), SessionError> {
+            Ok(())
+        }
+        fn shutdown(&self, _msg: Option<String>) -> Result<(), SessionError> {
+            Ok(())
+        }
+        fn connection_name(&self, player: Objid) -> Result<String, SessionError> {
+            Ok(format!("player-{}", player.0))
+        }
+        fn disconnect(&self, _player: Objid) -> Result<(), SessionError> {
+            Ok(())
+        }
+        fn connected_players(&self) -> Result<Vec<Objid>, SessionError> {
+            let gate = self.gate.lock().unwrap().take();
+            if let Some((arrived, release)) = gate {
+                arrived.send(()).unwrap();
+                release.recv().unwrap();
+            }
+            Ok(vec![])
+        }
+        fn connected_seconds(&self, _player: Objid) -> Result<f64, SessionError> {
+            Ok(0.0)
+        }
+        fn idle_seconds(&self, _player: Objid) -> Result<f64, SessionError> {
+            Ok(0.0)
+        }
+    }
+
+    /// A database with the minimal core in it, plus `#0.counter` and `#0.marker`, both 0.
+    fn test_db() -> Arc<dyn Database + Send + Sync> {
+        let (db, _) = RelBoxWorldState::open(None, 1 << 30);
+        let db: Arc<dyn Database + Send + Sync> = Arc::new(db);
+        let loader = db.clone().loader_client().unwrap();
+        let minimal_db = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/Minimal.db");
+        textdump_load(loader.clone(), minimal_db).expect("Could not load textdump");
+        assert_eq!(loader.commit().unwrap(), CommitResult::Success);
+
+        let mut tx = source(&db).new_world_state().unwrap();
+        for name in ["counter", "marker"] {
+            tx.define_property(
+                WIZARD,
+                SYSTEM_OBJECT,
+                SYSTEM_OBJECT,
+                name,
+                WIZARD,
+                BitEnum::new(),
+                Some(v_int(0)),
+            )
+            .unwrap();
+        }
+        assert_eq!(tx.commit().unwrap(), CommitResult::Success);
+        db
+    }
+
+    fn source(db: &Arc<dyn Database + Send + Sync>) -> Arc<dyn WorldStateSource> {
+        db.clone().world_state_source().unwrap()
+    }
+
+    fn property(db: &Arc<dyn Database + Send + Sync>, name: &str) -> Var {
+        let mut tx = source(db).new_world_state().unwrap();
+        let value = tx.retrieve_property(WIZARD, SYSTEM_OBJECT, name).unwrap();
+        tx.rollback().unwrap();
+        value
+    }
+
+    fn set_property(db: &Arc<dyn Database + Send + Sync>, name: &str, value: Var) {
+        let mut tx = source(db).new_world_state().unwrap();
+        tx.update_property(WIZARD, SYSTEM_OBJECT, name, &value)
+            .unwrap();
+        assert_eq!(tx.commit().unwrap(), CommitResult::Success);
+    }
+
+    fn start_scheduler(db: Arc<dyn Database + Send + Sync>) -> Arc<Scheduler> {
+        let scheduler = Arc::new(Scheduler::new(db, Config::default()));
+        let loop_scheduler = scheduler.clone();
+        std::thread::spawn(move || loop_scheduler.run());
+        scheduler
+    }
+
+    /// Run `code` as an eval task, which stops at the gate once (by calling `connected_players()`).
+    /// While it's there, `at_gate` is run, then the task is let go, and its result returned.
+    fn run_gated<F: FnOnce()>(
+        scheduler: &Arc<Scheduler>,
+        code: &str,
+        at_gate: F,
+    ) -> TaskWaiterResult {
+        let (session, gate) = gated_session();
+        let task_id = scheduler
+            .submit_eval_task(WIZARD, WIZARD, code.to_string(), false, session)
+            .unwrap();
+        gate.wait_for_task();
+        let result = scheduler.subscribe_to_task(task_id).unwrap();
+        at_gate();
+        gate.release();
+        result.recv().unwrap()
+    }
+
+    fn success(result: TaskWaiterResult) -> Var {
+        match result {
+            TaskWaiterResult::Success(value) => value,
+            TaskWaiterResult::Error(e) => panic!("Task failed: {:?}", e),
+        }
+    }
+
+    // The task's increment conflicts with the one made while it waits, so it's re-run, and then
+    // builds on it.
+    const INCREMENT: &str = "x = #0.counter; connected_players(); #0.counter = x + 1; return x;";
+
+    #[test]
+    fn conflicting_task_is_retried() {
+        let db = test_db();
+        let scheduler = start_scheduler(db.clone());
+
+        let result = run_gated(&scheduler, INCREMENT, || {
+            set_property(&db, "counter", v_int(10))
+        });
+        assert_eq!(success(result), v_int(10));
+        assert_eq!(property(&db, "counter"), v_int(11));
+        assert_eq!(scheduler.conflict_counters().retries, 1);
+        scheduler.stop().unwrap();
+    }
+
+    // Suspending commits, but the task can still be re-run from the top.
+    #[test]
+    fn suspended_task_is_retried() {
+        let db = test_db();
+        let scheduler = start_scheduler(db.clone());
+
+        let code = format!("suspend(0); {}", INCREMENT);
+        let result = run_gated(&scheduler, &code, || {
+            set_property(&db, "counter", v_int(10))
+        });
+        assert_eq!(success(result), v_int(10));
+        assert_eq!(property(&db, "counter"), v_int(11));
+        scheduler.stop().unwrap();
+    }
+
+    // What the task did before `commit()` is visible to others straight away, and what it does
+    // after isn't until it's done.
+    #[test]
+    fn commit_is_durable_immediately() {
+        let db = test_db();
+        let scheduler = start_scheduler(db.clone());
+
+        let code = "#0.counter = 5; commit(); #0.marker = 1; connected_players(); return 0;";
+        let result = run_gated(&scheduler, code, || {
+            assert_eq!(property(&db, "counter"), v_int(5));
+            assert_eq!(property(&db, "marker"), v_int(0));
+        });
+        assert_eq!(success(result), v_int(0));
+        assert_eq!(property(&db, "marker"), v_int(1));
+        scheduler.stop().unwrap();
+    }
+
+    #[test]
+    fn rollback_to_start_discards_changes() {
+        let db = test_db();
+        let scheduler = start_scheduler(db.clone());
+
+        let code = "#0.counter = 5; rollback_to_start(); connected_players(); return #0.counter;";
+        let result = run_gated(&scheduler, code, || {});
+        assert_eq!(success(result), v_int(0));
+        assert_eq!(property(&db, "counter"), v_int(0));
+        scheduler.stop().unwrap();
+    }
+
+    // Re-running a task which has committed part of its work would do that part twice, so it's
+    // aborted instead, leaving what it committed.
+    #[test]
+    fn conflict_after_commit_aborts() {
+        let db = test_db();
+        let scheduler = start_scheduler(db.clone());
+
+        let code = format!("#0.marker = #0.marker + 1; commit(); {}", INCREMENT);
+        let result = run_gated(&scheduler, &code, || {
+            set_property(&db, "counter", v_int(10))
+        });
+        assert!(matches!(
+            result,
+            TaskWaiterResult::Error(SchedulerError::TaskAbortedConflict(0))
+        ));
+        assert_eq!(property(&db, "marker"), v_int(1));
+        assert_eq!(property(&db, "counter"), v_int(10));
+        assert_eq!(scheduler.conflict_counters().abandoned, 1);
+        scheduler.stop().unwrap();
+    }
+
+    // Unless it's said that's fine.
+    #[test]
+    fn retry_safe_task_is_retried_after_commit() {
+        let db = test_db();
+        let scheduler = start_scheduler(db.clone());
+
+        let code = format!(
+            "set_task_retry_safe(1); #0.marker = #0.marker + 1; commit(); {}",
+            INCREMENT
+        );
+        let result = run_gated(&scheduler, &code, || {
+            set_property(&db, "counter", v_int(10))
+        });
+        assert_eq!(success(result), v_int(10));
+        assert_eq!(property(&db, "marker"), v_int(2));
+        assert_eq!(property(&db, "counter"), v_int(11));
+        scheduler.stop().unwrap();
+    }
+
+    // A restarted task starts over without the task local value or retry safety the first run
+    // set.
+    #[test]
+    fn retried_task_starts_afresh() {
+        let db = test_db();
+        let scheduler = start_scheduler(db.clone());
+
+        let code = format!(
+            "before = {{task_local(), set_task_retry_safe(1)}}; set_task_local(\"stale\"); {} return before;",
+            INCREMENT.trim_end_matches(" return x;")
+        );
+        let result = run_gated(&scheduler, &code, || {
+            set_property(&db, "counter", v_int(10))
+        });
+        assert_eq!(success(result), v_list(&[v_int(0), v_int(0)]));
+        assert_eq!(property(&db, "counter"), v_int(11));
+        scheduler.stop().unwrap();
+    }
+
+    // There's nowhere for a backup to go unless the server was given somewhere.
+    #[test]
+    fn backup_without_backup_dir_fails() {
+        let db = test_db();
+        let scheduler = start_scheduler(db.clone());
+
+        let code = "connected_players(); return `backup_database() ! ANY';";
+        let result = run_gated(&scheduler, code, || {});
+        assert_eq!(success(result), v_err(E_INVARG));
+        scheduler.stop().unwrap();
+    }
+}
@@ -17,7 +17,7 @@
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use moor_rdb::index::{AttrType, IndexType};
-use moor_rdb::{RelBox, RelationInfo};
+use moor_rdb::{RelBox, RelationInfo, SyncPolicy, WalConfig};
 use moor_values::util::SliceRef;
 use std::rc::Rc;
 use std::sync::Arc;
@@ -31,22 +31,23 @@ use moor_values::util::{BitArray, Bitset64};
 #[path = "../tests/test-support.rs"]
 mod support;
 
-/// Build a test database with a bunch of relations
-fn test_db() -> Arc<RelBox> {
-    // Generate the test relations that we'll use for testing.
-    let relations = (0..63)
+fn test_relations(index_type: IndexType) -> Vec<RelationInfo> {
+    (0..63)
         .map(|i| RelationInfo {
             name: format!("relation_{}", i),
             domain_type: AttrType::Integer,
             codomain_type: AttrType::Integer,
             secondary_indexed: false,
             unique_domain: true,
-            index_type: IndexType::AdaptiveRadixTree,
+            index_type,
             codomain_index_type: None,
         })
-        .collect::<Vec<_>>();
+        .collect()
+}
 
-    RelBox::new(1 << 24, None, &relations, 0)
+/// Build a test database with a bunch of relations
+fn test_db(index_type: IndexType) -> Arc<RelBox> {
+    RelBox::new(1 << 24, None, &test_relations(index_type), 0, 0)
 }
 
 fn from_val(value: i64) -> SliceRef {
@@ -64,11 +65,11 @@ fn load_history() -> Vec<History> {
     events.collect::<Vec<_>>()
 }
 
-fn list_append_scan_workload(iters: u64, events: &Vec<History>) -> Duration {
+fn list_append_scan_workload(iters: u64, events: &Vec<History>, index_type: IndexType) -> Duration {
     let mut cumulative = Duration::new(0, 0);
     for _ in 0..iters {
         // We create a brand new db for each iteration, so we have a clean slate.
-        let db = test_db();
+        let db = test_db(index_type);
 
         // Where to track the transactions running.
         let mut processes: BitArray<_, 256, Bitset64<8>> = BitArray::new();
@@ -123,11 +124,11 @@ fn list_append_scan_workload(iters: u64, events: &Vec<History>) -> Duration {
 }
 
 /// Same as above, but instead of predicate scan, does an individual tuple lookup, to measure that.
-fn list_append_seek_workload(iters: u64, events: &Vec<History>) -> Duration {
+fn list_append_seek_workload(iters: u64, events: &Vec<History>, index_type: IndexType) -> Duration {
     let mut cumulative = Duration::new(0, 0);
     for _ in 0..iters {
         // We create a brand new db for each iteration, so we have a clean slate.
-        let db = test_db();
+        let db = test_db(index_type);
 
         // Where to track the transactions running.
         let mut processes: BitArray<_, 256, Bitset64<8>> = BitArray::new();
@@ -197,14 +198,78 @@ pub fn throughput_bench(c: &mut Criterion) {
     group.sample_size(1000);
     group.measurement_time(Duration::from_secs(10));
     group.throughput(criterion::Throughput::Elements(tx_count as u64));
-    group.bench_function("list_append_scan", |b| {
-        b.iter_custom(|iters| list_append_scan_workload(iters, &events));
-    });
-    group.bench_function("list_append_seek", |b| {
-        b.iter_custom(|iters| list_append_seek_workload(iters, &events));
+    for (index_name, index_type) in [
+        ("art", IndexType::AdaptiveRadixTree),
+        ("hash", IndexType::Hash),
+        ("imhash", IndexType::ImHash),
+    ] {
+        group.bench_function(format!("list_append_scan_{}", index_name), |b| {
+            b.iter_custom(|iters| list_append_scan_workload(iters, &events, index_type));
+        });
+        group.bench_function(format!("list_append_seek_{}", index_name), |b| {
+            b.iter_custom(|iters| list_append_seek_workload(iters, &events, index_type));
+        });
+    }
+    group.finish();
+}
+
+/// Commit `iters` single-tuple transactions from each of `threads` threads against an on-disk
+/// database, so every commit has to go through the write-ahead log.
+fn durable_commit_workload(iters: u64, threads: u64, sync_policy: SyncPolicy) -> Duration {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let relations = test_relations(IndexType::AdaptiveRadixTree);
+    let wal_config = WalConfig {
+        sync_policy,
+        archive: None,
+    };
+    let db =
+        RelBox::new_with_wal_config(1 << 30, tmpdir.path().into(), wal_config, &relations, 0, 0)
+            .unwrap();
+
+    let start = Instant::now();
+    std::thread::scope(|s| {
+        for t in 0..threads {
+            let db = db.clone();
+            s.spawn(move || {
+                for i in 0..iters {
+                    let tx = db.clone().start_tx();
+                    let value = (t * iters + i) as i64;
+                    tx.relation(RelationId(t as usize))
+                        .insert_tuple(from_val(value), from_val(value))
+                        .unwrap();
+                    tx.commit().unwrap();
+                }
+            });
+        }
     });
+    // Shutting down waits for the writer to finish, so everything's on disk by the time we stop.
+    db.shutdown();
+    start.elapsed()
+}
+
+// Measure durable commits per second, serially and with several concurrent committers.
+pub fn durable_commit_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("durable_commit");
+    group.measurement_time(Duration::from_secs(10));
+    let policies = [
+        ("per_commit", SyncPolicy::PerCommit),
+        ("batched", SyncPolicy::Batched),
+        (
+            "interval_10ms",
+            SyncPolicy::Interval(Duration::from_millis(10)),
+        ),
+    ];
+    for (policy_name, sync_policy) in policies {
+        for threads in [1, 4] {
+            group.throughput(criterion::Throughput::Elements(threads));
+            group.bench_function(
+                format!("insert_commit_{}_{}_threads", policy_name, threads),
+                |b| b.iter_custom(|iters| durable_commit_workload(iters, threads, sync_policy)),
+            );
+        }
+    }
     group.finish();
 }
 
-criterion_group!(benches, throughput_bench);
+criterion_group!(benches, throughput_bench, durable_commit_bench);
 criterion_main!(benches);
@@ -13,6 +13,7 @@
 //
 
 use std::collections::HashSet;
+use std::ops::Bound;
 use tracing::error;
 
 use moor_values::util::SliceRef;
@@ -123,6 +124,27 @@ impl BaseRelation {
             .collect())
     }
 
+    /// Return the tuples whose domains fall within the given bounds, in domain order, up to
+    /// `limit` of them. Requires an ordered domain index.
+    pub fn scan_range(
+        &self,
+        lo: Bound<&SliceRef>,
+        hi: Bound<&SliceRef>,
+        limit: Option<usize>,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        Ok(self
+            .domain_index
+            .seek_range(lo, hi)?
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|id| {
+                self.tuples
+                    .get(&id)
+                    .expect("missing tuple for indexed id")
+                    .clone()
+            })
+            .collect())
+    }
+
     pub fn predicate_scan<F: Fn(&TupleRef) -> bool>(&self, f: &F) -> HashSet<TupleRef> {
         self.tuples.values().filter(|t| f(t)).cloned().collect()
     }
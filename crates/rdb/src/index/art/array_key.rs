@@ -54,7 +54,6 @@ impl<const N: usize> ArrayKey<N> {
         &self.data
     }
 
-    #[allow(dead_code)]
     pub fn as_slice(&self) -> &[u8] {
         &self.data[..self.len]
     }
@@ -199,7 +198,7 @@ macro_rules! impl_from_signed {
         impl<const N: usize> From<$t> for ArrayKey<N> {
             fn from(val: $t) -> Self {
                 let v: $tu = unsafe { mem::transmute(val) };
-                let xor = 1 << (std::mem::size_of::<$tu>() - 1);
+                let xor = 1 << (<$tu>::BITS - 1);
                 let i = (v ^ xor) & xor;
                 let j = i | (v & (<$tu>::MAX >> 1));
                 ArrayKey::new_from_slice(j.to_be_bytes().as_ref())
@@ -247,4 +246,13 @@ mod test {
         let k: ArrayKey<16> = 123213123123123u64.into();
         assert_eq!(k.to_be_u64(), 123213123123123u64);
     }
+
+    #[test]
+    fn signed_keys_are_distinct_and_ordered() {
+        let values = [i64::MIN, -129, -128, -1, 0, 1, 127, 128, 129, i64::MAX];
+        let keys: Vec<ArrayKey<16>> = values.iter().map(|v| v.into()).collect();
+        for pair in keys.windows(2) {
+            assert!(pair[0].as_slice() < pair[1].as_slice());
+        }
+    }
 }
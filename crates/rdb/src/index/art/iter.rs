@@ -12,6 +12,8 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use std::cmp::Ordering;
+
 use super::{node::Node, KeyTrait, Partial};
 
 type IterEntry<'a, P, V> = (u8, &'a Node<P, V>);
@@ -71,6 +73,84 @@ impl<'a, K: KeyTrait<PartialType = P> + 'a, P: Partial + Clone + 'a, V: Clone> I
     }
 }
 
+impl<'a, K: KeyTrait<PartialType = P> + 'a, P: Partial + Clone + 'a, V: Clone> Iter<'a, K, P, V> {
+    /// Iterate over the entries with keys from `lo` onwards, descending straight to where it would
+    /// be rather than walking past everything before it.
+    pub fn new_from(node: Option<&'a Node<P, V>>, lo: &K) -> Self {
+        let Some(root_node) = node else {
+            return Self::new(None);
+        };
+        Self {
+            inner: Box::new(IterInner::<K, P, V>::new_from(root_node, lo)),
+            _marker: Default::default(),
+        }
+    }
+}
+
+impl<'a, K: KeyTrait<PartialType = P>, P: Partial + Clone + 'a, V: Clone> IterInner<'a, K, P, V> {
+    pub fn new_from(root: &'a Node<P, V>, lo: &K) -> Self {
+        let mut iter = Self {
+            node_iter_stack: vec![],
+            cur_key: K::new_from_slice(&[]),
+        };
+
+        // Follow `lo` down the tree. At each inner node on its path, the children after the one it
+        // goes through come after it, so are left on the stack for once that one's done. Where it
+        // leaves the tree, the node there is either wholly before it, or wholly from it onwards.
+        let mut node = root;
+        let mut depth = 0;
+        loop {
+            match Self::compare_to_key(node, lo, depth) {
+                Ordering::Less => break,
+                Ordering::Greater => {
+                    iter.node_iter_stack
+                        .push((depth, Box::new(std::iter::once((0, node)))));
+                    break;
+                }
+                Ordering::Equal if node.is_leaf() => {
+                    iter.node_iter_stack
+                        .push((depth, Box::new(std::iter::once((0, node)))));
+                    break;
+                }
+                Ordering::Equal => {
+                    depth += node.prefix.len();
+                    let next = lo.at(depth);
+                    iter.node_iter_stack
+                        .push((depth, Box::new(node.iter().filter(move |(k, _)| *k > next))));
+                    iter.cur_key = iter.cur_key.extend_from_partial(&node.prefix);
+                    let Some(child) = node.seek_child(next) else {
+                        break;
+                    };
+                    node = child;
+                }
+            }
+        }
+        iter
+    }
+
+    /// How the keys under `node`, whose prefix starts `depth` bytes into them, compare with `key`:
+    /// all before it, all after it, or (for an inner node) matching it as far as its prefix goes,
+    /// or (for a leaf) equal to it.
+    fn compare_to_key(node: &Node<P, V>, key: &K, depth: usize) -> Ordering {
+        let key_len = key.length_at(0);
+        for i in 0..node.prefix.len() {
+            if depth + i >= key_len {
+                return Ordering::Greater;
+            }
+            match node.prefix.at(i).cmp(&key.at(depth + i)) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+        let key_remaining = depth + node.prefix.len() < key_len;
+        match (node.is_leaf(), key_remaining) {
+            (true, true) => Ordering::Less,
+            (false, false) => Ordering::Greater,
+            _ => Ordering::Equal,
+        }
+    }
+}
+
 impl<'a, K: KeyTrait<PartialType = P>, P: Partial + Clone + 'a, V: Clone> Iterator
     for Iter<'a, K, P, V>
 {
@@ -97,6 +97,11 @@ impl<KeyType: KeyTrait, ValueType: Clone> AdaptiveRadixTree<KeyType, ValueType>
         Iter::new(self.root.as_ref())
     }
 
+    /// Iterate, in key order, over the entries with keys from `lo` onwards.
+    pub fn iter_from(&self, lo: &KeyType) -> Iter<'_, KeyType, KeyType::PartialType, ValueType> {
+        Iter::new_from(self.root.as_ref(), lo)
+    }
+
     pub fn is_empty(&self) -> bool {
         self.root.is_none()
     }
@@ -172,7 +172,7 @@ macro_rules! impl_from_signed {
         impl From<$t> for VectorKey {
             fn from(val: $t) -> Self {
                 let v: $tu = unsafe { mem::transmute(val) };
-                let xor = 1 << (std::mem::size_of::<$tu>() - 1);
+                let xor = 1 << (<$tu>::BITS - 1);
                 let i = (v ^ xor) & xor;
                 let j = i | (v & (<$tu>::MAX >> 1));
                 VectorKey::new_from_slice(&j.to_be_bytes())
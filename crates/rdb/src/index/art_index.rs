@@ -12,11 +12,12 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use crate::index::{AdaptiveRadixTree, ArrayKey, AttrType, Index};
+use crate::index::{map_bounds, AdaptiveRadixTree, ArrayKey, AttrType, Index};
 use crate::tuples::TupleId;
 use crate::{IndexType, RelationError};
 use moor_values::util::SliceRef;
 use std::collections::HashSet;
+use std::ops::Bound;
 use tracing::error;
 
 /// Adaptive Radix Tree index for when the keys are fixed-length values.
@@ -118,6 +119,37 @@ impl Index for ArtArrayIndex {
         }))
     }
 
+    fn seek_range(
+        &self,
+        lo: Bound<&SliceRef>,
+        hi: Bound<&SliceRef>,
+    ) -> Result<Box<dyn Iterator<Item = TupleId> + '_>, RelationError> {
+        // Keys are encoded so that their byte order is their numeric order.
+        let (lo, hi) = map_bounds(lo, hi, |v| self.to_attr_key(v))?;
+        let from = match &lo {
+            Bound::Included(lo) | Bound::Excluded(lo) => self.index.iter_from(lo),
+            Bound::Unbounded => self.index.iter(),
+        };
+        let above_lo = move |k: &ArrayKey<16>| match &lo {
+            Bound::Included(lo) => k.as_slice() >= lo.as_slice(),
+            Bound::Excluded(lo) => k.as_slice() > lo.as_slice(),
+            Bound::Unbounded => true,
+        };
+        let below_hi = move |k: &ArrayKey<16>| match &hi {
+            Bound::Included(hi) => k.as_slice() <= hi.as_slice(),
+            Bound::Excluded(hi) => k.as_slice() < hi.as_slice(),
+            Bound::Unbounded => true,
+        };
+        Ok(Box::new(Iter {
+            iter: Box::new(
+                // Only a key equal to an excluded lower bound is skipped.
+                from.skip_while(move |(k, _)| !above_lo(k))
+                    .take_while(move |(k, _)| below_hi(k))
+                    .flat_map(|(_, set)| set.iter().cloned()),
+            ),
+        }))
+    }
+
     fn index_tuple(&mut self, key: &SliceRef, tuple_id: TupleId) -> Result<(), RelationError> {
         let attr_key = self.to_attr_key(key)?;
 
@@ -138,17 +170,22 @@ impl Index for ArtArrayIndex {
     }
 
     fn unindex_tuple(&mut self, key: &SliceRef, tuple_id: TupleId) -> Result<(), RelationError> {
-        let dattr_keymain = self.to_attr_key(key)?;
+        let attr_key = self.to_attr_key(key)?;
 
-        let Some(ref mut keys) = self.index.get_k_mut(&dattr_keymain) else {
+        let Some(tuples) = self.index.get_k_mut(&attr_key) else {
             return Err(RelationError::TupleNotFound);
         };
-
-        keys.remove(&tuple_id);
-
-        if self.unique && !keys.is_empty() {
+        if !tuples.remove(&tuple_id) {
+            return Err(RelationError::TupleNotFound);
+        }
+        if self.unique && !tuples.is_empty() {
             return Err(RelationError::UniqueConstraintViolation);
         }
+
+        // Don't leave empty sets behind, or they'd be walked over by every range scan.
+        if tuples.is_empty() {
+            self.index.remove_k(&attr_key);
+        }
         Ok(())
     }
 
@@ -160,3 +197,127 @@ impl Index for ArtArrayIndex {
         self.index = AdaptiveRadixTree::new();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ArtArrayIndex;
+    use crate::index::{AttrType, Index};
+    use crate::tuples::TupleId;
+    use moor_values::util::SliceRef;
+    use rand::Rng;
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+
+    fn key(k: i64) -> SliceRef {
+        SliceRef::from_bytes(&k.to_le_bytes())
+    }
+
+    fn tuple_id(n: i64) -> TupleId {
+        TupleId {
+            page: n as usize,
+            slot: 0,
+        }
+    }
+
+    fn contents(index: &ArtArrayIndex) -> Vec<TupleId> {
+        index
+            .seek_range(Bound::Unbounded, Bound::Unbounded)
+            .unwrap()
+            .collect()
+    }
+
+    /// Random inserts & removes checked against a BTreeMap, with clones taken along the way which
+    /// must not see anything done after them.
+    #[test]
+    fn matches_btree_through_snapshots() {
+        let mut rng = rand::thread_rng();
+        let mut index = ArtArrayIndex::new(AttrType::Integer, true);
+        let mut expected = BTreeMap::new();
+        let mut snapshots = vec![];
+        for i in 0..10_000 {
+            let k = rng.gen_range(-1000..1000);
+            if expected.remove(&k).is_some() {
+                index.unindex_tuple(&key(k), tuple_id(k)).unwrap();
+            } else {
+                index.index_tuple(&key(k), tuple_id(k)).unwrap();
+                expected.insert(k, tuple_id(k));
+            }
+            if i % 1000 == 0 {
+                snapshots.push((index.clone(), expected.clone()));
+            }
+        }
+        for k in -1000..1000 {
+            let found: Vec<_> = index.seek(&key(k)).unwrap().collect();
+            assert_eq!(found.first(), expected.get(&k));
+        }
+        assert_eq!(
+            contents(&index),
+            expected.values().cloned().collect::<Vec<_>>()
+        );
+        for (snapshot, expected) in snapshots {
+            assert_eq!(
+                contents(&snapshot),
+                expected.values().cloned().collect::<Vec<_>>()
+            );
+        }
+
+        // Removing what isn't there is an error, as with the other indexes.
+        let missing = (-1000..1000).find(|k| !expected.contains_key(k)).unwrap();
+        assert!(index
+            .unindex_tuple(&key(missing), tuple_id(missing))
+            .is_err());
+    }
+
+    /// Range scans, starting from bounds which are and aren't in the index, match a BTreeMap's.
+    #[test]
+    fn ranges_match_btree() {
+        let mut rng = rand::thread_rng();
+        let mut index = ArtArrayIndex::new(AttrType::Integer, true);
+        let mut expected = BTreeMap::new();
+        for _ in 0..2000 {
+            let k = rng.gen_range(-100_000..100_000);
+            if expected.insert(k, tuple_id(k)).is_none() {
+                index.index_tuple(&key(k), tuple_id(k)).unwrap();
+            }
+        }
+        let bound = |rng: &mut rand::rngs::ThreadRng, k: i64| match rng.gen_range(0..3) {
+            0 => Bound::Included(k),
+            1 => Bound::Excluded(k),
+            _ => Bound::Unbounded,
+        };
+        let keys: Vec<_> = expected.keys().cloned().collect();
+        for _ in 0..1000 {
+            // Half the time, start from a key that's there.
+            let lo = if rng.gen() {
+                keys[rng.gen_range(0..keys.len())]
+            } else {
+                rng.gen_range(-110_000..110_000)
+            };
+            let hi = lo + rng.gen_range(1..20_000);
+
+            // Straight from the tree, everything from the lower bound on...
+            let from_lo: Vec<_> = index
+                .index
+                .iter_from(&lo.into())
+                .flat_map(|(_, set)| set.iter().cloned())
+                .collect();
+            let wanted: Vec<_> = expected.range(lo..).map(|(_, t)| *t).collect();
+            assert_eq!(from_lo, wanted, "{:?}..", lo);
+
+            // ...and through the index, between bounds of each kind.
+            let (lo, hi) = (bound(&mut rng, lo), bound(&mut rng, hi));
+            let to_key = |b: &Bound<i64>| match b {
+                Bound::Included(k) => Bound::Included(key(*k)),
+                Bound::Excluded(k) => Bound::Excluded(key(*k)),
+                Bound::Unbounded => Bound::Unbounded,
+            };
+            let (lo_key, hi_key) = (to_key(&lo), to_key(&hi));
+            let found: Vec<_> = index
+                .seek_range(lo_key.as_ref(), hi_key.as_ref())
+                .unwrap()
+                .collect();
+            let wanted: Vec<_> = expected.range((lo, hi)).map(|(_, t)| *t).collect();
+            assert_eq!(found, wanted, "{:?}..{:?}", lo, hi);
+        }
+    }
+}
@@ -12,11 +12,12 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use crate::index::{AttrType, Index};
+use crate::index::{is_empty_range, map_bounds, AttrType, Index};
 use crate::tuples::TupleId;
 use crate::{IndexType, RelationError};
 use moor_values::util::SliceRef;
 use std::collections::{BTreeMap, HashSet};
+use std::ops::Bound;
 
 #[derive(Clone)]
 pub struct BtreeIndex {
@@ -32,6 +33,7 @@ enum Key {
     UnsignedInteger(SliceRef),
     Float(SliceRef),
     String(SliceRef),
+    Bytes(SliceRef),
 }
 
 impl PartialEq for Key {
@@ -41,6 +43,7 @@ impl PartialEq for Key {
             (Key::UnsignedInteger(a), Key::UnsignedInteger(b)) => a == b,
             (Key::Float(a), Key::Float(b)) => a == b,
             (Key::String(a), Key::String(b)) => a == b,
+            (Key::Bytes(a), Key::Bytes(b)) => a == b,
             _ => false,
         }
     }
@@ -56,22 +59,44 @@ impl PartialOrd for Key {
 impl Ord for Key {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         match (self, other) {
-            (Key::Integer(a), Key::Integer(b)) => a.cmp(b),
-            (Key::UnsignedInteger(a), Key::UnsignedInteger(b)) => a.cmp(b),
-            (Key::Float(a), Key::Float(b)) => a.cmp(b),
+            // Numbers are stored little-endian, so have to be decoded to compare them.
+            (Key::Integer(a), Key::Integer(b)) => {
+                cmp_decoded(a, b, i64::from_le_bytes, |a, b| a.cmp(&b))
+            }
+            (Key::UnsignedInteger(a), Key::UnsignedInteger(b)) => {
+                cmp_decoded(a, b, u64::from_le_bytes, |a, b| a.cmp(&b))
+            }
+            (Key::Float(a), Key::Float(b)) => {
+                cmp_decoded(a, b, f64::from_le_bytes, |a, b| a.total_cmp(&b))
+            }
             (Key::String(a), Key::String(b)) => a.cmp(b),
+            (Key::Bytes(a), Key::Bytes(b)) => a.cmp(b),
             _ => std::cmp::Ordering::Equal,
         }
     }
 }
 
+/// Compare two 8-byte values by decoding them, falling back to comparing the raw bytes if either
+/// is the wrong size.
+fn cmp_decoded<T>(
+    a: &SliceRef,
+    b: &SliceRef,
+    decode: fn([u8; 8]) -> T,
+    cmp: fn(T, T) -> std::cmp::Ordering,
+) -> std::cmp::Ordering {
+    match (a.as_slice().try_into(), b.as_slice().try_into()) {
+        (Ok(a), Ok(b)) => cmp(decode(a), decode(b)),
+        _ => a.cmp(b),
+    }
+}
+
 fn to_key(slice_ref: &SliceRef, attr_type: AttrType) -> Result<Key, RelationError> {
     match attr_type {
         AttrType::Integer => Ok(Key::Integer(slice_ref.clone())),
         AttrType::UnsignedInteger => Ok(Key::UnsignedInteger(slice_ref.clone())),
         AttrType::Float => Ok(Key::Float(slice_ref.clone())),
         AttrType::String => Ok(Key::String(slice_ref.clone())),
-        _ => Err(RelationError::BadKey),
+        AttrType::Bytes => Ok(Key::Bytes(slice_ref.clone())),
     }
 }
 
@@ -138,6 +163,26 @@ impl Index for BtreeIndex {
         }))
     }
 
+    fn seek_range(
+        &self,
+        lo: Bound<&SliceRef>,
+        hi: Bound<&SliceRef>,
+    ) -> Result<Box<dyn Iterator<Item = TupleId> + '_>, RelationError> {
+        let (lo, hi) = map_bounds(lo, hi, |v| to_key(v, self.attr_type))?;
+        if is_empty_range(&lo, &hi) {
+            return Ok(Box::new(Iter {
+                iter: Box::new(std::iter::empty()),
+            }));
+        }
+        Ok(Box::new(Iter {
+            iter: Box::new(
+                self.index
+                    .range((lo, hi))
+                    .flat_map(|(_, set)| set.iter().cloned()),
+            ),
+        }))
+    }
+
     fn index_tuple(&mut self, key: &SliceRef, tuple_id: TupleId) -> Result<(), RelationError> {
         let key = to_key(key, self.attr_type)?;
         let entry = self.index.entry(key).or_default();
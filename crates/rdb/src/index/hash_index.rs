@@ -17,6 +17,7 @@ use crate::tuples::TupleId;
 use crate::{IndexType, RelationError};
 use moor_values::util::SliceRef;
 use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
 
 #[derive(Clone)]
 pub struct HashIndex {
@@ -83,6 +84,14 @@ impl Index for HashIndex {
         }))
     }
 
+    fn seek_range(
+        &self,
+        _lo: Bound<&SliceRef>,
+        _hi: Bound<&SliceRef>,
+    ) -> Result<Box<dyn Iterator<Item = TupleId> + '_>, RelationError> {
+        Err(RelationError::UnorderedIndex)
+    }
+
     fn index_tuple(&mut self, key: &SliceRef, tuple_id: TupleId) -> Result<(), RelationError> {
         let entry = self.index.entry(key.clone()).or_default();
         if self.unique && !entry.is_empty() {
@@ -24,7 +24,6 @@ mod im_hash_index;
 
 use crate::tuples::TupleId;
 pub use art::array_key::ArrayKey;
-#[allow(unused_imports)] // Future use, stop warning me.
 pub use art::tree::AdaptiveRadixTree;
 pub use art::vector_key::VectorKey;
 
@@ -35,36 +34,45 @@ pub use art_index::ArtArrayIndex;
 pub use hash_index::HashIndex;
 pub use im_hash_index::ImHashIndex;
 use moor_values::util::SliceRef;
-use strum::EnumString;
+use std::ops::Bound;
+use strum::{EnumString, FromRepr};
 
 /// Types that domains or codomains can be for the purpose of indexing.
 ///
 /// Note that this is not the same as the `moor` Var type, but instead used for declaring the types of the purpose of
 /// indexing and querying. The actual user data can be stored in a variety of ways, but the TupleType is used by the
 /// indexing code to manage e.g. encoding, ordering, hashing, etc.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString)]
+///
+/// The discriminants are recorded in a database's catalog, so mustn't change.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString, FromRepr)]
 pub enum AttrType {
     /// The tuple attribute in question is a signed 64-bit integer.
-    Integer,
+    Integer = 0,
     /// The tuple attribute in question is an unsigned 64-bit integer.
-    UnsignedInteger,
+    UnsignedInteger = 1,
     /// The tuple attribute in question is a 64-bit floating point number.
-    Float,
+    Float = 2,
     /// The tuple attribute in question is a string.
-    String,
+    String = 3,
     /// The tuple attribute in question is a byte array.
-    Bytes,
+    Bytes = 4,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString)]
 pub enum IndexType {
     /// Unordered arbitrary keys. Lookup speed is O(1).
     Hash,
-    /// Viable for integer keys. Keys are ordered and must fit in a fixed size.
+    /// As `Hash`, but transactions' own copies of the index share structure with the relation's
+    /// too, as they do for `AdaptiveRadixTree`, making them cheaper to take but slower to update.
+    ImHash,
+    /// For integer keys only. Keys are ordered and must fit in a fixed size.
     /// Lookup speed is O(log N), but real world performance lies between Hash and BTree.
-    /// Linear scan is (theoretically) faster than both.
+    /// Linear scan is (theoretically) faster than both. Copies of the index share structure
+    /// until modified, so snapshots of a relation are cheap.
     AdaptiveRadixTree,
-    /// For ordered keys, valid for everything but Bytes.
+    /// For ordered keys, valid for all attribute types. Integers and floats are ordered
+    /// numerically, strings and bytes lexicographically.
     /// Lookup speed is O(log n).
     BTree,
 }
@@ -76,10 +84,20 @@ impl IndexType {
     pub fn is_ordered(&self) -> bool {
         match self {
             IndexType::AdaptiveRadixTree => true,
-            IndexType::Hash => false,
+            IndexType::Hash | IndexType::ImHash => false,
             IndexType::BTree => true,
         }
     }
+
+    /// Return true if the index can hold values of the given type.
+    pub fn supports(&self, attr_type: AttrType) -> bool {
+        match self {
+            IndexType::AdaptiveRadixTree => {
+                matches!(attr_type, AttrType::Integer | AttrType::UnsignedInteger)
+            }
+            IndexType::Hash | IndexType::ImHash | IndexType::BTree => true,
+        }
+    }
 }
 
 pub trait Index {
@@ -94,6 +112,13 @@ pub trait Index {
         &self,
         domain: &SliceRef,
     ) -> Result<Box<dyn Iterator<Item = TupleId> + '_>, RelationError>;
+    /// Seek the tuples whose values fall within the given bounds, in order. Only ordered indexes
+    /// support this; others return `RelationError::UnorderedIndex`.
+    fn seek_range(
+        &self,
+        lo: Bound<&SliceRef>,
+        hi: Bound<&SliceRef>,
+    ) -> Result<Box<dyn Iterator<Item = TupleId> + '_>, RelationError>;
     /// Index the given tuple.
     fn index_tuple(&mut self, key: &SliceRef, tuple_id: TupleId) -> Result<(), RelationError>;
     /// Remove the given tuple from the index.
@@ -105,6 +130,54 @@ pub trait Index {
     fn clear(&mut self);
 }
 
+/// The bounds which cover every value starting with `prefix`, when values are ordered
+/// byte-by-byte.
+pub fn prefix_bounds(prefix: &[u8]) -> (Bound<SliceRef>, Bound<SliceRef>) {
+    // The first value past the prefix is the prefix with its last non-0xff byte incremented, and
+    // everything after that byte dropped. If there's no such byte, there's no upper bound.
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last != 0xff {
+            upper.push(last + 1);
+            return (
+                Bound::Included(SliceRef::from_bytes(prefix)),
+                Bound::Excluded(SliceRef::from_vec(upper)),
+            );
+        }
+    }
+    (
+        Bound::Included(SliceRef::from_bytes(prefix)),
+        Bound::Unbounded,
+    )
+}
+
+/// True if no value could fall within the given bounds. (`BTreeMap::range` panics on these.)
+fn is_empty_range<K: Ord>(lo: &Bound<K>, hi: &Bound<K>) -> bool {
+    match (lo, hi) {
+        (Bound::Included(lo), Bound::Included(hi)) => lo > hi,
+        (Bound::Included(lo), Bound::Excluded(hi))
+        | (Bound::Excluded(lo), Bound::Included(hi))
+        | (Bound::Excluded(lo), Bound::Excluded(hi)) => lo >= hi,
+        _ => false,
+    }
+}
+
+/// Convert the bounds of a range over attribute values into bounds over an index's own keys.
+fn map_bounds<K>(
+    lo: Bound<&SliceRef>,
+    hi: Bound<&SliceRef>,
+    to_key: impl Fn(&SliceRef) -> Result<K, RelationError>,
+) -> Result<(Bound<K>, Bound<K>), RelationError> {
+    let map = |b: Bound<&SliceRef>| -> Result<Bound<K>, RelationError> {
+        Ok(match b {
+            Bound::Included(v) => Bound::Included(to_key(v)?),
+            Bound::Excluded(v) => Bound::Excluded(to_key(v)?),
+            Bound::Unbounded => Bound::Unbounded,
+        })
+    };
+    Ok((map(lo)?, map(hi)?))
+}
+
 pub fn pick_tx_index(
     relation_info: &crate::RelationInfo,
 ) -> (Box<dyn Index>, Option<Box<dyn Index>>) {
@@ -114,6 +187,7 @@ pub fn pick_tx_index(
             relation_info.unique_domain,
         )),
         IndexType::Hash => Box::new(HashIndex::new(relation_info.unique_domain)),
+        IndexType::ImHash => Box::new(ImHashIndex::new(relation_info.unique_domain)),
         IndexType::BTree => Box::new(BtreeIndex::new(
             relation_info.domain_type,
             relation_info.unique_domain,
@@ -125,6 +199,7 @@ pub fn pick_tx_index(
             false,
         ))),
         Some(IndexType::Hash) => Some(Box::new(HashIndex::new(false))),
+        Some(IndexType::ImHash) => Some(Box::new(ImHashIndex::new(false))),
         None => None,
         Some(IndexType::BTree) => Some(Box::new(BtreeIndex::new(
             relation_info.codomain_type,
@@ -146,7 +221,9 @@ pub fn pick_base_index(
             relation_info.domain_type,
             relation_info.unique_domain,
         )),
-        IndexType::Hash => Box::new(ImHashIndex::new(relation_info.unique_domain)),
+        IndexType::Hash | IndexType::ImHash => {
+            Box::new(ImHashIndex::new(relation_info.unique_domain))
+        }
         IndexType::BTree => Box::new(ImBtreeIndex::new(
             relation_info.domain_type,
             relation_info.unique_domain,
@@ -158,7 +235,7 @@ pub fn pick_base_index(
                 relation_info.codomain_type,
                 false,
             ))),
-            Some(IndexType::Hash) => Some(Box::new(ImHashIndex::new(false))),
+            Some(IndexType::Hash | IndexType::ImHash) => Some(Box::new(ImHashIndex::new(false))),
             None => None,
             Some(IndexType::BTree) => Some(Box::new(ImBtreeIndex::new(
                 relation_info.codomain_type,
@@ -24,22 +24,25 @@
 
 pub use index::AttrType;
 pub use index::IndexType;
-pub use relbox::{RelBox, RelationInfo};
+pub use paging::{
+    recover_to, Damage, OpenError, RecoveryError, RecoveryTarget, SyncPolicy, WalConfig,
+};
+pub use relbox::{BackupError, RelBox, RelationInfo};
+pub use schema::{CatalogEntry, Schema};
 use std::fmt::Display;
 use std::str::FromStr;
 use strum::EnumProperty;
 use thiserror::Error;
-pub use tx::{CommitError, Transaction};
+pub use tx::{Closure, CommitConflict, CommitError, Direction, Join, Transaction};
 
 mod base_relation;
 mod paging;
 mod pool;
 mod relbox;
+mod schema;
 mod tuples;
 mod tx;
 
-// Note: this is 'pub' just to shut dead_code compiler warnings up,
-// and can be removed once the ART index is actually being used
 pub mod index;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
@@ -65,6 +68,8 @@ pub enum RelationError {
     AmbiguousTuple,
     #[error("Invalid key type")]
     BadKey,
+    #[error("Ordered scan on a relation whose index is not ordered")]
+    UnorderedIndex,
 }
 
 /// Convert an enum schema description into RelationInfo (see WorldStateRelation for example)
@@ -123,6 +128,20 @@ pub fn relation_info_for<E: EnumProperty + Display>(relation: E) -> RelationInfo
         None
     };
 
+    for (attr_type, index_type) in [
+        (domain_type, Some(index_type)),
+        (codomain_type, codomain_index_type),
+    ] {
+        if let Some(index_type) = index_type {
+            if !index_type.supports(attr_type) {
+                panic!(
+                    "Index type {:?} can't index {:?} values, for declared relation {}",
+                    index_type, attr_type, relation
+                );
+            }
+        }
+    }
+
     RelationInfo {
         name: relation.to_string(),
         domain_type,
@@ -16,10 +16,14 @@
 //! Used for write-ahead type storage at commit-time, and backed by whatever preferred physical
 //! storage mechanism is desired.
 
-use kanal::Sender;
+use kanal::{Receiver, Sender};
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::thread::yield_now;
 
+use crate::schema::Schema;
 use crate::tx::WorkingSet;
+use crate::{BackupError, CommitError};
 
 pub struct BackingStoreClient {
     sender: Sender<WriterMessage>,
@@ -27,7 +31,19 @@ pub struct BackingStoreClient {
 }
 
 pub enum WriterMessage {
-    Commit(u64, WorkingSet, Vec<u64>),
+    /// A committed working set, its timestamp, the current sequence values and schema, and where
+    /// to acknowledge it once it's durable.
+    Commit(u64, WorkingSet, Vec<u64>, Arc<Schema>, Sender<()>),
+    /// Copy the store, as of the commits sent so far, to a new directory. The timestamp, sequence
+    /// values and schema are those of the last of those commits, and the result goes to the
+    /// sender once the copy is complete.
+    Backup(
+        PathBuf,
+        u64,
+        Vec<u64>,
+        Arc<Schema>,
+        Sender<Result<(), BackupError>>,
+    ),
     Shutdown,
 }
 
@@ -40,12 +56,38 @@ impl BackingStoreClient {
     }
 
     /// Sync out the working set from a committed transaction for the given transaction timestamp.
-    /// Used to support persistent storage of committed transactions, effectively as a write-ahead
-    /// log.
-    pub fn sync(&self, ts: u64, ws: WorkingSet, sequences: Vec<u64>) {
+    /// Returns a receiver which is signalled once the writer has made it durable (as far as its
+    /// sync policy goes).
+    pub fn sync(
+        &self,
+        ts: u64,
+        ws: WorkingSet,
+        sequences: Vec<u64>,
+        schema: Arc<Schema>,
+    ) -> Result<Receiver<()>, CommitError> {
+        let (ack_send, ack_receive) = kanal::bounded(1);
         self.sender
-            .send(WriterMessage::Commit(ts, ws, sequences))
-            .expect("Unable to send write-ahead sync message");
+            .send(WriterMessage::Commit(ts, ws, sequences, schema, ack_send))
+            .map_err(|_| CommitError::DurabilityFailure)?;
+        Ok(ack_receive)
+    }
+
+    /// Have the writer copy the store to `path`, once it's written out every commit sent before
+    /// this. Returns a receiver which gets the outcome once the copy is complete.
+    pub fn backup(
+        &self,
+        path: PathBuf,
+        ts: u64,
+        sequences: Vec<u64>,
+        schema: Arc<Schema>,
+    ) -> Result<Receiver<Result<(), BackupError>>, BackupError> {
+        let (done_send, done_receive) = kanal::bounded(1);
+        self.sender
+            .send(WriterMessage::Backup(
+                path, ts, sequences, schema, done_send,
+            ))
+            .map_err(|_| BackupError::WriterGone)?;
+        Ok(done_receive)
     }
 
     /// Shutdown the backing store writer thread.
@@ -12,61 +12,99 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-//! TODO: replace OkayWAL with our own WAL implementation
-
-use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
 
-use binary_layout::{binary_layout, Field};
 use human_bytes::human_bytes;
-use kanal::Receiver;
-use okaywal::WriteAheadLog;
-use tracing::{debug, error, info};
+use kanal::{ReceiveErrorTimeout, Receiver, Sender};
+use tracing::{debug, error, info, warn};
 
 use crate::base_relation::BaseRelation;
-use crate::paging::page_storage::PageStore;
-use crate::paging::wal::{make_wal_entry, WalEntryType, WalManager};
-use crate::paging::PageId;
+use crate::paging::legacy_wal::drain_legacy_wal;
+use crate::paging::page_storage::{BlobId, PageStore, PageStoreMutation};
+use crate::paging::sequence_page::{self, SequencePage};
+use crate::paging::slotted_page::slot_page_overhead;
+use crate::paging::wal::{
+    encode_record, make_wal_entry, SyncPolicy, WalConfig, WalEntryType, WriteAheadLog,
+};
+use crate::paging::wal_archive::{page_images, WalArchive};
 use crate::paging::TupleBox;
+use crate::paging::{Damage, OnDamage, OpenError, PageId, TupleBoxError};
+use crate::schema::{Schema, SchemaCheck};
+use crate::tuples::TupleId;
 use crate::tx::{TxTupleOp, WorkingSet};
+use crate::{BackupError, RelationId};
 
 use super::backing::{BackingStoreClient, WriterMessage};
 
 // TODO: move "cold storage" functionality under the pager rather than above it.
 
-/// Uses WAL + custom page store as the persistent backing store & write-ahead-log for the rdb.
+/// Uses our write-ahead log + custom page store as the persistent backing store for the rdb.
 pub struct ColdStorage {}
 
-binary_layout!(sequence_page, LittleEndian, {
-    // The number of sequences stored in this page.
-    num_sequences: u64,
-    // The sequences are here..
-    sequences: [u8],
-});
+const SEQUENCE_PAGE_ID: PageId = 0xfafe_babf;
 
-binary_layout!(sequence, LittleEndian, {
-    // The sequence id.
-    id: u64,
-    // The current value of the sequence.
-    value: u64,
-});
+/// The WAL entry writing the sequence page.
+fn sequence_wal_entry(ts: u64, sequence_page: &[u8]) -> Vec<u8> {
+    make_wal_entry(
+        WalEntryType::SequenceSync,
+        SEQUENCE_PAGE_ID as PageId,
+        None,
+        0,
+        ts,
+        0,
+        sequence_page.len(),
+        |buf| buf.copy_from_slice(sequence_page),
+    )
+    .expect("Failed to encode sequence WAL entry")
+}
 
-const SEQUENCE_PAGE_ID: PageId = 0xfafe_babf;
+/// The WAL entry carrying the contents of a blob.
+fn blob_wal_entry(blob_id: BlobId, ts: u64, blob: &[u8]) -> Vec<u8> {
+    make_wal_entry(
+        WalEntryType::Blob,
+        blob_id as PageId,
+        None,
+        0,
+        ts,
+        0,
+        blob.len(),
+        |buf| buf.copy_from_slice(blob),
+    )
+    .expect("Failed to encode blob WAL entry")
+}
 
 impl ColdStorage {
+    /// Recover and load the store at `path` (with its pages in `page_storage`), and start the
+    /// writer thread. If the config names a WAL archive, every commit is also appended there.
+    ///
+    /// Returns the highest transaction timestamp committed to the store (or archived) so far, if
+    /// known, so the caller can carry on numbering from it. Damaged pages and tuples are dealt
+    /// with per `on_damage`, and returned too. So is the schema the store is opened under, once
+    /// it's passed `schema_check`.
+    #[allow(clippy::too_many_arguments)]
     pub fn start(
         path: PathBuf,
+        page_storage: Arc<PageStore>,
+        wal_config: WalConfig,
         relations: &mut [BaseRelation],
         sequences: &mut [u64],
         tuple_box: Arc<TupleBox>,
-    ) -> BackingStoreClient {
-        let page_storage = PageStore::new(path.join("pages"));
-        let wal_manager = WalManager::new(page_storage.clone());
-
+        on_damage: OnDamage,
+        schema_check: &SchemaCheck,
+    ) -> Result<(BackingStoreClient, Option<u64>, Vec<Damage>, Schema), OpenError> {
         // Do initial recovery of anything left in the WAL before starting up, which should
         // flush everything to page storage, from which we can then go and load it.
-        let wal = match WriteAheadLog::recover(path.join("wal"), wal_manager) {
+        if let Err(e) = drain_legacy_wal(&path.join("wal"), page_storage.clone()) {
+            error!(?e, "Unable to recover OkayWAL write-ahead log");
+            panic!("Unable to recover OkayWAL write-ahead log");
+        }
+        let wal = match WriteAheadLog::recover(path.join("journal"), &page_storage) {
             Ok(wal) => wal,
             Err(e) => {
                 error!(?e, "Unable to recover write-ahead log");
@@ -74,100 +112,459 @@ impl ColdStorage {
             }
         };
 
-        // Grab page storage and wait for all the writes to complete.
-        page_storage.wait_complete();
+        let (schema, stored_ts) = Self::load_sequences(&path, sequences, schema_check)?;
+        let (referenced_blobs, damage) =
+            Self::load(&page_storage, relations, tuple_box.clone(), on_damage);
+        tuple_box.blobs_committed(referenced_blobs.iter().copied());
+
+        // Blobs written for transactions which never committed, or whose tuples have since been
+        // deleted or replaced, aren't referred to by anything.
+        let orphaned_blobs: Vec<_> = page_storage
+            .list_blobs()
+            .expect("Unable to list blobs")
+            .into_iter()
+            .filter(|blob_id| !referenced_blobs.contains(blob_id))
+            .collect();
+        if !orphaned_blobs.is_empty() {
+            info!("Removing {} orphaned blobs", orphaned_blobs.len());
+            page_storage
+                .remove_blobs(&orphaned_blobs)
+                .expect("Unable to remove orphaned blobs");
+        }
 
-        // Get the sequence page, and load the sequences from it, if any.
-        if let Ok(Some(sequence_page)) = page_storage.read_sequence_page() {
-            let sequence_page = sequence_page::View::new(&sequence_page[..]);
-            let num_sequences = sequence_page.num_sequences().read();
-            assert_eq!(num_sequences, sequences.len() as u64,
-                "Number of sequences in the sequence page does not match the number of sequences in the rdb");
-            let sequences_bytes = sequence_page.sequences().to_vec();
-            let sequence_size = sequence::SIZE.unwrap() as u64;
-            for i in 0..num_sequences {
-                let sequence =
-                    sequence::View::new(&sequences_bytes[(i * sequence_size) as usize..]);
-                let id = sequence.id().read();
-                let value = sequence.value().read();
-                sequences[id as usize] = value;
+        // If we're archiving, open the archive. A brand new archive starts with an image of
+        // everything already in the page store, so replaying it doesn't need anything else.
+        // Likewise if the store has moved on since the archive last saw it (because it was run
+        // without archiving in between, or the timestamp of its last commit wasn't recorded);
+        // the commits it missed can't be replayed from it, so it starts over from an image of
+        // the store as it is now.
+        let (archive, last_ts) = match wal_config.archive {
+            None => (None, stored_ts),
+            Some(archive_path) => {
+                let (mut archive, archived_ts) =
+                    WalArchive::open(&archive_path).expect("Unable to open WAL archive");
+                let last_ts = match (archived_ts, stored_ts) {
+                    (Some(archived_ts), Some(stored_ts)) if archived_ts >= stored_ts => archived_ts,
+                    _ => {
+                        let base_ts = stored_ts.unwrap_or(0);
+                        let base_image = Self::base_image(&page_storage);
+                        archive
+                            .append(&encode_record(
+                                base_ts,
+                                base_image.iter().map(Vec::as_slice),
+                            ))
+                            .expect("Unable to write base image to WAL archive");
+                        match archived_ts {
+                            None => info!(?archive_path, "Started new WAL archive"),
+                            Some(archived_ts) => warn!(
+                                ?archive_path,
+                                archived_ts,
+                                ?stored_ts,
+                                "Database has moved on since it was last archived; archived a new base image"
+                            ),
+                        }
+                        archived_ts.unwrap_or(0).max(base_ts)
+                    }
+                };
+                (Some(archive), Some(last_ts))
             }
+        };
+
+        // Start the listen loop
+        let (writer_send, writer_receive) = kanal::unbounded();
+        let ps = page_storage.clone();
+        let sync_policy = wal_config.sync_policy;
+        let cs_join = std::thread::Builder::new()
+            .name("moor-coldstorage-listen".to_string())
+            .spawn(move || {
+                Self::listen_loop(writer_receive, wal, sync_policy, archive, tuple_box, ps)
+            })
+            .expect("Unable to spawn coldstorage listen thread");
+
+        // And return the client to it.
+        Ok((
+            BackingStoreClient::new(writer_send, cs_join),
+            last_ts,
+            damage,
+            schema,
+        ))
+    }
+
+    /// Load the store at `path` without writing anything to it, and without a writer thread, so
+    /// there's nowhere for commits to go. As the write-ahead log can't be replayed into the page
+    /// store, it has to be empty. Damaged pages and tuples are set aside, and returned.
+    pub fn open_read_only(
+        path: PathBuf,
+        page_storage: Arc<PageStore>,
+        relations: &mut [BaseRelation],
+        sequences: &mut [u64],
+        tuple_box: Arc<TupleBox>,
+        schema_check: &SchemaCheck,
+    ) -> Result<(Vec<Damage>, Schema), OpenError> {
+        let pending = WriteAheadLog::pending_commits(&path.join("journal"))
+            .map_err(|e| OpenError::Io(e.to_string()))?;
+        if pending > 0 || path.join("wal").exists() {
+            return Err(OpenError::UnappliedJournal(path));
         }
+        let (schema, _) = Self::load_sequences(&path, sequences, schema_check)?;
+        let (_, damage) = Self::load(&page_storage, relations, tuple_box, OnDamage::SetAside);
+        Ok((damage, schema))
+    }
 
-        // Recover all the pages from cold storage and re-index all the tuples in them.
+    /// What's recorded in the sequence page of the store at `path`, if it has one yet, including
+    /// any update to it still in the write-ahead log.
+    pub(crate) fn stored_sequence_page(path: &Path) -> Result<Option<SequencePage>, OpenError> {
+        let io_error = |e: std::io::Error| OpenError::Io(e.to_string());
+        let sequence_page =
+            match WriteAheadLog::last_sequence_page(&path.join("journal")).map_err(io_error)? {
+                Some(page) => Some(page),
+                None => PageStore::read_sequence_page_in(&path.join("pages")).map_err(io_error)?,
+            };
+        sequence_page
+            .map(|page| {
+                sequence_page::decode(&page).map_err(|e| {
+                    OpenError::Io(format!("{}: damaged sequence page: {}", path.display(), e))
+                })
+            })
+            .transpose()
+    }
+
+    /// Load the sequences from the page store, once its schema has passed `schema_check`,
+    /// returning the schema the store is opened under, and the timestamp of its last commit (if
+    /// that's recorded).
+    fn load_sequences(
+        path: &Path,
+        sequences: &mut [u64],
+        schema_check: &SchemaCheck,
+    ) -> Result<(Schema, Option<u64>), OpenError> {
+        let Some(stored) = Self::stored_sequence_page(path)? else {
+            return Ok((schema_check.check(path, None)?, None));
+        };
+        let schema = schema_check.check(path, Some(stored.schema))?;
+        assert_eq!(stored.sequences.len(), sequences.len(),
+            "Number of sequences in the sequence page does not match the number of sequences in the rdb");
+        sequences.copy_from_slice(&stored.sequences);
+        Ok((schema, stored.last_ts))
+    }
+
+    /// Load the pages from the page store, and index all the tuples in them in their relations.
+    /// Returns the blobs the tuples refer to, and whatever damage was found.
+    fn load(
+        page_storage: &PageStore,
+        relations: &mut [BaseRelation],
+        tuple_box: Arc<TupleBox>,
+        on_damage: OnDamage,
+    ) -> (HashSet<BlobId>, Vec<Damage>) {
+        // Recover all the pages from cold storage, checking each one and each of the tuples in it
+        // as we go.
+        let stored_blobs: HashSet<_> = page_storage
+            .list_blobs()
+            .expect("Unable to list blobs")
+            .into_iter()
+            .collect();
         let ids = page_storage.list_pages();
-        let mut restored_slots = HashMap::new();
+        let mut damage = vec![];
+        let mut damaged_pages = HashSet::new();
+        let mut restored_tuples = vec![];
         let mut restored_bytes = 0;
         for (page_size, page_num, relation_id) in ids {
-            let tuple_ids = tuple_box
-                .clone()
-                .load_page(relation_id, page_num, page_size, |buf| {
-                    page_storage
-                        .read_page_buf(page_num, relation_id, buf)
-                        .expect("Unable to read page")
-                })
-                .expect("Unable to get page");
-
-            restored_slots
-                .entry(relation_id)
-                .or_insert_with(HashSet::new)
-                .insert(tuple_ids);
+            let tuples =
+                match tuple_box
+                    .clone()
+                    .load_page(relation_id, page_num, page_size, |buf| {
+                        page_storage
+                            .read_page_buf(page_num, relation_id, buf)
+                            .expect("Unable to read page")
+                    }) {
+                    Ok(tuples) => tuples,
+                    Err(TupleBoxError::MalformedPage(page, reason)) => {
+                        damage.push(Damage::MalformedPage {
+                            relation: relation_id,
+                            page,
+                            reason,
+                        });
+                        damaged_pages.insert((page_num, relation_id));
+                        continue;
+                    }
+                    Err(e) => panic!("Unable to get page {}: {}", page_num, e),
+                };
+            for tuple in tuples {
+                let TupleId { page, slot } = tuple.id();
+                let found = match (tuple.check(), tuple.blob_id()) {
+                    (Err(reason), _) => Damage::DamagedTuple {
+                        relation: relation_id,
+                        page,
+                        slot,
+                        reason,
+                    },
+                    (Ok(()), Some(blob)) if !stored_blobs.contains(&blob) => Damage::MissingBlob {
+                        relation: relation_id,
+                        page,
+                        slot,
+                        blob,
+                    },
+                    (Ok(()), _) => {
+                        restored_tuples.push((relation_id, tuple));
+                        continue;
+                    }
+                };
+                // Dropping the only reference to the tuple frees its slot.
+                damage.push(found);
+                damaged_pages.insert((page_num, relation_id));
+            }
             restored_bytes += page_size;
         }
 
-        // Now iterate all the slots we restored, and re-establish their indexes in the relations they belong to.
-        let mut restored_count = 0;
-        for (relation_id, relation_tuple_ids) in restored_slots {
-            for page_tuple_ids in relation_tuple_ids {
-                for tuple_id in page_tuple_ids {
-                    let relation = &mut relations[relation_id.0];
-                    relation.load_tuple(tuple_id);
-                    restored_count += 1;
+        if !damage.is_empty() {
+            match on_damage {
+                OnDamage::Refuse => {
+                    for d in &damage {
+                        error!("{}", d);
+                    }
+                    panic!(
+                        "Database is damaged ({} problems, starting with: {}); check it with moor-fsck",
+                        damage.len(),
+                        damage[0]
+                    );
+                }
+                OnDamage::SetAside => {
+                    warn!("Setting aside {} damaged pages and tuples", damage.len());
+                }
+                OnDamage::Discard => {
+                    warn!("Discarding {} damaged pages and tuples", damage.len());
+                    Self::rewrite_headers(page_storage, &tuple_box, &damaged_pages);
                 }
             }
         }
+
+        // Now re-establish the indexes of all the tuples in the relations they belong to.
+        let mut referenced_blobs = HashSet::new();
+        let restored_count = restored_tuples.len();
+        for (relation_id, tuple) in restored_tuples {
+            referenced_blobs.extend(tuple.blob_id());
+            relations[relation_id.0].load_tuple(tuple);
+        }
         info!(
             "Restored & re-indexed {} tuples from coldstorage across {} relations, in {}",
             restored_count,
             relations.len(),
             human_bytes(restored_bytes as f64)
         );
+        (referenced_blobs, damage)
+    }
 
-        // Start the listen loop
-        let (writer_send, writer_receive) = kanal::unbounded();
-        let ps = page_storage.clone();
-        let cs_join = std::thread::Builder::new()
-            .name("moor-coldstorage-listen".to_string())
-            .spawn(move || Self::listen_loop(writer_receive, wal, tuple_box.clone(), ps))
-            .expect("Unable to spawn coldstorage listen thread");
+    /// Write the headers of `pages` as they now are in memory (with their damaged slots freed)
+    /// back over the ones in the page store. Malformed pages were never loaded, so they're
+    /// written back as empty.
+    fn rewrite_headers(
+        page_storage: &PageStore,
+        tuple_box: &TupleBox,
+        pages: &HashSet<(PageId, RelationId)>,
+    ) {
+        let mut write_batch = vec![];
+        for (page_id, relation_id) in pages {
+            let data = match tuple_box.page_for(*page_id) {
+                Ok(page) => {
+                    let mut header = vec![0; page.header_size()];
+                    page.write_header(&mut header);
+                    header
+                }
+                Err(_) => vec![0; slot_page_overhead()],
+            };
+            write_batch.push(PageStoreMutation::PageHeaderWrite {
+                relation_id: *relation_id,
+                page_id: *page_id,
+                data: data.into_boxed_slice(),
+            });
+        }
+        page_storage
+            .enqueue_page_mutations(write_batch)
+            .expect("Unable to rewrite damaged pages");
+        page_storage.wait_complete();
+    }
 
-        // And return the client to it.
-        BackingStoreClient::new(writer_send, cs_join)
+    /// WAL chunks which, replayed into an empty page store, reproduce the current one.
+    fn base_image(page_storage: &PageStore) -> Vec<Vec<u8>> {
+        let mut chunks = vec![];
+        if let Ok(Some(sequence_page)) = page_storage.read_sequence_page() {
+            chunks.push(sequence_wal_entry(0, &sequence_page));
+        }
+        // A page header write of the whole page is a write of the whole page.
+        for (relation_id, page_id, page) in page_images(page_storage) {
+            let entry = make_wal_entry(
+                WalEntryType::PageHeader,
+                page_id,
+                Some(relation_id),
+                0,
+                0,
+                0,
+                page.len(),
+                |buf| buf.copy_from_slice(&page),
+            )
+            .expect("Failed to encode page image WAL entry");
+            chunks.push(entry);
+        }
+        for blob_id in page_storage.list_blobs().expect("Unable to list blobs") {
+            let blob = page_storage
+                .read_blob(blob_id)
+                .expect("Unable to read blob");
+            chunks.push(blob_wal_entry(blob_id, 0, &blob));
+        }
+        chunks
     }
 
     fn listen_loop(
         writer_receive: Receiver<WriterMessage>,
-        wal: WriteAheadLog,
+        mut wal: WriteAheadLog,
+        sync_policy: SyncPolicy,
+        mut archive: Option<WalArchive>,
         tuple_box: Arc<TupleBox>,
         ps: Arc<PageStore>,
     ) {
         ps.clone().start();
+        let mut last_sync = Instant::now();
+        let mut unsynced = false;
+        // A backup copying the page store, which mustn't be checkpointed into until it's done.
+        let mut backup: Option<JoinHandle<()>> = None;
+        // Blobs of tuples retired since the last checkpoint, which the page store still refers
+        // to until then.
+        let mut retired_blobs = vec![];
         loop {
-            match writer_receive.recv() {
-                Ok(WriterMessage::Commit(ts, ws, sequences)) => {
-                    Self::perform_writes(wal.clone(), tuple_box.clone(), ts, ws, sequences);
+            // Wait for something to do. If we're syncing on an interval and have unsynced
+            // writes, don't wait past when they're due.
+            let first = match sync_policy {
+                SyncPolicy::Interval(interval) if unsynced => {
+                    match writer_receive.recv_timeout(interval.saturating_sub(last_sync.elapsed()))
+                    {
+                        Ok(msg) => Some(msg),
+                        Err(ReceiveErrorTimeout::Timeout) => None,
+                        Err(e) => {
+                            error!(?e, "Error receiving message from writer thread");
+                            break;
+                        }
+                    }
+                }
+                _ => match writer_receive.recv() {
+                    Ok(msg) => Some(msg),
+                    Err(e) => {
+                        error!(?e, "Error receiving message from writer thread");
+                        break;
+                    }
+                },
+            };
+
+            // Group commit: take everything else that's waiting along with it.
+            let mut messages: Vec<_> = first.into_iter().collect();
+            while let Ok(Some(msg)) = writer_receive.try_recv() {
+                messages.push(msg);
+            }
+
+            let mut records = vec![];
+            let mut acks = vec![];
+            let mut shutdown = false;
+            for msg in messages {
+                match msg {
+                    WriterMessage::Commit(ts, ws, sequences, schema, ack) => {
+                        let chunks = Self::perform_writes(
+                            tuple_box.clone(),
+                            ts,
+                            ws,
+                            sequences,
+                            &schema,
+                            &mut retired_blobs,
+                        );
+                        records.extend(encode_record(ts, chunks.iter().map(Vec::as_slice)));
+                        acks.push(ack);
+                        if sync_policy == SyncPolicy::PerCommit {
+                            Self::write_records(&mut wal, archive.as_mut(), &records);
+                            wal.sync().expect("Unable to sync write-ahead log");
+                            records.clear();
+                            Self::acknowledge(&mut acks);
+                        }
+                    }
+                    WriterMessage::Backup(dest, ts, sequences, schema, done) => {
+                        // Everything before it has to be in the log before the log is copied.
+                        if !records.is_empty() {
+                            Self::write_records(&mut wal, archive.as_mut(), &records);
+                            records.clear();
+                        }
+                        wal.sync().expect("Unable to sync write-ahead log");
+                        unsynced = false;
+                        Self::acknowledge(&mut acks);
+
+                        if let Some(previous) = backup.take() {
+                            let _ = previous.join();
+                        }
+                        let ps = ps.clone();
+                        let segment = wal.current_segment();
+                        let sequence_page = sequence_page::encode(&schema, &sequences, ts);
+                        let copy = move || {
+                            let result = Self::copy_store(&ps, segment, ts, &sequence_page, &dest)
+                                .map_err(|e| BackupError::Copy(e.to_string()));
+                            // If whoever asked has gone away, there's nobody to tell.
+                            let _ = done.send(result);
+                        };
+                        backup = Some(
+                            std::thread::Builder::new()
+                                .name("moor-backup".to_string())
+                                .spawn(copy)
+                                .expect("Unable to spawn backup thread"),
+                        );
+                    }
+                    WriterMessage::Shutdown => shutdown = true,
+                }
+            }
+
+            if !records.is_empty() {
+                Self::write_records(&mut wal, archive.as_mut(), &records);
+                unsynced = true;
+            }
+            match sync_policy {
+                SyncPolicy::PerCommit => {}
+                SyncPolicy::Batched => {
+                    if unsynced {
+                        wal.sync().expect("Unable to sync write-ahead log");
+                        unsynced = false;
+                    }
+                }
+                SyncPolicy::Interval(interval) => {
+                    if unsynced && last_sync.elapsed() >= interval {
+                        wal.sync().expect("Unable to sync write-ahead log");
+                        unsynced = false;
+                        last_sync = Instant::now();
+                    }
                 }
-                Ok(WriterMessage::Shutdown) => {
-                    // Flush the WAL
-                    wal.shutdown().expect("Unable to flush WAL");
+            }
+            Self::acknowledge(&mut acks);
 
-                    info!("Shutting down WAL writer thread");
-                    break;
+            let backing_up = match backup.take() {
+                Some(copy) if copy.is_finished() => {
+                    let _ = copy.join();
+                    false
                 }
-                Err(e) => {
-                    error!(?e, "Error receiving message from writer thread");
-                    break;
+                running => {
+                    backup = running;
+                    backup.is_some()
+                }
+            };
+            if shutdown {
+                if let Some(copy) = backup.take() {
+                    let _ = copy.join();
                 }
+                // Bring the page store up to date, so the next startup has nothing to replay.
+                wal.checkpoint(&ps)
+                    .expect("Unable to checkpoint write-ahead log");
+                tuple_box.blobs_retired(&retired_blobs);
+                info!("Shutting down WAL writer thread");
+                break;
+            }
+            if wal.wants_checkpoint() && !backing_up {
+                wal.checkpoint(&ps)
+                    .expect("Unable to checkpoint write-ahead log");
+                tuple_box.blobs_retired(&retired_blobs);
+                retired_blobs.clear();
+                unsynced = false;
+                last_sync = Instant::now();
             }
         }
 
@@ -175,15 +572,60 @@ impl ColdStorage {
         ps.stop();
     }
 
-    /// Receive an (already committed) working set and write the modified pages out to the write-ahead-log to make
-    /// the changes durable.
+    /// Copy the page store and the first `len` bytes of the log segment at `segment` to a new
+    /// database at `dest`. Both are as of the commit at `ts`, except for the sequences, which can
+    /// move on without a commit, so a record setting them to `sequence_page` is added to the copy
+    /// of the log.
+    fn copy_store(
+        ps: &PageStore,
+        (segment, len): (PathBuf, u64),
+        ts: u64,
+        sequence_page: &[u8],
+        dest: &Path,
+    ) -> std::io::Result<()> {
+        ps.copy_to(&dest.join("pages"))?;
+
+        let journal = dest.join("journal");
+        std::fs::create_dir_all(&journal)?;
+        let mut copy = File::create(journal.join(segment.file_name().unwrap()))?;
+        std::io::copy(&mut File::open(&segment)?.take(len), &mut copy)?;
+        let entry = sequence_wal_entry(ts, sequence_page);
+        copy.write_all(&encode_record(ts, [entry.as_slice()]))?;
+        copy.sync_all()?;
+        File::open(&journal)?.sync_all()?;
+        File::open(dest)?.sync_all()
+    }
+
+    /// Append encoded records to the archive (if any) and then the write-ahead log.
+    fn write_records(wal: &mut WriteAheadLog, archive: Option<&mut WalArchive>, records: &[u8]) {
+        // Archive first, so the archive never falls behind what's in the log.
+        if let Some(archive) = archive {
+            archive
+                .append(records)
+                .expect("Failed to write to WAL archive");
+        }
+        wal.append(records).expect("Failed to write to WAL");
+    }
+
+    /// Tell the committers waiting on these commits that they're done.
+    fn acknowledge(acks: &mut Vec<Sender<()>>) {
+        for ack in acks.drain(..) {
+            // If the committer's gone away, there's nobody to tell.
+            let _ = ack.send(());
+        }
+    }
+
+    /// Receive an (already committed) working set and produce the WAL entries for the modified
+    /// pages, which make up the commit's record in the write-ahead log. The blobs of the tuples
+    /// it retires are added to `retired_blobs`.
     fn perform_writes(
-        wal: WriteAheadLog,
         tuple_box: Arc<TupleBox>,
         ts: u64,
         ws: WorkingSet,
         sequences: Vec<u64>,
-    ) {
+        schema: &Schema,
+        retired_blobs: &mut Vec<BlobId>,
+    ) -> Vec<Vec<u8>> {
         debug!("Committing write-ahead for ts {}", ts);
 
         // Where we stick all the page mutations we're going to write out.
@@ -193,34 +635,13 @@ impl ColdStorage {
         //   transaction, so we need some kind of signal from above that they have
         //   changed.
 
-        // Build the sequence page first, by copying the current values of all the
-        // sequences into it.
-        let seq_size = sequence::SIZE.unwrap();
-        let seq_page_size = sequence_page::sequences::OFFSET;
-        let seq_wal_entry = make_wal_entry(
-            WalEntryType::SequenceSync,
-            SEQUENCE_PAGE_ID as PageId,
-            None,
-            0,
-            ts,
-            0,
-            seq_page_size + (seq_size * sequences.len()),
-            |buf| {
-                let mut sequence_page = sequence_page::View::new(buf);
-                sequence_page
-                    .num_sequences_mut()
-                    .write(sequences.len() as u64);
-                for (i, sequence_value) in sequences.iter().enumerate() {
-                    let mut sequence = sequence::View::new(
-                        &mut sequence_page.sequences_mut()[i * seq_size..(i + 1) * seq_size],
-                    );
-                    sequence.id_mut().write(i as u64);
-                    sequence.value_mut().write(*sequence_value);
-                }
-            },
-        )
-        .expect("Failed to encode sequence WAL entry");
-        write_batch.push((SEQUENCE_PAGE_ID, Some(seq_wal_entry)));
+        // Build the sequence page first, from the current values of all the sequences, and the
+        // schema.
+        let sequence_page = sequence_page::encode(schema, &sequences, ts);
+        write_batch.push((
+            SEQUENCE_PAGE_ID,
+            Some(sequence_wal_entry(ts, &sequence_page)),
+        ));
 
         // Now iterate over all the tuples referred to in the working set and produce WAL entries for them.
         let mut dirty_pages = HashSet::new();
@@ -247,6 +668,14 @@ impl ColdStorage {
                         )
                         .expect("Failed to encode insert WAL entry");
 
+                        if let Some(blob_id) = new_tuple.blob_id() {
+                            let blob = tuple_box.get_blob(blob_id).expect("Unable to read blob");
+                            tuple_box.blobs_committed([blob_id]);
+                            write_batch.push((
+                                new_tuple.id().page,
+                                Some(blob_wal_entry(blob_id, ts, &blob)),
+                            ));
+                        }
                         write_batch.push((new_tuple.id().page, Some(wal_entry_buffer)));
                         dirty_pages.insert((new_tuple.id().page, r.1.id));
                     }
@@ -273,7 +702,20 @@ impl ColdStorage {
                         )
                         .expect("Failed to encode update WAL entry");
 
+                        if let Some(blob_id) = new_tuple.blob_id() {
+                            let blob = tuple_box.get_blob(blob_id).expect("Unable to read blob");
+                            tuple_box.blobs_committed([blob_id]);
+                            write_batch.push((
+                                new_tuple.id().page,
+                                Some(blob_wal_entry(blob_id, ts, &blob)),
+                            ));
+                        }
                         write_batch.push((new_tuple.id().page, Some(wal_entry_buffer)));
+                        tuple_box
+                            .retire(old_tuple.id())
+                            .expect("Unable to retire replaced tuple");
+                        retired_blobs.extend(old_tuple.blob_id());
+                        dirty_pages.insert((new_tuple.id().page, r.1.id));
                         dirty_pages.insert((old_tuple.id().page, r.1.id));
                     }
                     TxTupleOp::Tombstone(tref, _) => {
@@ -290,6 +732,10 @@ impl ColdStorage {
                         )
                         .expect("Failed to encode tombstone WAL entry");
                         write_batch.push((tuple_id.page, Some(wal_entry_buffer)));
+                        tuple_box
+                            .retire(tuple_id)
+                            .expect("Unable to retire removed tuple");
+                        retired_blobs.extend(tref.blob_id());
                         dirty_pages.insert((tuple_id.page, r.1.id));
                     }
                     TxTupleOp::Value(_) => {
@@ -322,14 +768,9 @@ impl ColdStorage {
             write_batch.push((*page_id, Some(wal_entry_buffer)));
         }
 
-        let mut sync_wal = wal.begin_entry().expect("Failed to begin WAL entry");
-        for (_page_id, wal_entry_buf) in write_batch {
-            if let Some(wal_entry_buf) = wal_entry_buf {
-                sync_wal
-                    .write_chunk(&wal_entry_buf)
-                    .expect("Failed to write to WAL");
-            }
-        }
-        sync_wal.commit().expect("Failed to commit WAL entry");
+        write_batch
+            .into_iter()
+            .filter_map(|(_page_id, wal_entry_buf)| wal_entry_buf)
+            .collect()
     }
 }
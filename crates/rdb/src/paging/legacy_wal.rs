@@ -0,0 +1,83 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Databases written before we had our own write-ahead log used OkayWAL, in the `wal` directory.
+//! The only thing we still do with it is replay whatever was left in it at startup.
+
+use std::fmt::{Debug, Formatter};
+use std::path::Path;
+use std::sync::Arc;
+
+use okaywal::{Entry, EntryId, LogManager, SegmentReader, WriteAheadLog};
+use tracing::{error, info};
+
+use super::page_storage::PageStore;
+use super::wal::record_to_mutations;
+
+struct LegacyWalManager {
+    page_storage: Arc<PageStore>,
+}
+
+impl Debug for LegacyWalManager {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LegacyWalManager").finish()
+    }
+}
+
+impl LogManager for LegacyWalManager {
+    fn recover(&mut self, entry: &mut Entry<'_>) -> std::io::Result<()> {
+        let Some(chunks) = entry.read_all_chunks()? else {
+            return Ok(());
+        };
+        self.page_storage
+            .enqueue_page_mutations(record_to_mutations(&chunks))
+    }
+
+    fn checkpoint_to(
+        &mut self,
+        _last_checkpointed_id: EntryId,
+        checkpointed_entries: &mut SegmentReader,
+        _wal: &WriteAheadLog,
+    ) -> std::io::Result<()> {
+        while let Some(mut entry) = checkpointed_entries.read_entry()? {
+            let chunks = match entry.read_all_chunks() {
+                Ok(Some(chunks)) => chunks,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!(?e, "Failed to read chunks from entry");
+                    continue;
+                }
+            };
+            self.page_storage
+                .enqueue_page_mutations(record_to_mutations(&chunks))?;
+        }
+        Ok(())
+    }
+}
+
+/// If there's an OkayWAL log in `dir`, replay it into the page store and remove it.
+pub(crate) fn drain_legacy_wal(dir: &Path, page_storage: Arc<PageStore>) -> std::io::Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    info!(?dir, "Replaying and removing OkayWAL write-ahead log");
+    let manager = LegacyWalManager {
+        page_storage: page_storage.clone(),
+    };
+    let wal = WriteAheadLog::recover(dir, manager)?;
+    // Shutting down waits for the checkpoint of everything recovered.
+    wal.shutdown()?;
+    page_storage.wait_complete();
+    std::fs::remove_dir_all(dir)
+}
@@ -12,21 +12,32 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use std::path::PathBuf;
+
 use thiserror::Error;
 
+use crate::schema::Schema;
+use crate::RelationId;
+
+pub use page_storage::BlobId;
 pub use pager::Pager;
 pub use slotted_page::SlotId;
 pub use tuple_box::{PageId, TupleBox};
 pub use tuple_ptr::TuplePtr;
+pub use wal::{SyncPolicy, WalConfig};
+pub use wal_archive::{recover_to, RecoveryError, RecoveryTarget};
 
 mod backing;
 mod cold_storage;
+mod legacy_wal;
 mod page_storage;
 mod pager;
+mod sequence_page;
 mod slotted_page;
 mod tuple_box;
 mod tuple_ptr;
 mod wal;
+mod wal_archive;
 
 #[derive(Debug, Clone, Error)]
 pub enum TupleBoxError {
@@ -34,4 +45,61 @@ pub enum TupleBoxError {
     BoxFull(usize, usize),
     #[error("Tuple not found at index {0}")]
     TupleNotFound(usize),
+    #[error("Could not store or retrieve out of line value: {0}")]
+    BlobError(String),
+    #[error("Page {0} is malformed: {1}")]
+    MalformedPage(PageId, String),
+}
+
+/// Something wrong with the pages or tuples of a database, found while loading it from disk.
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum Damage {
+    #[error("Page {page} of relation {} is malformed: {reason}", .relation.0)]
+    MalformedPage {
+        relation: RelationId,
+        page: PageId,
+        reason: String,
+    },
+    #[error("Tuple in slot {slot} of page {page} of relation {} is damaged: {reason}", .relation.0)]
+    DamagedTuple {
+        relation: RelationId,
+        page: PageId,
+        slot: SlotId,
+        reason: String,
+    },
+    #[error("Blob {blob} holding the value of the tuple in slot {slot} of page {page} of relation {} is missing", .relation.0)]
+    MissingBlob {
+        relation: RelationId,
+        page: PageId,
+        slot: SlotId,
+        blob: BlobId,
+    },
+}
+
+/// Errors which can occur while opening a database.
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum OpenError {
+    #[error("No database found at {0}")]
+    NotFound(PathBuf),
+    #[error("Database at {0} has commits in its write-ahead log which haven't been applied to its pages yet")]
+    UnappliedJournal(PathBuf),
+    #[error("Unable to read database: {0}")]
+    Io(String),
+    #[error("Database at {} was written with a different schema: {}", .path.display(), .stored.describe_difference(.expected))]
+    SchemaMismatch {
+        path: PathBuf,
+        stored: Schema,
+        expected: Schema,
+    },
+}
+
+/// What to do about damaged pages and tuples found while loading a database.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum OnDamage {
+    /// Refuse to open the database.
+    Refuse,
+    /// Leave them out of the relations, but leave them be on disk.
+    SetAside,
+    /// Leave them out of the relations, and remove them from disk too.
+    Discard,
 }
@@ -15,13 +15,13 @@
 // TODO: Robustness testing and proofing for page storage and WAL.
 //   A battery of tests is going to be needed on this, to verify the ACIDity.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{File, OpenOptions};
-use std::io::Read;
+use std::io::{Read, Write};
 use std::os::fd::{AsRawFd, RawFd};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::{yield_now, JoinHandle};
 use std::time::Duration;
@@ -40,6 +40,15 @@ use crate::RelationId;
 // TODO: we should probably have a way to handle io_uring backpressure.
 const IO_URING_SUBMISSION_Q_SIZE: u32 = 4096;
 
+/// The subdirectory of the page store that pages evicted from memory are written to.
+const EVICTED_DIR: &str = "evicted";
+
+/// The subdirectory of the page store that values too large to keep in a page are stored in.
+const BLOBS_DIR: &str = "blobs";
+
+/// Identifies a value stored out of line, in its own file, rather than in a page.
+pub type BlobId = u64;
+
 #[derive(Debug)]
 pub enum PageStoreMutation {
     PageTupleWrite {
@@ -56,6 +65,101 @@ pub enum PageStoreMutation {
     },
     WriteSequencePage(Box<[u8]>),
     DeleteTuple(PageId, RelationId),
+    WriteBlob {
+        blob_id: BlobId,
+        data: Box<[u8]>,
+    },
+}
+
+/// Collapse a batch of mutations into an equivalent one in which no two writes to the same file
+/// overlap, so that it doesn't matter what order they land in. (io_uring only orders writes which
+/// are linked, and the writes for one page can be spread through the batch.)
+/// Each page's writes come out together, ending with the write at the start of the page (the
+/// header), which is what gets the fsync.
+/// Blob writes come first, so they're in place before any page which refers to them.
+fn coalesce_mutations(batch: Vec<PageStoreMutation>) -> Vec<PageStoreMutation> {
+    // Page -> (relation, offset -> data), with the extents kept non-overlapping.
+    type Extents = BTreeMap<usize, Box<[u8]>>;
+    let mut pages: BTreeMap<PageId, (RelationId, Extents)> = BTreeMap::new();
+    let mut sequence_page = None;
+    let mut blobs = BTreeMap::new();
+    for mutation in batch {
+        let (relation_id, page_id, offset, data) = match mutation {
+            PageStoreMutation::PageHeaderWrite {
+                relation_id,
+                page_id,
+                data,
+            } => (relation_id, page_id, 0, data),
+            PageStoreMutation::PageTupleWrite {
+                relation_id,
+                page_id,
+                page_offset,
+                data,
+                ..
+            } => (relation_id, page_id, page_offset, data),
+            PageStoreMutation::WriteSequencePage(data) => {
+                sequence_page = Some(data);
+                continue;
+            }
+            PageStoreMutation::DeleteTuple(_, _) => continue,
+            PageStoreMutation::WriteBlob { blob_id, data } => {
+                blobs.insert(blob_id, data);
+                continue;
+            }
+        };
+        if data.is_empty() {
+            continue;
+        }
+        let (_, extents) = pages
+            .entry(page_id)
+            .or_insert_with(|| (relation_id, BTreeMap::new()));
+
+        // Trim back whatever this write covers, keeping any parts sticking out either side.
+        let end = offset + data.len();
+        let overlapping: Vec<usize> = extents
+            .range(..end)
+            .filter(|(o, d)| *o + d.len() > offset)
+            .map(|(o, _)| *o)
+            .collect();
+        for o in overlapping {
+            let old = extents.remove(&o).unwrap();
+            if o < offset {
+                extents.insert(o, old[..offset - o].into());
+            }
+            if o + old.len() > end {
+                extents.insert(end, old[end - o..].into());
+            }
+        }
+        extents.insert(offset, data);
+    }
+
+    let mut coalesced: Vec<_> = blobs
+        .into_iter()
+        .map(|(blob_id, data)| PageStoreMutation::WriteBlob { blob_id, data })
+        .collect();
+    for (page_id, (relation_id, mut extents)) in pages {
+        let header = extents.remove(&0);
+        for (page_offset, data) in extents {
+            coalesced.push(PageStoreMutation::PageTupleWrite {
+                relation_id,
+                page_id,
+                slot_id: 0, /* not used */
+                page_offset,
+                data,
+            });
+        }
+        if let Some(data) = header {
+            coalesced.push(PageStoreMutation::PageHeaderWrite {
+                relation_id,
+                page_id,
+                data,
+            });
+        }
+    }
+    if let Some(data) = sequence_page {
+        coalesced.push(PageStoreMutation::WriteSequencePage(data));
+    }
+    coalesced
 }
 
 /// Manages the directory of page files, currently one file per page.
@@ -67,7 +171,6 @@ pub enum PageStoreMutation {
 /// TODO: right now page storage is page-per-file which is maybe not the most efficient.
 /// TODO: verify the fsync chained to writes via io_uring is actually working, and that
 ///   the durability guarantees are, at least approximately, correct.
-/// TODO: we'll need reads once eviction/paging is implemented.
 /// TODO: we should have CRCs on disk-bound pages, and verify them on reads.  
 ///   could live in the page header maybe
 
@@ -78,6 +181,8 @@ pub struct PageStore {
     inner: Mutex<Inner>,
     running: Arc<AtomicBool>,
     join_handle: Mutex<Option<JoinHandle<()>>>,
+    /// The next blob id to hand out; above any blob that's still on disk.
+    next_blob_id: AtomicU64,
 }
 
 struct Inner {
@@ -130,6 +235,20 @@ impl PageStore {
         if !dir.exists() {
             std::fs::create_dir_all(&dir).unwrap();
         }
+        // Anything evicted by a previous run was only ever a copy of memory which is now gone.
+        let evicted_dir = dir.join(EVICTED_DIR);
+        if evicted_dir.exists() {
+            std::fs::remove_dir_all(&evicted_dir).unwrap();
+        }
+        let blobs_dir = dir.join(BLOBS_DIR);
+        if !blobs_dir.exists() {
+            std::fs::create_dir_all(&blobs_dir).unwrap();
+        }
+        let next_blob_id = list_blobs(&blobs_dir)
+            .unwrap()
+            .into_iter()
+            .max()
+            .map_or(0, |max| max + 1);
         let uring = IoUring::new(IO_URING_SUBMISSION_Q_SIZE).unwrap();
 
         let event_fd = make_eventfd();
@@ -149,6 +268,7 @@ impl PageStore {
             event_fd,
             running: Arc::new(AtomicBool::new(false)),
             join_handle: Mutex::new(None),
+            next_blob_id: AtomicU64::new(next_blob_id),
         })
     }
 
@@ -231,7 +351,7 @@ impl PageStore {
         for entry in std::fs::read_dir(&self.dir).unwrap() {
             let entry = entry.unwrap();
             let filename = entry.file_name();
-            if filename == "sequences.page" {
+            if filename == "sequences.page" || entry.file_type().unwrap().is_dir() {
                 continue;
             }
             let filename = filename.to_str().unwrap();
@@ -247,7 +367,12 @@ impl PageStore {
 
     /// Read the special sequences page into a buffer.
     pub(crate) fn read_sequence_page(&self) -> std::io::Result<Option<Vec<u8>>> {
-        let path = self.dir.join("sequences.page");
+        Self::read_sequence_page_in(&self.dir)
+    }
+
+    /// Read the sequences page of the page store in `dir`, without setting up a page store.
+    pub(crate) fn read_sequence_page_in(dir: &Path) -> std::io::Result<Option<Vec<u8>>> {
+        let path = dir.join("sequences.page");
         let mut file = match File::open(path) {
             Ok(f) => f,
             Err(e) => {
@@ -287,13 +412,106 @@ impl PageStore {
         Ok(())
     }
 
+    /// Write out the full image of a page being evicted from memory, to be read back by
+    /// `read_evicted_page` when it's next needed.
+    /// These aren't part of the database proper (which is what the WAL and the page files are for)
+    /// and don't survive a restart, so they aren't synced.
+    pub(crate) fn write_evicted_page(&self, page_id: PageId, buf: &[u8]) -> std::io::Result<()> {
+        let dir = self.dir.join(EVICTED_DIR);
+        if !dir.exists() {
+            std::fs::create_dir_all(&dir)?;
+        }
+        std::fs::write(dir.join(format!("{}.page", page_id)), buf)
+    }
+
+    /// Read back the image of a page written by `write_evicted_page`.
+    pub(crate) fn read_evicted_page(
+        &self,
+        page_id: PageId,
+        mut buf: Pin<&mut [u8]>,
+    ) -> std::io::Result<()> {
+        let path = self.dir.join(EVICTED_DIR).join(format!("{}.page", page_id));
+        File::open(path)?.read_exact(buf.as_mut().get_mut())
+    }
+
+    fn blob_path(&self, blob_id: BlobId) -> PathBuf {
+        self.dir.join(BLOBS_DIR).join(format!("{}.blob", blob_id))
+    }
+
+    /// Pick an id for a new blob.
+    pub(crate) fn next_blob_id(&self) -> BlobId {
+        self.next_blob_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// The ids of all the blobs on disk.
+    pub(crate) fn list_blobs(&self) -> std::io::Result<Vec<BlobId>> {
+        list_blobs(&self.dir.join(BLOBS_DIR))
+    }
+
+    /// Write the contents of a blob, replacing it atomically if it's already there, so readers
+    /// never see a partial blob. If `sync`, it's durable when this returns.
+    pub(crate) fn write_blob(
+        &self,
+        blob_id: BlobId,
+        data: &[u8],
+        sync: bool,
+    ) -> std::io::Result<()> {
+        self.next_blob_id.fetch_max(blob_id + 1, Ordering::SeqCst);
+        let path = self.blob_path(blob_id);
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        if sync {
+            file.sync_all()?;
+        }
+        std::fs::rename(tmp_path, path)
+    }
+
+    pub(crate) fn read_blob(&self, blob_id: BlobId) -> std::io::Result<Vec<u8>> {
+        std::fs::read(self.blob_path(blob_id))
+    }
+
+    pub(crate) fn remove_blobs(&self, blob_ids: &[BlobId]) -> std::io::Result<()> {
+        for blob_id in blob_ids {
+            match std::fs::remove_file(self.blob_path(*blob_id)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the pages, sequence page and blobs to a new page store in `dest`, syncing each file.
+    /// Evicted pages and half-written blobs are left behind. The caller has to make sure nothing
+    /// writes to the pages meanwhile; blobs which are no longer referred to by the pages can still
+    /// be removed, and are skipped.
+    pub(crate) fn copy_to(&self, dest: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dest.join(BLOBS_DIR))?;
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                copy_synced(&entry.path(), &dest.join(entry.file_name()))?;
+            }
+        }
+        for blob_id in self.list_blobs()? {
+            let name = format!("{}.blob", blob_id);
+            match copy_synced(&self.blob_path(blob_id), &dest.join(BLOBS_DIR).join(name)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     /// Enqueue a batch of mutations to be written to disk. Will return immediately after
-    /// submitting the batch to the kernel via io_uring.
+    /// submitting the batch to the kernel via io_uring. The end result is as if the mutations
+    /// were applied in order.
     pub(crate) fn enqueue_page_mutations(
         &self,
         batch: Vec<PageStoreMutation>,
     ) -> std::io::Result<()> {
         self.wait_complete();
+        let batch = coalesce_mutations(batch);
 
         // Open all the pages mentioned in the batch and index them by page id so we only have one file descriptor
         // open per page file.
@@ -328,6 +546,11 @@ impl PageStore {
         }
 
         for mutation in batch {
+            // Blobs are few, and big, so just write them out directly.
+            if let PageStoreMutation::WriteBlob { blob_id, data } = &mutation {
+                self.write_blob(*blob_id, data, true)?;
+                continue;
+            }
             let request_id = self
                 .next_request_id
                 .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
@@ -398,7 +621,13 @@ impl PageStore {
 
                     let len = data.len();
                     let mut options = OpenOptions::new();
-                    let file = options.write(true).append(false).create(true).open(path)?;
+                    // Truncate, in case the previous page was longer, so its tail isn't left over.
+                    let file = options
+                        .write(true)
+                        .append(false)
+                        .create(true)
+                        .truncate(true)
+                        .open(path)?;
                     let raw_fd = file.as_raw_fd();
 
                     let mut inner = self.inner.lock().unwrap();
@@ -432,6 +661,7 @@ impl PageStore {
                     // We could zero-out this data, but it's not really necessary, the header will
                     // be updated to indicate the slot is free.
                 }
+                PageStoreMutation::WriteBlob { .. } => unreachable!("blobs are written above"),
             }
             let inner = self.inner.lock().unwrap();
             inner.uring.submit().expect("Unable to submit to io_uring");
@@ -440,3 +670,99 @@ impl PageStore {
         Ok(())
     }
 }
+
+fn copy_synced(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::copy(from, to)?;
+    File::open(to)?.sync_all()
+}
+
+/// The ids of the blobs in `dir`, ignoring any half-written ones.
+fn list_blobs(dir: &Path) -> std::io::Result<Vec<BlobId>> {
+    let mut blobs = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("blob") {
+            continue;
+        }
+        if let Some(blob_id) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse().ok())
+        {
+            blobs.push(blob_id);
+        }
+    }
+    Ok(blobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{coalesce_mutations, PageStoreMutation};
+    use crate::RelationId;
+
+    fn write(offset: usize, data: &[u8]) -> PageStoreMutation {
+        if offset == 0 {
+            PageStoreMutation::PageHeaderWrite {
+                relation_id: RelationId(0),
+                page_id: 1,
+                data: data.into(),
+            }
+        } else {
+            PageStoreMutation::PageTupleWrite {
+                relation_id: RelationId(0),
+                page_id: 1,
+                slot_id: 0,
+                page_offset: offset,
+                data: data.into(),
+            }
+        }
+    }
+
+    /// Apply mutations (to page 1) in order to a buffer.
+    fn apply(mutations: &[PageStoreMutation]) -> Vec<u8> {
+        let mut page = vec![0; 16];
+        for m in mutations {
+            match m {
+                PageStoreMutation::PageHeaderWrite { data, .. } => {
+                    page[..data.len()].copy_from_slice(data)
+                }
+                PageStoreMutation::PageTupleWrite {
+                    page_offset, data, ..
+                } => page[*page_offset..*page_offset + data.len()].copy_from_slice(data),
+                _ => {}
+            }
+        }
+        page
+    }
+
+    #[test]
+    fn coalesced_writes_do_not_overlap() {
+        let batch = vec![
+            write(0, &[1; 8]),
+            write(4, &[2; 8]),
+            write(10, &[3; 2]),
+            write(0, &[4; 2]),
+            write(14, &[5; 2]),
+        ];
+        let expected = apply(&batch);
+        let coalesced = coalesce_mutations(batch);
+
+        // Any order gives the same result, since nothing overlaps; try it backwards.
+        let mut reversed = coalesced;
+        reversed.reverse();
+        assert_eq!(apply(&reversed), expected);
+
+        let mut extents: Vec<(usize, usize)> = reversed
+            .iter()
+            .map(|m| match m {
+                PageStoreMutation::PageHeaderWrite { data, .. } => (0, data.len()),
+                PageStoreMutation::PageTupleWrite {
+                    page_offset, data, ..
+                } => (*page_offset, data.len()),
+                _ => unreachable!(),
+            })
+            .collect();
+        extents.sort();
+        assert!(extents.windows(2).all(|w| w[0].0 + w[0].1 <= w[1].0));
+    }
+}
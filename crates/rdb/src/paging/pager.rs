@@ -19,64 +19,160 @@
 use crate::{
     base_relation::BaseRelation,
     pool::{Bid, BufferPool, PagerError},
+    schema::{Schema, SchemaCheck},
     tx::WorkingSet,
+    BackupError, CommitError,
 };
+use kanal::Receiver;
 use std::{
-    collections::HashMap,
-    path::PathBuf,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
     pin::Pin,
     sync::{
         atomic::{AtomicPtr, AtomicUsize, Ordering},
         Arc, Mutex,
     },
 };
+use tracing::warn;
 
-use super::{backing::BackingStoreClient, cold_storage::ColdStorage, PageId, TupleBox};
+use super::{
+    backing::BackingStoreClient,
+    cold_storage::ColdStorage,
+    page_storage::{BlobId, PageStore},
+    wal::WalConfig,
+    Damage, OnDamage, OpenError, PageId, TupleBox,
+};
+
+/// How much address space to reserve for each size class in the buffer pool. Only what's
+/// resident uses physical memory, so this just has to be comfortably more than we'll ever have
+/// resident at once.
+const VIRTUAL_RESERVATION: usize = 1 << 36;
 
 pub struct Pager {
     inner: Mutex<Inner>,
     next_pid: AtomicUsize,
     cold_storage: Mutex<Option<BackingStoreClient>>,
+    /// How many bytes of pages we try to keep in memory, once we have somewhere to evict to.
+    resident_budget: usize,
 }
 
 struct Inner {
     pool: BufferPool,
     page_table: HashMap<PageId, Bid>,
+    /// Where pages go when they're evicted. None until opened on a directory, in which case
+    /// everything stays in memory.
+    page_store: Option<Arc<PageStore>>,
+    /// The sizes of the pages which have been evicted, and which will have to be paged back in
+    /// before they can be used.
+    paged_out: HashMap<PageId, usize>,
+    /// Blobs, when there's no page store to keep them in.
+    blobs: HashMap<BlobId, Arc<[u8]>>,
+    next_blob_id: BlobId,
+    /// Blobs in the page store which have been handed to the write-ahead log, and so may be
+    /// referred to by pages on disk. Any others belong to tuples which were never committed.
+    committed_blobs: HashSet<BlobId>,
+    /// Blobs in the page store whose tuples are gone from memory, but which are still referred
+    /// to by pages on disk until the commit which retired the tuple is checkpointed.
+    freed_blobs: HashSet<BlobId>,
+    /// Blobs in the page store whose tuples have been retired on disk, but which are still
+    /// referred to in memory.
+    retired_blobs: HashSet<BlobId>,
 }
 
 impl Pager {
-    /// Construct a pager with the resident maximum memory size of `size` bytes.
-    pub fn new(size: usize) -> Result<Self, PagerError> {
-        let pool = BufferPool::new(size)?;
+    /// Construct a pager which keeps up to `resident_budget` bytes of pages in memory, evicting
+    /// pages to cold storage (once opened on a path) when there are more than that. Until then,
+    /// there's nowhere for pages to go, and the budget is a hard limit.
+    pub fn new(resident_budget: usize) -> Result<Self, PagerError> {
+        let pool = BufferPool::new(VIRTUAL_RESERVATION)?;
 
         Ok(Self {
             inner: Mutex::new(Inner {
                 pool,
                 page_table: HashMap::new(),
+                page_store: None,
+                paged_out: HashMap::new(),
+                blobs: HashMap::new(),
+                next_blob_id: 0,
+                committed_blobs: HashSet::new(),
+                freed_blobs: HashSet::new(),
+                retired_blobs: HashSet::new(),
             }),
             cold_storage: Mutex::new(None),
             next_pid: AtomicUsize::new(0),
+            resident_budget,
         })
     }
 
     /// Restore pages and the tuples they contain, and the indexes to those tuples, and set up
-    /// the pager to use the provided directory for cold storage.
-    pub fn open(
+    /// the pager to use the provided directory for cold storage, with its write-ahead log set up
+    /// per `wal_config`.
+    ///
+    /// Returns the last transaction timestamp committed (or archived), if known. Also returns the
+    /// damage found while loading, which was dealt with per `on_damage`, and the schema the
+    /// database is opened under, once its own has passed `schema_check`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn open(
         &self,
         path: PathBuf,
+        wal_config: WalConfig,
         relations: &mut [BaseRelation],
         sequences: &mut [u64],
         tuple_box: Arc<TupleBox>,
-    ) -> Result<(), PagerError> {
+        on_damage: OnDamage,
+        schema_check: &SchemaCheck,
+    ) -> Result<(Option<u64>, Vec<Damage>, Schema), OpenError> {
+        // Set up the page store first, so that pages can be evicted to it while loading.
+        let page_storage = PageStore::new(path.join("pages"));
+        self.inner.lock().unwrap().page_store = Some(page_storage.clone());
+
         let mut cs = self.cold_storage.lock().unwrap();
-        (*cs) = Some(ColdStorage::start(
+        let (client, last_ts, damage, schema) = ColdStorage::start(
             path,
+            page_storage,
+            wal_config,
             relations,
             sequences,
             tuple_box.clone(),
-        ));
+            on_damage,
+            schema_check,
+        )?;
+        (*cs) = Some(client);
 
-        Ok(())
+        Ok((last_ts, damage, schema))
+    }
+
+    /// As `open`, but without writing anything to the database, or allowing anything to be
+    /// written to it. Damaged pages and tuples are left out, and returned.
+    pub(crate) fn open_read_only(
+        &self,
+        path: PathBuf,
+        relations: &mut [BaseRelation],
+        sequences: &mut [u64],
+        tuple_box: Arc<TupleBox>,
+        schema_check: &SchemaCheck,
+    ) -> Result<(Vec<Damage>, Schema), OpenError> {
+        let pages_path = path.join("pages");
+        if !pages_path.is_dir() {
+            return Err(OpenError::NotFound(path));
+        }
+        // The page store is still needed to read blobs, and to evict pages to (which is scratch
+        // space, not part of the database).
+        let page_storage = PageStore::new(pages_path);
+        self.inner.lock().unwrap().page_store = Some(page_storage.clone());
+        ColdStorage::open_read_only(
+            path,
+            page_storage,
+            relations,
+            sequences,
+            tuple_box,
+            schema_check,
+        )
+    }
+
+    /// The schema recorded in the database at `path`, if anything has been committed to it yet.
+    pub(crate) fn stored_schema(path: &Path) -> Result<Option<Schema>, OpenError> {
+        Ok(ColdStorage::stored_sequence_page(path)?.map(|page| page.schema))
     }
 
     /// Allocate a page, and fill it with the provided function.
@@ -85,6 +181,7 @@ impl Pager {
         F: FnMut(Pin<&mut [u8]>),
     {
         let mut inner = self.inner.lock().unwrap();
+        self.check_room(&inner, size)?;
         let (bid, buf_ptr, used_size) = inner.pool.alloc(size)?;
         let as_slice = unsafe { std::slice::from_raw_parts_mut(buf_ptr, size) };
         let mut as_pin = Pin::new(as_slice);
@@ -98,21 +195,193 @@ impl Pager {
     /// Free a page, and return it to the pool.
     pub fn free(&self, page_id: PageId) -> Result<(), PagerError> {
         let mut inner = self.inner.lock().unwrap();
-        let bid = inner
-            .page_table
-            .remove(&page_id)
-            .ok_or(PagerError::InvalidPage)?;
+        let Some(bid) = inner.page_table.remove(&page_id) else {
+            return match inner.paged_out.remove(&page_id) {
+                Some(_) => Ok(()),
+                None => Err(PagerError::InvalidPage),
+            };
+        };
         inner.pool.free(bid)
     }
 
     /// Resolve a page id to a pointer to the page's buffer.
     pub fn resolve_ptr(&self, page_id: PageId) -> Result<(*mut u8, usize), PagerError> {
         let inner = self.inner.lock().unwrap();
-        let bid = inner
+        let Some(bid) = inner.page_table.get(&page_id) else {
+            return match inner.paged_out.contains_key(&page_id) {
+                true => Err(PagerError::PagedOut),
+                false => Err(PagerError::InvalidPage),
+            };
+        };
+        inner.pool.resolve_ptr(*bid)
+    }
+
+    /// The number of bytes of pages currently in memory.
+    pub fn resident_bytes(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner.pool.allocated_bytes()
+    }
+
+    /// How many bytes of pages would have to be evicted to get back within the resident budget.
+    /// Always 0 if there's nowhere to evict pages to.
+    pub fn over_budget(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        if inner.page_store.is_none() {
+            return 0;
+        }
+        inner
+            .pool
+            .allocated_bytes()
+            .saturating_sub(self.resident_budget)
+    }
+
+    /// How many bytes of pages we try to keep in memory.
+    pub fn resident_budget(&self) -> usize {
+        self.resident_budget
+    }
+
+    /// The ids of all the pages currently in memory, in order.
+    pub fn resident_pages(&self) -> Vec<PageId> {
+        let inner = self.inner.lock().unwrap();
+        let mut pages: Vec<_> = inner.page_table.keys().copied().collect();
+        pages.sort_unstable();
+        pages
+    }
+
+    /// Write a page out to the page store and release its buffer. It's up to the caller to make
+    /// sure nothing is using it. Returns the number of bytes released.
+    pub fn page_out(&self, page_id: PageId) -> Result<usize, PagerError> {
+        let mut inner = self.inner.lock().unwrap();
+        let page_store = inner.page_store.clone().ok_or(PagerError::PagingError(
+            "no page store to evict to".to_string(),
+        ))?;
+        let bid = *inner
             .page_table
             .get(&page_id)
             .ok_or(PagerError::InvalidPage)?;
-        inner.pool.resolve_ptr(*bid)
+        let (ptr, page_size) = inner.pool.resolve_ptr(bid)?;
+        let image = unsafe { std::slice::from_raw_parts(ptr, page_size) };
+        page_store
+            .write_evicted_page(page_id, image)
+            .map_err(|e| PagerError::PagingError(e.to_string()))?;
+        inner.page_table.remove(&page_id);
+        inner.pool.free(bid)?;
+        inner.paged_out.insert(page_id, page_size);
+        Ok(page_size)
+    }
+
+    /// Bring a page written out by `page_out` back into (a new buffer in) memory, returning its
+    /// new address and size.
+    pub fn page_in(&self, page_id: PageId) -> Result<(*mut u8, usize), PagerError> {
+        let mut inner = self.inner.lock().unwrap();
+        let page_size = *inner
+            .paged_out
+            .get(&page_id)
+            .ok_or(PagerError::InvalidPage)?;
+        let page_store = inner.page_store.clone().ok_or(PagerError::InvalidPage)?;
+        let (bid, buf_ptr, used_size) = inner.pool.alloc(page_size)?;
+        let buf = unsafe { std::slice::from_raw_parts_mut(buf_ptr, used_size) };
+        if let Err(e) = page_store.read_evicted_page(page_id, Pin::new(buf)) {
+            inner.pool.free(bid)?;
+            return Err(PagerError::PagingError(e.to_string()));
+        }
+        inner.paged_out.remove(&page_id);
+        inner.page_table.insert(page_id, bid);
+        Ok((buf_ptr, used_size))
+    }
+
+    /// Store a value out of line, outside of the buffer pool, returning the id to fetch it with.
+    pub fn put_blob(&self, data: &[u8]) -> Result<BlobId, PagerError> {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(page_store) = inner.page_store.clone() else {
+            let blob_id = inner.next_blob_id;
+            inner.next_blob_id += 1;
+            inner.blobs.insert(blob_id, data.into());
+            return Ok(blob_id);
+        };
+        drop(inner);
+
+        // This doesn't need to be durable; the commit which makes use of it will carry a copy of
+        // it in the write-ahead log.
+        let blob_id = page_store.next_blob_id();
+        page_store
+            .write_blob(blob_id, data, false)
+            .map_err(|e| PagerError::PagingError(e.to_string()))?;
+        Ok(blob_id)
+    }
+
+    /// Read the contents of a blob stored by `put_blob`.
+    pub fn get_blob(&self, blob_id: BlobId) -> Result<Vec<u8>, PagerError> {
+        let inner = self.inner.lock().unwrap();
+        let Some(page_store) = inner.page_store.clone() else {
+            return inner
+                .blobs
+                .get(&blob_id)
+                .map(|data| data.to_vec())
+                .ok_or(PagerError::InvalidPage);
+        };
+        drop(inner);
+        page_store
+            .read_blob(blob_id)
+            .map_err(|e| PagerError::PagingError(e.to_string()))
+    }
+
+    /// Let go of a blob that's no longer referred to in memory.
+    /// Blobs of tuples which were never committed are removed right away. Committed ones are only
+    /// removed once pages on disk stop referring to them too; that is, once the commit which
+    /// retired the tuple has been checkpointed (see `blobs_retired`).
+    pub fn free_blob(&self, blob_id: BlobId) {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(page_store) = inner.page_store.clone() else {
+            inner.blobs.remove(&blob_id);
+            return;
+        };
+        if inner.committed_blobs.contains(&blob_id) {
+            if !inner.retired_blobs.remove(&blob_id) {
+                inner.freed_blobs.insert(blob_id);
+                return;
+            }
+            inner.committed_blobs.remove(&blob_id);
+        }
+        drop(inner);
+        Self::remove_blobs(&page_store, &[blob_id]);
+    }
+
+    /// These blobs have been handed to the write-ahead log (or were loaded from the page store), so
+    /// pages on disk may refer to them until their tuples are retired.
+    pub(crate) fn blobs_committed(&self, blob_ids: impl IntoIterator<Item = BlobId>) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.page_store.is_none() {
+            return;
+        }
+        inner.committed_blobs.extend(blob_ids);
+    }
+
+    /// The commits which retired the tuples with these blobs have been checkpointed into the page
+    /// store, so the blobs can be removed from it once nothing in memory refers to them either.
+    pub(crate) fn blobs_retired(&self, blob_ids: &[BlobId]) {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(page_store) = inner.page_store.clone() else {
+            return;
+        };
+        let mut unused = vec![];
+        for blob_id in blob_ids {
+            if inner.freed_blobs.remove(blob_id) {
+                inner.committed_blobs.remove(blob_id);
+                unused.push(*blob_id);
+            } else {
+                inner.retired_blobs.insert(*blob_id);
+            }
+        }
+        drop(inner);
+        Self::remove_blobs(&page_store, &unused);
+    }
+
+    fn remove_blobs(page_store: &PageStore, blob_ids: &[BlobId]) {
+        // Anything left behind is cleaned up at the next startup.
+        if let Err(e) = page_store.remove_blobs(blob_ids) {
+            warn!(?e, "Unable to remove unused blobs");
+        }
     }
 
     /// Restore knowledge of a page (and a buffer) provided from cold storage.
@@ -122,6 +391,9 @@ impl Pager {
         page_size: usize,
     ) -> Result<(AtomicPtr<u8>, usize), PagerError> {
         let mut inner = self.inner.lock().unwrap();
+        // Make sure freshly allocated pages don't reuse the ids of restored ones.
+        self.next_pid.fetch_max(page_id + 1, Ordering::SeqCst);
+
         // If there's already a buffer for this page, confirm it's the
         // right size, and just return its existing address.
         // Otherwise allocate a new buffer.
@@ -135,16 +407,52 @@ impl Pager {
         }
 
         // Allocate a buffer for this page, and insert it into the page table.
+        self.check_room(&inner, page_size)?;
         let (bid, buf_ptr, used_size) = inner.pool.alloc(page_size)?;
         inner.page_table.insert(page_id, bid);
         Ok((AtomicPtr::new(buf_ptr), used_size))
     }
 
-    /// Sync the working set to cold storage (if any)
-    pub fn sync(&self, ts: u64, ws: WorkingSet, sequences: Vec<u64>) {
+    /// Without anywhere to evict pages to, we can't go over budget.
+    fn check_room(&self, inner: &Inner, size: usize) -> Result<(), PagerError> {
+        let allocated = inner.pool.allocated_bytes();
+        if inner.page_store.is_none() && allocated + size > self.resident_budget {
+            return Err(PagerError::InsufficientRoom {
+                desired: size,
+                available: self.resident_budget.saturating_sub(allocated),
+            });
+        }
+        Ok(())
+    }
+
+    /// Queue the working set to be synced to cold storage (if any), returning a receiver which is
+    /// signalled once it's durable.
+    pub fn queue_sync(
+        &self,
+        ts: u64,
+        ws: WorkingSet,
+        sequences: Vec<u64>,
+        schema: Arc<Schema>,
+    ) -> Result<Option<Receiver<()>>, CommitError> {
         let cs = self.cold_storage.lock().unwrap();
-        if let Some(cold_storage) = cs.as_ref() {
-            cold_storage.sync(ts, ws, sequences);
+        cs.as_ref()
+            .map(|cold_storage| cold_storage.sync(ts, ws, sequences, schema))
+            .transpose()
+    }
+
+    /// Start copying the store (as of the last commit synced) to `path`, returning a receiver for
+    /// the outcome. There's nothing to copy if there's no cold storage.
+    pub fn backup(
+        &self,
+        path: PathBuf,
+        ts: u64,
+        sequences: Vec<u64>,
+        schema: Arc<Schema>,
+    ) -> Result<Receiver<Result<(), BackupError>>, BackupError> {
+        let cs = self.cold_storage.lock().unwrap();
+        match cs.as_ref() {
+            Some(cold_storage) => cold_storage.backup(path, ts, sequences, schema),
+            None => Err(BackupError::NoBackingStore),
         }
     }
 
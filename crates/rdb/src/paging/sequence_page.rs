@@ -0,0 +1,266 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! The sequence page holds the current value of each sequence, the schema of the database, and
+//! the timestamp of the last commit. It's rewritten in full with every commit.
+
+use binary_layout::{binary_layout, Field};
+
+use crate::index::AttrType;
+use crate::schema::{CatalogEntry, Schema};
+
+/// Starts a sequence page which records the schema. Pages from before it was recorded start with
+/// the number of sequences instead, which is never going to be this.
+const SCHEMA_MAGIC: u64 = u64::from_le_bytes(*b"moorschm");
+
+binary_layout!(sequence_page, LittleEndian, {
+    // SCHEMA_MAGIC.
+    magic: u64,
+    // The version of the encoding of the tuples in the database.
+    schema_version: u64,
+    // The number of sequences stored in this page.
+    num_sequences: u64,
+    // The number of relations in the catalog.
+    num_relations: u64,
+    // The sequences, followed by the catalog, then the timestamp of the commit which wrote the
+    // page (as a u64; pages from before it was recorded stop after the catalog).
+    contents: [u8],
+});
+
+binary_layout!(legacy_sequence_page, LittleEndian, {
+    // The number of sequences stored in this page.
+    num_sequences: u64,
+    // The sequences are here..
+    sequences: [u8],
+});
+
+binary_layout!(sequence, LittleEndian, {
+    // The sequence id.
+    id: u64,
+    // The current value of the sequence.
+    value: u64,
+});
+
+binary_layout!(catalog_entry, LittleEndian, {
+    // The AttrType of the domain.
+    domain_type: u8,
+    // The AttrType of the codomain.
+    codomain_type: u8,
+    // The length of the name.
+    name_length: u16,
+    // The name of the relation, in UTF-8.
+    name: [u8],
+});
+
+/// What a sequence page records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SequencePage {
+    pub(crate) schema: Schema,
+    pub(crate) sequences: Vec<u64>,
+    /// The timestamp of the commit which wrote the page, if it was recorded.
+    pub(crate) last_ts: Option<u64>,
+}
+
+/// Encode the sequence page for the given schema and sequence values, as written by the commit
+/// at `ts`.
+pub(crate) fn encode(schema: &Schema, sequences: &[u64], ts: u64) -> Vec<u8> {
+    let sequence_size = sequence::SIZE.unwrap();
+    let catalog_size: usize = schema
+        .relations
+        .iter()
+        .map(|r| catalog_entry::name::OFFSET + r.name.len())
+        .sum();
+    let mut buf = vec![
+        0;
+        sequence_page::contents::OFFSET
+            + sequence_size * sequences.len()
+            + catalog_size
+            + std::mem::size_of::<u64>()
+    ];
+    let mut page = sequence_page::View::new(&mut buf[..]);
+    page.magic_mut().write(SCHEMA_MAGIC);
+    page.schema_version_mut().write(schema.version);
+    page.num_sequences_mut().write(sequences.len() as u64);
+    page.num_relations_mut()
+        .write(schema.relations.len() as u64);
+
+    let contents = page.contents_mut();
+    for (i, value) in sequences.iter().enumerate() {
+        let mut sequence =
+            sequence::View::new(&mut contents[i * sequence_size..(i + 1) * sequence_size]);
+        sequence.id_mut().write(i as u64);
+        sequence.value_mut().write(*value);
+    }
+    let mut offset = sequence_size * sequences.len();
+    for relation in &schema.relations {
+        let length = catalog_entry::name::OFFSET + relation.name.len();
+        let mut entry = catalog_entry::View::new(&mut contents[offset..offset + length]);
+        entry.domain_type_mut().write(relation.domain_type as u8);
+        entry
+            .codomain_type_mut()
+            .write(relation.codomain_type as u8);
+        entry.name_length_mut().write(relation.name.len() as u16);
+        entry.name_mut().copy_from_slice(relation.name.as_bytes());
+        offset += length;
+    }
+    contents[offset..].copy_from_slice(&ts.to_le_bytes());
+    buf
+}
+
+/// Decode a sequence page into the schema it records, the sequence values (by sequence id), and
+/// the timestamp of the commit which wrote it.
+pub(crate) fn decode(buf: &[u8]) -> Result<SequencePage, String> {
+    if buf.len() < legacy_sequence_page::sequences::OFFSET {
+        return Err(format!("sequence page is only {} bytes", buf.len()));
+    }
+    let page = sequence_page::View::new(buf);
+    if buf.len() < sequence_page::contents::OFFSET || page.magic().read() != SCHEMA_MAGIC {
+        let page = legacy_sequence_page::View::new(buf);
+        let sequences = decode_sequences(page.num_sequences().read(), page.sequences())?;
+        return Ok(SequencePage {
+            schema: Schema::unrecorded(),
+            sequences,
+            last_ts: None,
+        });
+    }
+
+    let num_sequences = page.num_sequences().read();
+    let contents = page.contents();
+    let sequences = decode_sequences(num_sequences, contents)?;
+    let mut offset = sequence::SIZE.unwrap() * num_sequences as usize;
+    let mut relations = vec![];
+    for i in 0..page.num_relations().read() {
+        let truncated = || format!("catalog entry {} is truncated", i);
+        let entry = contents
+            .get(offset..offset + catalog_entry::name::OFFSET)
+            .ok_or_else(truncated)?;
+        let entry = catalog_entry::View::new(entry);
+        let attr_type = |v: u8| {
+            AttrType::from_repr(v).ok_or_else(|| format!("catalog entry {} has type {}", i, v))
+        };
+        let domain_type = attr_type(entry.domain_type().read())?;
+        let codomain_type = attr_type(entry.codomain_type().read())?;
+        let name_start = offset + catalog_entry::name::OFFSET;
+        let name_end = name_start + entry.name_length().read() as usize;
+        let name = contents.get(name_start..name_end).ok_or_else(truncated)?;
+        let name = String::from_utf8(name.to_vec())
+            .map_err(|_| format!("catalog entry {} has a malformed name", i))?;
+        relations.push(CatalogEntry {
+            name,
+            domain_type,
+            codomain_type,
+        });
+        offset = name_end;
+    }
+    let schema = Schema {
+        version: page.schema_version().read(),
+        relations,
+    };
+    let last_ts = contents
+        .get(offset..offset + std::mem::size_of::<u64>())
+        .map(|ts| u64::from_le_bytes(ts.try_into().unwrap()));
+    Ok(SequencePage {
+        schema,
+        sequences,
+        last_ts,
+    })
+}
+
+fn decode_sequences(num_sequences: u64, buf: &[u8]) -> Result<Vec<u64>, String> {
+    let sequence_size = sequence::SIZE.unwrap();
+    let num_sequences = num_sequences as usize;
+    if buf.len() < num_sequences * sequence_size {
+        return Err(format!(
+            "sequence page is too short for {} sequences",
+            num_sequences
+        ));
+    }
+    let mut sequences = vec![0; num_sequences];
+    for i in 0..num_sequences {
+        let sequence = sequence::View::new(&buf[i * sequence_size..(i + 1) * sequence_size]);
+        let id = sequence.id().read() as usize;
+        let value = sequence.value().read();
+        *sequences
+            .get_mut(id)
+            .ok_or_else(|| format!("sequence id {} is out of range", id))? = value;
+    }
+    Ok(sequences)
+}
+
+#[cfg(test)]
+mod tests {
+    use binary_layout::Field;
+
+    use super::{decode, encode, legacy_sequence_page, sequence, SequencePage};
+    use crate::index::AttrType;
+    use crate::schema::{CatalogEntry, Schema};
+
+    #[test]
+    fn round_trip() {
+        let schema = Schema {
+            version: 3,
+            relations: vec![
+                CatalogEntry {
+                    name: "a".into(),
+                    domain_type: AttrType::Integer,
+                    codomain_type: AttrType::Bytes,
+                },
+                CatalogEntry {
+                    name: "relation_b".into(),
+                    domain_type: AttrType::Bytes,
+                    codomain_type: AttrType::String,
+                },
+            ],
+        };
+        let page = encode(&schema, &[5, 6, 7], 42);
+        assert_eq!(
+            decode(&page),
+            Ok(SequencePage {
+                schema: schema.clone(),
+                sequences: vec![5, 6, 7],
+                last_ts: Some(42),
+            })
+        );
+
+        // Pages from before the timestamp was recorded don't have one.
+        let untimed = &page[..page.len() - 8];
+        assert_eq!(decode(untimed).unwrap().last_ts, None);
+        assert_eq!(decode(untimed).unwrap().schema, schema);
+        assert!(decode(&untimed[..untimed.len() - 1]).is_err());
+    }
+
+    // Pages from before the schema was recorded still give up their sequences.
+    #[test]
+    fn legacy() {
+        let sequence_size = sequence::SIZE.unwrap();
+        let mut page = vec![0; legacy_sequence_page::sequences::OFFSET + 2 * sequence_size];
+        let mut view = legacy_sequence_page::View::new(&mut page[..]);
+        view.num_sequences_mut().write(2);
+        for (i, value) in [10, 20].iter().enumerate() {
+            let mut sequence = sequence::View::new(
+                &mut view.sequences_mut()[i * sequence_size..(i + 1) * sequence_size],
+            );
+            sequence.id_mut().write(i as u64);
+            sequence.value_mut().write(*value);
+        }
+        assert_eq!(
+            decode(&page),
+            Ok(SequencePage {
+                schema: Schema::unrecorded(),
+                sequences: vec![10, 20],
+                last_ts: None,
+            })
+        );
+    }
+}
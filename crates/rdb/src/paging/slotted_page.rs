@@ -119,6 +119,10 @@ impl PageHeader {
 #[repr(C, align(8))]
 struct IndexEntry {
     used: bool,
+    // Set when a commit has removed (or replaced) the tuple in this slot, but older transactions
+    // may still be reading it. It stays in use in memory until they're done, but is written out
+    // as free.
+    retired: bool,
     // The number of live references to this slot
     refcount: u16,
     // The offset of the slot in the content region
@@ -162,6 +166,7 @@ impl IndexEntry {
             index_entry.allocated = content_length as u32;
             index_entry.refcount = 0;
             index_entry.used = true;
+            index_entry.retired = false;
         }
     }
 
@@ -171,6 +176,8 @@ impl IndexEntry {
         unsafe {
             let entry = self.as_mut().get_unchecked_mut();
             entry.used = true;
+            entry.retired = false;
+            entry.refcount = 0;
             entry.used_bytes = size as u32;
         }
     }
@@ -181,6 +188,7 @@ impl IndexEntry {
             let index_entry = self.as_mut().get_unchecked_mut();
             let used_bytes = index_entry.used_bytes as usize;
             index_entry.used = false;
+            index_entry.retired = false;
             index_entry.used_bytes = 0;
             index_entry.refcount = 0;
             used_bytes
@@ -221,6 +229,35 @@ impl<'a> SlottedPage<'a> {
         Self::as_page_mut(base_address, page_size).write_lock()
     }
 
+    /// Write lock the page, unless someone else already has it locked.
+    pub fn try_for_page_mut(base_address: *mut u8, page_size: usize) -> Option<PageWriteGuard<'a>> {
+        let sp = Self::as_page_mut(base_address, page_size);
+        sp.header()
+            .lock_state
+            .compare_exchange(0, u32::MAX, Acquire, Relaxed)
+            .ok()?;
+        Some(PageWriteGuard {
+            base_address,
+            page_size: page_size as u32,
+            _marker: Default::default(),
+        })
+    }
+
+    /// Pick up a page whose image was written out by the evictor (while write locked) and has
+    /// just been read back in: clear its lock state, and return the slots in use and their
+    /// (new) addresses. Unlike `load`, refcounts are left alone, since the tuples were never
+    /// let go of.
+    pub(crate) fn paged_in(base_address: *mut u8, page_size: usize) -> Vec<(SlotId, *mut u8)> {
+        let sp = Self::as_page_mut(base_address, page_size);
+        let header = sp.header_mut();
+        header.lock_state.store(0, SeqCst);
+        header.writer_wake_counter.store(0, SeqCst);
+        sp.used_slots()
+            .into_iter()
+            .map(|(slot_id, _, ptr)| (slot_id, ptr))
+            .collect()
+    }
+
     fn as_page(base_address: *const u8, page_size: usize) -> Self {
         Self {
             base_address: base_address as *mut u8,
@@ -309,7 +346,8 @@ impl<'a> SlottedPage<'a> {
 
         // If the content start bleeds over into the index (+ our new entry), then we can't fit the slot.
         let index_entry_size = std::mem::size_of::<IndexEntry>();
-        if content_start_position <= current_index_end + index_entry_size {
+        let index_end = std::mem::size_of::<PageHeader>() + current_index_end + index_entry_size;
+        if content_start_position < index_end {
             return Err(TupleBoxError::BoxFull(
                 size + index_entry_size,
                 self.available_content_bytes(),
@@ -345,11 +383,12 @@ impl<'a> SlottedPage<'a> {
 
     /// Load into this page from an external byte source, which is assumed to be in our page
     /// format, and then reset all refcounts to 0, clear lock state, and return the set of all valid
-    /// slot IDs.
+    /// slot IDs. If what was loaded doesn't hold together as a page (see `check_layout`), the
+    /// problem is returned instead, and the page should not be used.
     pub(crate) fn load<LF: FnMut(Pin<&mut [u8]>)>(
         &self,
         mut lf: LF,
-    ) -> Vec<(SlotId, usize, *mut u8)> {
+    ) -> Result<Vec<(SlotId, usize, *mut u8)>, String> {
         // First copy in the physical bytes into our address.
         let memory_as_slice = unsafe {
             Pin::new_unchecked(std::slice::from_raw_parts_mut(
@@ -364,13 +403,92 @@ impl<'a> SlottedPage<'a> {
         header.lock_state.store(0, SeqCst);
         header.writer_wake_counter.store(0, SeqCst);
 
-        // Now reset all the refcounts to 1, and collect the list of all active slots.,
+        self.check_layout()?;
+
+        // Now reset all the refcounts to 1, and collect the list of all active slots.
+        let slots = self.used_slots();
+        for (slot_id, _, _) in &slots {
+            let mut index_entry = self.get_index_entry_mut(*slot_id);
+            let index_entry = unsafe { index_entry.as_mut().get_unchecked_mut() };
+            index_entry.refcount = 1;
+            index_entry.retired = false;
+        }
+        Ok(slots)
+    }
+
+    /// Check that the header and index describe a page which fits in its bounds: the index and
+    /// every slot it points to lie inside the page without overlapping each other, and the
+    /// header's count of used bytes agrees with the index.
+    fn check_layout(&self) -> Result<(), String> {
+        let header = self.header();
+        let page_size = self.page_size as usize;
+        let num_slots = header.num_slots as usize;
+        let index_length = header.index_length as usize;
+        let content_length = header.content_length as usize;
+        if index_length != num_slots * std::mem::size_of::<IndexEntry>() {
+            return Err(format!(
+                "index length {} does not match {} slots",
+                index_length, num_slots
+            ));
+        }
+        if std::mem::size_of::<PageHeader>() + index_length + content_length > page_size {
+            return Err(format!(
+                "index ({} bytes) and content ({} bytes) overrun the {} byte page",
+                index_length, content_length, page_size
+            ));
+        }
+
+        let content_start = page_size - content_length;
+        let mut extents = Vec::with_capacity(num_slots);
+        let mut used_bytes = 0;
+        for slot_id in 0..num_slots as SlotId {
+            let index_entry = self.get_index_entry(slot_id);
+            let offset = index_entry.offset as usize;
+            let allocated = index_entry.allocated as usize;
+            if offset % 8 != 0 || offset < content_start || offset + allocated > page_size {
+                return Err(format!(
+                    "slot {} at {}..{} is outside the content region {}..{}",
+                    slot_id,
+                    offset,
+                    offset + allocated,
+                    content_start,
+                    page_size
+                ));
+            }
+            if index_entry.used {
+                if index_entry.used_bytes > index_entry.allocated {
+                    return Err(format!(
+                        "slot {} uses {} bytes of its {}",
+                        slot_id, index_entry.used_bytes, allocated
+                    ));
+                }
+                used_bytes += index_entry.used_bytes as usize;
+            }
+            extents.push((offset, allocated, slot_id));
+        }
+        extents.sort();
+        for pair in extents.windows(2) {
+            let ((offset, allocated, slot_id), (next_offset, _, next_slot_id)) = (pair[0], pair[1]);
+            if offset + allocated > next_offset {
+                return Err(format!("slot {} overlaps slot {}", slot_id, next_slot_id));
+            }
+        }
+        if used_bytes != header.used_bytes as usize {
+            return Err(format!(
+                "header counts {} used bytes, but the slots use {}",
+                header.used_bytes, used_bytes
+            ));
+        }
+        Ok(())
+    }
+
+    /// The id, size, and address of every slot in use.
+    fn used_slots(&self) -> Vec<(SlotId, usize, *mut u8)> {
         let mut slots = vec![];
-        let num_slots = header.num_slots;
+        let num_slots = self.header().num_slots;
         for i in 0..num_slots {
-            let mut index_entry = self.get_index_entry_mut(i as SlotId);
+            let index_entry = self.get_index_entry(i as SlotId);
             if index_entry.used {
-                unsafe { index_entry.as_mut().get_unchecked_mut() }.refcount = 1;
                 let slot_id = i as SlotId;
                 let ptr = unsafe { self.base_address.offset(index_entry.offset as isize) };
                 slots.push((slot_id, index_entry.used_bytes as usize, ptr));
@@ -403,6 +521,15 @@ impl<'a> SlottedPage<'a> {
         Ok((self.available_content_bytes(), slot_size, is_empty))
     }
 
+    fn retire(&self, slot_id: SlotId) -> Result<(), TupleBoxError> {
+        let mut index_entry = self.get_index_entry_mut(slot_id);
+        if !index_entry.used {
+            return Err(TupleBoxError::TupleNotFound(slot_id as usize));
+        }
+        unsafe { index_entry.as_mut().get_unchecked_mut() }.retired = true;
+        Ok(())
+    }
+
     fn refcount(&self, slot_id: SlotId) -> Result<u16, TupleBoxError> {
         let index_entry = self.get_index_entry(slot_id);
         if !index_entry.used {
@@ -426,7 +553,6 @@ impl<'a> SlottedPage<'a> {
         Ok(false)
     }
 
-    #[allow(dead_code)]
     fn get_slot(&self, slot_id: SlotId) -> Result<Pin<&'a [u8]>, TupleBoxError> {
         // Check that the index is in bounds
         let num_slots = self.header().num_slots as SlotId;
@@ -718,13 +844,24 @@ impl<'a> PageWriteGuard<'a> {
     }
 
     #[inline]
-    pub fn load<LF: FnMut(Pin<&mut [u8]>)>(&mut self, lf: LF) -> Vec<(SlotId, usize, *mut u8)> {
+    pub fn load<LF: FnMut(Pin<&mut [u8]>)>(
+        &mut self,
+        lf: LF,
+    ) -> Result<Vec<(SlotId, usize, *mut u8)>, String> {
         let sp = SlottedPage::as_page_mut(self.base_address, self.page_size as usize);
         sp.load(lf)
     }
 
+    #[inline]
+    pub fn used_slots(&self) -> Vec<SlotId> {
+        let sp = SlottedPage::as_page(self.base_address, self.page_size as usize);
+        sp.used_slots()
+            .into_iter()
+            .map(|(slot_id, _, _)| slot_id)
+            .collect()
+    }
+
     #[inline(always)]
-    #[allow(dead_code)]
     pub fn get_slot(&self, slot_id: SlotId) -> Result<Pin<&'a [u8]>, TupleBoxError> {
         let sp = SlottedPage::as_page(self.base_address, self.page_size as usize);
         sp.get_slot(slot_id)
@@ -736,6 +873,12 @@ impl<'a> PageWriteGuard<'a> {
         sp.upcount(slot_id)
     }
 
+    #[inline(always)]
+    pub(crate) fn retire(&mut self, slot_id: SlotId) -> Result<(), TupleBoxError> {
+        let sp = SlottedPage::as_page_mut(self.base_address, self.page_size as usize);
+        sp.retire(slot_id)
+    }
+
     #[inline(always)]
     pub(crate) fn dncount(&mut self, slot_id: SlotId) -> Result<bool, TupleBoxError> {
         let sp = SlottedPage::as_page_mut(self.base_address, self.page_size as usize);
@@ -772,7 +915,8 @@ impl<'a> PageReadGuard<'a> {
         unsafe { Pin::new_unchecked(&*header_ptr) }
     }
 
-    /// Write the header + index portion of the page into the provided buffer
+    /// Write the header + index portion of the page into the provided buffer, with retired
+    /// slots marked free.
     pub(crate) fn write_header(&self, buf: &mut [u8]) {
         let header = self.header();
         let total_header_index_length =
@@ -780,6 +924,31 @@ impl<'a> PageReadGuard<'a> {
         let header_as_slice =
             unsafe { std::slice::from_raw_parts(self.base_address, total_header_index_length) };
         buf.copy_from_slice(header_as_slice);
+
+        // The buffer needn't be aligned, so fields in it are written unaligned.
+        let sp = SlottedPage::as_page(self.base_address, self.page_size as usize);
+        let mut retired_bytes = 0;
+        for slot_id in 0..header.num_slots as SlotId {
+            let index_entry = sp.get_index_entry(slot_id);
+            if !(index_entry.used && index_entry.retired) {
+                continue;
+            }
+            retired_bytes += index_entry.used_bytes;
+            let index_offset = std::mem::size_of::<PageHeader>()
+                + ((slot_id as usize) * std::mem::size_of::<IndexEntry>());
+            unsafe {
+                let entry = buf.as_mut_ptr().add(index_offset) as *mut IndexEntry;
+                std::ptr::addr_of_mut!((*entry).used).write_unaligned(false);
+                std::ptr::addr_of_mut!((*entry).retired).write_unaligned(false);
+                std::ptr::addr_of_mut!((*entry).refcount).write_unaligned(0);
+                std::ptr::addr_of_mut!((*entry).used_bytes).write_unaligned(0);
+            }
+        }
+        unsafe {
+            let header = buf.as_mut_ptr() as *mut PageHeader;
+            std::ptr::addr_of_mut!((*header).used_bytes)
+                .write_unaligned(self.header().used_bytes - retired_bytes);
+        }
     }
 
     pub(crate) fn header_size(&self) -> usize {
@@ -826,7 +995,7 @@ impl<'a> Drop for PageReadGuard<'a> {
 #[cfg(test)]
 mod tests {
     use crate::paging::slotted_page::{
-        slot_page_empty_size, IndexEntry, PageWriteGuard, SlotId, SlottedPage,
+        slot_page_empty_size, IndexEntry, PageHeader, PageWriteGuard, SlotId, SlottedPage,
     };
     use crate::paging::TupleBoxError;
 
@@ -874,6 +1043,39 @@ mod tests {
         assert_eq!(test_data, *slot);
     }
 
+    // A page image only loads if its header and index hold together.
+    #[test]
+    fn load_checks_layout() {
+        let mut page_memory = vec![0; 4096];
+        let mut page = SlottedPage::for_page_mut(page_memory.as_mut_ptr(), 4096);
+        let collected_slots = random_fill(&mut page);
+        drop(page);
+
+        let load = |image: &[u8]| {
+            let mut target = vec![0; 4096];
+            let mut page = SlottedPage::for_page_mut(target.as_mut_ptr(), 4096);
+            page.load(|mut buf| buf.copy_from_slice(image))
+                .map(|slots| slots.len())
+        };
+        assert_eq!(load(&page_memory), Ok(collected_slots.len()));
+
+        // One more slot than the index has room for.
+        let mut image = page_memory.clone();
+        image[12] += 1;
+        assert!(load(&image).is_err());
+
+        // The first slot pointing into the index.
+        let mut image = page_memory.clone();
+        let offset = std::mem::size_of::<PageHeader>() + 4;
+        image[offset..offset + 4].copy_from_slice(&8u32.to_le_bytes());
+        assert!(load(&image).is_err());
+
+        // Used bytes not adding up.
+        let mut image = page_memory.clone();
+        image[0] ^= 1;
+        assert!(load(&image).is_err());
+    }
+
     #[test]
     fn fill_until_full() {
         // Fill the page with a bunch of randomly sized slices of bytes, until we fill, to verify
@@ -14,11 +14,8 @@
 
 // TODO: add fixed-size slotted page impl for Sized items,
 //  should be way more efficient for the most common case of fixed-size tuples.
-// TODO: implement the ability to expire and page-out tuples
-//       based on LRU or random/second
-//       chance eviction (ala leanstore). will require separate PageIds from Bids, and will
-//       involve rewriting TuplePtr on the fly to point to a new page when restored.
-//       TuplePtr will also get a new field for last-access-time, so that we can do our eviction
+// TODO: evicting a page per access-bit sweep is crude; something closer to leanstore's cooling
+//       stage would avoid writing out pages that are about to be touched again.
 // TODO: verify locking/concurrency safety of the pager & tuple storage
 //       loom test, stateright, or jepsen, etc.
 // TODO: improve dynamic slot allocation packing in slotted page
@@ -39,13 +36,14 @@ use tracing::warn;
 
 use moor_values::util::{BitArray, Bitset64};
 
+use crate::paging::page_storage::BlobId;
 use crate::paging::slotted_page::{
     slot_index_overhead, slot_page_empty_size, PageReadGuard, PageWriteGuard, SlottedPage,
 };
 use crate::paging::tuple_ptr::TuplePtr;
 use crate::paging::TupleBoxError;
 use crate::pool::PagerError;
-use crate::tuples::{TupleId, TupleRef};
+use crate::tuples::{blob_of, TupleId, TupleRef};
 use crate::RelationId;
 
 use super::pager::Pager;
@@ -99,9 +97,17 @@ impl TupleBox {
         let mut page = inner.do_restore_page(id, page_size).unwrap();
 
         // Find all the slots referenced in this page.
-        let slot_ids = page.load(|buf| {
+        let slot_ids = match page.load(|buf| {
             lf(buf);
-        });
+        }) {
+            Ok(slot_ids) => slot_ids,
+            Err(reason) => {
+                // Nothing in it can be trusted, so it's not ours to hand out.
+                drop(page);
+                inner.pager.free(id).expect("Unable to free malformed page");
+                return Err(TupleBoxError::MalformedPage(id, reason));
+            }
+        };
 
         // Now make sure we have pointers for all of them.
         let mut refs = vec![];
@@ -117,16 +123,18 @@ impl TupleBox {
         }
         // The allocator needs to know that this page is used.
         inner.do_mark_page_used(relation_id, page.available_content_bytes(), id);
+        drop(page);
+
+        // Large databases won't fit in memory as they load, so make room as we go.
+        inner.evict_cold_pages();
         Ok(refs)
     }
 
-    #[allow(dead_code)]
+    /// Page back in the (evicted) page holding `tuple_id`, pointing its tuples at their new
+    /// addresses.
     pub(crate) fn page_fault(&self, tuple_id: TupleId) -> Result<(), TupleBoxError> {
         let mut inner = self.inner.lock().unwrap();
-        inner
-            .do_handle_page_fault(tuple_id)
-            .expect("Could not page in");
-        Ok(())
+        inner.do_handle_page_fault(tuple_id)
     }
 
     #[inline(always)]
@@ -148,6 +156,14 @@ impl TupleBox {
         page_handle.upcount(id.slot)
     }
 
+    /// Mark the tuple as removed by a commit, so that it's written out as free, though it stays
+    /// in memory for as long as anything refers to it.
+    pub fn retire(&self, id: TupleId) -> Result<(), TupleBoxError> {
+        let inner = self.inner.lock().unwrap();
+        let mut page_handle = inner.page_for_mut(id.page)?;
+        page_handle.retire(id.slot)
+    }
+
     #[inline(always)]
     pub fn dncount(&self, id: TupleId) -> Result<(), TupleBoxError> {
         let mut inner = self.inner.lock().unwrap();
@@ -174,6 +190,36 @@ impl TupleBox {
         Ok(())
     }
 
+    /// These blobs may now be referred to by pages on disk; see `Pager::blobs_committed`.
+    pub(crate) fn blobs_committed(&self, blob_ids: impl IntoIterator<Item = BlobId>) {
+        let inner = self.inner.lock().unwrap();
+        inner.pager.blobs_committed(blob_ids)
+    }
+
+    /// The commits which retired the tuples with these blobs have been checkpointed; see
+    /// `Pager::blobs_retired`.
+    pub(crate) fn blobs_retired(&self, blob_ids: &[BlobId]) {
+        let inner = self.inner.lock().unwrap();
+        inner.pager.blobs_retired(blob_ids)
+    }
+
+    /// Store a value too large to keep in a page out of line.
+    pub(crate) fn put_blob(&self, data: &[u8]) -> Result<BlobId, TupleBoxError> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .pager
+            .put_blob(data)
+            .map_err(|e| TupleBoxError::BlobError(e.to_string()))
+    }
+
+    pub(crate) fn get_blob(&self, blob_id: BlobId) -> Result<Vec<u8>, TupleBoxError> {
+        // Don't hold up the rest of the box while reading it in.
+        let pager = self.inner.lock().unwrap().pager.clone();
+        pager
+            .get_blob(blob_id)
+            .map_err(|e| TupleBoxError::BlobError(e.to_string()))
+    }
+
     pub fn used_bytes(&self) -> usize {
         let inner = self.inner.lock().unwrap();
         inner.used_bytes()
@@ -210,6 +256,8 @@ struct Inner {
     /// There has to be a stable-memory address for each of these, as they are referenced by
     /// pointers in the TupleRefs themselves.
     tuple_ptrs: HashMap<TupleId, Pin<Box<TuplePtr>>>,
+    /// Where the evictor's sweep over the resident pages left off.
+    clock_hand: PageId,
 }
 
 impl Inner {
@@ -218,6 +266,7 @@ impl Inner {
             available_page_space: Box::new(BitArray::new()),
             pager,
             tuple_ptrs: HashMap::new(),
+            clock_hand: 0,
         }
     }
 
@@ -231,6 +280,9 @@ impl Inner {
         let tuple_size = size + slot_index_overhead();
         let page_size = max(32768, tuple_size.next_power_of_two());
 
+        // Make room for the page we may be about to allocate.
+        self.evict_cold_pages();
+
         // Our selected page should not in theory get taken while we're holding this allocation lock,
         // but to be paranoid, we'll loop around and try again if it does.
         let mut tries = 0;
@@ -274,7 +326,7 @@ impl Inner {
             .get_mut(&id)
             .map(|tptr| {
                 let tptr_ref = unsafe { Pin::into_inner_unchecked(tptr.as_mut()) };
-                let tptr_ptr = tptr_ref.as_mut_ptr();
+                let tptr_ptr = tptr_ref as *mut TuplePtr;
 
                 tptr_ref.upcount();
                 TupleRef::at_tptr(tptr_ptr)
@@ -315,6 +367,10 @@ impl Inner {
         id: TupleId,
         mut page_handle: PageWriteGuard,
     ) -> Result<(), TupleBoxError> {
+        // If the tuple kept its value out of line, that goes too.
+        if let Some(blob_id) = blob_of(&page_handle.get_slot(id.slot)?) {
+            self.pager.free_blob(blob_id);
+        }
         let (new_free, _, is_empty) = page_handle.remove_slot(id.slot)?;
         self.report_free(id.page, new_free, is_empty);
         self.tuple_ptrs.remove(&id);
@@ -322,50 +378,147 @@ impl Inner {
         Ok(())
     }
 
-    fn do_handle_page_fault(&mut self, _id: TupleId) -> Result<(), TupleBoxError> {
-        // Ask the pager to restore the page, and then we'll swizzle the pointer to it.
-
-        // 1. Ask the pager to restore the page, and get an address.
-
-        // 2. Find the tuple pointer for this tuple id.
-        // let tuple_ptr = self.tuple_ptrs.get_mut(&id).unwrap();
-
-        // 3. Mark the tuple as paged with the new address.
-        // tuple_ptr.mark_paged_in(self.pager.resolve_ptr(id.page)?.0);
+    fn do_handle_page_fault(&mut self, id: TupleId) -> Result<(), TupleBoxError> {
+        match self.pager.resolve_ptr(id.page) {
+            // Someone else already brought it back in.
+            Ok(_) => return Ok(()),
+            Err(PagerError::PagedOut) => {}
+            Err(_) => return Err(TupleBoxError::TupleNotFound(id.page)),
+        }
+        self.do_page_in(id.page)?;
 
-        todo!("page in");
+        // Bringing the page in may have put us over budget.
+        self.evict_cold_pages();
+        Ok(())
     }
 
-    fn page_for<'a>(&self, page_num: PageId) -> Result<PageReadGuard<'a>, TupleBoxError> {
-        let (page_address, page_size) = match self.pager.resolve_ptr(page_num) {
+    /// Read an evicted page back into memory, and swizzle the pointers of all the tuples on it to
+    /// their new addresses.
+    fn do_page_in(&self, page_id: PageId) -> Result<(*mut u8, usize), TupleBoxError> {
+        let (page_address, page_size) = match self.pager.page_in(page_id) {
             Ok(v) => v,
-            Err(PagerError::CouldNotAccess) => {
-                return Err(TupleBoxError::TupleNotFound(page_num));
-            }
             Err(PagerError::InvalidPage) => {
-                return Err(TupleBoxError::TupleNotFound(page_num));
+                return Err(TupleBoxError::TupleNotFound(page_id));
             }
-            _ => {
-                panic!("Unexpected buffer pool error");
+            Err(e) => {
+                panic!("Could not page in page {}: {:?}", page_id, e);
             }
         };
-        let page_handle = SlottedPage::for_page(page_address, page_size);
-        Ok(page_handle)
+        for (slot, bufaddr) in SlottedPage::paged_in(page_address, page_size) {
+            if let Some(tuple_ptr) = self.tuple_ptrs.get(&TupleId {
+                page: page_id,
+                slot,
+            }) {
+                tuple_ptr.mark_paged_in(bufaddr);
+            }
+        }
+        Ok((page_address, page_size))
     }
 
-    fn page_for_mut<'a>(&self, page_num: PageId) -> Result<PageWriteGuard<'a>, TupleBoxError> {
-        let (page_address, page_size) = match self.pager.resolve_ptr(page_num) {
-            Ok(v) => v,
-            Err(PagerError::CouldNotAccess) => {
-                return Err(TupleBoxError::TupleNotFound(page_num));
+    /// If the pager is over its resident memory budget, sweep around the resident pages evicting
+    /// cold ones until it isn't, or there's nothing left that can be evicted.
+    /// A page is cold if none of its tuples have been accessed since the sweep last came past it
+    /// (i.e. "second chance" clock replacement), so a page can need to be visited twice before
+    /// it can go.
+    fn evict_cold_pages(&mut self) {
+        let mut over_budget = self.pager.over_budget();
+        if over_budget == 0 {
+            return;
+        }
+        let pages = self.pager.resident_pages();
+        if pages.is_empty() {
+            return;
+        }
+        let start = pages.partition_point(|pid| *pid < self.clock_hand);
+        for i in 0..pages.len() * 2 {
+            let page_id = pages[(start + i) % pages.len()];
+            self.clock_hand = page_id + 1;
+            if let Some(released) = self.try_evict(page_id) {
+                over_budget = over_budget.saturating_sub(released);
+                if over_budget == 0 {
+                    return;
+                }
             }
-            Err(PagerError::InvalidPage) => {
-                return Err(TupleBoxError::TupleNotFound(page_num));
+        }
+    }
+
+    /// Evict the page if it's cold and nothing's using it, returning the number of bytes freed.
+    fn try_evict(&self, page_id: PageId) -> Option<usize> {
+        let (page_address, page_size) = self.pager.resolve_ptr(page_id).ok()?;
+
+        // If anybody holds a lock on the page, it's in use.
+        let page_handle = SlottedPage::try_for_page_mut(page_address, page_size)?;
+        let tuple_ptrs: Vec<_> = page_handle
+            .used_slots()
+            .into_iter()
+            .filter_map(|slot| {
+                self.tuple_ptrs.get(&TupleId {
+                    page: page_id,
+                    slot,
+                })
+            })
+            .collect();
+
+        // Clear all the access bits (not just up to the first one set), so the page gets exactly
+        // one more chance.
+        let mut accessed = false;
+        for tuple_ptr in &tuple_ptrs {
+            accessed |= tuple_ptr.take_accessed();
+        }
+        if accessed {
+            return None;
+        }
+
+        // Cut the tuples loose from the page, backing out if any of them turn out to be in use.
+        let mut swizzled = Vec::with_capacity(tuple_ptrs.len());
+        for tuple_ptr in &tuple_ptrs {
+            match tuple_ptr.mark_paged_out() {
+                Some(bufaddr) => swizzled.push((tuple_ptr, bufaddr)),
+                None => {
+                    for (tuple_ptr, bufaddr) in swizzled {
+                        tuple_ptr.mark_paged_in(bufaddr);
+                    }
+                    return None;
+                }
+            }
+        }
+
+        // Nothing can get at the page now except through us, and we hold the lock, so let go of
+        // the page lock to write it out unlocked.
+        drop(page_handle);
+        match self.pager.page_out(page_id) {
+            Ok(released) => Some(released),
+            Err(e) => {
+                warn!(?e, page_id, "Could not evict page");
+                for (tuple_ptr, bufaddr) in swizzled {
+                    tuple_ptr.mark_paged_in(bufaddr);
+                }
+                None
             }
+        }
+    }
+
+    /// The address and size of the page, paging it in first if it's been evicted.
+    fn resolve_page(&self, page_num: PageId) -> Result<(*mut u8, usize), TupleBoxError> {
+        match self.pager.resolve_ptr(page_num) {
+            Ok(v) => Ok(v),
+            Err(PagerError::PagedOut) => self.do_page_in(page_num),
+            Err(PagerError::CouldNotAccess) => Err(TupleBoxError::TupleNotFound(page_num)),
+            Err(PagerError::InvalidPage) => Err(TupleBoxError::TupleNotFound(page_num)),
             _ => {
                 panic!("Unexpected buffer pool error");
             }
-        };
+        }
+    }
+
+    fn page_for<'a>(&self, page_num: PageId) -> Result<PageReadGuard<'a>, TupleBoxError> {
+        let (page_address, page_size) = self.resolve_page(page_num)?;
+        let page_handle = SlottedPage::for_page(page_address, page_size);
+        Ok(page_handle)
+    }
+
+    fn page_for_mut<'a>(&self, page_num: PageId) -> Result<PageWriteGuard<'a>, TupleBoxError> {
+        let (page_address, page_size) = self.resolve_page(page_num)?;
         Ok(SlottedPage::for_page_mut(page_address, page_size))
     }
 
@@ -787,6 +940,29 @@ mod tests {
         }
     }
 
+    // Values too big for any page are stored out of line, and read back in when asked for.
+    #[test]
+    fn test_oversized_codomain() {
+        let pager = Arc::new(Pager::new(32768 * 64).unwrap());
+        let sb = Arc::new(TupleBox::new(pager));
+        let domain = vec![1, 2, 3];
+        let codomain: Vec<u8> = (0..4 << 20).map(|i| i as u8).collect();
+        let tuple = TupleRef::allocate(RelationId(0), sb.clone(), 0, &domain, &codomain).unwrap();
+        assert!(tuple.blob_id().is_some());
+        assert_eq!(tuple.domain().as_slice(), domain);
+        assert_eq!(tuple.codomain().as_slice(), codomain);
+
+        // Small ones stay in the page.
+        let small = TupleRef::allocate(RelationId(0), sb.clone(), 0, &domain, &domain).unwrap();
+        assert!(small.blob_id().is_none());
+        assert_eq!(small.codomain().as_slice(), domain);
+
+        // And the blob goes when the tuple does.
+        let blob_id = tuple.blob_id().unwrap();
+        drop(tuple);
+        assert!(sb.get_blob(blob_id).is_err());
+    }
+
     #[test]
     fn alloc_encode_decode() {
         let pid = 12345;
@@ -13,11 +13,10 @@
 //
 
 use std::hash::Hash;
-use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32};
 use std::sync::Arc;
 
-use moor_values::util::ByteSource;
-
 use crate::paging::TupleBox;
 use crate::tuples::TupleId;
 
@@ -25,6 +24,9 @@ use crate::tuples::TupleId;
 /// which allows the TupleBox to manage the lifetime of the tuple, swizzling it in and out of memory as needed.
 /// Adds a layer of indirection to each tuple access, but is better than passing around tuple ids + TupleBox
 /// references.
+///
+/// When the tuple's page is evicted, the buffer address is nulled out, and the next access to the tuple faults
+/// the page back in (at a new address) through the TupleBox.
 
 // TODO: rather than decoding a tuple out of a buffer in a slot, the slot should just hold the tuple structure
 pub struct TuplePtr {
@@ -32,6 +34,10 @@ pub struct TuplePtr {
     id: TupleId,
     buflen: u32,
     bufaddr: AtomicPtr<u8>,
+    /// The number of accesses to the buffer underway. The page can't be evicted while there are any.
+    pins: AtomicU32,
+    /// Set on access, and cleared by the evictor as it sweeps past, so it can tell which pages have gone cold.
+    accessed: AtomicBool,
 
     _pin: std::marker::PhantomPinned,
 }
@@ -48,6 +54,8 @@ impl TuplePtr {
             id: tuple_id,
             bufaddr: AtomicPtr::new(bufaddr),
             buflen: buflen as u32,
+            pins: AtomicU32::new(0),
+            accessed: AtomicBool::new(true),
             _pin: std::marker::PhantomPinned,
         }
     }
@@ -67,62 +75,89 @@ impl Hash for TuplePtr {
     }
 }
 
+/// Keeps the tuple's page in memory until dropped.
+struct Pinned<'a> {
+    ptr: &'a TuplePtr,
+    bufaddr: *mut u8,
+}
+
+impl<'a> Drop for Pinned<'a> {
+    fn drop(&mut self) {
+        self.ptr.pins.fetch_sub(1, SeqCst);
+    }
+}
+
 impl TuplePtr {
     #[inline]
     pub fn id(&self) -> TupleId {
         self.id
     }
 
-    /// Mark the tuple as paged out. Accesses to the tuple will fault, and we'll need to page it back in.
+    /// The box the tuple lives in.
     #[inline]
-    #[allow(dead_code)]
-    pub(crate) fn mark_paged_out(&mut self) {
-        self.bufaddr
-            .store(std::ptr::null_mut(), std::sync::atomic::Ordering::SeqCst);
-    }
-
-    #[allow(dead_code)]
-    pub(crate) fn mark_paged_in(&mut self, bufaddr: *mut u8) {
-        self.bufaddr
-            .store(bufaddr, std::sync::atomic::Ordering::SeqCst);
+    pub(crate) fn tuple_box(&self) -> &TupleBox {
+        &self.tb
     }
 
+    /// Try to mark the tuple as paged out, returning its buffer address so it can be put back if the page can't be
+    /// evicted after all. Accesses to the tuple will fault, and we'll need to page it back in.
+    /// Fails (with nothing changed) if the tuple is being accessed.
     #[inline]
-    pub(crate) fn as_ptr<T>(&self) -> *const T {
-        if self
-            .bufaddr
-            .load(std::sync::atomic::Ordering::SeqCst)
-            .is_null()
-        {
-            self.tb.page_fault(self.id).unwrap();
+    pub(crate) fn mark_paged_out(&self) -> Option<*mut u8> {
+        let bufaddr = self.bufaddr.swap(std::ptr::null_mut(), SeqCst);
+        // Anyone who pinned the buffer before we swapped it out saw the old address, so we have to wait for them.
+        if self.pins.load(SeqCst) != 0 {
+            self.bufaddr.store(bufaddr, SeqCst);
+            return None;
         }
-        self.bufaddr.load(std::sync::atomic::Ordering::SeqCst) as *const T
+        Some(bufaddr)
+    }
+
+    /// Point the tuple at its (new) buffer address. Counts as an access, so the page isn't immediately evicted again.
+    pub(crate) fn mark_paged_in(&self, bufaddr: *mut u8) {
+        self.accessed.store(true, Relaxed);
+        self.bufaddr.store(bufaddr, SeqCst);
+    }
+
+    /// Whether the tuple has been accessed since this was last asked, clearing the flag.
+    pub(crate) fn take_accessed(&self) -> bool {
+        self.accessed.swap(false, Relaxed)
     }
 
     #[inline]
-    pub(crate) fn as_mut_ptr<T>(&mut self) -> *mut T {
-        // TODO: if the ptr is null, this is a page fault, and we'll
-        //   need to ask the tuplebox to ask the pager to page us in
-        self.bufaddr.load(std::sync::atomic::Ordering::SeqCst) as *mut T
+    fn pin(&self) -> Pinned<'_> {
+        loop {
+            self.pins.fetch_add(1, SeqCst);
+            let bufaddr = self.bufaddr.load(SeqCst);
+            if !bufaddr.is_null() {
+                if !self.accessed.load(Relaxed) {
+                    self.accessed.store(true, Relaxed);
+                }
+                return Pinned { ptr: self, bufaddr };
+            }
+            // Paged out. Let go so it can be paged back in, and try again.
+            self.pins.fetch_sub(1, SeqCst);
+            self.tb.page_fault(self.id).unwrap();
+        }
     }
 
+    /// Call `f` with the tuple's buffer, paging it in first if needed.
     #[inline]
-    pub(crate) fn buffer(&self) -> &[u8] {
-        let buf_addr = self.as_ptr();
-        unsafe { std::slice::from_raw_parts(buf_addr, self.buflen as usize) }
+    pub(crate) fn with_buffer<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> R {
+        let pinned = self.pin();
+        f(unsafe { std::slice::from_raw_parts(pinned.bufaddr, self.buflen as usize) })
     }
 
+    /// Call `f` with the tuple's buffer, mutably, paging it in first if needed.
     #[inline]
-    #[allow(dead_code)]
-    pub fn byte_source(&self) -> SlotByteSource {
-        SlotByteSource::new(self)
+    pub(crate) fn with_buffer_mut<R, F: FnOnce(&mut [u8]) -> R>(&mut self, f: F) -> R {
+        let pinned = self.pin();
+        f(unsafe { std::slice::from_raw_parts_mut(pinned.bufaddr, self.buflen as usize) })
     }
 
     #[allow(dead_code)]
     pub fn is_paged_out(&self) -> bool {
-        self.bufaddr
-            .load(std::sync::atomic::Ordering::SeqCst)
-            .is_null()
+        self.bufaddr.load(SeqCst).is_null()
     }
 
     #[inline]
@@ -140,52 +175,3 @@ impl TuplePtr {
         self.tb.dncount(self.id).unwrap();
     }
 }
-
-/// So we can build SliceRefs off of TuplePtrs
-pub struct SlotByteSource {
-    ptr: *const TuplePtr,
-}
-
-unsafe impl Send for SlotByteSource {}
-unsafe impl Sync for SlotByteSource {}
-
-impl SlotByteSource {
-    fn new(ptr: *const TuplePtr) -> Self {
-        // upcnt
-        let tp = unsafe { &(*ptr) };
-        tp.upcount();
-        SlotByteSource { ptr }
-    }
-}
-impl ByteSource for SlotByteSource {
-    #[inline]
-    fn as_slice(&self) -> &[u8] {
-        let buffer = (unsafe { &(*self.ptr) }).buffer();
-        buffer
-    }
-
-    #[inline]
-    fn len(&self) -> usize {
-        let buffer = (unsafe { &(*self.ptr) }).buffer();
-        buffer.len()
-    }
-
-    fn touch(&self) {}
-}
-
-impl Clone for SlotByteSource {
-    fn clone(&self) -> Self {
-        // Upcount
-        let tp = unsafe { &(*self.ptr) };
-        tp.upcount();
-        SlotByteSource { ptr: self.ptr }
-    }
-}
-
-impl Drop for SlotByteSource {
-    fn drop(&mut self) {
-        // Downcount
-        let tp = unsafe { &(*self.ptr) };
-        tp.dncount();
-    }
-}
@@ -12,13 +12,27 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+//! moor's write-ahead log.
+//!
+//! Every commit is written as a single checksummed record holding the WAL entries (see
+//! `make_wal_entry`) for the pages it touched. Records are appended to numbered segment files.
+//! The page store only needs to be current at startup, so it's brought up to date from the log
+//! when a segment fills up (and at shutdown), after which the segment is deleted.
+//!
+//! When the log is fsynced is governed by the `SyncPolicy`; the writer thread groups together
+//! whatever commits are waiting when it gets to them, so under load one fsync covers many
+//! commits.
+
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use binary_layout::{binary_layout, Field, LayoutAs};
-use okaywal::{Entry, EntryId, LogManager, SegmentReader, WriteAheadLog};
-use std::fmt::{Debug, Formatter};
-use std::sync::Arc;
 use strum::FromRepr;
 use thiserror::Error;
-use tracing::{error, info, warn};
+use tracing::{info, warn};
 
 use crate::tuples::TupleId;
 use crate::RelationId;
@@ -26,73 +40,344 @@ use crate::RelationId;
 use super::page_storage::{PageStore, PageStoreMutation};
 use super::{PageId, SlotId};
 
-pub struct WalManager {
-    page_storage: Arc<PageStore>,
+const RECORD_MAGIC: u32 = 0xfeed_10c5;
+const SEGMENT_EXTENSION: &str = "wal";
+
+/// Once the current segment is bigger than this, the log is checkpointed & a new one started.
+const CHECKPOINT_SEGMENT_SIZE: u64 = 1 << 24;
+
+/// When the write-ahead log is flushed to disk, and so when a commit is acknowledged as durable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncPolicy {
+    /// fsync after every commit, and acknowledge each commit once its own fsync is done.
+    PerCommit,
+    /// fsync once for each group of commits that were waiting together, and acknowledge them
+    /// all after that.
+    #[default]
+    Batched,
+    /// fsync at most once per interval. Commits are acknowledged once they're written to the
+    /// log, so a crash of the machine (but not just the server) can lose the last interval's
+    /// worth of them.
+    Interval(Duration),
 }
 
-impl WalManager {
-    pub fn new(page_storage: Arc<PageStore>) -> Self {
-        Self { page_storage }
+impl FromStr for SyncPolicy {
+    type Err = String;
+
+    /// `per-commit`, `batched`, or an interval in milliseconds like `100ms`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "per-commit" => Ok(Self::PerCommit),
+            "batched" => Ok(Self::Batched),
+            _ => {
+                let millis = s
+                    .strip_suffix("ms")
+                    .and_then(|ms| ms.parse::<u64>().ok())
+                    .ok_or_else(|| {
+                        format!(
+                            "Invalid sync policy {}; expected per-commit, batched, or <n>ms",
+                            s
+                        )
+                    })?;
+                Ok(Self::Interval(Duration::from_millis(millis)))
+            }
+        }
     }
 }
 
-impl Debug for WalManager {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("WalManager").finish()
+/// How the write-ahead log is set up for a database.
+#[derive(Debug, Clone, Default)]
+pub struct WalConfig {
+    pub sync_policy: SyncPolicy,
+    /// If set, every commit is also archived here, for point-in-time recovery (see `recover_to`).
+    pub archive: Option<PathBuf>,
+}
+
+binary_layout!(wal_record_header, LittleEndian, {
+    // Validity marker.
+    magic_marker: u32,
+    // CRC32C of the rest of the header after this field, and the payload.
+    checksum: u32,
+    // The timestamp of the transaction being committed.
+    timestamp: u64,
+    // Wall-clock time the record was written, in microseconds since the UNIX epoch.
+    wall_time_us: u64,
+    // The number of WAL entries in the payload.
+    num_chunks: u64,
+    // The size of the payload following the header: each WAL entry, prefixed with its length.
+    payload_size: u64,
+});
+
+/// One commit, as read back from the log.
+pub(crate) struct WalRecord {
+    pub(crate) timestamp: u64,
+    pub(crate) wall_time: SystemTime,
+    pub(crate) chunks: Vec<Vec<u8>>,
+}
+
+/// Encode the WAL entries for one commit as a log record.
+pub(crate) fn encode_record<'a>(ts: u64, chunks: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+    let header_size = wal_record_header::SIZE.unwrap();
+    let mut record = vec![0; header_size];
+    let mut num_chunks = 0;
+    for chunk in chunks {
+        record.extend_from_slice(&(chunk.len() as u64).to_le_bytes());
+        record.extend_from_slice(chunk);
+        num_chunks += 1;
+    }
+    let payload_size = (record.len() - header_size) as u64;
+    let wall_time_us = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+    let mut header = wal_record_header::View::new(&mut record[..header_size]);
+    header.magic_marker_mut().write(RECORD_MAGIC);
+    header.timestamp_mut().write(ts);
+    header.wall_time_us_mut().write(wall_time_us);
+    header.num_chunks_mut().write(num_chunks);
+    header.payload_size_mut().write(payload_size);
+    let checksum = crc32c::crc32c(&record[wal_record_header::timestamp::OFFSET..]);
+    wal_record_header::View::new(&mut record[..header_size])
+        .checksum_mut()
+        .write(checksum);
+    record
+}
+
+/// Read the records in the log file at `path` in order, handing each to `f` until it returns
+/// false. Reading stops at the first record that's incomplete or fails its checksum, which is
+/// what a crash part way through an append leaves behind. Returns the length of the file up to
+/// the end of the last good record read.
+pub(crate) fn read_records<F: FnMut(WalRecord) -> bool>(
+    path: &Path,
+    mut f: F,
+) -> std::io::Result<u64> {
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let header_size = wal_record_header::SIZE.unwrap();
+    let mut position = 0;
+    loop {
+        let mut record = vec![0; header_size];
+        match file.read_exact(&mut record) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let header = wal_record_header::View::new(&record[..]);
+        let payload_size = header.payload_size().read();
+        if header.magic_marker().read() != RECORD_MAGIC
+            || payload_size > file_len - position - header_size as u64
+        {
+            warn!(?path, position, "Invalid or incomplete record in log");
+            break;
+        }
+        let checksum = header.checksum().read();
+        let num_chunks = header.num_chunks().read();
+        record.resize(header_size + payload_size as usize, 0);
+        file.read_exact(&mut record[header_size..])?;
+        if crc32c::crc32c(&record[wal_record_header::timestamp::OFFSET..]) != checksum {
+            warn!(?path, position, "Checksum mismatch for record in log");
+            break;
+        }
+        let header = wal_record_header::View::new(&record[..header_size]);
+        let timestamp = header.timestamp().read();
+        let wall_time = UNIX_EPOCH + Duration::from_micros(header.wall_time_us().read());
+
+        let mut chunks = Vec::with_capacity(num_chunks as usize);
+        let mut payload = &record[header_size..];
+        for _ in 0..num_chunks {
+            let (len, rest) = payload.split_at(8);
+            let len = u64::from_le_bytes(len.try_into().unwrap()) as usize;
+            let (chunk, rest) = rest.split_at(len);
+            chunks.push(chunk.to_vec());
+            payload = rest;
+        }
+        position += record.len() as u64;
+
+        if !f(WalRecord {
+            timestamp,
+            wall_time,
+            chunks,
+        }) {
+            break;
+        }
     }
+    Ok(position)
 }
 
-impl LogManager for WalManager {
-    fn recover(&mut self, entry: &mut Entry<'_>) -> std::io::Result<()> {
-        let Some(chunks) = entry.read_all_chunks()? else {
-            info!("No chunks found, nothing to recover");
-            return Ok(());
+/// The numbered files with the given extension in `dir`, in order.
+pub(crate) fn list_segments(dir: &Path, extension: &str) -> std::io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(extension) {
+            continue;
+        }
+        let Some(number) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse::<u64>().ok())
+        else {
+            continue;
         };
-        let mut write_batch = vec![];
-        let mut evicted = vec![];
-        for chunk in chunks {
-            Self::chunk_to_mutations(&chunk, &mut write_batch, &mut evicted);
+        segments.push((number, path));
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+pub(crate) fn segment_path(dir: &Path, segment: u64, extension: &str) -> PathBuf {
+    dir.join(format!("{:016}.{}", segment, extension))
+}
+
+/// Turn the WAL entries of a record into page store mutations.
+pub(crate) fn record_to_mutations(chunks: &[Vec<u8>]) -> Vec<PageStoreMutation> {
+    let mut write_batch = vec![];
+    let mut evicted = vec![];
+    for chunk in chunks {
+        chunk_to_mutations(chunk, &mut write_batch, &mut evicted);
+    }
+    write_batch
+}
+
+pub(crate) struct WriteAheadLog {
+    dir: PathBuf,
+    segment: File,
+    segment_number: u64,
+    segment_size: u64,
+}
+
+impl WriteAheadLog {
+    /// Open the log in `dir`, replaying anything in it into the page store first, then starting a
+    /// fresh segment.
+    pub(crate) fn recover(dir: PathBuf, page_storage: &PageStore) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let segments = list_segments(&dir, SEGMENT_EXTENSION)?;
+        let mut recovered = 0;
+        for (_, path) in &segments {
+            recovered += replay_segment(path, page_storage)?;
+        }
+        page_storage.wait_complete();
+        if recovered > 0 {
+            info!("Recovered {} commits from the write-ahead log", recovered);
         }
-        let ps = self.page_storage.clone();
-        ps.enqueue_page_mutations(write_batch)
-            .expect("Unable to write batch");
 
-        Ok(())
+        // Everything's in the page store now, so the old segments can go.
+        for (_, path) in &segments {
+            std::fs::remove_file(path)?;
+        }
+        let segment_number = segments.last().map(|(n, _)| n + 1).unwrap_or(0);
+        let segment = Self::create_segment(&dir, segment_number)?;
+        Ok(Self {
+            dir,
+            segment,
+            segment_number,
+            segment_size: 0,
+        })
     }
 
-    fn checkpoint_to(
-        &mut self,
-        _last_checkpointed_id: EntryId,
-        checkpointed_entries: &mut SegmentReader,
-        _wal: &WriteAheadLog,
-    ) -> std::io::Result<()> {
-        // Replay the write-ahead log into cold storage, to make sure that it's consistent.
-        let mut write_batch = vec![];
-        let mut evicted = vec![];
-
-        while let Some(mut entry) = checkpointed_entries.read_entry()? {
-            let chunks = match entry.read_all_chunks() {
-                Ok(c) => c,
-                Err(e) => {
-                    error!(?e, "Failed to read chunks from entry");
-                    continue;
+    /// How many intact records there are in the log in `dir`; that is, commits which haven't yet
+    /// been applied to the page store by a checkpoint.
+    pub(crate) fn pending_commits(dir: &Path) -> std::io::Result<usize> {
+        if !dir.exists() {
+            return Ok(0);
+        }
+        let mut pending = 0;
+        for (_, path) in list_segments(dir, SEGMENT_EXTENSION)? {
+            read_records(&path, |_| {
+                pending += 1;
+                true
+            })?;
+        }
+        Ok(pending)
+    }
+
+    /// The contents of the sequence page as of the last intact record in the log in `dir`, if
+    /// any; that is, what the sequence page will be once the log has been applied.
+    pub(crate) fn last_sequence_page(dir: &Path) -> std::io::Result<Option<Vec<u8>>> {
+        if !dir.exists() {
+            return Ok(None);
+        }
+        let mut last = None;
+        for (_, path) in list_segments(dir, SEGMENT_EXTENSION)? {
+            read_records(&path, |record| {
+                for chunk in &record.chunks {
+                    if chunk.len() < wal_entry::data::OFFSET {
+                        continue;
+                    }
+                    let wal_entry = wal_entry::View::new(&chunk[..]);
+                    let action = wal_entry.header().action().try_read().ok();
+                    if action == Some(WalEntryType::SequenceSync) {
+                        last = Some(wal_entry.data().to_vec());
+                    }
                 }
-            };
-            let Some(chunks) = chunks else {
-                continue;
-            };
-            for chunk in chunks {
-                Self::chunk_to_mutations(&chunk, &mut write_batch, &mut evicted);
-            }
+                true
+            })?;
         }
+        Ok(last)
+    }
 
-        if let Err(e) = self.page_storage.enqueue_page_mutations(write_batch) {
-            error!("Unable to write batch: {:?}", e);
-            return Ok(());
-        };
+    fn create_segment(dir: &Path, segment_number: u64) -> std::io::Result<File> {
+        OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(segment_path(dir, segment_number, SEGMENT_EXTENSION))
+    }
 
+    /// Write already-encoded records to the log. They aren't durable until `sync`.
+    pub(crate) fn append(&mut self, records: &[u8]) -> std::io::Result<()> {
+        self.segment.write_all(records)?;
+        self.segment_size += records.len() as u64;
         Ok(())
     }
+
+    /// Flush everything appended so far to disk.
+    pub(crate) fn sync(&mut self) -> std::io::Result<()> {
+        self.segment.sync_data()
+    }
+
+    /// The file of the segment being written, and how much of it has been written so far.
+    /// Everything before that is left alone until the next checkpoint.
+    pub(crate) fn current_segment(&self) -> (PathBuf, u64) {
+        (
+            segment_path(&self.dir, self.segment_number, SEGMENT_EXTENSION),
+            self.segment_size,
+        )
+    }
+
+    /// True if the current segment is big enough that it should be checkpointed.
+    pub(crate) fn wants_checkpoint(&self) -> bool {
+        self.segment_size >= CHECKPOINT_SEGMENT_SIZE
+    }
+
+    /// Bring the page store up to date with everything in the log, then start a new segment and
+    /// throw away the old one.
+    pub(crate) fn checkpoint(&mut self, page_storage: &PageStore) -> std::io::Result<()> {
+        self.sync()?;
+        let old_path = segment_path(&self.dir, self.segment_number, SEGMENT_EXTENSION);
+        replay_segment(&old_path, page_storage)?;
+        page_storage.wait_complete();
+        self.segment_number += 1;
+        self.segment = Self::create_segment(&self.dir, self.segment_number)?;
+        self.segment_size = 0;
+        std::fs::remove_file(old_path)
+    }
+}
+
+/// Apply every (intact) record in the log file at `path` to the page store, returning how many
+/// there were. They go to the page store as one batch, so each page file is only synced once.
+fn replay_segment(path: &Path, page_storage: &PageStore) -> std::io::Result<usize> {
+    let mut replayed = 0;
+    let mut write_batch = vec![];
+    let valid_len = read_records(path, |record| {
+        write_batch.extend(record_to_mutations(&record.chunks));
+        replayed += 1;
+        true
+    })?;
+    if valid_len != std::fs::metadata(path)?.len() {
+        warn!(?path, "Discarding torn tail of write-ahead log");
+    }
+    page_storage.enqueue_page_mutations(write_batch)?;
+    Ok(replayed)
 }
 
 #[repr(u8)]
@@ -109,6 +394,8 @@ pub enum WalEntryType {
     // Write current state of sequences to the sequence page. Ignores page id, slot id. Data is
     // the contents of the sequence page.
     SequenceSync = 4,
+    // Write a value stored out of line. The page id is the blob id, and the data is its contents.
+    Blob = 5,
 }
 
 #[derive(Error, Debug)]
@@ -206,99 +493,163 @@ binary_layout!(wal_entry, LittleEndian, {
     data: [u8],
 });
 
-impl WalManager {
-    fn chunk_to_mutations(
-        chunk: &[u8],
-        write_mutations: &mut Vec<PageStoreMutation>,
-        to_evict: &mut Vec<TupleId>,
-    ) {
-        // The first N bytes have to be WAL_MAGIC or this is an invalid chunk.
-        if chunk.len() < wal_entry::data::OFFSET {
-            warn!("Chunk is too small to be valid");
-            return;
+fn chunk_to_mutations(
+    chunk: &[u8],
+    write_mutations: &mut Vec<PageStoreMutation>,
+    to_evict: &mut Vec<TupleId>,
+) {
+    // The first N bytes have to be WAL_MAGIC or this is an invalid chunk.
+    if chunk.len() < wal_entry::data::OFFSET {
+        warn!("Chunk is too small to be valid");
+        return;
+    }
+    if chunk[0..4] != WAL_MAGIC.to_le_bytes() {
+        warn!("Chunk does not have valid magic marker");
+        return;
+    }
+    let wal_entry = wal_entry::View::new(&chunk);
+    if wal_entry.header().magic_marker().read() != WAL_MAGIC {
+        warn!("Chunk does not have valid magic marker");
+        return;
+    }
+    let pid = wal_entry.header().pid().read();
+
+    // Copied onto heap so we can pass it to the write batch without it getting moved around,
+    // because the kernel will need a stable pointer to it.
+    let data = wal_entry.data().to_vec().into_boxed_slice();
+
+    let action = wal_entry
+        .header()
+        .action()
+        .try_read()
+        .expect("Invalid WAL action");
+    match action {
+        WalEntryType::Insert | WalEntryType::Update => {
+            let relation_id = RelationId(
+                wal_entry
+                    .header()
+                    .relation_id()
+                    .try_read()
+                    .expect("Invalid relation ID") as usize,
+            );
+
+            let mutation = PageStoreMutation::PageTupleWrite {
+                relation_id,
+                page_id: pid as PageId,
+                slot_id: wal_entry
+                    .header()
+                    .slot_id()
+                    .try_read()
+                    .expect("Could not read WAL slot id") as SlotId,
+                page_offset: wal_entry
+                    .header()
+                    .offset()
+                    .try_read()
+                    .expect("Could not read WAL offset") as usize,
+                data,
+            };
+
+            write_mutations.push(mutation);
         }
-        if chunk[0..4] != WAL_MAGIC.to_le_bytes() {
-            warn!("Chunk does not have valid magic marker");
-            return;
+        WalEntryType::PageHeader => {
+            let relation_id = RelationId(
+                wal_entry
+                    .header()
+                    .relation_id()
+                    .try_read()
+                    .expect("Invalid relation ID") as usize,
+            );
+
+            let mutation = PageStoreMutation::PageHeaderWrite {
+                relation_id,
+                page_id: pid as PageId,
+                data,
+            };
+            write_mutations.push(mutation);
         }
-        let wal_entry = wal_entry::View::new(&chunk);
-        if wal_entry.header().magic_marker().read() != WAL_MAGIC {
-            warn!("Chunk does not have valid magic marker");
-            return;
+
+        WalEntryType::SequenceSync => {
+            // Write current state of sequences to the sequence page. Ignores page id, slot id.
+            // Data is the contents of the sequence page.
+            write_mutations.push(PageStoreMutation::WriteSequencePage(data));
         }
-        let pid = wal_entry.header().pid().read();
-
-        // Copied onto heap so we can pass it to the write batch without it getting moved around,
-        // because the kernel will need a stable pointer to it.
-        let data = wal_entry.data().to_vec().into_boxed_slice();
-
-        let action = wal_entry
-            .header()
-            .action()
-            .try_read()
-            .expect("Invalid WAL action");
-        match action {
-            WalEntryType::Insert | WalEntryType::Update => {
-                let relation_id = RelationId(
-                    wal_entry
-                        .header()
-                        .relation_id()
-                        .try_read()
-                        .expect("Invalid relation ID") as usize,
-                );
-
-                let mutation = PageStoreMutation::PageTupleWrite {
-                    relation_id,
-                    page_id: pid as PageId,
-                    slot_id: wal_entry
-                        .header()
-                        .slot_id()
-                        .try_read()
-                        .expect("Could not read WAL slot id")
-                        as SlotId,
-                    page_offset: wal_entry
-                        .header()
-                        .offset()
-                        .try_read()
-                        .expect("Could not read WAL offset")
-                        as usize,
-                    data,
-                };
-
-                write_mutations.push(mutation);
-            }
-            WalEntryType::PageHeader => {
-                let relation_id = RelationId(
-                    wal_entry
-                        .header()
-                        .relation_id()
-                        .try_read()
-                        .expect("Invalid relation ID") as usize,
-                );
-
-                let mutation = PageStoreMutation::PageHeaderWrite {
-                    relation_id,
-                    page_id: pid as PageId,
-                    data,
-                };
-                write_mutations.push(mutation);
-            }
+        WalEntryType::Blob => {
+            write_mutations.push(PageStoreMutation::WriteBlob { blob_id: pid, data });
+        }
+        WalEntryType::Delete => {
+            // Delete
+            let relation_id = RelationId(wal_entry.header().relation_id().read() as usize);
+            let slot_id = wal_entry.header().slot_id().read();
+            write_mutations.push(PageStoreMutation::DeleteTuple(pid as PageId, relation_id));
+            to_evict.push(TupleId {
+                page: pid as PageId,
+                slot: slot_id as SlotId,
+            });
+        }
+    }
+}
 
-            WalEntryType::SequenceSync => {
-                // Write current state of sequences to the sequence page. Ignores page id, slot id.
-                // Data is the contents of the sequence page.
-                write_mutations.push(PageStoreMutation::WriteSequencePage(data));
-            }
-            WalEntryType::Delete => {
-                // Delete
-                let relation_id = RelationId(wal_entry.header().relation_id().read() as usize);
-                let slot_id = wal_entry.header().slot_id().read();
-                write_mutations.push(PageStoreMutation::DeleteTuple(pid as PageId, relation_id));
-                to_evict.push(TupleId {
-                    page: pid as PageId,
-                    slot: slot_id as SlotId,
-                });
-            }
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{encode_record, read_records};
+
+    fn write_log(records: &[Vec<u8>]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for record in records {
+            file.write_all(record).unwrap();
         }
+        file
+    }
+
+    type Records = Vec<(u64, Vec<Vec<u8>>)>;
+
+    fn read_all(file: &tempfile::NamedTempFile) -> (Records, u64) {
+        let mut records = vec![];
+        let valid_len = read_records(file.path(), |r| {
+            records.push((r.timestamp, r.chunks));
+            true
+        })
+        .unwrap();
+        (records, valid_len)
+    }
+
+    #[test]
+    fn round_trip() {
+        let first = encode_record(1, [&b"abc"[..], &b""[..], &b"defgh"[..]]);
+        let second = encode_record(2, []);
+        let file = write_log(&[first.clone(), second.clone()]);
+        let (records, valid_len) = read_all(&file);
+        assert_eq!(
+            records,
+            vec![
+                (1, vec![b"abc".to_vec(), vec![], b"defgh".to_vec()]),
+                (2, vec![])
+            ]
+        );
+        assert_eq!(valid_len, (first.len() + second.len()) as u64);
+    }
+
+    #[test]
+    fn torn_tail_is_ignored() {
+        let first = encode_record(1, [&b"abc"[..]]);
+        let second = encode_record(2, [&b"defgh"[..]]);
+        let file = write_log(&[first.clone(), second[..second.len() - 2].to_vec()]);
+        let (records, valid_len) = read_all(&file);
+        assert_eq!(records, vec![(1, vec![b"abc".to_vec()])]);
+        assert_eq!(valid_len, first.len() as u64);
+    }
+
+    #[test]
+    fn corrupt_record_stops_replay() {
+        let first = encode_record(1, [&b"abc"[..]]);
+        let mut second = encode_record(2, [&b"defgh"[..]]);
+        let last = second.len() - 1;
+        second[last] ^= 0xff;
+        let third = encode_record(3, [&b"ijk"[..]]);
+        let file = write_log(&[first, second, third]);
+        let (records, _) = read_all(&file);
+        assert_eq!(records, vec![(1, vec![b"abc".to_vec()])]);
     }
 }
@@ -0,0 +1,257 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! An append-only archive of every commit written to the write-ahead log, kept so the database
+//! can be rebuilt as of some earlier point in time.
+//!
+//! The WAL itself is thrown away once it has been checkpointed into the page store, so it can't
+//! be used to go backwards. The archive keeps a copy of every record the writer thread puts in the
+//! WAL, in commit order, in numbered segment files (a new segment per open).
+//! The very first record in an archive is a base image of whatever was already in the page
+//! store when archiving was switched on, so replay always starts from an empty directory. If the
+//! database has been committed to without archiving since, another base image is archived when
+//! archiving resumes, bringing replay up to date with it. (Pages are never removed from the page
+//! store, so a later image covers everything an earlier one did.)
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::SystemTime;
+
+use thiserror::Error;
+use tracing::info;
+
+use crate::paging::page_storage::PageStore;
+use crate::paging::wal::{list_segments, read_records, record_to_mutations, segment_path};
+use crate::paging::PageId;
+use crate::RelationId;
+
+const SEGMENT_EXTENSION: &str = "segment";
+
+/// Where to stop replaying the archive when recovering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryTarget {
+    /// Stop before the first commit whose transaction timestamp is greater than this.
+    Timestamp(u64),
+    /// Stop before the first commit archived after this wall-clock time.
+    Time(SystemTime),
+}
+
+#[derive(Debug, Error)]
+pub enum RecoveryError {
+    #[error("Recovery destination {0} already exists and is not empty")]
+    DestinationNotEmpty(PathBuf),
+    #[error("No WAL archive segments found in {0}")]
+    NoArchive(PathBuf),
+    #[error("Recovery target precedes the start of the WAL archive")]
+    TargetPrecedesArchive,
+    #[error("I/O error during recovery: {0}")]
+    Io(String),
+}
+
+/// The writer side of the archive; owned by the cold storage writer thread.
+pub(crate) struct WalArchive {
+    segment: File,
+}
+
+impl WalArchive {
+    /// Open the archive in `dir`, creating it if necessary, and start a new segment for this
+    /// session's commits. Returns the archive along with the highest transaction timestamp
+    /// archived so far, if there is anything in the archive at all.
+    pub(crate) fn open(dir: &Path) -> std::io::Result<(Self, Option<u64>)> {
+        std::fs::create_dir_all(dir)?;
+        let segments = list_segments(dir, SEGMENT_EXTENSION)?;
+
+        // Look back through the segments for the last one with anything in it.
+        let mut last_ts = None;
+        for (_, path) in segments.iter().rev() {
+            read_records(path, |record| {
+                last_ts = Some(last_ts.unwrap_or(0).max(record.timestamp));
+                true
+            })?;
+            if last_ts.is_some() {
+                break;
+            }
+        }
+
+        let next_segment = segments.last().map(|(n, _)| n + 1).unwrap_or(0);
+        let segment = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(segment_path(dir, next_segment, SEGMENT_EXTENSION))?;
+        Ok((Self { segment }, last_ts))
+    }
+
+    /// Durably append already-encoded WAL records.
+    pub(crate) fn append(&mut self, records: &[u8]) -> std::io::Result<()> {
+        self.segment.write_all(records)?;
+        self.segment.sync_data()
+    }
+}
+
+/// Build a new database in `dest` by replaying the WAL archive in `archive` from the beginning,
+/// stopping at `target`. Returns the number of commits replayed.
+///
+/// Commits are replayed in the order they were made, and replay stops at the first one past the
+/// target; since the archive holds physical page writes, a later commit can't be applied without
+/// the ones before it.
+pub fn recover_to(
+    archive: &Path,
+    dest: &Path,
+    target: RecoveryTarget,
+) -> Result<u64, RecoveryError> {
+    if dest
+        .read_dir()
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false)
+    {
+        return Err(RecoveryError::DestinationNotEmpty(dest.to_path_buf()));
+    }
+    let io_err = |e: std::io::Error| RecoveryError::Io(e.to_string());
+
+    let segments = list_segments(archive, SEGMENT_EXTENSION).map_err(io_err)?;
+    if segments.is_empty() {
+        return Err(RecoveryError::NoArchive(archive.to_path_buf()));
+    }
+
+    let page_storage = PageStore::new(dest.join("pages"));
+    let mut replayed = 0;
+    let mut reached_target = false;
+    for (_, path) in &segments {
+        // Each segment goes to the page store as one batch, so each page file is synced once.
+        let mut write_batch = vec![];
+        read_records(path, |record| {
+            let past_target = match target {
+                RecoveryTarget::Timestamp(ts) => record.timestamp > ts,
+                RecoveryTarget::Time(time) => record.wall_time > time,
+            };
+            if past_target {
+                reached_target = true;
+                return false;
+            }
+            write_batch.extend(record_to_mutations(&record.chunks));
+            replayed += 1;
+            true
+        })
+        .map_err(io_err)?;
+        page_storage
+            .enqueue_page_mutations(write_batch)
+            .map_err(io_err)?;
+        if reached_target {
+            break;
+        }
+    }
+    page_storage.wait_complete();
+
+    if replayed == 0 {
+        return Err(RecoveryError::TargetPrecedesArchive);
+    }
+    info!(
+        ?archive,
+        ?dest,
+        ?target,
+        "Recovered {} commits from WAL archive",
+        replayed
+    );
+    Ok(replayed)
+}
+
+/// Read the full contents of every page currently in the page store, for the base image at the
+/// start of a new archive.
+pub(crate) fn page_images(page_storage: &PageStore) -> Vec<(RelationId, PageId, Vec<u8>)> {
+    let mut images = vec![];
+    for (page_size, page_id, relation_id) in page_storage.list_pages() {
+        let mut buf = vec![0; page_size];
+        page_storage
+            .read_page_buf(page_id, relation_id, Pin::new(&mut buf[..]))
+            .expect("Unable to read page for WAL archive base image");
+        images.push((relation_id, page_id, buf));
+    }
+    images
+}
+
+#[cfg(test)]
+mod tests {
+    use moor_values::util::SliceRef;
+
+    use crate::index::{AttrType, IndexType};
+    use crate::paging::wal::{list_segments, read_records};
+    use crate::paging::WalConfig;
+    use crate::{RelBox, RelationId, RelationInfo};
+
+    use super::SEGMENT_EXTENSION;
+
+    // Replay stops at the first commit past its target, so commits made concurrently have to be
+    // archived in timestamp order.
+    #[test]
+    fn archived_in_timestamp_order() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let archive_dir = tmpdir.path().join("archive");
+        let relations = [RelationInfo {
+            name: "relation".to_string(),
+            domain_type: AttrType::Integer,
+            codomain_type: AttrType::Integer,
+            secondary_indexed: false,
+            unique_domain: true,
+            index_type: IndexType::AdaptiveRadixTree,
+            codomain_index_type: None,
+        }];
+        let wal_config = WalConfig {
+            archive: Some(archive_dir.clone()),
+            ..Default::default()
+        };
+        let db = RelBox::new_with_wal_config(
+            1 << 24,
+            tmpdir.path().join("db"),
+            wal_config,
+            &relations,
+            1,
+            1,
+        )
+        .unwrap();
+
+        let threads: Vec<_> = (0..8i64)
+            .map(|thread| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    for i in 0..50 {
+                        let tx = db.clone().start_tx();
+                        let key = SliceRef::from_bytes(&(thread * 1000 + i).to_le_bytes()[..]);
+                        tx.relation(RelationId(0))
+                            .insert_tuple(key.clone(), key)
+                            .unwrap();
+                        tx.commit().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        db.shutdown();
+
+        let mut timestamps = vec![];
+        for (_, path) in list_segments(&archive_dir, SEGMENT_EXTENSION).unwrap() {
+            read_records(&path, |record| {
+                timestamps.push(record.timestamp);
+                true
+            })
+            .unwrap();
+        }
+        // The base image, then every commit.
+        assert_eq!(timestamps.len(), 1 + 8 * 50);
+        assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -33,11 +33,9 @@ use crate::pool::size_class::SizeClass;
 use crate::pool::{Bid, PagerError};
 
 // 32k -> 1MB page sizes supported.
-// TODO: Handle storage of big-values / big-pages / blobs
-//       If we end up with values bigger than 1MB, they should probably be handled by "external" pages,
-//       that is, pages that are not part of the buffer pool, but are instead read directly from file
-//       references as needed, because they are likely to just thrash the crap out of the buffer pool
-//       and are probably just big binary blogs like images, etc.
+// Values bigger than that (in fact, anything over `MAX_INLINE_CODOMAIN`) never make it here; they're
+// stored as blobs outside the buffer pool and read in directly from file as needed, since they'd
+// just thrash the crap out of it.
 pub const LOWEST_SIZE_CLASS_POWER_OF: usize = 12;
 pub const HIGHEST_SIZE_CLASS_POWER_OF: usize = 20;
 
@@ -42,4 +42,10 @@ pub enum PagerError {
 
     #[error("Invalid page")]
     InvalidPage,
+
+    #[error("Page is paged out")]
+    PagedOut,
+
+    #[error("Could not page in or out: {0}")]
+    PagingError(String),
 }
@@ -16,7 +16,9 @@ use std::io;
 use std::ptr::null_mut;
 
 use human_bytes::human_bytes;
-use libc::{madvise, MADV_DONTNEED, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+use libc::{
+    madvise, MADV_DONTNEED, MAP_ANONYMOUS, MAP_NORESERVE, MAP_PRIVATE, PROT_READ, PROT_WRITE,
+};
 use tracing::info;
 
 use crate::pool::PagerError;
@@ -40,12 +42,14 @@ unsafe impl Sync for SizeClass {}
 
 impl SizeClass {
     pub fn new_anon(block_size: usize, virt_size: usize) -> Result<Self, PagerError> {
+        // This is address space only; physical memory is only used by the blocks in use, so
+        // don't have the kernel count the whole region against its overcommit limit.
         let base_addr = unsafe {
             libc::mmap64(
                 null_mut(),
                 virt_size,
                 PROT_READ | PROT_WRITE,
-                MAP_ANONYMOUS | MAP_PRIVATE,
+                MAP_ANONYMOUS | MAP_PRIVATE | MAP_NORESERVE,
                 -1,
                 0,
             )
@@ -12,20 +12,20 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-// TODO: 'join' and transitive closure on db relations
-//   -> datalog-style variable unification
-//   can be used for some of the inheritance graph / verb & property resolution activity done manually now
-
 use crate::base_relation::BaseRelation;
 use crate::index::{AttrType, IndexType};
-use crate::paging::TupleBox;
+use crate::paging::{Damage, OnDamage, OpenError, TupleBox, WalConfig};
+use crate::schema::{Schema, SchemaCheck};
 use crate::tx::WorkingSet;
-use crate::tx::{CommitError, CommitSet, Transaction};
+use crate::tx::{CommitConflict, CommitError, CommitSet, Transaction};
 use crate::RelationId;
+use kanal::Receiver;
 use std::fmt::Debug;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicU64;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use thiserror::Error;
+use tracing::info;
 
 use super::paging::Pager;
 
@@ -48,6 +48,21 @@ pub struct RelationInfo {
     pub codomain_index_type: Option<IndexType>,
 }
 
+/// Errors which can occur while taking a backup with `RelBox::backup`.
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum BackupError {
+    #[error("Backup destination {0} already exists and is not empty")]
+    DestinationNotEmpty(PathBuf),
+    #[error("Unable to create backup destination: {0}")]
+    CreateDestination(String),
+    #[error("Database has no backing store to back up")]
+    NoBackingStore,
+    #[error("Backing store writer is no longer running")]
+    WriterGone,
+    #[error("Unable to copy database: {0}")]
+    Copy(String),
+}
+
 /// The "RelBox" is the set of relations, referenced by their unique (usize) relation ID.
 /// It exposes interfaces for starting & managing transactions on those relations.
 /// It is, essentially, a micro database.
@@ -66,11 +81,24 @@ pub struct RelBox {
     /// a lock for reads.
     canonical: RwLock<Vec<BaseRelation>>,
 
+    /// The most recent snapshot of the canonical relations handed out to read-only transactions,
+    /// along with the timestamp of the last commit it reflects. Shared until the next commit.
+    snapshot: Mutex<Option<(u64, Arc<Vec<BaseRelation>>)>>,
+
     /// The pager (which contains the buffer pool)
     pager: Arc<Pager>,
 
     /// Management of tuples happens through the tuple box (which uses said pager)
     tuple_box: Arc<TupleBox>,
+
+    /// If opened with `open_read_only`, there's nowhere for commits to go, so they're refused.
+    read_only: bool,
+
+    /// Damaged pages and tuples found while opening the database, which were left out of it.
+    damage: Vec<Damage>,
+
+    /// The schema recorded in the database with every commit.
+    schema: RwLock<Arc<Schema>>,
 }
 
 impl Debug for RelBox {
@@ -84,27 +112,208 @@ impl Debug for RelBox {
 }
 
 impl RelBox {
+    /// Open (or create) the database at `path`, or a transient one if there's no path. The
+    /// database records `schema_version` and the name & types of each of `relations`, and won't
+    /// open if what it has recorded is any different.
     pub fn new(
         memory_size: usize,
         path: Option<PathBuf>,
         relations: &[RelationInfo],
         num_sequences: usize,
+        schema_version: u64,
     ) -> Arc<Self> {
+        Self::open(
+            memory_size,
+            path,
+            WalConfig::default(),
+            OnDamage::Refuse,
+            relations,
+            num_sequences,
+            SchemaCheck::Exact(Schema::new(schema_version, relations)),
+        )
+        .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As `new`, but with the write-ahead log for the database at `path` set up per `wal_config`.
+    /// If it names a WAL archive, every commit is archived there, and the database can later be
+    /// rebuilt as of any point since (see `recover_to`). Transaction timestamps carry on from the
+    /// last commit, so they identify commits uniquely across restarts.
+    pub fn new_with_wal_config(
+        memory_size: usize,
+        path: PathBuf,
+        wal_config: WalConfig,
+        relations: &[RelationInfo],
+        num_sequences: usize,
+        schema_version: u64,
+    ) -> Result<Arc<Self>, OpenError> {
+        Self::open(
+            memory_size,
+            Some(path),
+            wal_config,
+            OnDamage::Refuse,
+            relations,
+            num_sequences,
+            SchemaCheck::Exact(Schema::new(schema_version, relations)),
+        )
+    }
+
+    /// Open the database at `path`, last written with schema version `from_version`, so that it
+    /// can be upgraded to the schema for `relations`. Its relations have to be the first of
+    /// `relations`; the rest start out empty. The database keeps `from_version` until it's
+    /// changed with `set_schema_version` or `Transaction::commit_with_schema_version`.
+    pub fn open_for_upgrade(
+        memory_size: usize,
+        path: PathBuf,
+        wal_config: WalConfig,
+        relations: &[RelationInfo],
+        num_sequences: usize,
+        from_version: u64,
+    ) -> Result<Arc<Self>, OpenError> {
+        Self::open(
+            memory_size,
+            Some(path),
+            wal_config,
+            OnDamage::Refuse,
+            relations,
+            num_sequences,
+            SchemaCheck::UpgradeFrom(from_version, Schema::new(from_version, relations)),
+        )
+    }
+
+    /// Open the database at `path` in spite of any damaged pages or tuples in it (which would
+    /// otherwise stop it from opening), removing them from it for good. What was removed is
+    /// available from `damage`.
+    pub fn salvage(
+        memory_size: usize,
+        path: PathBuf,
+        wal_config: WalConfig,
+        relations: &[RelationInfo],
+        num_sequences: usize,
+        schema_version: u64,
+    ) -> Result<Arc<Self>, OpenError> {
+        Self::open(
+            memory_size,
+            Some(path),
+            wal_config,
+            OnDamage::Discard,
+            relations,
+            num_sequences,
+            SchemaCheck::Exact(Schema::new(schema_version, relations)),
+        )
+    }
+
+    /// Open the database at `path` for inspection, without writing anything to it. Transactions
+    /// can be run against it, but not committed. Its write-ahead log has to have been fully
+    /// applied to its pages (as it is after a clean shutdown), as replaying it would mean writing.
+    /// Damaged pages and tuples are left out, and are available from `damage`.
+    pub fn open_read_only(
+        memory_size: usize,
+        path: PathBuf,
+        relations: &[RelationInfo],
+        num_sequences: usize,
+        schema_version: u64,
+    ) -> Result<Arc<Self>, OpenError> {
         let pager = Arc::new(Pager::new(memory_size).expect("Unable to create pager"));
         let tuple_box = Arc::new(TupleBox::new(pager.clone()));
-        let mut base_relations = Vec::with_capacity(relations.len());
-        for (rid, r) in relations.iter().enumerate() {
-            base_relations.push(BaseRelation::new(RelationId(rid), r.clone(), 0));
-        }
+        let mut base_relations = Self::base_relations(relations);
+        let mut sequences = vec![0; num_sequences];
+        let (damage, schema) = pager.open_read_only(
+            path,
+            &mut base_relations,
+            &mut sequences,
+            tuple_box.clone(),
+            &SchemaCheck::Exact(Schema::new(schema_version, relations)),
+        )?;
+        Ok(Self::assemble(
+            relations,
+            base_relations,
+            sequences,
+            0,
+            pager,
+            tuple_box,
+            true,
+            damage,
+            schema,
+        ))
+    }
+
+    /// The schema recorded in the database at `path`, without opening it, or `None` if nothing
+    /// has been committed to it yet. Databases from before schemas were recorded have version 0,
+    /// and no relations.
+    pub fn stored_schema(path: &Path) -> Result<Option<Schema>, OpenError> {
+        Pager::stored_schema(path)
+    }
+
+    fn open(
+        memory_size: usize,
+        path: Option<PathBuf>,
+        wal_config: WalConfig,
+        on_damage: OnDamage,
+        relations: &[RelationInfo],
+        num_sequences: usize,
+        schema_check: SchemaCheck,
+    ) -> Result<Arc<Self>, OpenError> {
+        let pager = Arc::new(Pager::new(memory_size).expect("Unable to create pager"));
+        let tuple_box = Arc::new(TupleBox::new(pager.clone()));
+        let mut base_relations = Self::base_relations(relations);
         let mut sequences = vec![0; num_sequences];
 
         // Open the pager to the provided path, and restore the relations and sequences from it.
         // (If there's no path, this is a no-op and the database will be transient and empty).
-        if let Some(path) = path {
-            pager
-                .open(path, &mut base_relations, &mut sequences, tuple_box.clone())
-                .expect("Unable to open database at path");
-        }
+        let mut first_ts = 0;
+        let mut damage = vec![];
+        let schema = match path {
+            Some(path) => {
+                let (last_ts, found, schema) = pager.open(
+                    path,
+                    wal_config,
+                    &mut base_relations,
+                    &mut sequences,
+                    tuple_box.clone(),
+                    on_damage,
+                    &schema_check,
+                )?;
+                if let Some(last_ts) = last_ts {
+                    first_ts = last_ts + 1;
+                }
+                damage = found;
+                schema
+            }
+            None => schema_check.check(Path::new(""), None)?,
+        };
+        Ok(Self::assemble(
+            relations,
+            base_relations,
+            sequences,
+            first_ts,
+            pager,
+            tuple_box,
+            false,
+            damage,
+            schema,
+        ))
+    }
+
+    fn base_relations(relations: &[RelationInfo]) -> Vec<BaseRelation> {
+        relations
+            .iter()
+            .enumerate()
+            .map(|(rid, r)| BaseRelation::new(RelationId(rid), r.clone(), 0))
+            .collect()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn assemble(
+        relations: &[RelationInfo],
+        base_relations: Vec<BaseRelation>,
+        sequences: Vec<u64>,
+        first_ts: u64,
+        pager: Arc<Pager>,
+        tuple_box: Arc<TupleBox>,
+        read_only: bool,
+        damage: Vec<Damage>,
+        schema: Schema,
+    ) -> Arc<Self> {
         let sequences = sequences
             .into_iter()
             .map(AtomicU64::new)
@@ -112,11 +321,15 @@ impl RelBox {
 
         Arc::new(Self {
             relation_info: relations.to_vec(),
-            maximum_transaction: AtomicU64::new(0),
+            maximum_transaction: AtomicU64::new(first_ts),
             canonical: RwLock::new(base_relations),
+            snapshot: Mutex::new(None),
             sequences,
             tuple_box,
             pager,
+            read_only,
+            damage,
+            schema: RwLock::new(Arc::new(schema)),
         })
     }
 
@@ -124,6 +337,46 @@ impl RelBox {
         self.relation_info.clone()
     }
 
+    /// True if the database was opened with `open_read_only`, and so can't be committed to.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// The damaged pages and tuples which were left out of the database when it was opened.
+    pub fn damage(&self) -> &[Damage] {
+        &self.damage
+    }
+
+    /// The schema the database is recorded as having.
+    pub fn schema(&self) -> Arc<Schema> {
+        self.schema.read().unwrap().clone()
+    }
+
+    /// Record `version` as the database's schema version, once whatever was in it has been
+    /// upgraded to it, and commit that along with the relations it was opened with.
+    pub fn set_schema_version(&self, version: u64) -> Result<(), CommitError> {
+        if self.read_only {
+            return Err(CommitError::ReadOnly);
+        }
+        self.record_schema_version(version);
+        // Taking the commit lock keeps it in timestamp order with other commits.
+        let durable = {
+            let _canonical_lock = self.canonical.write().unwrap();
+            let ts = self
+                .maximum_transaction
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let working_set = WorkingSet::new(self.tuple_box.clone(), &self.relation_info, ts);
+            self.queue_sync(ts, working_set)?
+        };
+        Self::await_sync(durable)
+    }
+
+    /// Have the next commit record `version` as the database's schema version.
+    pub(crate) fn record_schema_version(&self, version: u64) {
+        let mut schema = self.schema.write().unwrap();
+        *schema = Arc::new(Schema::new(version, &self.relation_info));
+    }
+
     /// Begin a transaction against the current canonical relations.
     pub fn start_tx(self: Arc<Self>) -> Transaction {
         let next_ts = self
@@ -132,6 +385,38 @@ impl RelBox {
         Transaction::new(next_ts, self.tuple_box.clone(), self.clone())
     }
 
+    /// Begin a transaction which is expected to only read. Rather than building up its own working
+    /// set it reads from a snapshot of the canonical relations shared with other read-only
+    /// transactions started since the last commit.
+    pub fn start_read_only_tx(self: Arc<Self>) -> Transaction {
+        let next_ts = self
+            .maximum_transaction
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let snapshot = self.snapshot();
+        Transaction::new_read_only(next_ts, snapshot, self.clone())
+    }
+
+    /// Get the shared snapshot of the canonical relations, taking a new one if there have been
+    /// commits since the last one was taken.
+    fn snapshot(&self) -> Arc<Vec<BaseRelation>> {
+        // Holding the read lock means no commit can swap in new relations while we look.
+        let canonical = self.canonical.read().unwrap();
+        let last_commit = canonical.iter().map(|r| r.ts).max().unwrap_or(0);
+        let mut snapshot = self.snapshot.lock().unwrap();
+        if let Some((ts, relations)) = snapshot.as_ref() {
+            if *ts == last_commit {
+                return relations.clone();
+            }
+        }
+        let relations = Arc::new(canonical.clone());
+        *snapshot = Some((last_commit, relations.clone()));
+        relations
+    }
+
+    pub(crate) fn tuple_box(&self) -> Arc<TupleBox> {
+        self.tuple_box.clone()
+    }
+
     pub fn next_ts(self: Arc<Self>) -> u64 {
         self.maximum_transaction
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
@@ -174,26 +459,46 @@ impl RelBox {
 
     /// Prepare a commit set for the given transaction. This will scan through the transaction's
     /// working set, and for each tuple, check to see if it's safe to commit. If it is, then we'll
-    /// add it to the commit set.
+    /// add it to the commit set. If it is not, the tuple that conflicted is described in the
+    /// returned error.
+    /// Take the commit lock and check the working set against the canonical relations, giving
+    /// the commit its timestamp. It's taken under the lock so that timestamps follow commit order.
     pub(crate) fn prepare_commit_set(
         &self,
-        commit_ts: u64,
         tx_working_set: &mut WorkingSet,
-    ) -> Result<CommitSet, CommitError> {
+    ) -> Result<CommitSet, CommitConflict> {
         // The lock belongs to the transaction now now.
         let canonical_lock = self.canonical.write().unwrap();
+        let commit_ts = self
+            .maximum_transaction
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         let mut commitset = CommitSet::new(commit_ts, canonical_lock);
         commitset.prepare(tx_working_set)?;
         Ok(commitset)
     }
 
-    pub fn sync(&self, ts: u64, working_set: WorkingSet) {
+    /// Hand a committed working set to the writer, along with the current sequences, returning
+    /// what to wait on for it to be durable (nothing, if there's no backing store). Called with
+    /// the commit lock held, so commits are queued in timestamp order.
+    pub(crate) fn queue_sync(
+        &self,
+        ts: u64,
+        working_set: WorkingSet,
+    ) -> Result<Option<Receiver<()>>, CommitError> {
         let seqs = self
             .sequences
             .iter()
             .map(|s| s.load(std::sync::atomic::Ordering::SeqCst))
             .collect();
-        self.pager.sync(ts, working_set, seqs);
+        self.pager.queue_sync(ts, working_set, seqs, self.schema())
+    }
+
+    /// Wait for a commit queued by `queue_sync` to be durable.
+    pub(crate) fn await_sync(durable: Option<Receiver<()>>) -> Result<(), CommitError> {
+        match durable {
+            Some(durable) => durable.recv().map_err(|_| CommitError::DurabilityFailure),
+            None => Ok(()),
+        }
     }
 
     pub fn db_usage_bytes(&self) -> usize {
@@ -204,6 +509,44 @@ impl RelBox {
         self.pager.shutdown();
     }
 
+    /// Write a consistent copy of the database, as of the most recent commit, to a new database at
+    /// `path`, returning the timestamp of the commit it reflects.
+    ///
+    /// The writer thread copies the page store and the write-ahead log as they stand once it's
+    /// written out every commit before the backup, holding off checkpoints until the copy is
+    /// done, so commits carry on meanwhile. The copy has the usual `pages` & `journal` layout, and
+    /// can be opened in place of the original.
+    pub fn backup(&self, path: &Path) -> Result<u64, BackupError> {
+        if path
+            .read_dir()
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(false)
+        {
+            return Err(BackupError::DestinationNotEmpty(path.to_path_buf()));
+        }
+        std::fs::create_dir_all(path).map_err(|e| BackupError::CreateDestination(e.to_string()))?;
+
+        // Take the timestamp and sequences together, so they agree with each other, and hand the
+        // backup to the writer before any later commit can get there.
+        let (ts, done) = {
+            let canonical = self.canonical.read().unwrap();
+            let sequences: Vec<_> = self
+                .sequences
+                .iter()
+                .map(|s| s.load(std::sync::atomic::Ordering::SeqCst))
+                .collect();
+            let ts = canonical.iter().map(|r| r.ts).max().unwrap_or(0);
+            let done = self
+                .pager
+                .backup(path.to_path_buf(), ts, sequences, self.schema())?;
+            (ts, done)
+        };
+        done.recv().map_err(|_| BackupError::WriterGone)??;
+
+        info!("Backed up database as of ts {} to {}", ts, path.display());
+        Ok(ts)
+    }
+
     /// Get a copy of the set of the database's canonical relations (generally used for
     /// testing purposes only.)
     pub fn copy_canonical(&self) -> Vec<BaseRelation> {
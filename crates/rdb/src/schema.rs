@@ -0,0 +1,141 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! What a database records about the shape of what's in it, so that it isn't opened by code which
+//! expects something else.
+
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use crate::index::AttrType;
+use crate::paging::OpenError;
+use crate::RelationInfo;
+
+/// A relation, as recorded in a database's catalog.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CatalogEntry {
+    pub name: String,
+    pub domain_type: AttrType,
+    pub codomain_type: AttrType,
+}
+
+impl Display for CatalogEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}({:?} -> {:?})",
+            self.name, self.domain_type, self.codomain_type
+        )
+    }
+}
+
+/// The version of the encoding the database's user writes its tuples in (which the rdb doesn't
+/// interpret), and the relations it keeps them in, in relation id order.
+///
+/// Databases from before the schema was recorded read as version 0, with no relations.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Schema {
+    pub version: u64,
+    pub relations: Vec<CatalogEntry>,
+}
+
+impl Schema {
+    pub fn new(version: u64, relations: &[RelationInfo]) -> Self {
+        Self {
+            version,
+            relations: relations
+                .iter()
+                .map(|r| CatalogEntry {
+                    name: r.name.clone(),
+                    domain_type: r.domain_type,
+                    codomain_type: r.codomain_type,
+                })
+                .collect(),
+        }
+    }
+
+    /// The schema of a database written before schemas were recorded.
+    pub(crate) fn unrecorded() -> Self {
+        Self {
+            version: 0,
+            relations: vec![],
+        }
+    }
+
+    /// How this (stored) schema differs from the `expected` one, for telling someone why their
+    /// database won't open.
+    pub fn describe_difference(&self, expected: &Schema) -> String {
+        if self.version != expected.version {
+            return format!(
+                "it has schema version {} where {} was expected",
+                self.version, expected.version
+            );
+        }
+        for (i, (ours, theirs)) in self.relations.iter().zip(&expected.relations).enumerate() {
+            if ours != theirs {
+                return format!(
+                    "its relation {} is {} where {} was expected",
+                    i, ours, theirs
+                );
+            }
+        }
+        format!(
+            "it has {} relations where {} were expected",
+            self.relations.len(),
+            expected.relations.len()
+        )
+    }
+}
+
+/// What schema a database must have been written with to be opened.
+pub(crate) enum SchemaCheck {
+    /// Exactly this one, unless the database is new.
+    Exact(Schema),
+    /// The given (older) version, with relations which are a prefix of the given schema's. It's
+    /// opened with all of the latter's relations (the new ones empty), but keeps its version until
+    /// it's upgraded with `RelBox::set_schema_version`.
+    UpgradeFrom(u64, Schema),
+}
+
+impl SchemaCheck {
+    /// Check the schema of the database at `path` (if there is one yet), returning the schema
+    /// it's to be opened under.
+    pub(crate) fn check(&self, path: &Path, stored: Option<Schema>) -> Result<Schema, OpenError> {
+        match (self, stored) {
+            (SchemaCheck::Exact(expected), None) => Ok(expected.clone()),
+            (SchemaCheck::Exact(expected), Some(stored)) if stored == *expected => Ok(stored),
+            (SchemaCheck::Exact(expected), Some(stored)) => Err(OpenError::SchemaMismatch {
+                path: path.to_path_buf(),
+                stored,
+                expected: expected.clone(),
+            }),
+            (SchemaCheck::UpgradeFrom(_, _), None) => Err(OpenError::NotFound(path.to_path_buf())),
+            (SchemaCheck::UpgradeFrom(version, target), Some(stored)) => {
+                let opened = Schema {
+                    version: *version,
+                    relations: target.relations.clone(),
+                };
+                if stored.version == *version && target.relations.starts_with(&stored.relations) {
+                    Ok(opened)
+                } else {
+                    Err(OpenError::SchemaMismatch {
+                        path: path.to_path_buf(),
+                        stored,
+                        expected: opened,
+                    })
+                }
+            }
+        }
+    }
+}
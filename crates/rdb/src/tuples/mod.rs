@@ -12,6 +12,7 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+pub(crate) use tuple_ref::blob_of;
 pub use tuple_ref::TupleRef;
 
 use crate::paging::{PageId, SlotId};
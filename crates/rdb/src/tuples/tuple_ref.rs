@@ -20,7 +20,7 @@ use std::sync::Arc;
 use moor_values::util::SliceRef;
 
 use crate::paging::TuplePtr;
-use crate::paging::{TupleBox, TupleBoxError};
+use crate::paging::{BlobId, TupleBox, TupleBoxError};
 use crate::tuples::TupleId;
 use crate::RelationId;
 
@@ -29,6 +29,23 @@ pub struct TupleRef {
     sp: *mut TuplePtr,
 }
 
+/// Codomains bigger than this are stored out of line as blobs, so that huge values don't take up
+/// (and churn) pages in the buffer pool, and are only read in when they're actually used.
+pub const MAX_INLINE_CODOMAIN: usize = 1 << 16;
+
+/// Set in `codomain_size` if the codomain is a blob, in which case the slot holds its id in place
+/// of the codomain itself.
+const BLOB_CODOMAIN: u32 = 1 << 31;
+
+/// Set in `codomain_size` if the slot ends with a CRC32C of the domain and (inline) codomain,
+/// following them. Tuples written before checksums were introduced don't have one, and the header
+/// layout is unchanged so that their pages can still be read.
+const CHECKSUMMED: u32 = 1 << 30;
+
+const CODOMAIN_FLAGS: u32 = BLOB_CODOMAIN | CHECKSUMMED;
+
+const CHECKSUM_SIZE: usize = std::mem::size_of::<u32>();
+
 #[repr(C, align(8))]
 struct TupleHeader {
     ts: u64,
@@ -36,6 +53,60 @@ struct TupleHeader {
     codomain_size: u32,
 }
 
+impl TupleHeader {
+    /// The number of bytes the codomain takes up in the slot.
+    fn inline_codomain_size(&self) -> usize {
+        if self.codomain_size & BLOB_CODOMAIN != 0 {
+            std::mem::size_of::<BlobId>()
+        } else {
+            (self.codomain_size & !CODOMAIN_FLAGS) as usize
+        }
+    }
+}
+
+/// Check that the header of the tuple in `slot` describes a tuple that fits in it, and that its
+/// contents match the checksum taken when it was written, if there is one.
+pub(crate) fn check_slot(slot: &[u8]) -> Result<(), String> {
+    let header_size = std::mem::size_of::<TupleHeader>();
+    if slot.len() < header_size {
+        return Err(format!("{} bytes is too short to hold a tuple", slot.len()));
+    }
+    let header = unsafe { &*(slot.as_ptr() as *const TupleHeader) };
+    let content_end = header_size + header.domain_size as usize + header.inline_codomain_size();
+    let checksummed = header.codomain_size & CHECKSUMMED != 0;
+    let tuple_size = content_end + if checksummed { CHECKSUM_SIZE } else { 0 };
+    if tuple_size > slot.len() {
+        return Err(format!(
+            "tuple of {} bytes overruns its {} byte slot",
+            tuple_size,
+            slot.len()
+        ));
+    }
+    if !checksummed {
+        return Ok(());
+    }
+    let checksum = crc32c::crc32c(&slot[header_size..content_end]);
+    let recorded = u32::from_le_bytes(slot[content_end..tuple_size].try_into().unwrap());
+    if checksum != recorded {
+        return Err(format!(
+            "checksum {:#010x} does not match recorded {:#010x}",
+            checksum, recorded
+        ));
+    }
+    Ok(())
+}
+
+/// The id of the blob holding the codomain of the tuple in `slot`, if it's stored out of line.
+pub(crate) fn blob_of(slot: &[u8]) -> Option<BlobId> {
+    let header = unsafe { &*(slot.as_ptr() as *const TupleHeader) };
+    if header.codomain_size & BLOB_CODOMAIN == 0 {
+        return None;
+    }
+    let blob_start = std::mem::size_of::<TupleHeader>() + header.domain_size as usize;
+    let blob_id = slot.get(blob_start..blob_start + std::mem::size_of::<BlobId>())?;
+    Some(BlobId::from_le_bytes(blob_id.try_into().unwrap()))
+}
+
 unsafe impl Send for TupleRef {}
 unsafe impl Sync for TupleRef {}
 impl TupleRef {
@@ -54,23 +125,39 @@ impl TupleRef {
         domain: &[u8],
         codomain: &[u8],
     ) -> Result<TupleRef, TupleBoxError> {
-        let total_size = std::mem::size_of::<TupleHeader>() + domain.len() + codomain.len();
+        // Oversized codomains go out of line, leaving just the blob id in the slot.
+        let (codomain_size, inline_codomain) = if codomain.len() > MAX_INLINE_CODOMAIN {
+            let blob_id = sb.put_blob(codomain)?;
+            (
+                codomain.len() as u32 | BLOB_CODOMAIN,
+                blob_id.to_le_bytes().to_vec(),
+            )
+        } else {
+            (codomain.len() as u32, codomain.to_vec())
+        };
+        let total_size = std::mem::size_of::<TupleHeader>()
+            + domain.len()
+            + inline_codomain.len()
+            + CHECKSUM_SIZE;
         let tuple_ref = sb.clone().allocate(total_size, relation_id, None)?;
         sb.update_with(tuple_ref.id(), |mut buffer| {
             let domain_len = domain.len();
-            let codomain_len = codomain.len();
+            let codomain_len = inline_codomain.len();
             {
                 let header_ptr = buffer.as_mut().as_mut_ptr() as *mut TupleHeader;
                 let header = unsafe { &mut *header_ptr };
                 header.ts = ts;
                 header.domain_size = domain_len as u32;
-                header.codomain_size = codomain_len as u32;
+                header.codomain_size = codomain_size | CHECKSUMMED;
             }
             let start_pos = std::mem::size_of::<TupleHeader>();
             let codomain_start = start_pos + domain_len;
             let codomain_end = codomain_start + codomain_len;
             buffer[start_pos..start_pos + domain_len].copy_from_slice(domain);
-            buffer[codomain_start..codomain_end].copy_from_slice(codomain);
+            buffer[codomain_start..codomain_end].copy_from_slice(&inline_codomain);
+            let checksum = crc32c::crc32c(&buffer[start_pos..codomain_end]);
+            buffer[codomain_end..codomain_end + CHECKSUM_SIZE]
+                .copy_from_slice(&checksum.to_le_bytes());
         })?;
 
         // Initial refcount should be 1, because we have a reference to it.
@@ -87,59 +174,86 @@ impl TupleRef {
     /// Update the timestamp of the tuple.
     #[inline]
     pub fn update_timestamp(&mut self, ts: u64) {
-        let header = self.header_mut();
-        header.ts = ts;
+        self.with_header_mut(|header| header.ts = ts);
     }
 
     /// The timestamp of the tuple.
     #[inline]
     pub fn ts(&self) -> u64 {
-        let header = self.header();
-        header.ts
+        self.with_header(|header| header.ts)
     }
 
     /// The domain of the tuple.
     #[inline]
     pub fn domain(&self) -> SliceRef {
-        let header = self.header();
-        let domain_size = header.domain_size as usize;
-        let buffer = self.slot_buffer();
-        let domain_start = std::mem::size_of::<TupleHeader>();
-        buffer.slice(domain_start..domain_start + domain_size)
+        let slot_ptr = self.resolve_slot_ptr();
+        slot_ptr.with_buffer(|buffer| {
+            let header = unsafe { &*(buffer.as_ptr() as *const TupleHeader) };
+            let domain_size = header.domain_size as usize;
+            let domain_start = std::mem::size_of::<TupleHeader>();
+            SliceRef::from_vec(buffer[domain_start..domain_start + domain_size].to_vec())
+        })
     }
 
-    /// The codomain of the tuple.
+    /// The codomain of the tuple, read in from its blob if it's stored out of line.
     #[inline]
     pub fn codomain(&self) -> SliceRef {
-        let header = self.header();
-        let domain_size = header.domain_size as usize;
-        let codomain_size = header.codomain_size as usize;
-        let buffer = self.slot_buffer();
-        let codomain_start = std::mem::size_of::<TupleHeader>() + domain_size;
-        buffer.slice(codomain_start..codomain_start + codomain_size)
+        let slot_ptr = self.resolve_slot_ptr();
+        let codomain = slot_ptr.with_buffer(|buffer| {
+            if blob_of(buffer).is_some() {
+                return None;
+            }
+            let header = unsafe { &*(buffer.as_ptr() as *const TupleHeader) };
+            let domain_size = header.domain_size as usize;
+            let codomain_size = header.inline_codomain_size();
+            let codomain_start = std::mem::size_of::<TupleHeader>() + domain_size;
+            Some(SliceRef::from_vec(
+                buffer[codomain_start..codomain_start + codomain_size].to_vec(),
+            ))
+        });
+        if let Some(codomain) = codomain {
+            return codomain;
+        }
+        // Read the blob without keeping the tuple's page pinned.
+        let blob_id = self.blob_id().unwrap();
+        let blob = slot_ptr.tuple_box().get_blob(blob_id).unwrap_or_else(|e| {
+            panic!("Unable to read blob {} for {:?}: {}", blob_id, self.id(), e)
+        });
+        SliceRef::from_vec(blob)
+    }
+
+    /// The id of the blob the codomain is stored in, if it's stored out of line.
+    #[inline]
+    pub fn blob_id(&self) -> Option<BlobId> {
+        self.resolve_slot_ptr().with_buffer(blob_of)
+    }
+
+    /// Check the tuple's slot against its header and checksum; see `check_slot`.
+    pub(crate) fn check(&self) -> Result<(), String> {
+        self.resolve_slot_ptr().with_buffer(check_slot)
     }
 
     /// The raw buffer of the tuple, including the header, not dividing up the domain and codomain.
     pub fn slot_buffer(&self) -> SliceRef {
         let slot_ptr = self.resolve_slot_ptr();
-        let buffer = slot_ptr.buffer();
-        SliceRef::from_vec(buffer.to_vec())
+        slot_ptr.with_buffer(|buffer| SliceRef::from_vec(buffer.to_vec()))
     }
 }
 
 impl TupleRef {
+    /// Call `f` with the tuple's header, which is only valid for the duration of the call, as the
+    /// tuple's page could be evicted afterwards.
     #[inline]
-    fn header(&self) -> &TupleHeader {
+    fn with_header<R, F: FnOnce(&TupleHeader) -> R>(&self, f: F) -> R {
         let slot_ptr = self.resolve_slot_ptr();
-        let header: *const TupleHeader = slot_ptr.as_ptr();
-        unsafe { &*header }
+        slot_ptr.with_buffer(|buffer| f(unsafe { &*(buffer.as_ptr() as *const TupleHeader) }))
     }
 
     #[inline]
-    fn header_mut(&mut self) -> &mut TupleHeader {
+    fn with_header_mut<R, F: FnOnce(&mut TupleHeader) -> R>(&mut self, f: F) -> R {
         let slot_ptr = self.resolve_slot_ptr_mut();
-        let header: *mut TupleHeader = unsafe { slot_ptr.get_unchecked_mut() }.as_mut_ptr();
-        unsafe { &mut *header }
+        unsafe { slot_ptr.get_unchecked_mut() }
+            .with_buffer_mut(|buffer| f(unsafe { &mut *(buffer.as_mut_ptr() as *mut TupleHeader) }))
     }
 
     #[inline]
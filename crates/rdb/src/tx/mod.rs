@@ -12,9 +12,11 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-pub use transaction::{CommitError, CommitSet, Transaction};
+pub use query::{Closure, Direction, Join};
+pub use transaction::{CommitConflict, CommitError, CommitSet, Transaction};
 pub use working_set::WorkingSet;
 
+mod query;
 mod relvar;
 mod transaction;
 mod tx_tuple;
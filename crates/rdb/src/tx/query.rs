@@ -0,0 +1,141 @@
+// Copyright (C) 2024 Ryan Daum <ryan.daum@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! A small query layer over the relations in a transaction: transitive closure over a relation
+//! which maps a thing to another of the same kind (e.g. object -> parent), and joins of a stream
+//! of values against another relation.
+//!
+//! Both are lazy, so a search can stop as soon as it has what it wants (e.g. the nearest ancestor
+//! defining a verb) without visiting the rest.
+
+use std::collections::{HashSet, VecDeque};
+
+use moor_values::util::SliceRef;
+
+use crate::tuples::TupleRef;
+use crate::tx::relvar::RelVar;
+use crate::RelationError;
+
+/// Which way to follow a relation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// From domain to codomain.
+    Forward,
+    /// From codomain to domain. The relation must have a secondary index on its codomain.
+    Backward,
+}
+
+/// The values reachable from a starting value by following a relation over and over, breadth
+/// first, beginning with the starting value itself. Each value is produced only once, so cycles
+/// terminate.
+pub struct Closure<'a> {
+    relvar: RelVar<'a>,
+    direction: Direction,
+    queue: VecDeque<SliceRef>,
+    seen: HashSet<SliceRef>,
+    /// The last value produced, which is only followed when the next one is asked for.
+    unexpanded: Option<SliceRef>,
+}
+
+impl<'a> Closure<'a> {
+    pub(crate) fn new(relvar: RelVar<'a>, start: SliceRef, direction: Direction) -> Self {
+        Self {
+            relvar,
+            direction,
+            queue: VecDeque::from([start.clone()]),
+            seen: HashSet::from([start]),
+            unexpanded: None,
+        }
+    }
+
+    fn expand(&mut self, value: SliceRef) -> Result<(), RelationError> {
+        let tuples = match self.direction {
+            Direction::Forward => self.relvar.seek_by_domain(value)?,
+            Direction::Backward => self.relvar.seek_by_codomain(value)?,
+        };
+        for t in tuples {
+            let next = match self.direction {
+                Direction::Forward => t.codomain(),
+                Direction::Backward => t.domain(),
+            };
+            if self.seen.insert(next.clone()) {
+                self.queue.push_back(next);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for Closure<'a> {
+    type Item = Result<SliceRef, RelationError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(value) = self.unexpanded.take() {
+            if let Err(e) = self.expand(value) {
+                self.queue.clear();
+                return Some(Err(e));
+            }
+        }
+        let value = self.queue.pop_front()?;
+        self.unexpanded = Some(value.clone());
+        Some(Ok(value))
+    }
+}
+
+/// Each value from some source paired with the tuples whose domain matches it in a relation,
+/// after mapping it through a key function. Values with no match are skipped; the order of the
+/// source is kept.
+pub struct Join<'a, I, K> {
+    left: I,
+    relvar: RelVar<'a>,
+    key: K,
+    matched: VecDeque<(SliceRef, TupleRef)>,
+}
+
+impl<'a, I, K> Join<'a, I, K> {
+    pub(crate) fn new(left: I, relvar: RelVar<'a>, key: K) -> Self {
+        Self {
+            left,
+            relvar,
+            key,
+            matched: VecDeque::new(),
+        }
+    }
+}
+
+impl<'a, I, K> Iterator for Join<'a, I, K>
+where
+    I: Iterator<Item = Result<SliceRef, RelationError>>,
+    K: Fn(&SliceRef) -> SliceRef,
+{
+    type Item = Result<(SliceRef, TupleRef), RelationError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(matched) = self.matched.pop_front() {
+                return Some(Ok(matched));
+            }
+            let value = match self.left.next()? {
+                Ok(value) => value,
+                Err(e) => return Some(Err(e)),
+            };
+            let tuples = match self.relvar.seek_by_domain((self.key)(&value)) {
+                Ok(tuples) => tuples,
+                Err(e) => return Some(Err(e)),
+            };
+            self.matched
+                .extend(tuples.into_iter().map(|t| (value.clone(), t)));
+        }
+    }
+}
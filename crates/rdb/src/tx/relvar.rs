@@ -13,16 +13,20 @@
 //
 
 use std::collections::HashSet;
+use std::ops::Bound;
 
 use moor_values::util::SliceRef;
 
+use crate::index::prefix_bounds;
 use crate::tuples::TupleRef;
+use crate::tx::query::{Closure, Direction, Join};
 use crate::tx::transaction::Transaction;
 use crate::{RelationError, RelationId};
 
 /// A reference / handle / pointer to a relation, the actual operations are managed through the
 /// transaction.
 /// A more convenient handle tied to the lifetime of the transaction.
+#[derive(Copy, Clone)]
 pub struct RelVar<'a> {
     pub(crate) tx: &'a Transaction,
     pub(crate) id: RelationId,
@@ -73,6 +77,51 @@ impl<'a> RelVar<'a> {
         self.tx.remove_by_domain(self.id, domain)
     }
 
+    /// Scan for tuples whose domain falls within the given bounds, in domain order. Only
+    /// relations with an ordered domain index (see `IndexType::is_ordered`) can be scanned this
+    /// way; others give `RelationError::UnorderedIndex`.
+    pub fn scan_range(
+        &self,
+        lo: Bound<SliceRef>,
+        hi: Bound<SliceRef>,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        self.tx.scan_range(self.id, lo.as_ref(), hi.as_ref(), None)
+    }
+
+    /// As `scan_range`, but stop after the first `limit` tuples.
+    pub fn scan_range_limit(
+        &self,
+        lo: Bound<SliceRef>,
+        hi: Bound<SliceRef>,
+        limit: usize,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        self.tx
+            .scan_range(self.id, lo.as_ref(), hi.as_ref(), Some(limit))
+    }
+
+    /// Scan for tuples whose domain starts with the given bytes, in domain order. Only makes sense
+    /// for string or byte domains, with an ordered index.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<TupleRef>, RelationError> {
+        let (lo, hi) = prefix_bounds(prefix);
+        self.scan_range(lo, hi)
+    }
+
+    /// Follow this relation transitively from `start`, producing `start` and then everything
+    /// reachable from it, nearest first. Going `Backward` needs a secondary index.
+    pub fn transitive_closure(&self, start: SliceRef, direction: Direction) -> Closure<'a> {
+        Closure::new(*self, start, direction)
+    }
+
+    /// Join a stream of values against this relation, producing each value alongside each tuple
+    /// whose domain is `key` of that value.
+    pub fn join<I, K>(&self, left: I, key: K) -> Join<'a, I::IntoIter, K>
+    where
+        I: IntoIterator<Item = Result<SliceRef, RelationError>>,
+        K: Fn(&SliceRef) -> SliceRef,
+    {
+        Join::new(left.into_iter(), *self, key)
+    }
+
     pub fn predicate_scan<F: Fn(&TupleRef) -> bool>(
         &self,
         f: &F,
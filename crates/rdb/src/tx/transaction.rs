@@ -12,8 +12,9 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use std::cell::RefCell;
+use std::cell::{RefCell, RefMut};
 use std::collections::HashSet;
+use std::ops::Bound;
 use std::sync::{Arc, RwLockWriteGuard};
 use std::thread::yield_now;
 
@@ -39,6 +40,13 @@ pub struct Transaction {
     /// to the transaction, and represents the set of values that will be committed to the base
     /// relations at commit time.
     pub(crate) working_set: RefCell<Option<WorkingSet>>,
+    /// For transactions started read-only, the shared snapshot of the canonical relations which
+    /// reads are served from. The working set is only created (over this snapshot) on first write.
+    snapshot: RefCell<Option<Arc<Vec<BaseRelation>>>>,
+    /// Our timestamp, used to create the working set lazily for read-only transactions.
+    ts: u64,
+    /// The details of the conflict which caused the last commit attempt to fail, if any.
+    conflict: RefCell<Option<CommitConflict>>,
 
     unsend: PhantomUnsend,
     unsync: PhantomUnsync,
@@ -59,6 +67,32 @@ pub enum CommitError {
     /// inserted into for the same unique domain.
     #[error("Unique constraint violation")]
     UniqueConstraintViolation,
+    /// The commit was applied, but the write-ahead log never acknowledged it as durable, because
+    /// the writer has failed or shut down.
+    #[error("Commit could not be made durable")]
+    DurabilityFailure,
+    /// The database was opened read-only, so has nowhere to write commits to.
+    #[error("Database is open read-only")]
+    ReadOnly,
+}
+
+/// Diagnostic information about the tuple which caused a commit to fail, used to find out which
+/// writes are contending with which committed transactions.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CommitConflict {
+    /// The relation in which the conflict was detected.
+    pub relation_id: RelationId,
+    /// The (human readable) name of that relation.
+    pub relation_name: String,
+    /// The domain (key) of the conflicting tuple.
+    pub domain: Vec<u8>,
+    /// The timestamp of the version of the tuple our transaction was working against.
+    pub local_ts: u64,
+    /// The timestamp of the transaction which committed the conflicting version of the tuple, if
+    /// that version is still present in the canonical relation.
+    pub committed_ts: Option<u64>,
+    /// The kind of failure this conflict produced.
+    pub error: CommitError,
 }
 
 impl Transaction {
@@ -68,11 +102,34 @@ impl Transaction {
         Self {
             db,
             working_set: RefCell::new(Some(ws)),
+            snapshot: RefCell::new(None),
+            ts,
+            conflict: RefCell::new(None),
             unsend: Default::default(),
             unsync: Default::default(),
         }
     }
 
+    /// Start a transaction which reads from a shared snapshot of the canonical relations rather
+    /// than building up its own working set. If it ends up writing after all, it falls back to
+    /// a regular working set at that point.
+    pub fn new_read_only(ts: u64, snapshot: Arc<Vec<BaseRelation>>, db: Arc<RelBox>) -> Self {
+        Self {
+            db,
+            working_set: RefCell::new(None),
+            snapshot: RefCell::new(Some(snapshot)),
+            ts,
+            conflict: RefCell::new(None),
+            unsend: Default::default(),
+            unsync: Default::default(),
+        }
+    }
+
+    /// True if this transaction has not (yet) had to create a working set.
+    pub fn is_read_only(&self) -> bool {
+        self.snapshot.borrow().is_some()
+    }
+
     pub fn increment_sequence(&self, sequence_number: usize) -> u64 {
         self.db.clone().increment_sequence(sequence_number)
     }
@@ -84,19 +141,62 @@ impl Transaction {
         self.db.clone().update_sequence_max(sequence_number, value)
     }
     pub fn commit(&self) -> Result<(), CommitError> {
+        self.commit_recording(None)
+    }
+
+    /// Commit, recording `version` as the database's schema version in the same commit, so that
+    /// the changes made to upgrade a database to a version are never recorded without it, nor it
+    /// without them.
+    pub fn commit_with_schema_version(&self, version: u64) -> Result<(), CommitError> {
+        self.commit_recording(Some(version))
+    }
+
+    fn commit_recording(&self, schema_version: Option<u64>) -> Result<(), CommitError> {
+        // Transactions which never wrote anything have nothing to validate or persist, so don't
+        // bother taking the commit lock or syncing to the pager.
+        let mut wrote_nothing = self.snapshot.borrow_mut().take().is_some();
+        {
+            let mut working_set = self.working_set.borrow_mut();
+            if working_set.as_ref().is_some_and(|ws| ws.is_read_only()) {
+                working_set.take();
+                wrote_nothing = true;
+            }
+        }
+        if wrote_nothing {
+            return match schema_version {
+                Some(version) => self.db.set_schema_version(version),
+                None => Ok(()),
+            };
+        }
+
+        if self.db.is_read_only() {
+            return Err(CommitError::ReadOnly);
+        }
+
         let mut tries = 0;
         'retry: loop {
             tries += 1;
-            let commit_ts = self.db.clone().next_ts();
             let mut working_set = self.working_set.borrow_mut();
-            let commit_set = self
-                .db
-                .prepare_commit_set(commit_ts, working_set.as_mut().unwrap())?;
+            let commit_set = match self.db.prepare_commit_set(working_set.as_mut().unwrap()) {
+                Ok(commit_set) => commit_set,
+                Err(conflict) => {
+                    let error = conflict.error.clone();
+                    self.conflict.replace(Some(conflict));
+                    return Err(error);
+                }
+            };
+            let commit_ts = commit_set.ts;
             match commit_set.try_commit() {
-                Ok(()) => {
+                Ok(canonical_lock) => {
+                    // Hand the commit to the writer before anyone else can commit, so commits
+                    // reach the log (and the archive) in timestamp order.
                     let working_set = working_set.take().unwrap();
-                    self.db.sync(commit_ts, working_set);
-                    return Ok(());
+                    if let Some(version) = schema_version {
+                        self.db.record_schema_version(version);
+                    }
+                    let durable = self.db.queue_sync(commit_ts, working_set);
+                    drop(canonical_lock);
+                    return RelBox::await_sync(durable?);
                 }
                 Err(CommitError::RelationContentionConflict) => {
                     if tries > 50 {
@@ -114,11 +214,18 @@ impl Transaction {
         }
     }
 
+    /// If the last commit attempt failed because of a conflict with another transaction, return
+    /// the details of the tuple which conflicted.
+    pub fn conflict(&self) -> Option<CommitConflict> {
+        self.conflict.borrow().clone()
+    }
+
     pub fn db_usage_bytes(&self) -> usize {
         self.db.db_usage_bytes()
     }
 
     pub fn rollback(&self) -> Result<(), CommitError> {
+        self.snapshot.borrow_mut().take();
         let Some(mut ws) = self.working_set.borrow_mut().take() else {
             return Ok(());
         };
@@ -141,6 +248,9 @@ impl Transaction {
         relation_id: RelationId,
         domain: SliceRef,
     ) -> Result<HashSet<TupleRef>, RelationError> {
+        if let Some(snapshot) = self.snapshot.borrow().as_ref() {
+            return snapshot[relation_id.0].seek_by_domain(domain);
+        }
         let mut ws = self.working_set.borrow_mut();
         ws.as_mut()
             .unwrap()
@@ -154,6 +264,16 @@ impl Transaction {
         relation_id: RelationId,
         domain: SliceRef,
     ) -> Result<TupleRef, RelationError> {
+        if let Some(snapshot) = self.snapshot.borrow().as_ref() {
+            let tuples = snapshot[relation_id.0].seek_by_domain(domain)?;
+            if tuples.len() > 1 {
+                return Err(RelationError::AmbiguousTuple);
+            }
+            return tuples
+                .into_iter()
+                .next()
+                .ok_or(RelationError::TupleNotFound);
+        }
         let mut ws = self.working_set.borrow_mut();
         ws.as_mut()
             .unwrap()
@@ -167,6 +287,9 @@ impl Transaction {
         relation_id: RelationId,
         codomain: SliceRef,
     ) -> Result<HashSet<TupleRef>, RelationError> {
+        if let Some(snapshot) = self.snapshot.borrow().as_ref() {
+            return snapshot[relation_id.0].seek_by_codomain(codomain);
+        }
         let mut ws = self.working_set.borrow_mut();
         ws.as_mut()
             .unwrap()
@@ -181,7 +304,7 @@ impl Transaction {
         domain: SliceRef,
         codomain: SliceRef,
     ) -> Result<(), RelationError> {
-        let mut ws = self.working_set.borrow_mut();
+        let mut ws = self.writable_working_set();
         ws.as_mut()
             .unwrap()
             .insert_tuple(&self.db, relation_id, domain, codomain)
@@ -192,12 +315,36 @@ impl Transaction {
         relation_id: RelationId,
         f: &F,
     ) -> Result<Vec<TupleRef>, RelationError> {
+        if let Some(snapshot) = self.snapshot.borrow().as_ref() {
+            return Ok(snapshot[relation_id.0]
+                .predicate_scan(f)
+                .into_iter()
+                .collect());
+        }
         let mut ws = self.working_set.borrow_mut();
         ws.as_mut()
             .unwrap()
             .predicate_scan(&self.db, relation_id, f)
     }
 
+    /// Scan for the tuples whose domains fall within the given bounds, in domain order, up to
+    /// `limit` of them. The relation's domain index must be ordered.
+    pub(crate) fn scan_range(
+        &self,
+        relation_id: RelationId,
+        lo: Bound<&SliceRef>,
+        hi: Bound<&SliceRef>,
+        limit: Option<usize>,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        if let Some(snapshot) = self.snapshot.borrow().as_ref() {
+            return snapshot[relation_id.0].scan_range(lo, hi, limit);
+        }
+        let mut ws = self.working_set.borrow_mut();
+        ws.as_mut()
+            .unwrap()
+            .scan_range(&self.db, relation_id, lo, hi, limit)
+    }
+
     /// Attempt to update a tuple in the transaction's working set, with the intent of eventually
     /// committing it to the canonical base relations.
     pub(crate) fn update_by_domain(
@@ -206,7 +353,7 @@ impl Transaction {
         domain: SliceRef,
         codomain: SliceRef,
     ) -> Result<(), RelationError> {
-        let mut ws = self.working_set.borrow_mut();
+        let mut ws = self.writable_working_set();
         ws.as_mut()
             .unwrap()
             .update_by_domain(&self.db, relation_id, domain, codomain)
@@ -220,7 +367,7 @@ impl Transaction {
         domain: SliceRef,
         codomain: SliceRef,
     ) -> Result<(), RelationError> {
-        let mut ws = self.working_set.borrow_mut();
+        let mut ws = self.writable_working_set();
         ws.as_mut()
             .unwrap()
             .upsert_by_domain(&self.db, relation_id, domain, codomain)
@@ -233,11 +380,27 @@ impl Transaction {
         relation_id: RelationId,
         domain: SliceRef,
     ) -> Result<(), RelationError> {
-        let mut ws = self.working_set.borrow_mut();
+        let mut ws = self.writable_working_set();
         ws.as_mut()
             .unwrap()
             .remove_by_domain(&self.db, relation_id, domain)
     }
+
+    /// Get at the working set for a write, creating it first if we were started read-only.
+    /// That working set carries on from the snapshot we've been reading, so anything we write is
+    /// checked at commit against the version we could have read, not whatever is there now.
+    fn writable_working_set(&self) -> RefMut<'_, Option<WorkingSet>> {
+        let mut ws = self.working_set.borrow_mut();
+        if let Some(snapshot) = self.snapshot.borrow_mut().take() {
+            *ws = Some(WorkingSet::over_snapshot(
+                self.db.tuple_box(),
+                &self.db.relation_info(),
+                self.ts,
+                snapshot,
+            ));
+        }
+        ws
+    }
 }
 
 /// A set of tuples to be committed to the canonical base relations, based on a transaction's
@@ -265,7 +428,10 @@ impl<'a> CommitSet<'a> {
         }
     }
 
-    pub(crate) fn prepare(&mut self, tx_working_set: &mut WorkingSet) -> Result<(), CommitError> {
+    pub(crate) fn prepare(
+        &mut self,
+        tx_working_set: &mut WorkingSet,
+    ) -> Result<(), CommitConflict> {
         for (_, local_relation) in tx_working_set.relations.iter_mut() {
             let relation_id = local_relation.id;
             // scan through the local working set, and for each tuple, check to see if it's safe to
@@ -286,14 +452,22 @@ impl<'a> CommitSet<'a> {
                         let mut replacements = im::HashSet::new();
                         for t in results_canonical {
                             if canonical.info.unique_domain && t.ts() > tuple.ts() {
-                                return Err(CommitError::UniqueConstraintViolation);
+                                return Err(self.conflict(
+                                    relation_id,
+                                    tuple,
+                                    CommitError::UniqueConstraintViolation,
+                                ));
                             }
                             // Check the timestamp on the upstream value, if it's newer than the read-timestamp,
                             // we have for this tuple then that's a conflict, because it means someone else has
                             // already committed a change to this tuple.
                             // Otherwise, we clobber their value.
                             if t.ts() > tuple.ts() {
-                                return Err(CommitError::TupleVersionConflict);
+                                return Err(self.conflict(
+                                    relation_id,
+                                    tuple,
+                                    CommitError::TupleVersionConflict,
+                                ));
                             }
                             replacements.insert(t);
                         }
@@ -317,7 +491,11 @@ impl<'a> CommitSet<'a> {
                         if !canonical.has_tuple(&old_tuple.id()) {
                             // Someone got here first and deleted the tuple we're trying to update.
                             // By definition, this is a conflict.
-                            return Err(CommitError::TupleVersionConflict);
+                            return Err(self.conflict(
+                                relation_id,
+                                old_tuple,
+                                CommitError::TupleVersionConflict,
+                            ));
                         };
 
                         // TODO tuple uniqueness constraint check?
@@ -343,7 +521,11 @@ impl<'a> CommitSet<'a> {
                                     .expect("failed to seek for constraints check");
                                 for t in results_canonical {
                                     if t.ts() > tuple.ts() {
-                                        return Err(CommitError::UniqueConstraintViolation);
+                                        return Err(self.conflict(
+                                            relation_id,
+                                            tuple,
+                                            CommitError::UniqueConstraintViolation,
+                                        ));
                                     }
                                 }
                             }
@@ -363,7 +545,11 @@ impl<'a> CommitSet<'a> {
         Ok(())
     }
 
-    pub(crate) fn try_commit(mut self) -> Result<(), CommitError> {
+    /// Swap in the new canonical relations, returning the lock on them, still held, so the commit
+    /// can be queued for the writer before anyone else's.
+    pub(crate) fn try_commit(
+        mut self,
+    ) -> Result<RwLockWriteGuard<'a, Vec<BaseRelation>>, CommitError> {
         // Everything passed, so we can commit the changes by swapping in the new canonical
         // before releasing the lock.
         let commit_ts = self.ts;
@@ -374,7 +560,30 @@ impl<'a> CommitSet<'a> {
             self.write_guard[idx] = relation;
         }
 
-        Ok(())
+        Ok(self.write_guard)
+    }
+
+    /// Describe a conflict on the given tuple, looking up the version of it (if any) which is now
+    /// present in the canonical relation.
+    fn conflict(
+        &self,
+        relation_id: RelationId,
+        tuple: &TupleRef,
+        error: CommitError,
+    ) -> CommitConflict {
+        let canonical = &self.write_guard[relation_id.0];
+        let committed_ts = canonical
+            .seek_by_domain(tuple.domain())
+            .ok()
+            .and_then(|tuples| tuples.iter().map(|t| t.ts()).max());
+        CommitConflict {
+            relation_id,
+            relation_name: canonical.info.name.clone(),
+            domain: tuple.domain().as_slice().to_vec(),
+            local_ts: tuple.ts(),
+            committed_ts,
+            error,
+        }
     }
 
     /// Fork the given base relation into the commit set, if it's not already there.
@@ -389,6 +598,7 @@ impl<'a> CommitSet<'a> {
 
 #[cfg(test)]
 mod tests {
+    use std::ops::Bound;
     use std::sync::Arc;
 
     use rand::Rng;
@@ -399,6 +609,7 @@ mod tests {
     use crate::relbox::{RelBox, RelationInfo};
     use crate::tuples::TupleRef;
     use crate::tx::transaction::CommitError;
+    use crate::tx::Direction;
     use crate::{RelationError, RelationId, Transaction};
 
     fn attr(slice: &[u8]) -> SliceRef {
@@ -432,8 +643,18 @@ mod tests {
                     index_type: IndexType::AdaptiveRadixTree,
                     codomain_index_type: None,
                 },
+                RelationInfo {
+                    name: "test3".to_string(),
+                    domain_type: AttrType::Bytes,
+                    codomain_type: AttrType::String,
+                    secondary_indexed: false,
+                    unique_domain: true,
+                    index_type: IndexType::BTree,
+                    codomain_index_type: None,
+                },
             ],
             0,
+            0,
         )
     }
 
@@ -800,6 +1021,165 @@ mod tests {
         );
     }
 
+    /// Verify that a failed commit describes which tuple conflicted, and with which committed
+    /// version of it.
+    #[test]
+    fn conflict_details() {
+        let db = test_db();
+        let rid = RelationId(0);
+
+        let init_tx = db.clone().start_tx();
+        init_tx
+            .insert_tuple(rid, attr(b"abc"), attr(b"def"))
+            .unwrap();
+        init_tx.commit().unwrap();
+        assert_eq!(init_tx.conflict(), None);
+
+        let tx1 = db.clone().start_tx();
+        let tx2 = db.clone().start_tx();
+        tx1.update_by_domain(rid, attr(b"abc"), attr(b"123"))
+            .unwrap();
+        tx2.update_by_domain(rid, attr(b"abc"), attr(b"321"))
+            .unwrap();
+        tx1.commit().unwrap();
+        assert_eq!(
+            tx2.commit().expect_err("Expected conflict"),
+            CommitError::TupleVersionConflict
+        );
+
+        let conflict = tx2.conflict().expect("Expected conflict details");
+        assert_eq!(conflict.relation_id, rid);
+        assert_eq!(conflict.relation_name, "test");
+        assert_eq!(conflict.domain, b"abc".to_vec());
+        assert_eq!(conflict.error, CommitError::TupleVersionConflict);
+        let committed_ts = conflict.committed_ts.expect("Expected committed version");
+        assert!(committed_ts > conflict.local_ts);
+    }
+
+    /// Read-only transactions read from a shared snapshot, never conflict, and fall back to a
+    /// regular working set if they write after all.
+    #[test]
+    fn read_only_tx() {
+        let db = test_db();
+        let rid = RelationId(0);
+
+        let init_tx = db.clone().start_tx();
+        init_tx
+            .insert_tuple(rid, attr(b"abc"), attr(b"def"))
+            .unwrap();
+        init_tx.commit().unwrap();
+
+        let ro1 = db.clone().start_read_only_tx();
+        let ro2 = db.clone().start_read_only_tx();
+        assert!(ro1.is_read_only());
+        assert_eq!(
+            ro1.seek_unique_by_domain(rid, attr(b"abc"))
+                .unwrap()
+                .codomain()
+                .as_slice(),
+            b"def"
+        );
+
+        // A concurrent write doesn't disturb the readers, and they commit without conflict.
+        let tx = db.clone().start_tx();
+        tx.update_by_domain(rid, attr(b"abc"), attr(b"123"))
+            .unwrap();
+        tx.commit().unwrap();
+        assert_eq!(
+            ro2.seek_unique_by_domain(rid, attr(b"abc"))
+                .unwrap()
+                .codomain()
+                .as_slice(),
+            b"def"
+        );
+        ro1.commit().unwrap();
+        ro2.commit().unwrap();
+
+        // A new one sees the new value; and if it writes, it becomes a normal transaction.
+        let ro3 = db.clone().start_read_only_tx();
+        assert_eq!(
+            ro3.seek_unique_by_domain(rid, attr(b"abc"))
+                .unwrap()
+                .codomain()
+                .as_slice(),
+            b"123"
+        );
+        ro3.insert_tuple(rid, attr(b"ghi"), attr(b"jkl")).unwrap();
+        assert!(!ro3.is_read_only());
+        ro3.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        assert_eq!(
+            tx.seek_unique_by_domain(rid, attr(b"ghi"))
+                .unwrap()
+                .codomain()
+                .as_slice(),
+            b"jkl"
+        );
+    }
+
+    /// A read-only transaction which writes something it read, after someone else changed it,
+    /// conflicts rather than overwriting their change.
+    #[test]
+    fn read_only_tx_read_then_write() {
+        let db = test_db();
+        let rid = RelationId(0);
+
+        let init_tx = db.clone().start_tx();
+        init_tx
+            .insert_tuple(rid, attr(b"abc"), attr(b"def"))
+            .unwrap();
+        init_tx.commit().unwrap();
+
+        let ro = db.clone().start_read_only_tx();
+        let read = ro.seek_unique_by_domain(rid, attr(b"abc")).unwrap();
+        assert_eq!(read.codomain().as_slice(), b"def");
+
+        let tx = db.clone().start_tx();
+        tx.update_by_domain(rid, attr(b"abc"), attr(b"123"))
+            .unwrap();
+        tx.commit().unwrap();
+
+        // Still working from what it read, even once it's writing.
+        ro.update_by_domain(rid, attr(b"abc"), attr(b"def2"))
+            .unwrap();
+        assert!(!ro.is_read_only());
+        assert_eq!(ro.commit(), Err(CommitError::TupleVersionConflict));
+
+        let tx = db.clone().start_tx();
+        assert_eq!(
+            tx.seek_unique_by_domain(rid, attr(b"abc"))
+                .unwrap()
+                .codomain()
+                .as_slice(),
+            b"123"
+        );
+    }
+
+    /// A transaction which only read tuples commits even if those tuples have since changed.
+    #[test]
+    fn read_only_commit_fast_path() {
+        let db = test_db();
+        let rid = RelationId(0);
+
+        let init_tx = db.clone().start_tx();
+        init_tx
+            .insert_tuple(rid, attr(b"abc"), attr(b"def"))
+            .unwrap();
+        init_tx.commit().unwrap();
+
+        let reader = db.clone().start_tx();
+        reader.seek_unique_by_domain(rid, attr(b"abc")).unwrap();
+
+        let writer = db.clone().start_tx();
+        writer
+            .update_by_domain(rid, attr(b"abc"), attr(b"123"))
+            .unwrap();
+        writer.commit().unwrap();
+
+        reader.commit().unwrap();
+    }
+
     fn random_tuple() -> (Vec<u8>, Vec<u8>) {
         let mut rng = rand::thread_rng();
         let domain = (0..16).map(|_| rng.gen::<u8>()).collect::<Vec<u8>>();
@@ -962,6 +1342,177 @@ mod tests {
         assert_same(&tuples, &items);
     }
 
+    /// Composite (object, id) keys enumerated per object with a prefix scan over a BTree index,
+    /// both from committed state and with the transaction's own changes on top.
+    #[test]
+    fn prefix_scan() {
+        let db = test_db();
+        let rid = RelationId(2);
+        let key = |o: i64, id: u8| {
+            let mut k = o.to_le_bytes().to_vec();
+            k.push(id);
+            attr(&k)
+        };
+        let domains = |tuples: Vec<TupleRef>| {
+            tuples
+                .iter()
+                .map(|t| t.domain().as_slice().to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        let tx = db.clone().start_tx();
+        for o in [1, 2, 3] {
+            for id in [3, 1, 2] {
+                tx.insert_tuple(rid, key(o, id), attr(b"v")).unwrap();
+            }
+        }
+        let r = tx.relation(rid);
+        let expected = [key(2, 1), key(2, 2), key(2, 3)];
+        let expected: Vec<_> = expected.iter().map(|k| k.as_slice().to_vec()).collect();
+        assert_eq!(
+            domains(r.scan_prefix(&2i64.to_le_bytes()).unwrap()),
+            expected
+        );
+        tx.commit().unwrap();
+
+        // Now from the base relation, and with local changes.
+        let tx = db.clone().start_tx();
+        let r = tx.relation(rid);
+        assert_eq!(
+            domains(r.scan_prefix(&2i64.to_le_bytes()).unwrap()),
+            expected
+        );
+        r.remove_by_domain(key(2, 2)).unwrap();
+        r.insert_tuple(key(2, 0), attr(b"v")).unwrap();
+        r.update_by_domain(key(2, 3), attr(b"w")).unwrap();
+        let tuples = r.scan_prefix(&2i64.to_le_bytes()).unwrap();
+        assert_eq!(
+            domains(tuples.clone()),
+            vec![
+                key(2, 0).as_slice().to_vec(),
+                key(2, 1).as_slice().to_vec(),
+                key(2, 3).as_slice().to_vec()
+            ]
+        );
+        assert_eq!(tuples[2].codomain().as_slice(), b"w");
+
+        // Limits apply after the local changes.
+        let limited = r
+            .scan_range_limit(Bound::Included(key(2, 0)), Bound::Unbounded, 4)
+            .unwrap();
+        assert_eq!(
+            domains(limited),
+            vec![
+                key(2, 0).as_slice().to_vec(),
+                key(2, 1).as_slice().to_vec(),
+                key(2, 3).as_slice().to_vec(),
+                key(3, 1).as_slice().to_vec()
+            ]
+        );
+        tx.commit().unwrap();
+
+        // And the same again, read-only.
+        let tx = db.clone().start_tx();
+        let r = tx.relation(rid);
+        assert_eq!(r.scan_prefix(&2i64.to_le_bytes()).unwrap().len(), 3);
+        assert!(r.scan_prefix(&4i64.to_le_bytes()).unwrap().is_empty());
+    }
+
+    /// Integer ranges over an ART index come out in numeric order, negatives included.
+    #[test]
+    fn range_scan_art() {
+        let db = test_db();
+        let rid = RelationId(1);
+        let tx = db.clone().start_tx();
+        for i in [128, -5, 0, 7, -300, 129, 1000] {
+            tx.insert_tuple(rid, attr2(i), attr(b"v")).unwrap();
+        }
+        let r = tx.relation(rid);
+        let scanned = r
+            .scan_range(Bound::Excluded(attr2(-300)), Bound::Included(attr2(129)))
+            .unwrap();
+        let values: Vec<_> = scanned
+            .iter()
+            .map(|t| i64::from_le_bytes(t.domain().as_slice().try_into().unwrap()))
+            .collect();
+        assert_eq!(values, vec![-5, 0, 7, 128, 129]);
+
+        // Reversed bounds are just empty.
+        assert!(r
+            .scan_range(Bound::Included(attr2(10)), Bound::Excluded(attr2(0)))
+            .unwrap()
+            .is_empty());
+
+        // Unordered indexes can't do this at all.
+        assert_eq!(
+            tx.relation(RelationId(0))
+                .scan_range(Bound::Unbounded, Bound::Unbounded)
+                .unwrap_err(),
+            RelationError::UnorderedIndex
+        );
+    }
+
+    /// Closure in both directions over a relation with a cycle in it, and a join from it.
+    #[test]
+    fn closure_and_join() {
+        let db = test_db();
+        let rid = RelationId(0);
+        let tx = db.clone().start_tx();
+        for (d, c) in [(b"a", b"b"), (b"b", b"c"), (b"c", b"a"), (b"d", b"b")] {
+            tx.insert_tuple(rid, attr(d), attr(c)).unwrap();
+        }
+        tx.insert_tuple(RelationId(2), attr(b"c!"), attr(b"C"))
+            .unwrap();
+        tx.insert_tuple(RelationId(2), attr(b"a!"), attr(b"A"))
+            .unwrap();
+        tx.commit().unwrap();
+
+        let tx = db.clone().start_tx();
+        let r = tx.relation(rid);
+        let closure = |start: &[u8], direction| {
+            r.transitive_closure(attr(start), direction)
+                .map(|v| v.unwrap().as_slice().to_vec())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            closure(b"a", Direction::Forward),
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+        );
+        // Going over the same tuples again is fine.
+        assert_eq!(
+            closure(b"d", Direction::Forward),
+            vec![b"d".to_vec(), b"b".to_vec(), b"c".to_vec(), b"a".to_vec()]
+        );
+        let mut backward = closure(b"b", Direction::Backward);
+        assert_eq!(backward[0], b"b".to_vec());
+        backward.sort();
+        assert_eq!(
+            backward,
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]
+        );
+
+        // Join what's reachable from b against the other relation, with a suffix on the key.
+        let joined = tx
+            .relation(RelationId(2))
+            .join(r.transitive_closure(attr(b"b"), Direction::Forward), |v| {
+                let mut key = v.as_slice().to_vec();
+                key.push(b'!');
+                attr(&key)
+            })
+            .map(|j| {
+                let (v, t) = j.unwrap();
+                (v.as_slice().to_vec(), t.codomain().as_slice().to_vec())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            joined,
+            vec![
+                (b"c".to_vec(), b"C".to_vec()),
+                (b"a".to_vec(), b"A".to_vec())
+            ]
+        );
+    }
+
     // TODO: More tests for transaction.rs and transactions generally
     //    Loom tests? Stateright tests?
     //    Test sequences & their behaviour
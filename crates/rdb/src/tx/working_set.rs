@@ -13,12 +13,14 @@
 //
 
 use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
 use std::sync::Arc;
 use tracing::{error, warn};
 
 use moor_values::util::{BitArray, Bitset64};
 use moor_values::util::{PhantomUnsend, PhantomUnsync, SliceRef};
 
+use crate::base_relation::BaseRelation;
 use crate::index::{pick_tx_index, Index};
 use crate::paging::TupleBox;
 use crate::relbox::{RelBox, RelationInfo};
@@ -35,6 +37,10 @@ pub struct WorkingSet {
     pub(crate) schema: Vec<RelationInfo>,
     pub(crate) tuplebox: Arc<TupleBox>,
     pub(crate) relations: Box<BitArray<TxBaseRelation, 64, Bitset64<1>>>,
+    /// If set, the base relations are read from this snapshot, rather than from whatever the
+    /// canonical relations are at the time, so that what's committed is checked against the
+    /// versions of tuples the transaction could have seen since it started.
+    snapshot: Option<Arc<Vec<BaseRelation>>>,
 
     unsend: PhantomUnsend,
     unsync: PhantomUnsync,
@@ -48,11 +54,33 @@ impl WorkingSet {
             tuplebox: slotbox,
             schema: schema.to_vec(),
             relations,
+            snapshot: None,
             unsend: Default::default(),
             unsync: Default::default(),
         }
     }
 
+    /// A working set whose base relations are those in `snapshot`, for a transaction which has
+    /// already been reading from it.
+    pub(crate) fn over_snapshot(
+        slotbox: Arc<TupleBox>,
+        schema: &[RelationInfo],
+        ts: u64,
+        snapshot: Arc<Vec<BaseRelation>>,
+    ) -> Self {
+        Self {
+            snapshot: Some(snapshot),
+            ..Self::new(slotbox, schema, ts)
+        }
+    }
+
+    /// True if nothing in this working set is a mutation, i.e. the transaction only ever read.
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.relations
+            .iter()
+            .all(|(_, rel)| rel.tuples().all(|t| matches!(t.op, TxTupleOp::Value(_))))
+    }
+
     pub(crate) fn clear(&mut self) {
         for rel in self.relations.iter_mut() {
             // let Some(rel) = rel else { continue };
@@ -60,6 +88,20 @@ impl WorkingSet {
         }
     }
 
+    /// Look at the base relation for `relation_id`, either in our snapshot or in the canonical
+    /// relations.
+    fn with_base_relation<R, F: Fn(&BaseRelation) -> R>(
+        snapshot: &Option<Arc<Vec<BaseRelation>>>,
+        db: &RelBox,
+        relation_id: RelationId,
+        f: F,
+    ) -> R {
+        match snapshot {
+            Some(snapshot) => f(&snapshot[relation_id.0]),
+            None => db.with_relation(relation_id, f),
+        }
+    }
+
     fn get_relation_mut<'a>(
         relation_id: RelationId,
         schema: &[RelationInfo],
@@ -95,21 +137,12 @@ impl WorkingSet {
         let relation = Self::get_relation_mut(relation_id, &self.schema, self.relations.as_mut());
 
         // Get the list of matches from the base relation, and then apply the local working set overtop.
-        let tuples = db.with_relation(relation_id, |relation| {
+        let tuples = Self::with_base_relation(&self.snapshot, db, relation_id, |relation| {
             relation.seek_by_domain(domain.clone())
         })?;
 
         // Stash local references to the tuple we've seen, in case updates happen upstream.
-        for t in tuples {
-            let apply = TupleApply {
-                data_source: DataSource::Base,
-                op_source: OpSource::Seek,
-                replacement_op: Some(TxTupleOp::Value(t.clone())),
-                add_tuple: Some(t.clone()),
-                del_tuple: None,
-            };
-            relation.tuple_apply(apply)?;
-        }
+        relation.stash_base_tuples(tuples)?;
 
         let relation = Self::get_relation_mut(relation_id, &self.schema, &mut self.relations);
         let domain_tuples = relation.domain_index.seek(&domain)?;
@@ -152,7 +185,7 @@ impl WorkingSet {
                 };
             }
         }
-        let canon_t = db.with_relation(relation_id, |relation| {
+        let canon_t = Self::with_base_relation(&self.snapshot, db, relation_id, |relation| {
             let tuples = relation.seek_by_domain(domain.clone())?;
             if tuples.is_empty() {
                 return Err(RelationError::TupleNotFound);
@@ -196,7 +229,7 @@ impl WorkingSet {
                 panic!("Attempted to seek by codomain on a relation with no secondary index");
             }
 
-            db.with_relation(relation_id, |relation| {
+            Self::with_base_relation(&self.snapshot, db, relation_id, |relation| {
                 relation.seek_by_codomain(codomain.clone())
             })?
         };
@@ -232,7 +265,7 @@ impl WorkingSet {
 
         // Enforce unique domain constraint before doing anything else
         relation.domain_index.check_constraints(&domain)?;
-        db.with_relation(relation_id, |relation| {
+        Self::with_base_relation(&self.snapshot, db, relation_id, |relation| {
             relation.check_domain_constraints(&domain)?;
             Ok(())
         })?;
@@ -263,8 +296,10 @@ impl WorkingSet {
         f: F,
     ) -> Result<Vec<TupleRef>, RelationError> {
         // First collect all the tuples from the canonical relation that match.
-        let mut tuples: HashMap<TupleId, TupleRef> = db
-            .with_relation(relation_id, |relation| relation.predicate_scan(&f))
+        let mut tuples: HashMap<TupleId, TupleRef> =
+            Self::with_base_relation(&self.snapshot, db, relation_id, |relation| {
+                relation.predicate_scan(&f)
+            })
             .iter()
             .map(|t| (t.id(), t.clone()))
             .collect();
@@ -323,6 +358,40 @@ impl WorkingSet {
         Ok(tuples.values().cloned().collect())
     }
 
+    pub(crate) fn scan_range(
+        &mut self,
+        db: &Arc<RelBox>,
+        relation_id: RelationId,
+        lo: Bound<&SliceRef>,
+        hi: Bound<&SliceRef>,
+        limit: Option<usize>,
+    ) -> Result<Vec<TupleRef>, RelationError> {
+        let relation = Self::get_relation_mut(relation_id, &self.schema, &mut self.relations);
+
+        // Each tuple in the working set can hide at most one from the base relation, so fetching
+        // that many more than the limit is enough to be sure of filling it.
+        let base_limit = limit.map(|limit| limit.saturating_add(relation.tx_tuple_events.len()));
+        let tuples = Self::with_base_relation(&self.snapshot, db, relation_id, |relation| {
+            relation.scan_range(lo, hi, base_limit)
+        })?;
+
+        // Stash local references to the tuples, as with a seek, so that the local index can give
+        // us the range with the working set's own changes applied.
+        relation.stash_base_tuples(tuples)?;
+
+        let range_tuples = relation.domain_index.seek_range(lo, hi)?;
+        let tuples = range_tuples.filter_map(|tid| {
+            let t = relation.tx_tuple_events.get(&tid).unwrap();
+            match &t.op {
+                TxTupleOp::Insert(t)
+                | TxTupleOp::Update { to_tuple: t, .. }
+                | TxTupleOp::Value(t) => Some(t.clone()),
+                TxTupleOp::Tombstone { .. } => None,
+            }
+        });
+        Ok(tuples.take(limit.unwrap_or(usize::MAX)).collect())
+    }
+
     pub(crate) fn update_by_domain(
         &mut self,
         db: &Arc<RelBox>,
@@ -374,7 +443,7 @@ impl WorkingSet {
         // Check canonical for existing values.  And get timestamps for each...
         // We will use the ts on that to determine the derivation timestamp for our own version.
         // If there's nothing there or its tombstoned, that's NotFound, and die.
-        let canon_tuples = db.with_relation(relation_id, |relation| {
+        let canon_tuples = Self::with_base_relation(&self.snapshot, db, relation_id, |relation| {
             let tuples = relation.seek_by_domain(domain.clone())?;
 
             Ok(tuples)
@@ -460,7 +529,7 @@ impl WorkingSet {
         }
 
         // Nothing, local, do canonical...
-        let apply = db.with_relation(relation_id, |relation| {
+        let apply = Self::with_base_relation(&self.snapshot, db, relation_id, |relation| {
             let old_tuples = relation.seek_by_domain(domain.clone())?;
             // If there's more than one value for this domain, this operation makes no sense, so raise an
             // ambig error.
@@ -553,7 +622,7 @@ impl WorkingSet {
             return Ok(());
         }
 
-        let old_tuples = db.with_relation(relation_id, |relation| {
+        let old_tuples = Self::with_base_relation(&self.snapshot, db, relation_id, |relation| {
             let tuples = relation.seek_by_domain(domain.clone())?;
 
             if relation.info.unique_domain {
@@ -619,6 +688,33 @@ impl TxBaseRelation {
         false
     }
 
+    /// Stash local references to tuples seen in the base relation, in case updates happen
+    /// upstream. Tuples we already have our own version of are left alone.
+    fn stash_base_tuples(
+        &mut self,
+        tuples: impl IntoIterator<Item = TupleRef>,
+    ) -> Result<(), RelationError> {
+        for t in tuples {
+            if self.tx_tuple_events.contains_key(&t.id()) {
+                continue;
+            }
+            if self.relation_info.unique_domain
+                && self.domain_index.seek(&t.domain())?.next().is_some()
+            {
+                continue;
+            }
+            let apply = TupleApply {
+                data_source: DataSource::Base,
+                op_source: OpSource::Seek,
+                replacement_op: Some(TxTupleOp::Value(t.clone())),
+                add_tuple: Some(t),
+                del_tuple: None,
+            };
+            self.tuple_apply(apply)?;
+        }
+        Ok(())
+    }
+
     /// Apply a tuple apply event to the working set relation, storing it in our set, and updating indexes appropriately.
     /// This is done to have a consistent process for applying updates to the working set, to avoid having to
     /// do the index updates in multiple places.
@@ -22,6 +22,7 @@ mod tests {
     use std::sync::Arc;
     use tracing_test::traced_test;
 
+    use moor_rdb::index::IndexType;
     use moor_rdb::RelBox;
     use moor_rdb::{RelationId, Transaction};
 
@@ -116,12 +117,11 @@ mod tests {
         }
     }
 
-    #[traced_test]
-    #[test]
-    fn test_generate() {
+    /// Replay the history against relations indexed with `index_type`, checking every read.
+    fn check_history(index_type: IndexType) {
         let tmpdir = tempfile::tempdir().unwrap();
 
-        let db = support::test_db(tmpdir.path().into());
+        let db = support::test_db_with_index(tmpdir.path().into(), index_type);
 
         let lines = include_str!("append-dataset.json")
             .lines()
@@ -183,4 +183,22 @@ mod tests {
             }
         }
     }
+
+    #[traced_test]
+    #[test]
+    fn test_generate() {
+        check_history(IndexType::AdaptiveRadixTree);
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_generate_hash() {
+        check_history(IndexType::Hash);
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_generate_imhash() {
+        check_history(IndexType::ImHash);
+    }
 }
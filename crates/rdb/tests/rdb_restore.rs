@@ -20,12 +20,17 @@ mod test {
     use std::collections::HashMap;
     use std::path::PathBuf;
     use std::rc::Rc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     use std::sync::Arc;
+    use tracing::info;
     use tracing_test::traced_test;
 
     use crate::support::{History, Type, Value};
     use moor_rdb::index::{AttrType, IndexType};
-    use moor_rdb::{RelBox, RelationInfo};
+    use moor_rdb::{
+        recover_to, CommitError, Damage, OpenError, RecoveryTarget, RelBox, RelationInfo, Schema,
+        WalConfig,
+    };
     use moor_rdb::{RelationId, Transaction};
     use moor_values::util::SliceRef;
 
@@ -82,9 +87,8 @@ mod test {
             }
         }
     }
-    pub fn test_db(dir: PathBuf) -> Arc<RelBox> {
-        // Generate 10 test relations that we'll use for testing.
-        let relations = (0..100)
+    fn test_relations() -> Vec<RelationInfo> {
+        (0..100)
             .map(|i| RelationInfo {
                 name: format!("relation_{}", i),
                 domain_type: AttrType::Integer,
@@ -94,9 +98,19 @@ mod test {
                 index_type: IndexType::AdaptiveRadixTree,
                 codomain_index_type: None,
             })
-            .collect::<Vec<_>>();
+            .collect()
+    }
+
+    pub fn test_db(dir: PathBuf) -> Arc<RelBox> {
+        RelBox::new(1 << 24, Some(dir), &test_relations(), 1, 1)
+    }
 
-        RelBox::new(1 << 24, Some(dir), &relations, 0)
+    fn archived_test_db(dir: PathBuf, wal_archive: PathBuf) -> Arc<RelBox> {
+        let wal_config = WalConfig {
+            archive: Some(wal_archive),
+            ..Default::default()
+        };
+        RelBox::new_with_wal_config(1 << 24, dir, wal_config, &test_relations(), 1, 1).unwrap()
     }
 
     // Open a db in a test dir, fill it with some goop, close it, reopen it, and check that the goop is still there.
@@ -131,8 +145,8 @@ mod test {
             expected
         };
 
-        // Verify the WAL directory is not empty.
-        assert!(std::fs::read_dir(format!("{}/wal", tmpdir_str))
+        // Verify the write-ahead log directory is not empty.
+        assert!(std::fs::read_dir(format!("{}/journal", tmpdir_str))
             .unwrap()
             .next()
             .is_some());
@@ -166,4 +180,900 @@ mod test {
             db.shutdown();
         }
     }
+
+    // Tuples removed or replaced by a commit stay gone across a reopen, even if an older
+    // transaction was still reading them when the commit (and later ones to the same page) went out.
+    #[test]
+    #[traced_test]
+    fn removals_and_updates_reopen() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        {
+            let db = test_db(tmpdir.path().into());
+            let tx = db.clone().start_tx();
+            for i in 0..3 {
+                tx.relation(RelationId(0))
+                    .insert_tuple(from_val(i), from_val(i))
+                    .unwrap();
+            }
+            tx.commit().unwrap();
+
+            let reader = db.clone().start_tx();
+            reader
+                .relation(RelationId(0))
+                .predicate_scan(&|_| true)
+                .unwrap();
+
+            let tx = db.clone().start_tx();
+            tx.relation(RelationId(0))
+                .remove_by_domain(from_val(1))
+                .unwrap();
+            tx.relation(RelationId(0))
+                .upsert_by_domain(from_val(2), from_val(20))
+                .unwrap();
+            tx.commit().unwrap();
+
+            let tx = db.clone().start_tx();
+            tx.relation(RelationId(0))
+                .insert_tuple(from_val(3), from_val(3))
+                .unwrap();
+            tx.commit().unwrap();
+
+            reader.rollback().unwrap();
+            db.shutdown();
+        }
+
+        let db = test_db(tmpdir.path().into());
+        let tx = db.clone().start_tx();
+        let mut present: Vec<_> = tx
+            .relation(RelationId(0))
+            .predicate_scan(&|_| true)
+            .unwrap()
+            .into_iter()
+            .map(|t| (to_val(t.domain()), to_val(t.codomain())))
+            .collect();
+        present.sort();
+        assert_eq!(present, vec![(0, 0), (2, 20), (3, 3)]);
+        tx.rollback().unwrap();
+        db.shutdown();
+    }
+
+    // Take a backup of a live db, keep committing to the original, and check that the backup
+    // opens with exactly what was there at the time it was taken.
+    #[test]
+    #[traced_test]
+    fn backup_restore() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let backup_dir = tmpdir.path().join("backup");
+        let db = test_db(tmpdir.path().join("db"));
+
+        let tx = db.clone().start_tx();
+        for i in 0..100 {
+            tx.relation(RelationId(i as usize % 10))
+                .insert_tuple(from_val(i), from_val(i * 2))
+                .unwrap();
+        }
+        tx.commit().unwrap();
+        db.clone().increment_sequence(0);
+
+        db.backup(&backup_dir).unwrap();
+
+        // Changes after the backup shouldn't show up in it.
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(1000), from_val(1000))
+            .unwrap();
+        tx.relation(RelationId(1))
+            .remove_by_domain(from_val(1))
+            .unwrap();
+        tx.commit().unwrap();
+        db.clone().increment_sequence(0);
+
+        // A second backup can't overwrite the first.
+        assert!(db.backup(&backup_dir).is_err());
+        db.shutdown();
+
+        let restored = test_db(backup_dir);
+        assert_eq!(restored.clone().sequence_current(0), 1);
+        let tx = restored.clone().start_tx();
+        for i in 0..100 {
+            let t = tx
+                .relation(RelationId(i as usize % 10))
+                .seek_unique_by_domain(from_val(i))
+                .unwrap();
+            assert_eq!(to_val(t.codomain()), i * 2);
+        }
+        assert!(tx
+            .relation(RelationId(0))
+            .seek_unique_by_domain(from_val(1000))
+            .is_err());
+        tx.rollback().unwrap();
+        restored.shutdown();
+    }
+
+    // Take a backup while another thread keeps committing, enough to fill more than a log
+    // segment, and check that the backup has exactly the commits up to the one it reports.
+    #[test]
+    #[traced_test]
+    fn backup_during_commits() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let backup_dir = tmpdir.path().join("backup");
+        let db = test_db(tmpdir.path().join("db"));
+        // Blobs go in the log whole, so a handful of commits will call for a checkpoint.
+        let payload = |i: i64| {
+            let mut value = vec![0; 2 << 20];
+            value[..8].copy_from_slice(&i.to_le_bytes());
+            SliceRef::from_vec(value)
+        };
+
+        // Give the backup plenty to copy, so it's still going while the commits carry on.
+        let tx = db.clone().start_tx();
+        for i in 0..16 {
+            tx.relation(RelationId(2))
+                .insert_tuple(from_val(i), payload(i))
+                .unwrap();
+        }
+        tx.commit().unwrap();
+
+        let committed = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let committer = {
+            let (db, committed, stop) = (db.clone(), committed.clone(), stop.clone());
+            std::thread::spawn(move || {
+                let mut commit_ts = vec![];
+                for i in 0.. {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let tx = db.clone().start_tx();
+                    tx.relation(RelationId(0))
+                        .insert_tuple(from_val(i), payload(i))
+                        .unwrap();
+                    tx.relation(RelationId(1))
+                        .upsert_by_domain(from_val(0), from_val(i))
+                        .unwrap();
+                    tx.commit().unwrap();
+                    let tx = db.clone().start_tx();
+                    let t = tx
+                        .relation(RelationId(1))
+                        .seek_unique_by_domain(from_val(0))
+                        .unwrap();
+                    commit_ts.push(t.ts());
+                    tx.rollback().unwrap();
+                    committed.fetch_add(1, Ordering::SeqCst);
+                }
+                commit_ts
+            })
+        };
+
+        while committed.load(Ordering::SeqCst) < 4 {
+            std::thread::yield_now();
+        }
+        let before = committed.load(Ordering::SeqCst);
+        let ts = db.backup(&backup_dir).unwrap();
+        let during = committed.load(Ordering::SeqCst) - before;
+        while committed.load(Ordering::SeqCst) < before + during + 10 {
+            std::thread::yield_now();
+        }
+        stop.store(true, Ordering::SeqCst);
+        let commit_ts = committer.join().unwrap();
+        db.shutdown();
+        info!("{} commits while the backup was taken", during);
+
+        // Exactly the commits up to the backup's timestamp are in it.
+        let expected: Vec<i64> = (0..)
+            .zip(&commit_ts)
+            .take_while(|(_, commit_ts)| **commit_ts <= ts)
+            .map(|(i, _)| i)
+            .collect();
+        assert!(commit_ts[expected.len()..].iter().all(|t| *t > ts));
+        let restored = test_db(backup_dir);
+        assert_eq!(present_in(&restored, 0), expected);
+        assert_eq!(present_in(&restored, 2), (0..16).collect::<Vec<_>>());
+        let tx = restored.clone().start_tx();
+        let last = tx
+            .relation(RelationId(1))
+            .seek_unique_by_domain(from_val(0))
+            .unwrap();
+        assert_eq!(to_val(last.codomain()), *expected.last().unwrap());
+        let t = tx
+            .relation(RelationId(0))
+            .seek_unique_by_domain(from_val(*expected.last().unwrap()))
+            .unwrap();
+        assert_eq!(t.codomain(), payload(*expected.last().unwrap()));
+        tx.rollback().unwrap();
+        restored.shutdown();
+    }
+
+    // Archive the WAL across a couple of sessions, then rebuild the database as it was between
+    // them, and as it is at the end.
+    #[test]
+    #[traced_test]
+    fn point_in_time_recovery() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let db_dir = tmpdir.path().join("db");
+        let archive_dir = tmpdir.path().join("archive");
+
+        // Some data is already there before archiving is switched on.
+        let db = test_db(db_dir.clone());
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(-1), from_val(-1))
+            .unwrap();
+        tx.commit().unwrap();
+        db.shutdown();
+
+        let db = archived_test_db(db_dir.clone(), archive_dir.clone());
+        let tx = db.clone().start_tx();
+        for i in 0..100 {
+            tx.relation(RelationId(i as usize % 10))
+                .insert_tuple(from_val(i), from_val(i * 2))
+                .unwrap();
+        }
+        db.clone().increment_sequence(0);
+        tx.commit().unwrap();
+        db.shutdown();
+
+        let before_sweep = std::time::SystemTime::now();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        // The mistake we want to go back to before.
+        let db = archived_test_db(db_dir.clone(), archive_dir.clone());
+        let tx = db.clone().start_tx();
+        for i in 100..150 {
+            tx.relation(RelationId(i as usize % 10))
+                .insert_tuple(from_val(i), from_val(0))
+                .unwrap();
+        }
+        tx.commit().unwrap();
+        db.shutdown();
+
+        // Rebuild from just before it.
+        let recovered_dir = tmpdir.path().join("recovered");
+        recover_to(
+            &archive_dir,
+            &recovered_dir,
+            RecoveryTarget::Time(before_sweep),
+        )
+        .unwrap();
+        let recovered = test_db(recovered_dir.clone());
+        assert_eq!(recovered.clone().sequence_current(0), 1);
+        let tx = recovered.clone().start_tx();
+        let t = tx
+            .relation(RelationId(0))
+            .seek_unique_by_domain(from_val(-1))
+            .unwrap();
+        assert_eq!(to_val(t.codomain()), -1);
+        for i in 0..100 {
+            let t = tx
+                .relation(RelationId(i as usize % 10))
+                .seek_unique_by_domain(from_val(i))
+                .unwrap();
+            assert_eq!(to_val(t.codomain()), i * 2);
+        }
+        for i in 100..150 {
+            assert!(tx
+                .relation(RelationId(i as usize % 10))
+                .seek_unique_by_domain(from_val(i))
+                .is_err());
+        }
+        tx.rollback().unwrap();
+        recovered.shutdown();
+
+        // Can't recover over the top of an existing database.
+        assert!(recover_to(
+            &archive_dir,
+            &recovered_dir,
+            RecoveryTarget::Timestamp(u64::MAX)
+        )
+        .is_err());
+
+        // And replaying everything gets us the current state.
+        let latest_dir = tmpdir.path().join("latest");
+        recover_to(
+            &archive_dir,
+            &latest_dir,
+            RecoveryTarget::Timestamp(u64::MAX),
+        )
+        .unwrap();
+        let latest = test_db(latest_dir);
+        let tx = latest.clone().start_tx();
+        for i in 0..150 {
+            let t = tx
+                .relation(RelationId(i as usize % 10))
+                .seek_unique_by_domain(from_val(i))
+                .unwrap();
+            assert_eq!(to_val(t.codomain()), if i < 100 { i * 2 } else { 0 });
+        }
+        tx.rollback().unwrap();
+        latest.shutdown();
+    }
+
+    // Commits made while archiving was switched off can't be replayed from the archive, so when
+    // it's switched back on the archive picks up from an image of the database as it is then.
+    #[test]
+    #[traced_test]
+    fn archive_gap() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let db_dir = tmpdir.path().join("db");
+        let archive_dir = tmpdir.path().join("archive");
+        let insert = |db: Arc<RelBox>, i: i64| {
+            let tx = db.clone().start_tx();
+            tx.relation(RelationId(0))
+                .insert_tuple(from_val(i), from_val(i))
+                .unwrap();
+            tx.commit().unwrap();
+            db.shutdown();
+        };
+
+        insert(archived_test_db(db_dir.clone(), archive_dir.clone()), 1);
+        insert(test_db(db_dir.clone()), 2);
+        insert(archived_test_db(db_dir.clone(), archive_dir.clone()), 3);
+
+        let latest_dir = tmpdir.path().join("latest");
+        recover_to(
+            &archive_dir,
+            &latest_dir,
+            RecoveryTarget::Timestamp(u64::MAX),
+        )
+        .unwrap();
+        let latest = test_db(latest_dir);
+        let tx = latest.clone().start_tx();
+        for i in 1..=3 {
+            let t = tx
+                .relation(RelationId(0))
+                .seek_unique_by_domain(from_val(i))
+                .unwrap();
+            assert_eq!(to_val(t.codomain()), i);
+        }
+        tx.rollback().unwrap();
+        latest.shutdown();
+    }
+
+    // Fill a db with several times more data than its resident memory budget, and check it's all
+    // still there (faulted back in from disk as needed), both as written and after a reopen.
+    #[test]
+    #[traced_test]
+    fn larger_than_memory() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let resident_budget = 1 << 18;
+        let value = |i: i64| SliceRef::from_vec(vec![i as u8; 1024]);
+        let small_db = || {
+            RelBox::new(
+                resident_budget,
+                Some(tmpdir.path().into()),
+                &test_relations(),
+                1,
+                1,
+            )
+        };
+
+        let db = small_db();
+        for batch in 0..10 {
+            let tx = db.clone().start_tx();
+            for i in batch * 100..(batch + 1) * 100 {
+                tx.relation(RelationId(i as usize % 10))
+                    .insert_tuple(from_val(i), value(i))
+                    .unwrap();
+            }
+            tx.commit().unwrap();
+        }
+        assert!(
+            std::fs::read_dir(tmpdir.path().join("pages").join("evicted"))
+                .unwrap()
+                .next()
+                .is_some()
+        );
+
+        let check = |db: &Arc<RelBox>| {
+            let tx = db.clone().start_tx();
+            for i in 0..1000 {
+                let t = tx
+                    .relation(RelationId(i as usize % 10))
+                    .seek_unique_by_domain(from_val(i))
+                    .unwrap();
+                assert_eq!(t.codomain(), value(i));
+            }
+            tx.rollback().unwrap();
+        };
+        check(&db);
+        db.shutdown();
+
+        let db = small_db();
+        check(&db);
+        db.shutdown();
+    }
+
+    // Values too big for a page are kept in blobs, which survive a restart, are cleaned up when
+    // nothing refers to them, and are carried in the WAL archive.
+    #[test]
+    #[traced_test]
+    fn oversized_values() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let db_dir = tmpdir.path().join("db");
+        let archive_dir = tmpdir.path().join("archive");
+        let blobs_dir = db_dir.join("pages").join("blobs");
+        let big = |fill: u8| SliceRef::from_vec(vec![fill; 2 << 20]);
+        let seek = |db: &Arc<RelBox>, i: i64| {
+            let tx = db.clone().start_tx();
+            let t = tx
+                .relation(RelationId(0))
+                .seek_unique_by_domain(from_val(i))
+                .unwrap();
+            tx.rollback().unwrap();
+            t.codomain()
+        };
+
+        let db = archived_test_db(db_dir.clone(), archive_dir.clone());
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(1), big(1))
+            .unwrap();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(2), from_val(2))
+            .unwrap();
+        tx.commit().unwrap();
+        assert_eq!(seek(&db, 1), big(1));
+
+        // This one never makes it.
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(3), big(3))
+            .unwrap();
+        tx.rollback().unwrap();
+        db.shutdown();
+
+        let db = archived_test_db(db_dir.clone(), archive_dir.clone());
+        assert_eq!(std::fs::read_dir(&blobs_dir).unwrap().count(), 1);
+        assert_eq!(seek(&db, 1), big(1));
+        assert_eq!(seek(&db, 2), from_val(2));
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(4), big(4))
+            .unwrap();
+        tx.commit().unwrap();
+        db.shutdown();
+
+        let recovered_dir = tmpdir.path().join("recovered");
+        recover_to(
+            &archive_dir,
+            &recovered_dir,
+            RecoveryTarget::Timestamp(u64::MAX),
+        )
+        .unwrap();
+        let recovered = test_db(recovered_dir);
+        assert_eq!(seek(&recovered, 1), big(1));
+        assert_eq!(seek(&recovered, 4), big(4));
+        assert!(recovered
+            .clone()
+            .start_tx()
+            .relation(RelationId(0))
+            .seek_unique_by_domain(from_val(3))
+            .is_err());
+        recovered.shutdown();
+    }
+
+    // The blobs of replaced and removed tuples are removed from disk once the change is
+    // checkpointed into the page store, without waiting for a restart.
+    #[test]
+    #[traced_test]
+    fn oversized_values_reclaimed() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let db_dir = tmpdir.path().join("db");
+        let blobs_dir = db_dir.join("pages").join("blobs");
+        let big = |fill: u8| SliceRef::from_vec(vec![fill; 2 << 20]);
+        let blobs = || std::fs::read_dir(&blobs_dir).unwrap().count();
+
+        let db = test_db(db_dir.clone());
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(1), big(0))
+            .unwrap();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(2), big(0))
+            .unwrap();
+        tx.commit().unwrap();
+
+        // Each of these goes in the log, so enough of them fill a segment and get checkpointed.
+        for fill in 1..=10 {
+            let tx = db.clone().start_tx();
+            tx.relation(RelationId(0))
+                .update_by_domain(from_val(1), big(fill))
+                .unwrap();
+            tx.commit().unwrap();
+        }
+        assert!(blobs() < 10, "{} blobs left after a checkpoint", blobs());
+
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(0))
+            .remove_by_domain(from_val(2))
+            .unwrap();
+        tx.commit().unwrap();
+        db.shutdown();
+        assert_eq!(blobs(), 1);
+
+        let db = test_db(db_dir.clone());
+        let tx = db.clone().start_tx();
+        let t = tx
+            .relation(RelationId(0))
+            .seek_unique_by_domain(from_val(1))
+            .unwrap();
+        assert_eq!(t.codomain(), big(10));
+        tx.rollback().unwrap();
+        db.shutdown();
+    }
+
+    // The blobs of tuples which never get committed, whether rolled back or replaced within their
+    // own transaction, are removed from disk straight away.
+    #[test]
+    #[traced_test]
+    fn uncommitted_oversized_values_reclaimed() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let db_dir = tmpdir.path().join("db");
+        let blobs_dir = db_dir.join("pages").join("blobs");
+        let big = |fill: u8| SliceRef::from_vec(vec![fill; 2 << 20]);
+        let blobs = || std::fs::read_dir(&blobs_dir).unwrap().count();
+
+        let db = test_db(db_dir.clone());
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(1), big(1))
+            .unwrap();
+        tx.rollback().unwrap();
+        assert_eq!(blobs(), 0);
+
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(1), big(1))
+            .unwrap();
+        tx.relation(RelationId(0))
+            .update_by_domain(from_val(1), big(2))
+            .unwrap();
+        tx.commit().unwrap();
+        assert_eq!(blobs(), 1);
+        db.shutdown();
+
+        let db = test_db(db_dir.clone());
+        let tx = db.clone().start_tx();
+        let t = tx
+            .relation(RelationId(0))
+            .seek_unique_by_domain(from_val(1))
+            .unwrap();
+        assert_eq!(t.codomain(), big(2));
+        tx.rollback().unwrap();
+        db.shutdown();
+    }
+
+    // Damaged tuples and missing blobs are found when a database is opened; read-only they're
+    // left out without touching the disk, and salvaging removes them for good.
+    #[test]
+    #[traced_test]
+    fn damaged_tuples() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let pages_dir = tmpdir.path().join("pages");
+        let marker = |i: i64| from_val(0x0123_4567_89ab_0000 + i);
+        let present = |db: &Arc<RelBox>| {
+            let tx = db.clone().start_tx();
+            let found: Vec<_> = (1..=4)
+                .filter(|i| {
+                    tx.relation(RelationId(0))
+                        .seek_unique_by_domain(from_val(*i))
+                        .is_ok()
+                })
+                .collect();
+            tx.rollback().unwrap();
+            found
+        };
+
+        let db = test_db(tmpdir.path().into());
+        let tx = db.clone().start_tx();
+        for i in 1..=3 {
+            tx.relation(RelationId(0))
+                .insert_tuple(from_val(i), marker(i))
+                .unwrap();
+        }
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(4), SliceRef::from_vec(vec![4; 2 << 20]))
+            .unwrap();
+        tx.commit().unwrap();
+
+        // Until it's shut down, the commit is only in the write-ahead log.
+        assert!(matches!(
+            RelBox::open_read_only(1 << 24, tmpdir.path().into(), &test_relations(), 1, 1),
+            Err(OpenError::UnappliedJournal(_))
+        ));
+        db.shutdown();
+
+        // Flip a bit in the value of the second tuple, and lose the blob of the fourth.
+        let page_path = std::fs::read_dir(&pages_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| p.to_str().unwrap().ends_with("_0.page"))
+            .unwrap();
+        let mut page = std::fs::read(&page_path).unwrap();
+        let target = marker(2);
+        let position = page
+            .windows(8)
+            .position(|w| w == target.as_slice())
+            .unwrap();
+        page[position] ^= 1;
+        std::fs::write(&page_path, &page).unwrap();
+        for blob in std::fs::read_dir(pages_dir.join("blobs")).unwrap() {
+            std::fs::remove_file(blob.unwrap().path()).unwrap();
+        }
+
+        let db =
+            RelBox::open_read_only(1 << 24, tmpdir.path().into(), &test_relations(), 1, 1).unwrap();
+        assert!(db.is_read_only());
+        assert!(matches!(
+            db.damage(),
+            [Damage::DamagedTuple { .. }, Damage::MissingBlob { .. }]
+        ));
+        assert_eq!(present(&db), vec![1, 3]);
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(5), from_val(5))
+            .unwrap();
+        assert_eq!(tx.commit(), Err(CommitError::ReadOnly));
+        db.shutdown();
+        assert_eq!(std::fs::read(&page_path).unwrap(), page);
+
+        let db = RelBox::salvage(
+            1 << 24,
+            tmpdir.path().into(),
+            WalConfig::default(),
+            &test_relations(),
+            1,
+            1,
+        )
+        .unwrap();
+        assert_eq!(db.damage().len(), 2);
+        db.shutdown();
+
+        let db = test_db(tmpdir.path().into());
+        assert!(db.damage().is_empty());
+        assert_eq!(present(&db), vec![1, 3]);
+        db.shutdown();
+    }
+
+    // Tuples written before they carried checksums have the same header, without the flag or the
+    // checksum after the codomain, and are read as they are.
+    #[test]
+    #[traced_test]
+    fn unchecksummed_tuples() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let pages_dir = tmpdir.path().join("pages");
+        let marker = |i: i64| from_val(0x0123_4567_89ab_0000 + i);
+
+        let db = test_db(tmpdir.path().into());
+        let tx = db.clone().start_tx();
+        for i in 1..=3 {
+            tx.relation(RelationId(0))
+                .insert_tuple(from_val(i), marker(i))
+                .unwrap();
+        }
+        tx.commit().unwrap();
+        db.shutdown();
+
+        // Rewrite the tuples as they used to be: clear the flag in the high bits of the codomain
+        // size, which comes just before the domain, and the checksum following the codomain.
+        let page_path = std::fs::read_dir(&pages_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| p.to_str().unwrap().ends_with("_0.page"))
+            .unwrap();
+        let mut page = std::fs::read(&page_path).unwrap();
+        for i in 1..=3 {
+            let target = marker(i);
+            let codomain = page
+                .windows(8)
+                .position(|w| w == target.as_slice())
+                .unwrap();
+            let codomain_size = codomain - 8 - 4;
+            assert_eq!(page[codomain_size..codomain_size + 4], [8, 0, 0, 0x40]);
+            page[codomain_size + 3] = 0;
+            page[codomain + 8..codomain + 12].fill(0);
+        }
+        std::fs::write(&page_path, &page).unwrap();
+
+        let db = test_db(tmpdir.path().into());
+        assert!(db.damage().is_empty());
+        let tx = db.clone().start_tx();
+        for i in 1..=3 {
+            let t = tx
+                .relation(RelationId(0))
+                .seek_unique_by_domain(from_val(i))
+                .unwrap();
+            assert_eq!(t.codomain(), marker(i));
+        }
+        tx.relation(RelationId(0))
+            .update_by_domain(from_val(2), from_val(2))
+            .unwrap();
+        tx.commit().unwrap();
+        db.shutdown();
+
+        let db =
+            RelBox::open_read_only(1 << 24, tmpdir.path().into(), &test_relations(), 1, 1).unwrap();
+        assert!(db.damage().is_empty());
+        let tx = db.clone().start_tx();
+        let t = tx
+            .relation(RelationId(0))
+            .seek_unique_by_domain(from_val(2))
+            .unwrap();
+        assert_eq!(t.codomain(), from_val(2));
+        tx.rollback().unwrap();
+        db.shutdown();
+    }
+
+    // Transactions can only work with the first 64 relations, so leave room for another.
+    fn fewer_relations() -> Vec<RelationInfo> {
+        test_relations()[..10].to_vec()
+    }
+
+    fn upgraded_relations() -> Vec<RelationInfo> {
+        let mut relations = fewer_relations();
+        relations.push(RelationInfo {
+            name: "added".to_string(),
+            ..relations[0].clone()
+        });
+        relations
+    }
+
+    fn open_versioned(
+        dir: PathBuf,
+        relations: &[RelationInfo],
+        schema_version: u64,
+    ) -> Result<Arc<RelBox>, OpenError> {
+        RelBox::new_with_wal_config(
+            1 << 24,
+            dir,
+            WalConfig::default(),
+            relations,
+            1,
+            schema_version,
+        )
+    }
+
+    fn present_in(db: &Arc<RelBox>, relation: usize) -> Vec<i64> {
+        let tx = db.clone().start_tx();
+        let mut values: Vec<_> = tx
+            .relation(RelationId(relation))
+            .predicate_scan(&|_| true)
+            .unwrap()
+            .into_iter()
+            .map(|t| to_val(t.domain()))
+            .collect();
+        values.sort();
+        tx.rollback().unwrap();
+        values
+    }
+
+    // A database records the schema it was written with, and won't open with any other, until
+    // it's been upgraded.
+    #[test]
+    #[traced_test]
+    fn schema_mismatch_and_upgrade() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        assert_eq!(RelBox::stored_schema(tmpdir.path()), Ok(None));
+
+        let db = open_versioned(tmpdir.path().into(), &fewer_relations(), 1).unwrap();
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(1), from_val(1))
+            .unwrap();
+        tx.commit().unwrap();
+        db.shutdown();
+        assert_eq!(
+            RelBox::stored_schema(tmpdir.path()),
+            Ok(Some(Schema::new(1, &fewer_relations())))
+        );
+
+        let err = open_versioned(tmpdir.path().into(), &fewer_relations(), 2).unwrap_err();
+        assert!(matches!(err, OpenError::SchemaMismatch { .. }));
+        assert!(err
+            .to_string()
+            .contains("it has schema version 1 where 2 was expected"));
+        let err = open_versioned(tmpdir.path().into(), &upgraded_relations(), 1).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("it has 10 relations where 11 were expected"));
+        let mut renamed = fewer_relations();
+        renamed[3].name = "renamed".to_string();
+        let err = open_versioned(tmpdir.path().into(), &renamed, 1).unwrap_err();
+        assert!(err.to_string().contains("its relation 3 is relation_3("));
+        // Only the version it's actually at can be upgraded from.
+        assert!(matches!(
+            RelBox::open_for_upgrade(
+                1 << 24,
+                tmpdir.path().into(),
+                WalConfig::default(),
+                &upgraded_relations(),
+                1,
+                0,
+            ),
+            Err(OpenError::SchemaMismatch { .. })
+        ));
+
+        // Upgrade it, filling in the new relation in the same commit that records the version.
+        let db = RelBox::open_for_upgrade(
+            1 << 24,
+            tmpdir.path().into(),
+            WalConfig::default(),
+            &upgraded_relations(),
+            1,
+            1,
+        )
+        .unwrap();
+        assert_eq!(db.schema().version, 1);
+        assert_eq!(present_in(&db, 0), vec![1]);
+        assert!(present_in(&db, 10).is_empty());
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(10))
+            .insert_tuple(from_val(2), from_val(2))
+            .unwrap();
+        tx.commit_with_schema_version(2).unwrap();
+        assert_eq!(db.schema().version, 2);
+        db.shutdown();
+
+        assert!(open_versioned(tmpdir.path().into(), &fewer_relations(), 1).is_err());
+        let db = open_versioned(tmpdir.path().into(), &upgraded_relations(), 2).unwrap();
+        assert_eq!(*db.schema(), Schema::new(2, &upgraded_relations()));
+        assert_eq!(present_in(&db, 0), vec![1]);
+        assert_eq!(present_in(&db, 10), vec![2]);
+        db.shutdown();
+    }
+
+    // Databases from before the schema was recorded are at version 0, and can be upgraded from it.
+    #[test]
+    #[traced_test]
+    fn unrecorded_schema() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let db = test_db(tmpdir.path().into());
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(0))
+            .insert_tuple(from_val(1), from_val(1))
+            .unwrap();
+        tx.commit().unwrap();
+        db.clone().update_sequence_max(0, 42);
+        let tx = db.clone().start_tx();
+        tx.relation(RelationId(1))
+            .insert_tuple(from_val(2), from_val(2))
+            .unwrap();
+        tx.commit().unwrap();
+        db.shutdown();
+
+        // Put back the sequence page as it used to be written: the number of sequences, then
+        // the id and value of each.
+        let legacy_page: Vec<u8> = [1u64, 0, 42].iter().flat_map(|v| v.to_le_bytes()).collect();
+        std::fs::write(tmpdir.path().join("pages/sequences.page"), legacy_page).unwrap();
+        assert_eq!(
+            RelBox::stored_schema(tmpdir.path()),
+            Ok(Some(Schema {
+                version: 0,
+                relations: vec![],
+            }))
+        );
+        let err = open_versioned(tmpdir.path().into(), &test_relations(), 1).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("it has schema version 0 where 1 was expected"));
+
+        let db = RelBox::open_for_upgrade(
+            1 << 24,
+            tmpdir.path().into(),
+            WalConfig::default(),
+            &test_relations(),
+            1,
+            0,
+        )
+        .unwrap();
+        assert_eq!(db.clone().sequence_current(0), 42);
+        db.set_schema_version(1).unwrap();
+        db.shutdown();
+
+        let db = test_db(tmpdir.path().into());
+        assert_eq!(db.clone().sequence_current(0), 42);
+        assert_eq!(present_in(&db, 0), vec![1]);
+        assert_eq!(present_in(&db, 1), vec![2]);
+        db.shutdown();
+    }
 }
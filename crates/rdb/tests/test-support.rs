@@ -20,6 +20,12 @@ use std::sync::Arc;
 /// Build a test database with a bunch of relations
 #[allow(dead_code)]
 pub fn test_db(dir: PathBuf) -> Arc<RelBox> {
+    test_db_with_index(dir, IndexType::AdaptiveRadixTree)
+}
+
+/// Build a test database with a bunch of relations, all indexed with the given kind of index.
+#[allow(dead_code)]
+pub fn test_db_with_index(dir: PathBuf, index_type: IndexType) -> Arc<RelBox> {
     // Generate 10 test relations that we'll use for testing.
     let relations = (0..100)
         .map(|i| RelationInfo {
@@ -28,12 +34,12 @@ pub fn test_db(dir: PathBuf) -> Arc<RelBox> {
             codomain_type: AttrType::Integer,
             secondary_indexed: false,
             unique_domain: true,
-            index_type: IndexType::AdaptiveRadixTree,
+            index_type,
             codomain_index_type: None,
         })
         .collect::<Vec<_>>();
 
-    RelBox::new(1 << 24, Some(dir), &relations, 0)
+    RelBox::new(1 << 24, Some(dir), &relations, 0, 0)
 }
 
 #[derive(Debug, serde::Deserialize, Copy, Clone, PartialEq)]
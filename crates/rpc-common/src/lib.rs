@@ -63,8 +63,9 @@ pub enum RpcRequest {
     RequestedInput(ClientToken, AuthToken, u128, String),
     /// Send an "out of band" command to be executed.
     OutOfBand(ClientToken, AuthToken, String),
-    /// Evaluate a MOO expression.
-    Eval(ClientToken, AuthToken, String),
+    /// Evaluate a MOO expression. If the caller knows the expression won't modify anything, it can
+    /// say so, and the daemon will run it more cheaply.
+    Eval(ClientToken, AuthToken, String, bool /* read-only? */),
     /// Respond to a ping request.
     Pong(ClientToken, SystemTime),
     /// We're done with this connection, buh-bye.
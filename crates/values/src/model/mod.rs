@@ -47,8 +47,22 @@ mod world_state;
 /// The result code from a commit/complete operation on the world's state.
 #[derive(Debug, Eq, PartialEq)]
 pub enum CommitResult {
-    Success,       // Value was committed
-    ConflictRetry, // Value was not committed due to conflict, caller should abort and retry tx
+    Success, // Value was committed
+    // Value was not committed due to conflict, caller should abort and retry tx.
+    // Carries a description of the conflict, if the database was able to provide one.
+    ConflictRetry(Option<ConflictInfo>),
+}
+
+/// Describes which write in a transaction conflicted with which previously committed transaction,
+/// for diagnosing contention hot spots.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConflictInfo {
+    /// The name of the relation (or table) the conflicting write was made against.
+    pub relation: String,
+    /// The raw key of the tuple which conflicted.
+    pub key: Vec<u8>,
+    /// The timestamp of the committed transaction we conflicted with, if known.
+    pub committed_ts: Option<u64>,
 }
 
 /// Errors related to the world state and operations on it.
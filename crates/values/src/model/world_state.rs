@@ -272,6 +272,12 @@ pub trait WorldState {
     /// Get the children of the given object.
     fn children_of(&self, perms: Objid, obj: Objid) -> Result<ObjSet, WorldStateError>;
 
+    /// Get the ancestors of the given object, from its parent up to the root.
+    fn ancestors_of(&self, perms: Objid, obj: Objid) -> Result<ObjSet, WorldStateError>;
+
+    /// Get all the descendants of the given object: its children, their children, and so on.
+    fn descendants_of(&self, perms: Objid, obj: Objid) -> Result<ObjSet, WorldStateError>;
+
     /// Check the validity of an object.
     fn valid(&self, obj: Objid) -> Result<bool, WorldStateError>;
 
@@ -292,4 +298,10 @@ pub trait WorldStateSource: Send + Sync {
     /// Create a new world state for the given player.
     /// Returns the world state, and a permissions context for the player.
     fn new_world_state(&self) -> Result<Box<dyn WorldState>, WorldStateError>;
+
+    /// Create a new world state for work which is expected to only read, which the source may be
+    /// able to provide more cheaply. Writing to it must still work, just without the savings.
+    fn new_read_only_world_state(&self) -> Result<Box<dyn WorldState>, WorldStateError> {
+        self.new_world_state()
+    }
 }
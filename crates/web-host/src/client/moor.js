@@ -102,7 +102,7 @@ class MoorRPCObject {
     async get_property(property_name) {
         let self = "#" + this.object_id;
         let expr = "return #" + self + "." + property_name + ";";
-        return perform_eval(this.auth_token, expr);
+        return perform_eval(this.auth_token, expr, true);
     }
 
     async get_verbs() {
@@ -114,7 +114,7 @@ class MoorRPCObject {
             "  r = {@r, {v, verb_args(" + self + ", v), verb_info(" + self + ", v)}};" +
             "endfor;" +
             "return r;";
-        let verbs = perform_eval(this.auth_token, expr);
+        let verbs = perform_eval(this.auth_token, expr, true);
         return (await verbs).map((verb) => {
             return new MoorVerb(this.object_id, verb[0], verb[1], verb[2], this.auth_token);
         });
@@ -123,13 +123,13 @@ class MoorRPCObject {
     async get_properties() {
         let self = "#" + this.object_id;
         let expr = "return properties(" + self + ");";
-        return perform_eval(this.auth_token, expr);
+        return perform_eval(this.auth_token, expr, true);
     }
 
     async get_verb_code(verb_name) {
         let self = "#" + this.object_id;
         let expr = "return verb_code(" + self + ", \"" + verb_name + "\");";
-        return perform_eval(this.auth_token, expr);
+        return perform_eval(this.auth_token, expr, true);
     }
 }
 
@@ -146,7 +146,7 @@ class MoorVerb {
     async get_code() {
         let self = "#" + this.object_id;
         let expr = "return verb_code(" + self + ", \"" + this.verb_name + "\");";
-        return perform_eval(this.auth_token, expr);
+        return perform_eval(this.auth_token, expr, true);
     }
 }
 
@@ -158,14 +158,20 @@ async function call_builtin(auth_token, builtin, args) {
 }
 
 // Evaluate a MOO expression on the server and return the result.
-async function perform_eval(auth_token, expr) {
+// If `read_only` is set, the expression is known not to modify anything, and the server can run it
+// more cheaply.
+async function perform_eval(auth_token, expr, read_only = false) {
     // HTTP POST with the body being the expression. And add in the X-Moor-Auth-Token header.
+    let headers = {
+        "X-Moor-Auth-Token": context.auth_token
+    };
+    if (read_only) {
+        headers["X-Moor-Read-Only"] = "true";
+    }
     let result = await fetch("/eval", {
         method: "POST",
         body: expr,
-        headers: {
-            "X-Moor-Auth-Token": context.auth_token
-        }
+        headers: headers
     });
     if (result.ok) {
         let expr = await result.json();
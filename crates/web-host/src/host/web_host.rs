@@ -410,11 +410,17 @@ pub async fn eval_handler(
     };
     let expression = String::from_utf8_lossy(&expression).to_string();
 
+    // Callers which are only fetching things (properties, verb listings, etc.) can say so, and the
+    // daemon will run them without a full transaction.
+    let read_only = header_map
+        .get("X-Moor-Read-Only")
+        .is_some_and(|v| v.as_bytes() == b"true");
+
     debug!("Evaluating expression: {}", expression);
     let response = match rpc_client
         .make_rpc_call(
             client_id,
-            RpcRequest::Eval(client_token.clone(), auth_token, expression),
+            RpcRequest::Eval(client_token.clone(), auth_token, expression, read_only),
         )
         .await
     {